@@ -0,0 +1,156 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use zune_jpeg::{ColorSpace, Decoder, DecoderOptions, FeedStatus, IncrementalDecoder};
+
+const COLORSPACES: [ColorSpace; 7] = [
+    ColorSpace::RGB,
+    ColorSpace::RGBA,
+    ColorSpace::RGBX,
+    ColorSpace::GRAYSCALE,
+    ColorSpace::YCbCr,
+    ColorSpace::CMYK,
+    ColorSpace::YCCK,
+];
+
+/// Settings to decode an image with, taken from the bits of a hash of the input
+///
+/// Hashing keeps corpus entries plain jpeg files, while every mutation of an
+/// input is also decoded with other settings.
+#[derive(Clone, Copy)]
+struct Configuration(u64);
+
+impl Configuration
+{
+    fn new(data: &[u8]) -> Configuration
+    {
+        // FNV-1a
+        let hash = data.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, x| {
+            (hash ^ u64::from(*x)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+        Configuration(hash)
+    }
+
+    /// Take the next `bits` bits of the hash
+    fn take(&mut self, bits: u32) -> usize
+    {
+        let value = self.0 & ((1 << bits) - 1);
+
+        self.0 >>= bits;
+
+        value as usize
+    }
+
+    /// A decoder with these settings
+    fn decoder(mut self, data: &[u8]) -> Decoder
+    {
+        // keep allocations small enough for the fuzzer
+        let options = DecoderOptions::default()
+            .set_max_pixels(1 << 22)
+            .set_max_memory(128 << 20)
+            .set_max_scans(256);
+
+        let mut decoder = Decoder::new_with_options(options);
+
+        decoder.set_output_colorspace(COLORSPACES[self.take(3) % COLORSPACES.len()]);
+
+        decoder.set_strict_mode(self.take(1) == 0);
+
+        decoder.set_scale(1, 1 << self.take(2));
+
+        // a rectangle inside the image, most others would be rejected up front
+        if self.take(1) == 1 && decoder.read_headers(data).is_ok()
+        {
+            if let Some((width, height)) = decoder.output_dimensions()
+            {
+                if width > 0 && height > 0
+                {
+                    let x = self.take(8) % width;
+
+                    let y = self.take(8) % height;
+
+                    let crop_width = 1 + self.take(8) % (width - x);
+
+                    let crop_height = 1 + self.take(8) % (height - y);
+
+                    decoder.set_crop(x, y, crop_width, crop_height);
+                }
+            }
+        }
+        decoder.set_apply_orientation(self.take(1) == 1);
+
+        decoder
+    }
+}
+
+/// Decode `data` through every way the decoder reads images
+fn decode(data: &[u8], configuration: Configuration)
+{
+    let _ = configuration.decoder(data).decode_buffer(data);
+
+    let _ = configuration.decoder(data).decode_buffer_u16(data);
+
+    let _ = configuration.decoder(data).decode_planar(data);
+
+    let _ = configuration.decoder(data).decode_coefficients(data);
+
+    let mut decoder = configuration.decoder(data);
+
+    if let Ok(mut scanlines) = decoder.scanlines(data)
+    {
+        while let Ok(Some(_)) = scanlines.next_rows()
+        {}
+    }
+
+    let mut decoder = configuration.decoder(data);
+
+    if let Ok(mut scans) = decoder.progressive_scans(data)
+    {
+        scans.set_block_smoothing(true);
+
+        while let Ok(true) = scans.next_scan()
+        {
+            scans.render();
+        }
+    }
+
+    let mut decoder = IncrementalDecoder::new(configuration.decoder(data));
+
+    for chunk in data.chunks(512)
+    {
+        let mut status = decoder.feed(chunk);
+
+        while let Ok(FeedStatus::Headers | FeedStatus::Rows(_) | FeedStatus::Scan) = status
+        {
+            status = decoder.feed(&[]);
+        }
+    }
+    while let Ok(FeedStatus::Headers | FeedStatus::Rows(_) | FeedStatus::Scan) = decoder.finish()
+    {}
+}
+
+fuzz_target!(|data: &[u8]| {
+    let configuration = Configuration::new(data);
+
+    decode(data, configuration);
+
+    // The same data read as a baseline and as a progressive image, most inputs are
+    // corpus images of one kind and the entropy decoders of the other would
+    // otherwise rarely run
+    if let Some(sof) = data
+        .windows(2)
+        .position(|x| x[0] == 0xFF && matches!(x[1], 0xC0 | 0xC1 | 0xC2))
+    {
+        let mut data = data.to_vec();
+
+        for marker in [0xC0, 0xC2]
+        {
+            if data[sof + 1] != marker
+            {
+                data[sof + 1] = marker;
+
+                decode(&data, configuration);
+            }
+        }
+    }
+});
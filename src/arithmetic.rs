@@ -0,0 +1,658 @@
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_possible_wrap,
+    clippy::doc_markdown
+)]
+//! This file exposes a single struct that can decode an arithmetic coded
+//! (QM-coder) bitstream in a JPEG file
+//!
+//! The implementation follows Annex D and F.1.4.4 / F.2.4 of the spec
+//! (https://www.w3.org/Graphics/JPEG/itu-t81.pdf) and is a port of the
+//! arithmetic decoder found in libjpeg(-turbo)'s `jdarith.c`.
+//!
+//! Unlike Huffman coding, arithmetic coding is adaptive, the decoder keeps
+//! statistics bins for every DC and AC table which are updated after every decision,
+//! this means we cannot use lookup tables and tricks like in `bitstream.rs`, we decode
+//! one binary decision at a time.
+//!
+//! Each statistics bin is a single byte, the top bit (0x80) contains the meaning of
+//! the more probable symbol (MPS) and the lower bits index into the probability estimation
+//! state machine ([`QE_TABLE`]).
+//!
+//! Furthermore, hitting a marker in the middle of an arithmetic coded segment is legal,
+//! the convention is to supply zeroes until the decoding is complete, so the marker is
+//! stored and handed back to the caller once the scan is done.
+use std::io::Cursor;
+
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::misc::UN_ZIGZAG;
+
+/// Number of statistics bins needed for DC coding (F.1.4.4.1.3 says 49)
+const DC_STAT_BINS: usize = 64;
+
+/// Number of statistics bins needed for AC coding (F.1.4.4.2 says 245)
+const AC_STAT_BINS: usize = 256;
+
+/// Index of the fixed probability (0.5) estimate in [`QE_TABLE`]
+const FIXED_BIN: u8 = 113;
+
+/// Pack an entry of Table D.2 into a single `u32`
+///
+/// Layout is `Qe_Value << 16 | Next_Index_MPS << 8 | Switch_MPS << 7 | Next_Index_LPS`
+#[allow(clippy::similar_names)]
+const fn qe(qe_value: u32, next_lps: u32, next_mps: u32, switch_mps: u32) -> u32
+{
+    (qe_value << 16) | (next_mps << 8) | (switch_mps << 7) | next_lps
+}
+
+/// Table D.2 Qe values and probability estimation state machine
+///
+/// The last entry is used for the fixed probability estimate of 0.5
+/// as recommended in Section 10.3 Table 5 of ITU-T Rec. T.851.
+#[rustfmt::skip]
+const QE_TABLE: [u32; 114] = [
+    qe(0x5a1d,   1,   1, 1), qe(0x2586,  14,   2, 0), qe(0x1114,  16,   3, 0),
+    qe(0x080b,  18,   4, 0), qe(0x03d8,  20,   5, 0), qe(0x01da,  23,   6, 0),
+    qe(0x00e5,  25,   7, 0), qe(0x006f,  28,   8, 0), qe(0x0036,  30,   9, 0),
+    qe(0x001a,  33,  10, 0), qe(0x000d,  35,  11, 0), qe(0x0006,   9,  12, 0),
+    qe(0x0003,  10,  13, 0), qe(0x0001,  12,  13, 0), qe(0x5a7f,  15,  15, 1),
+    qe(0x3f25,  36,  16, 0), qe(0x2cf2,  38,  17, 0), qe(0x207c,  39,  18, 0),
+    qe(0x17b9,  40,  19, 0), qe(0x1182,  42,  20, 0), qe(0x0cef,  43,  21, 0),
+    qe(0x09a1,  45,  22, 0), qe(0x072f,  46,  23, 0), qe(0x055c,  48,  24, 0),
+    qe(0x0406,  49,  25, 0), qe(0x0303,  51,  26, 0), qe(0x0240,  52,  27, 0),
+    qe(0x01b1,  54,  28, 0), qe(0x0144,  56,  29, 0), qe(0x00f5,  57,  30, 0),
+    qe(0x00b7,  59,  31, 0), qe(0x008a,  60,  32, 0), qe(0x0068,  62,  33, 0),
+    qe(0x004e,  63,  34, 0), qe(0x003b,  32,  35, 0), qe(0x002c,  33,   9, 0),
+    qe(0x5ae1,  37,  37, 1), qe(0x484c,  64,  38, 0), qe(0x3a0d,  65,  39, 0),
+    qe(0x2ef1,  67,  40, 0), qe(0x261f,  68,  41, 0), qe(0x1f33,  69,  42, 0),
+    qe(0x19a8,  70,  43, 0), qe(0x1518,  72,  44, 0), qe(0x1177,  73,  45, 0),
+    qe(0x0e74,  74,  46, 0), qe(0x0bfb,  75,  47, 0), qe(0x09f8,  77,  48, 0),
+    qe(0x0861,  78,  49, 0), qe(0x0706,  79,  50, 0), qe(0x05cd,  48,  51, 0),
+    qe(0x04de,  50,  52, 0), qe(0x040f,  50,  53, 0), qe(0x0363,  51,  54, 0),
+    qe(0x02d4,  52,  55, 0), qe(0x025c,  53,  56, 0), qe(0x01f8,  54,  57, 0),
+    qe(0x01a4,  55,  58, 0), qe(0x0160,  56,  59, 0), qe(0x0125,  57,  60, 0),
+    qe(0x00f6,  58,  61, 0), qe(0x00cb,  59,  62, 0), qe(0x00ab,  61,  63, 0),
+    qe(0x008f,  61,  32, 0), qe(0x5b12,  65,  65, 1), qe(0x4d04,  80,  66, 0),
+    qe(0x412c,  81,  67, 0), qe(0x37d8,  82,  68, 0), qe(0x2fe8,  83,  69, 0),
+    qe(0x293c,  84,  70, 0), qe(0x2379,  86,  71, 0), qe(0x1edf,  87,  72, 0),
+    qe(0x1aa9,  87,  73, 0), qe(0x174e,  72,  74, 0), qe(0x1424,  72,  75, 0),
+    qe(0x119c,  74,  76, 0), qe(0x0f6b,  74,  77, 0), qe(0x0d51,  75,  78, 0),
+    qe(0x0bb6,  77,  79, 0), qe(0x0a40,  77,  48, 0), qe(0x5832,  80,  81, 1),
+    qe(0x4d1c,  88,  82, 0), qe(0x438e,  89,  83, 0), qe(0x3bdd,  90,  84, 0),
+    qe(0x34ee,  91,  85, 0), qe(0x2eae,  92,  86, 0), qe(0x299a,  93,  87, 0),
+    qe(0x2516,  86,  71, 0), qe(0x5570,  88,  89, 1), qe(0x4ca9,  95,  90, 0),
+    qe(0x44d9,  96,  91, 0), qe(0x3e22,  97,  92, 0), qe(0x3824,  99,  93, 0),
+    qe(0x32b4,  99,  94, 0), qe(0x2e17,  93,  86, 0), qe(0x56a8,  95,  96, 1),
+    qe(0x4f46, 101,  97, 0), qe(0x47e5, 102,  98, 0), qe(0x41cf, 103,  99, 0),
+    qe(0x3c3d, 104, 100, 0), qe(0x375e,  99,  93, 0), qe(0x5231, 105, 102, 0),
+    qe(0x4c0f, 106, 103, 0), qe(0x4639, 107, 104, 0), qe(0x415e, 103,  99, 0),
+    qe(0x5627, 105, 106, 1), qe(0x50e7, 108, 107, 0), qe(0x4b85, 109, 103, 0),
+    qe(0x5597, 110, 109, 0), qe(0x504f, 111, 107, 0), qe(0x5a10, 110, 111, 1),
+    qe(0x5522, 112, 109, 0), qe(0x59eb, 112, 111, 1), qe(0x5a1d, 113, 113, 0),
+];
+
+/// The QM-coder registers
+///
+/// Split from [`ArithmeticDecoder`] so that we can borrow a statistics bin
+/// and the registers mutably at the same time
+struct QmRegisters
+{
+    /// C register, base of coding interval plus input bit buffer
+    c: i64,
+    /// A register, normalized size of coding interval
+    a: i64,
+    /// Bit shift counter, number of bits left in the bit buffer part of C
+    ///
+    /// `-16` forces reading 2 initial bytes.
+    ct: i32,
+    /// A marker found while reading the entropy coded segment
+    unread_marker: Option<u8>,
+}
+
+impl QmRegisters
+{
+    const fn new() -> QmRegisters
+    {
+        QmRegisters {
+            c: 0,
+            a: 0,
+            ct: -16,
+            unread_marker: None,
+        }
+    }
+
+    /// Read the next byte of the entropy coded segment.
+    ///
+    /// Stuffed zeroes are removed, and if we hit a marker, we store it and supply
+    /// zeroes from then on.
+    #[inline]
+    fn read_byte(&mut self, reader: &mut Cursor<&[u8]>) -> i64
+    {
+        if self.unread_marker.is_some()
+        {
+            return 0;
+        }
+
+        let mut data = read_u8(reader);
+
+        if data == 0xFF
+        {
+            // swallow extra 0xFF bytes
+            while data == 0xFF
+            {
+                data = read_u8(reader);
+            }
+
+            if data == 0
+            {
+                // stuffed zero byte
+                data = 0xFF;
+            }
+            else
+            {
+                self.unread_marker = Some(data);
+
+                data = 0;
+            }
+        }
+        i64::from(data)
+    }
+
+    /// Decode a single binary decision using statistics bin `st`
+    ///
+    /// Section D.2.4 to D.2.6
+    #[inline]
+    #[allow(clippy::similar_names)]
+    fn decode(&mut self, reader: &mut Cursor<&[u8]>, st: &mut u8) -> u8
+    {
+        // Renormalization & data input per section D.2.6
+        while self.a < 0x8000
+        {
+            self.ct -= 1;
+
+            if self.ct < 0
+            {
+                self.c = (self.c << 8) | self.read_byte(reader);
+
+                self.ct += 8;
+
+                if self.ct < 0
+                {
+                    // Need more initial bytes
+                    self.ct += 1;
+
+                    if self.ct == 0
+                    {
+                        // Got 2 initial bytes, re-init A and exit loop
+                        self.a = 0x8000;
+                    }
+                }
+            }
+            self.a <<= 1;
+        }
+
+        let mut sv = *st;
+
+        let entry = QE_TABLE[usize::from(sv & 0x7F)];
+
+        let next_lps = (entry & 0xFF) as u8;
+
+        let next_mps = ((entry >> 8) & 0xFF) as u8;
+
+        let qe = i64::from(entry >> 16);
+
+        // Decode & estimation procedures per sections D.2.4 & D.2.5
+        self.a -= qe;
+
+        let temp = self.a << self.ct;
+
+        if self.c >= temp
+        {
+            self.c -= temp;
+            // Conditional LPS (less probable symbol) exchange
+            if self.a < qe
+            {
+                self.a = qe;
+                *st = (sv & 0x80) ^ next_mps;
+            }
+            else
+            {
+                self.a = qe;
+                *st = (sv & 0x80) ^ next_lps;
+                sv ^= 0x80;
+            }
+        }
+        else if self.a < 0x8000
+        {
+            // Conditional MPS (more probable symbol) exchange
+            if self.a < qe
+            {
+                *st = (sv & 0x80) ^ next_lps;
+                sv ^= 0x80;
+            }
+            else
+            {
+                *st = (sv & 0x80) ^ next_mps;
+            }
+        }
+        sv >> 7
+    }
+}
+
+/// An arithmetic entropy decoder for JPEG images
+pub(crate) struct ArithmeticDecoder
+{
+    registers: QmRegisters,
+    /// DC statistics bins for each table
+    dc_stats: [[u8; DC_STAT_BINS]; MAX_COMPONENTS],
+    /// AC statistics bins for each table
+    ac_stats: [[u8; AC_STAT_BINS]; MAX_COMPONENTS],
+    /// Statistics bin for coding with fixed probability 0.5
+    fixed_bin: u8,
+    /// Context index for DC conditioning, one for each component
+    dc_context: [usize; MAX_COMPONENTS],
+    /// DC conditioning lower bound `L` for each table
+    dc_l: [u8; MAX_COMPONENTS],
+    /// DC conditioning upper bound `U` for each table
+    dc_u: [u8; MAX_COMPONENTS],
+    /// AC conditioning `Kx` for each table
+    ac_k: [u8; MAX_COMPONENTS],
+}
+
+impl ArithmeticDecoder
+{
+    /// Create a new arithmetic decoder
+    ///
+    /// Conditioning values are the defaults specified in F.1.4.4.1.4 and F.1.4.4.2.1,
+    /// they should be overwritten by those found in DAC segments via
+    /// [`set_conditioning`](Self::set_conditioning)
+    pub(crate) const fn new() -> ArithmeticDecoder
+    {
+        ArithmeticDecoder {
+            registers: QmRegisters::new(),
+            dc_stats: [[0; DC_STAT_BINS]; MAX_COMPONENTS],
+            ac_stats: [[0; AC_STAT_BINS]; MAX_COMPONENTS],
+            fixed_bin: FIXED_BIN,
+            dc_context: [0; MAX_COMPONENTS],
+            dc_l: [0; MAX_COMPONENTS],
+            dc_u: [1; MAX_COMPONENTS],
+            ac_k: [5; MAX_COMPONENTS],
+        }
+    }
+
+    /// Set conditioning values for all tables.
+    pub(crate) fn set_conditioning(
+        &mut self, dc_conditioning: [(u8, u8); MAX_COMPONENTS], ac_conditioning: [u8; MAX_COMPONENTS],
+    )
+    {
+        for (i, (l, u)) in dc_conditioning.into_iter().enumerate()
+        {
+            self.dc_l[i] = l;
+            self.dc_u[i] = u;
+        }
+        self.ac_k = ac_conditioning;
+    }
+
+    /// Reset the decoder, this is called at the start of every scan and after every
+    /// restart marker.
+    ///
+    /// Statistics areas and DC contexts are re-initialized and the next decision will
+    /// read two fresh bytes from the stream.
+    pub(crate) fn reset(&mut self)
+    {
+        self.registers = QmRegisters::new();
+
+        self.dc_stats = [[0; DC_STAT_BINS]; MAX_COMPONENTS];
+
+        self.ac_stats = [[0; AC_STAT_BINS]; MAX_COMPONENTS];
+
+        self.dc_context = [0; MAX_COMPONENTS];
+    }
+
+    /// Take the marker that stopped the entropy coded segment, if any.
+    pub(crate) fn take_marker(&mut self) -> Option<u8>
+    {
+        self.registers.unread_marker.take()
+    }
+
+    /// Return a marker to the decoder, it will be the next one handed back by
+    /// [`take_marker`](Self::take_marker)
+    pub(crate) fn set_marker(&mut self, marker: u8)
+    {
+        self.registers.unread_marker = Some(marker);
+    }
+
+    /// Decode a DC difference for component `component` using table `table`
+    ///
+    /// Section F.1.4.4.1 and F.2.4.1
+    fn decode_dc_diff(
+        &mut self, reader: &mut Cursor<&[u8]>, component: usize, table: usize,
+    ) -> Result<i32, DecodeErrors>
+    {
+        let stats = &mut self.dc_stats[table];
+
+        let regs = &mut self.registers;
+
+        // Table F.4: Point to statistics bin S0 for DC coefficient coding
+        let mut st = self.dc_context[component];
+
+        // Figure F.19: Decode_DC_DIFF
+        if regs.decode(reader, &mut stats[st]) == 0
+        {
+            self.dc_context[component] = 0;
+
+            return Ok(0);
+        }
+        // Figure F.22: Decoding the sign of v
+        let sign = regs.decode(reader, &mut stats[st + 1]);
+
+        st += 2 + usize::from(sign);
+
+        // Figure F.23: Decoding the magnitude category of v
+        let mut m = i32::from(regs.decode(reader, &mut stats[st]));
+
+        if m != 0
+        {
+            // Table F.4: X1 = 20
+            st = 20;
+
+            while regs.decode(reader, &mut stats[st]) != 0
+            {
+                m <<= 1;
+
+                if m == 0x8000
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Arithmetic DC magnitude overflow, corrupt JPEG".to_string(),
+                    ));
+                }
+                st += 1;
+            }
+        }
+        // Section F.1.4.4.1.2: Establish dc_context conditioning category
+        if m < (1 << self.dc_l[table]) >> 1
+        {
+            // zero diff category
+            self.dc_context[component] = 0;
+        }
+        else if m > (1 << self.dc_u[table]) >> 1
+        {
+            // large diff category
+            self.dc_context[component] = 12 + usize::from(sign) * 4;
+        }
+        else
+        {
+            // small diff category
+            self.dc_context[component] = 4 + usize::from(sign) * 4;
+        }
+
+        // Figure F.24: Decoding the magnitude bit pattern of v
+        let mut v = m;
+
+        st += 14;
+
+        m >>= 1;
+
+        while m != 0
+        {
+            if regs.decode(reader, &mut stats[st]) != 0
+            {
+                v |= m;
+            }
+            m >>= 1;
+        }
+        v += 1;
+
+        if sign != 0
+        {
+            v = -v;
+        }
+        Ok(v)
+    }
+
+    /// Decode a Minimum Code Unit(MCU) block for a sequential image
+    ///
+    /// # Arguments
+    /// - reader: The stream from where we read more bytes.
+    /// - component: Index of the component, used to keep track of the DC context
+    /// - dc_table: Conditioning table for DC coefficients
+    /// - ac_table: Conditioning table for AC coefficients
+    /// - block: A memory region where we will write out the decoded values, in natural order
+    /// - dc_prediction: Last DC value for this component
+    pub(crate) fn decode_mcu_block(
+        &mut self, reader: &mut Cursor<&[u8]>, component: usize, dc_table: usize,
+        ac_table: usize, block: &mut [i16; 64], dc_prediction: &mut i32,
+    ) -> Result<(), DecodeErrors>
+    {
+        self.decode_dc_first(reader, component, dc_table, &mut block[0], dc_prediction, 0)?;
+
+        self.decode_ac_first(reader, ac_table, 1, 63, 0, block)
+    }
+
+    /// Decode the DC coefficient of a block in a sequential or DC first progressive scan
+    ///
+    /// The coefficient is shifted left by the successive approximation bit position `al`
+    /// (zero for sequential images).
+    ///
+    /// Section F.2.4.1 and G.1.3.1
+    pub(crate) fn decode_dc_first(
+        &mut self, reader: &mut Cursor<&[u8]>, component: usize, table: usize,
+        coeff: &mut i16, dc_prediction: &mut i32, al: u8,
+    ) -> Result<(), DecodeErrors>
+    {
+        let diff = self.decode_dc_diff(reader, component, table)?;
+
+        *dc_prediction = dc_prediction.wrapping_add(diff);
+
+        *coeff = (*dc_prediction as i16).wrapping_shl(u32::from(al));
+
+        Ok(())
+    }
+
+    /// Refine the DC coefficient of a block in a progressive DC refinement scan
+    ///
+    /// Refinement bits are coded with the fixed probability estimate, see G.1.3.1
+    pub(crate) fn decode_dc_refine(&mut self, reader: &mut Cursor<&[u8]>, coeff: &mut i16, al: u8)
+    {
+        if self.registers.decode(reader, &mut self.fixed_bin) != 0
+        {
+            *coeff |= 1 << al;
+        }
+    }
+
+    /// Decode AC coefficients `start..=end` of a block in a sequential or
+    /// AC first progressive scan
+    ///
+    /// Section F.1.4.4.2, F.2.4.2 and G.1.3.2
+    pub(crate) fn decode_ac_first(
+        &mut self, reader: &mut Cursor<&[u8]>, table: usize, start: u8, end: u8, al: u8,
+        block: &mut [i16; 64],
+    ) -> Result<(), DecodeErrors>
+    {
+        let stats = &mut self.ac_stats[table];
+
+        let regs = &mut self.registers;
+
+        let kx = self.ac_k[table];
+
+        let mut k = start;
+
+        // Figure F.20: Decode_AC_coefficients
+        while k <= end
+        {
+            let mut st = 3 * usize::from(k - 1);
+
+            // EOB flag
+            if regs.decode(reader, &mut stats[st]) != 0
+            {
+                break;
+            }
+
+            while regs.decode(reader, &mut stats[st + 1]) == 0
+            {
+                st += 3;
+
+                k += 1;
+
+                if k > end
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Arithmetic AC spectral overflow, corrupt JPEG".to_string(),
+                    ));
+                }
+            }
+            // Figure F.21: Decoding nonzero value v
+            // Figure F.22: Decoding the sign of v
+            let sign = regs.decode(reader, &mut self.fixed_bin);
+
+            st += 2;
+
+            // Figure F.23: Decoding the magnitude category of v
+            let mut m = i32::from(regs.decode(reader, &mut stats[st]));
+
+            if m != 0 && regs.decode(reader, &mut stats[st]) != 0
+            {
+                m <<= 1;
+
+                st = if k <= kx { 189 } else { 217 };
+
+                while regs.decode(reader, &mut stats[st]) != 0
+                {
+                    m <<= 1;
+
+                    if m == 0x8000
+                    {
+                        return Err(DecodeErrors::MCUError(
+                            "Arithmetic AC magnitude overflow, corrupt JPEG".to_string(),
+                        ));
+                    }
+                    st += 1;
+                }
+            }
+            // Figure F.24: Decoding the magnitude bit pattern of v
+            let mut v = m;
+
+            st += 14;
+
+            m >>= 1;
+
+            while m != 0
+            {
+                if regs.decode(reader, &mut stats[st]) != 0
+                {
+                    v |= m;
+                }
+                m >>= 1;
+            }
+            v += 1;
+
+            if sign != 0
+            {
+                v = -v;
+            }
+            block[UN_ZIGZAG[usize::from(k)] & 63] = (v as i16).wrapping_shl(u32::from(al));
+
+            k += 1;
+        }
+        Ok(())
+    }
+
+    /// Refine AC coefficients `start..=end` of a block in a progressive AC refinement scan
+    ///
+    /// Section G.1.3.3
+    pub(crate) fn decode_ac_refine(
+        &mut self, reader: &mut Cursor<&[u8]>, table: usize, start: u8, end: u8, al: u8,
+        block: &mut [i16; 64],
+    ) -> Result<(), DecodeErrors>
+    {
+        let stats = &mut self.ac_stats[table];
+
+        let regs = &mut self.registers;
+
+        let p1 = 1_i16 << al;
+
+        let m1 = -1_i16 << al;
+
+        // Establish EOBx, the end of block position of the previous stage
+        let mut kex = end;
+
+        while kex > 0 && block[UN_ZIGZAG[usize::from(kex)] & 63] == 0
+        {
+            kex -= 1;
+        }
+
+        let mut k = start;
+
+        while k <= end
+        {
+            let mut st = 3 * usize::from(k - 1);
+
+            // EOB flag, only present after the previous end of block
+            if k > kex && regs.decode(reader, &mut stats[st]) != 0
+            {
+                break;
+            }
+
+            loop
+            {
+                let coeff = &mut block[UN_ZIGZAG[usize::from(k)] & 63];
+
+                if *coeff != 0
+                {
+                    // previously non-zero coefficient, decode correction bit
+                    if regs.decode(reader, &mut stats[st + 2]) != 0
+                    {
+                        if *coeff < 0
+                        {
+                            *coeff = coeff.wrapping_add(m1);
+                        }
+                        else
+                        {
+                            *coeff = coeff.wrapping_add(p1);
+                        }
+                    }
+                    break;
+                }
+
+                if regs.decode(reader, &mut stats[st + 1]) != 0
+                {
+                    // newly non-zero coefficient
+                    if regs.decode(reader, &mut self.fixed_bin) != 0
+                    {
+                        *coeff = m1;
+                    }
+                    else
+                    {
+                        *coeff = p1;
+                    }
+                    break;
+                }
+                st += 3;
+
+                k += 1;
+
+                if k > end
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Arithmetic AC spectral overflow, corrupt JPEG".to_string(),
+                    ));
+                }
+            }
+            k += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Read a byte from underlying buffer, returning zero if we have nothing left.
+#[inline]
+fn read_u8(reader: &mut Cursor<&[u8]>) -> u8
+{
+    let pos = reader.position();
+
+    reader.set_position(pos + 1);
+
+    *reader.get_ref().get(pos as usize).unwrap_or(&0)
+}
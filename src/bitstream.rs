@@ -92,6 +92,7 @@ macro_rules! decode_huff {
 
 /// A `BitStream` struct, a bit by bit reader with super powers
 ///
+#[derive(Clone)]
 pub(crate) struct BitStream
 {
     /// A MSB type buffer that is used for some certain operations
@@ -105,6 +106,13 @@ pub(crate) struct BitStream
     pub(crate) bits_left: u8,
     /// Did we find a marker(RST/EOF) during decoding?
     pub marker: Option<Marker>,
+    /// Number of zero bits made up after running into a marker
+    zero_bits: u32,
+    /// Data was missing or corrupt, MCUs are left out up to the next restart marker,
+    /// only set in lenient mode
+    pub(crate) lost_sync: bool,
+    /// Number of the restart marker expected next, RST0 to RST7
+    pub(crate) next_restart: u8,
 
     /// Progressive decoding
     pub successive_high: u8,
@@ -124,6 +132,9 @@ impl BitStream
             aligned_buffer: 0,
             bits_left: 0,
             marker: None,
+            zero_bits: 0,
+            lost_sync: false,
+            next_restart: 0,
             successive_high: 0,
             successive_low: 0,
             spec_start: 0,
@@ -141,6 +152,9 @@ impl BitStream
             aligned_buffer: 0,
             bits_left: 0,
             marker: None,
+            zero_bits: 0,
+            lost_sync: false,
+            next_restart: 0,
             successive_high: ah,
             successive_low: al,
             spec_start,
@@ -157,7 +171,7 @@ impl BitStream
     ///
     /// This function will only refill if `self.count` is less than 32
     #[inline(never)] // to many call sites?
-    fn refill(&mut self, reader: &mut Cursor<Vec<u8>>) -> bool
+    fn refill(&mut self, reader: &mut Cursor<&[u8]>) -> bool
     {
         /// Macro version of a single byte refill.
         /// Arguments
@@ -199,8 +213,16 @@ impl BitStream
 
                             $bits_left -= 8;
 
-                            self.aligned_buffer = $buffer << (64 - $bits_left);
-                            self.marker = Some(Marker::from_u8(next_byte as u8).unwrap());
+                            // nothing is left if the marker came right after the last
+                            // bits we handed out
+                            self.aligned_buffer =
+                                $buffer.checked_shl(u32::from(64 - $bits_left)).unwrap_or(0);
+
+                            let next_byte = next_byte as u8;
+
+                            self.marker = Some(
+                                Marker::from_u8(next_byte).unwrap_or(Marker::Unknown(next_byte)),
+                            );
                             return false;
                         }
                     }
@@ -261,7 +283,9 @@ impl BitStream
         }
         else if self.marker.is_some()
         {
-            // fill with zeroes
+            // fill with zeroes, keeping track of how many we made up
+            self.zero_bits += u32::from(63_u8.saturating_sub(self.bits_left));
+
             self.bits_left = 63;
         }
 
@@ -278,7 +302,7 @@ impl BitStream
     )]
     #[inline(always)]
     fn decode_dc(
-        &mut self, reader: &mut Cursor<Vec<u8>>, dc_table: &HuffmanTable, dc_prediction: &mut i32,
+        &mut self, reader: &mut Cursor<&[u8]>, dc_table: &HuffmanTable, dc_prediction: &mut i32,
     ) -> Result<bool, DecodeErrors>
     {
         let (mut symbol, r);
@@ -296,6 +320,12 @@ impl BitStream
 
         if symbol != 0
         {
+            // values of 12 bit images take up to 15 bits, which may not have been
+            // there with the code
+            if self.bits_left < symbol as u8
+            {
+                self.refill(reader);
+            }
             r = self.get_bits(symbol as u8);
 
             symbol = huff_extend(r, symbol);
@@ -306,6 +336,39 @@ impl BitStream
         return Ok(true);
     }
 
+    /// Decode a lossless difference value
+    ///
+    /// This is like decoding a DC coefficient, except that differences may be of
+    /// category 16, which has no additional bits and stands for 32768, see H.1.2.2
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn decode_lossless_diff(
+        &mut self, reader: &mut Cursor<&[u8]>, table: &HuffmanTable,
+    ) -> Result<i32, DecodeErrors>
+    {
+        self.refill(reader);
+
+        let mut symbol = self.peek_bits::<HUFF_LOOKAHEAD>();
+
+        symbol = table.lookup[symbol as usize];
+
+        decode_huff!(self, symbol, table);
+
+        match symbol
+        {
+            0 => Ok(0),
+            1..=15 =>
+            {
+                let r = self.get_bits(symbol as u8);
+
+                Ok(huff_extend(r, symbol))
+            }
+            16 => Ok(32768),
+            _ => Err(DecodeErrors::HuffmanDecode(format!(
+                "Invalid lossless difference category {symbol}"
+            ))),
+        }
+    }
+
     /// Decode a Minimum Code Unit(MCU) as quickly as possible
     ///
     /// # Arguments
@@ -324,7 +387,7 @@ impl BitStream
     #[inline(always)]
     pub fn decode_mcu_block(
         &mut self,
-        reader: &mut Cursor<Vec<u8>>,
+        reader: &mut Cursor<&[u8]>,
         dc_table: &HuffmanTable,
         ac_table: &HuffmanTable,
         block: &mut [i16; 64],
@@ -413,7 +476,7 @@ impl BitStream
     #[inline]
     fn drop_bits(&mut self, n: u8)
     {
-        self.bits_left -= n;
+        self.bits_left = self.bits_left.saturating_sub(n);
 
         // remove top n bits  in lsb buffer
         self.aligned_buffer <<= n;
@@ -432,7 +495,8 @@ impl BitStream
         let bits = (self.aligned_buffer & mask) as i32;
 
         // Reduce the bits left, this influences the MSB buffer
-        self.bits_left -= n_bits;
+        // corrupt data may ask for more bits than are left
+        self.bits_left = self.bits_left.saturating_sub(n_bits);
 
         // shift out bits read in the LSB buffer
         bits
@@ -442,18 +506,18 @@ impl BitStream
     #[allow(clippy::cast_possible_truncation)]
     #[inline]
     pub(crate) fn decode_prog_dc_first(
-        &mut self, reader: &mut Cursor<Vec<u8>>, dc_table: &HuffmanTable, block: &mut i16,
+        &mut self, reader: &mut Cursor<&[u8]>, dc_table: &HuffmanTable, block: &mut i16,
         dc_prediction: &mut i32,
     ) -> Result<(), DecodeErrors>
     {
         self.decode_dc(reader, dc_table, dc_prediction)?;
 
-        *block = (*dc_prediction as i16) * (1_i16 << self.successive_low);
+        *block = (*dc_prediction as i16).wrapping_mul(1_i16 << self.successive_low);
 
         return Ok(());
     }
     #[inline]
-    pub(crate) fn decode_prog_dc_refine(&mut self, reader: &mut Cursor<Vec<u8>>, block: &mut i16)
+    pub(crate) fn decode_prog_dc_refine(&mut self, reader: &mut Cursor<&[u8]>, block: &mut i16)
     {
         // refinement scan
         if self.bits_left < 1
@@ -462,7 +526,7 @@ impl BitStream
         }
         if self.get_bit() == 1
         {
-            *block += 1 << self.successive_low;
+            *block = block.wrapping_add(1 << self.successive_low);
         }
     }
 
@@ -477,7 +541,7 @@ impl BitStream
         return k;
     }
     pub(crate) fn decode_mcu_ac_first(
-        &mut self, reader: &mut Cursor<Vec<u8>>, ac_table: &HuffmanTable, block: &mut [i16; 64],
+        &mut self, reader: &mut Cursor<&[u8]>, ac_table: &HuffmanTable, block: &mut [i16; 64],
     ) -> Result<bool, DecodeErrors>
     {
         let shift = self.successive_low;
@@ -509,7 +573,7 @@ impl BitStream
                 // run
                 k += ((fac >> 4) & 63) as usize;
                 // value
-                block[UN_ZIGZAG[min(k, 63)] & 63] = (fac >> 10) * (1 << shift);
+                block[UN_ZIGZAG[min(k, 63)] & 63] = (fac >> 10).wrapping_mul(1 << shift);
 
                 self.drop_bits((fac & 15) as u8);
                 k += 1;
@@ -530,7 +594,8 @@ impl BitStream
 
                     symbol = huff_extend(r, symbol);
 
-                    block[UN_ZIGZAG[k as usize & 63] & 63] = symbol as i16 * (1 << shift);
+                    block[UN_ZIGZAG[k as usize & 63] & 63] =
+                        (symbol as i16).wrapping_mul(1 << shift);
 
                     k += 1;
                 }
@@ -560,7 +625,7 @@ impl BitStream
         return Ok(true);
     }
     pub(crate) fn decode_mcu_ac_refine(
-        &mut self, reader: &mut Cursor<Vec<u8>>, table: &HuffmanTable, block: &mut [i16; 64],
+        &mut self, reader: &mut Cursor<&[u8]>, table: &HuffmanTable, block: &mut [i16; 64],
     ) -> Result<bool, DecodeErrors>
     {
         let bit = (1 << self.successive_low) as i16;
@@ -633,11 +698,11 @@ impl BitStream
                         {
                             if *coefficient >= 0
                             {
-                                *coefficient += bit;
+                                *coefficient = coefficient.wrapping_add(bit);
                             }
                             else
                             {
-                                *coefficient -= bit;
+                                *coefficient = coefficient.wrapping_sub(bit);
                             }
                         }
                         if self.bits_left < 1
@@ -691,11 +756,11 @@ impl BitStream
                         {
                             if *coefficient >= 0
                             {
-                                *coefficient += bit;
+                                *coefficient = coefficient.wrapping_add(bit);
                             }
                             else
                             {
-                                *coefficient -= bit;
+                                *coefficient = coefficient.wrapping_sub(bit);
                             }
                         }
                     }
@@ -722,10 +787,26 @@ impl BitStream
         self.spec_end = spec_end;
     }
 
+    /// Return true if the stream has handed out more bits than the
+    /// entropy coded segment contained
+    ///
+    /// Bits made up after a marker or past the end of the data are always zeroes
+    /// appended below the real bits, so we overran the segment once fewer bits than
+    /// were made up are left.
+    pub(crate) fn overread(&self, reader: &Cursor<&[u8]>) -> bool
+    {
+        // every byte read past the end of the data is a zero byte
+        let past_end = reader
+            .position()
+            .saturating_sub(reader.get_ref().len() as u64);
+
+        u64::from(self.bits_left) < u64::from(self.zero_bits) + 8 * past_end
+    }
+
     /// Reset the stream if we have a restart marker
     ///
     /// Restart markers indicate drop those bits in the stream and zero out
-    /// everything
+    /// everything, except for the number of the restart marker expected next
     #[cold]
     pub fn reset(&mut self)
     {
@@ -733,6 +814,10 @@ impl BitStream
 
         self.marker = None;
 
+        self.zero_bits = 0;
+
+        self.lost_sync = false;
+
         self.buffer = 0;
 
         self.aligned_buffer = 0;
@@ -755,7 +840,7 @@ fn huff_extend(x: i32, s: i32) -> i32
 /// Function is inlined (as always)
 #[inline(always)]
 #[allow(clippy::cast_possible_truncation)]
-fn read_u8(reader: &mut Cursor<Vec<u8>>) -> u64
+fn read_u8(reader: &mut Cursor<&[u8]>) -> u64
 {
     let pos = reader.position();
 
@@ -0,0 +1,95 @@
+//! Access to quantized DCT coefficients
+//!
+//! Forensics, steganalysis and recompression tools work on the coefficients stored in
+//! the file rather than on pixels, so [`Decoder::decode_coefficients`] stops after
+//! entropy decoding, with the same routines scaled and cropped images use to gather
+//! the coefficients of the whole image, and never runs the IDCT.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use std::io::Cursor;
+
+use crate::errors::DecodeErrors;
+use crate::Decoder;
+
+/// Quantized DCT coefficients of a single component
+///
+/// Returned by [`Decoder::decode_coefficients`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComponentCoefficients
+{
+    /// Blocks of 64 coefficients in natural (row major) order, `blocks_wide`
+    /// blocks to a row
+    ///
+    /// Block `(x, y)` starts at `64 * (y * blocks_wide + x)`. Values are as stored
+    /// in the file, multiply them by `quantization_table` to dequantize them.
+    pub coefficients: Vec<i16>,
+    /// Quantization table of the component, in natural order
+    pub quantization_table: [u16; 64],
+    /// Number of blocks in a row of the grid, padded to whole MCUs
+    pub blocks_wide: usize,
+    /// Number of block rows in the grid, padded to whole MCUs
+    pub blocks_high: usize,
+    /// Horizontal sampling factor of the component
+    pub horizontal_sample: usize,
+    /// Vertical sampling factor of the component
+    pub vertical_sample: usize,
+}
+
+impl Decoder
+{
+    /// Decode the quantized DCT coefficients of every component, without carrying
+    /// out IDCT, up-sampling or color conversion
+    ///
+    /// Components are returned in frame header order. Blocks past the edge of the
+    /// image which pad the grid to whole MCUs are included, as the file stores
+    /// them.
+    ///
+    /// # Errors
+    /// If the image is not a valid jpeg file, or is a lossless or hierarchical
+    /// image, which have no DCT coefficients
+    pub fn decode_coefficients(
+        &mut self, buf: &[u8],
+    ) -> Result<Vec<ComponentCoefficients>, DecodeErrors>
+    {
+        let mut buf = Cursor::new(buf);
+
+        self.prepare_headers(&mut buf)?;
+
+        if self.info.sof.is_lossless() || self.hierarchical.is_some()
+        {
+            return Err(DecodeErrors::Format(
+                "Coefficients are only available for DCT images".to_string(),
+            ));
+        }
+
+        let block = self.decode_all_coefficients(&mut buf)?;
+
+        let components = self
+            .components
+            .iter()
+            .zip(block)
+            .map(|(component, coefficients)| {
+                let mut quantization_table = [0; 64];
+
+                for (out, value) in quantization_table
+                    .iter_mut()
+                    .zip(component.quantization_table.0.iter())
+                {
+                    // tables hold at most 16 bit values
+                    *out = *value as u16;
+                }
+
+                ComponentCoefficients {
+                    coefficients,
+                    quantization_table,
+                    blocks_wide: self.mcu_x * component.horizontal_sample,
+                    blocks_high: self.mcu_y * component.vertical_sample,
+                    horizontal_sample: component.horizontal_sample,
+                    vertical_sample: component.vertical_sample,
+                }
+            })
+            .collect();
+
+        Ok(components)
+    }
+}
@@ -16,6 +16,7 @@
 //! Currently supported conversions are
 //!
 //! - `YCbCr` to `RGB,RGBA,GRAYSCALE,RGBX`.
+//! - `RGB` to `RGB,RGBA,RGBX`.
 //!
 //!
 //! Hey there, if your reading this it means you probably need something, so let me help you.
@@ -53,7 +54,9 @@ mod avx;
 mod scalar;
 mod sse;
 
-pub use scalar::{ycbcr_to_grayscale, ycbcr_to_ycbcr};
+pub use scalar::{
+    rgb_to_rgb_16_scalar, rgb_to_rgba_16_scalar, ycbcr_to_grayscale, ycbcr_to_ycbcr,
+};
 
 use crate::misc::ColorSpace;
 
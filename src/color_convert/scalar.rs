@@ -88,15 +88,60 @@ pub fn ycbcr_to_rgb_16_scalar(
     *pos += 48;
 }
 
+/// Copy R, G and B samples of images stored as RGB to RGB output
+///
+/// Takes the arguments of the YCbCr functions, so they go through the same
+/// up-sampling and row handling.
+pub fn rgb_to_rgb_16_scalar(
+    r: &[i16; 16], g: &[i16; 16], b: &[i16; 16], output: &mut [u8], pos: &mut usize,
+)
+{
+    let opt = &mut output[*pos..*pos + 48];
+
+    for (((out, r), g), b) in opt.chunks_exact_mut(3).zip(r).zip(g).zip(b)
+    {
+        out[0] = clamp(*r);
+
+        out[1] = clamp(*g);
+
+        out[2] = clamp(*b);
+    }
+
+    *pos += 48;
+}
+
+/// Copy R, G and B samples of images stored as RGB to RGBA output
+pub fn rgb_to_rgba_16_scalar(
+    r: &[i16; 16], g: &[i16; 16], b: &[i16; 16], output: &mut [u8], pos: &mut usize,
+)
+{
+    let opt = &mut output[*pos..*pos + 64];
+
+    for (((out, r), g), b) in opt.chunks_exact_mut(4).zip(r).zip(g).zip(b)
+    {
+        out[0] = clamp(*r);
+
+        out[1] = clamp(*g);
+
+        out[2] = clamp(*b);
+
+        out[3] = 255;
+    }
+
+    *pos += 64;
+}
+
 pub fn ycbcr_to_grayscale(y: &[i16], width: usize, output: &mut [u8])
 {
     // Convert i16's to u8's
     let temp_output = y.iter().map(|x| *x as u8).collect::<Vec<u8>>();
     // chunk according to width.
 
-    let width_mcu = y.len() / width;
+    // rows of `y` are padded to a whole number of MCUs, `output` holds the same number
+    // of rows without the padding
+    let rows = output.len() / width;
 
-    let width_chunk = y.len() / width_mcu;
+    let width_chunk = y.len() / rows;
 
     let mut start = 0;
 
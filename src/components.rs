@@ -16,7 +16,7 @@ pub type UpSampler = fn(&[i16], usize) -> Vec<i16>;
 #[derive(Clone)]
 pub(crate) struct Components
 {
-    /// The type of component that has the metadata below, can be Y,Cb or Cr
+    /// The type of component that has the metadata below, can be Y,Cb,Cr or K
     pub component_id: ComponentID,
     /// Sub-sampling ratio of this component in the x-plane
     pub vertical_sample: usize,
@@ -52,10 +52,11 @@ impl Components
             1 => ComponentID::Y,
             2 => ComponentID::Cb,
             3 => ComponentID::Cr,
+            4 => ComponentID::K,
             r =>
             {
                 return Err(DecodeErrors::Format(format!(
-                        "Unknown component id found,{}, expected value between 1 and 3\nNote I and Q components are not supported yet",
+                        "Unknown component id found,{}, expected value between 1 and 4\nNote I and Q components are not supported yet",
                         r
                     )));
             }
@@ -71,17 +72,20 @@ impl Components
 
         // check that upsampling ratios are powers of two
         // if these fail, it's probably a corrupt image.
-        assert!(
-            horizontal_sample.is_power_of_two(),
-            "Horizontal sample is not a power of two({}) cannot decode",
-            horizontal_sample
-        );
-
-        assert!(
-            vertical_sample.is_power_of_two(),
-            "Vertical sub-sample is not power of two({}) cannot decode",
-            vertical_sample
-        );
+        if !horizontal_sample.is_power_of_two() || horizontal_sample > 4
+        {
+            return Err(DecodeErrors::SofError(format!(
+                "Horizontal sample is not 1, 2 or 4 ({}) cannot decode",
+                horizontal_sample
+            )));
+        }
+        if !vertical_sample.is_power_of_two() || vertical_sample > 4
+        {
+            return Err(DecodeErrors::SofError(format!(
+                "Vertical sub-sample is not 1, 2 or 4 ({}) cannot decode",
+                vertical_sample
+            )));
+        }
 
         info!(
             "Component ID:{:?}\tHS:{} VS:{} QT:{}",
@@ -123,6 +127,8 @@ pub enum ComponentID
     Cb,
     /// Red chrominance
     Cr,
+    /// Black, the last component of CMYK and YCCK images
+    K,
 }
 
 #[derive(Copy, Debug, Clone, PartialEq, Eq)]
@@ -2,27 +2,35 @@
 #![allow(clippy::doc_markdown)]
 
 use std::fs::read;
-use std::io::{BufRead, Cursor, Read};
+use std::io::{BufRead, BufReader, Cursor, Read};
 use std::path::Path;
 
 use crate::color_convert::choose_ycbcr_to_rgb_convert_func;
 use crate::components::{Components, SubSampRatios};
-use crate::errors::{DecodeErrors, UnsupportedSchemes};
-use crate::headers::{parse_app, parse_dqt, parse_huffman, parse_sos, parse_start_of_frame};
+use crate::errors::{DecodeErrors, DecodeWarning, UnsupportedSchemes, WarningKind};
+use crate::exif::ExifData;
+use crate::headers::{
+    parse_app, parse_dac, parse_dhp, parse_dqt, parse_dri, parse_huffman, parse_sos,
+    parse_start_of_frame,
+};
 use crate::huffman::HuffmanTable;
+use crate::icc::{assemble_profile, IccChunk};
 use crate::idct::choose_idct_func;
 use crate::marker::Marker;
-use crate::misc::{read_byte, read_u16_be, Aligned32, ColorSpace, SOFMarkers};
+use crate::mcu_hierarchical::HierarchicalInfo;
+use crate::mcu_source::StreamSource;
+use crate::misc::{
+    read_byte, read_u16_be, skip_bytes, Aligned32, ColorSpace, PositionReader, SOFMarkers,
+};
+use crate::options::DecoderOptions;
 use crate::upsampler::{
     choose_horizontal_samp_function, choose_hv_samp_function, upsample_vertical,
 };
+use crate::worker::Orientation;
 
 /// Maximum components
 pub(crate) const MAX_COMPONENTS: usize = 4;
 
-/// Maximum image dimensions supported.
-pub(crate) const MAX_DIMENSIONS: usize = 1 << 27;
-
 /// Color conversion function that can convert YcbCr colorspace to RGB(A/X) for
 /// 16 values
 ///
@@ -54,7 +62,7 @@ pub type ColorConvert16Ptr = fn(&[i16; 16], &[i16; 16], &[i16; 16], &mut [u8], &
 pub type IDCTPtr = fn(&[i16], &Aligned32<[i32; 64]>, usize, usize, usize) -> Vec<i16>;
 
 /// A Decoder Instance
-#[allow(clippy::upper_case_acronyms)]
+#[allow(clippy::upper_case_acronyms, clippy::struct_excessive_bools)]
 pub struct Decoder
 {
     /// Struct to hold image information from SOI
@@ -122,6 +130,51 @@ pub struct Decoder
     /// restart markers
     pub(crate) restart_interval: usize,
     pub(crate) todo: usize,
+
+    /// Arithmetic coding DC conditioning bounds `(L,U)` for each table
+    pub(crate) arith_dc_conditioning: [(u8, u8); MAX_COMPONENTS],
+    /// Arithmetic coding AC conditioning value `Kx` for each table
+    pub(crate) arith_ac_conditioning: [u8; MAX_COMPONENTS],
+
+    /// Final dimensions and precision of a hierarchical image, set if the
+    /// image has a DHP segment
+    pub(crate) hierarchical: Option<HierarchicalInfo>,
+    /// The frame header left the height at zero, it is defined by a DNL
+    /// segment after the first scan
+    pub(crate) deferred_height: bool,
+    /// Position of the first scan, set if `read_headers` parsed the headers
+    /// and the next decode can start from there
+    pub(crate) headers_end: Option<u64>,
+    /// Width and height of the samples the IDCT produces for every block, 8
+    /// unless a smaller output was asked for via `set_scale`
+    pub(crate) scale: usize,
+    /// Region of the output to decode, as `(x, y, width, height)`, set via
+    /// `set_crop`
+    pub(crate) crop: Option<(usize, usize, usize, usize)>,
+    /// Limits images must fit in, set via `set_options`
+    pub(crate) options: DecoderOptions,
+    /// Number of scans parsed so far, checked against the limit in `options`
+    pub(crate) scans_parsed: usize,
+    /// Fail on corrupt entropy coded data instead of concealing it, set via
+    /// `set_strict_mode`
+    pub(crate) strict: bool,
+    /// Problems found while decoding which did not stop it
+    pub(crate) warnings: Vec<DecodeWarning>,
+    /// Kinds of warnings which fail the decode, set via `set_warnings_as_errors`
+    pub(crate) fatal_warnings: Vec<WarningKind>,
+    /// Offset in the image of the data being decoded, warnings are found at
+    /// offsets relative to it, zero unless data is decoded a window at a time
+    pub(crate) data_offset: usize,
+    /// Turn decoded images upright per their Exif orientation, set via
+    /// `set_apply_orientation`
+    pub(crate) apply_orientation: bool,
+    /// Pieces of the ICC profile found in the headers, put together into
+    /// `icc_profile` once they are all read
+    pub(crate) icc_chunks: Vec<IccChunk>,
+    /// ICC profile of the image, see `icc_profile`
+    pub(crate) icc_profile: Option<Vec<u8>>,
+    /// Color transform of the Adobe APP14 segment, `None` if the image has none
+    pub(crate) adobe_transform: Option<u8>,
 }
 
 impl Default for Decoder
@@ -170,6 +223,26 @@ impl Default for Decoder
 
             restart_interval: 0,
             todo: 0x7fff_ffff,
+
+            // Default conditioning, see F.1.4.4.1.4 and F.1.4.4.2.1
+            arith_dc_conditioning: [(0, 1); MAX_COMPONENTS],
+            arith_ac_conditioning: [5; MAX_COMPONENTS],
+
+            hierarchical: None,
+            deferred_height: false,
+            headers_end: None,
+            scale: 8,
+            crop: None,
+            options: DecoderOptions::default(),
+            scans_parsed: 0,
+            strict: true,
+            warnings: vec![],
+            fatal_warnings: vec![],
+            data_offset: 0,
+            apply_orientation: false,
+            icc_chunks: vec![],
+            icc_profile: None,
+            adobe_transform: None,
         }
     }
 }
@@ -181,11 +254,196 @@ impl Decoder
     /// The buffer should be a valid jpeg file, perhaps created by the command
     /// `std:::fs::read()` or a JPEG file downloaded from the internet.
     ///
+    /// The buffer is decoded in place, it is not copied.
+    ///
     /// # Errors
     /// If the image is not a valid jpeg file
     pub fn decode_buffer(&mut self, buf: &[u8]) -> Result<Vec<u8>, DecodeErrors>
     {
-        self.decode_internal(Cursor::new(buf.to_vec()))
+        self.decode_internal(Cursor::new(buf))
+    }
+
+    /// Decode a buffer already in memory into 16 bit samples
+    ///
+    /// This works for images of any supported precision, 12 bit extended
+    /// sequential images can only be decoded via this method, their samples range
+    /// from 0 to 4095.
+    ///
+    /// 8 bit images are decoded as usual, each sample is widened to a `u16`
+    ///
+    /// Lossless images of 2 to 16 bits are not color converted, the output contains
+    /// one sample for each image component regardless of the output colorspace.
+    ///
+    /// # Errors
+    /// If the image is not a valid jpeg file
+    pub fn decode_buffer_u16(&mut self, buf: &[u8]) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let mut buf = Cursor::new(buf);
+
+        self.prepare_headers(&mut buf)?;
+
+        if self.hierarchical.is_some()
+        {
+            let samples = self.decode_mcu_hierarchical(&mut buf)?;
+
+            return Ok(self.crop_output(&samples));
+        }
+        if self.info.sof.is_lossless()
+        {
+            let samples = self.decode_mcu_lossless(&mut buf)?;
+
+            return Ok(self.crop_output(&samples));
+        }
+        if self.reconstructs_whole_planes()
+        {
+            return self.decode_mcu_scaled(&mut buf);
+        }
+        if self.info.pixel_density > 8
+        {
+            return self.decode_mcu_extended(&mut buf);
+        }
+
+        let mut pixels = vec![0; self.output_size()];
+
+        self.decode_mcu(&mut buf, &mut pixels)?;
+
+        Ok(pixels.into_iter().map(u16::from).collect())
+    }
+
+    /// Decode a buffer already in memory into `out`
+    ///
+    /// Pixels are laid out as `decode_buffer` would return them, `out` must be at
+    /// least [`output_buffer_size`](Self::output_buffer_size) bytes long, to size it
+    /// before decoding, call [`read_headers`](Self::read_headers) first.
+    ///
+    /// # Errors
+    /// If the image is not a valid jpeg file, or `out` is too small to hold it
+    pub fn decode_into(&mut self, input: &[u8], out: &mut [u8]) -> Result<(), DecodeErrors>
+    {
+        let mut buf = Cursor::new(input);
+
+        self.prepare_headers(&mut buf)?;
+
+        let size = self.output_size();
+
+        if out.len() < size
+        {
+            return Err(DecodeErrors::TooSmallOutput(size, out.len()));
+        }
+
+        if let Some(pixels) = self.decode_whole_planes(&mut buf)?
+        {
+            let out_len = out.len();
+
+            out.get_mut(..pixels.len())
+                .ok_or(DecodeErrors::TooSmallOutput(pixels.len(), out_len))?
+                .copy_from_slice(&pixels);
+
+            return Ok(());
+        }
+        let out = &mut out[..size];
+
+        // not every byte of the output is written for every image, start from zeroes
+        // so the result matches `decode_buffer`
+        out.fill(0);
+
+        self.decode_mcu(&mut buf, out)
+    }
+
+    /// Return the number of bytes `decode_into` writes
+    ///
+    /// This depends on the output colorspace, and is only known once headers
+    /// have been read, otherwise `None` is returned.
+    #[must_use]
+    pub fn output_buffer_size(&self) -> Option<usize>
+    {
+        if self.info == ImageInfo::default()
+        {
+            return None;
+        }
+
+        Some(self.output_size())
+    }
+
+    /// Size of the decoded image in bytes
+    pub(crate) fn output_size(&self) -> usize
+    {
+        let (width, height) = self.decoded_dimensions();
+
+        // lossless images are not color converted
+        let components = if self.info.sof.is_lossless()
+        {
+            usize::from(self.info.components)
+        }
+        else
+        {
+            self.output_colorspace.num_components()
+        };
+
+        width * height * components
+    }
+
+    /// Width and height of the decoded image
+    pub(crate) fn decoded_dimensions(&self) -> (usize, usize)
+    {
+        match self.crop
+        {
+            Some((_, _, width, height)) => (width, height),
+            None => self.uncropped_dimensions(),
+        }
+    }
+
+    /// Width and height of the decoded image before it is cropped
+    pub(crate) fn uncropped_dimensions(&self) -> (usize, usize)
+    {
+        // hierarchical images end up at the size given by the DHP segment
+        let (width, height) = self
+            .hierarchical
+            .map_or((self.info.width, self.info.height), |x| (x.width, x.height));
+
+        let (width, height) = (usize::from(width), usize::from(height));
+
+        if self.is_scaled()
+        {
+            return (
+                (width * self.scale).div_ceil(8),
+                (height * self.scale).div_ceil(8),
+            );
+        }
+        (width, height)
+    }
+
+    /// Whether samples of a DCT image are reconstructed a whole plane at a time,
+    /// see `mcu_scaled.rs`, rather than an MCU row at a time
+    ///
+    /// MCU rows are post processed for at most three components, four component
+    /// images take the same route as scaled and cropped ones.
+    pub(crate) fn reconstructs_whole_planes(&self) -> bool
+    {
+        self.is_scaled() || self.crop.is_some() || self.input_colorspace.num_components() == 4
+    }
+
+    /// Number of components reconstructed to produce the output colorspace
+    ///
+    /// Components color conversion does not look at, e.g. chroma of an image decoded
+    /// to grayscale, are not reconstructed, CMYK and YCCK images need all four for
+    /// any output.
+    pub(crate) fn needed_components(&self) -> usize
+    {
+        let components = self.input_colorspace.num_components();
+
+        if components == 4
+        {
+            return components;
+        }
+        components.min(self.output_colorspace.num_components())
+    }
+
+    /// Whether the image is decoded at a reduced size, lossless and hierarchical
+    /// images are always decoded at their full size
+    pub(crate) fn is_scaled(&self) -> bool
+    {
+        self.scale != 8 && self.hierarchical.is_none() && !self.info.sof.is_lossless()
     }
 
     /// Create a new Decoder instance
@@ -202,16 +460,116 @@ impl Decoder
         P: AsRef<Path> + Clone,
     {
         //Read to an in memory buffer
-        let buffer = Cursor::new(read(file)?);
-        info!("File size: {} bytes", buffer.get_ref().len());
+        let buffer = read(file)?;
+        info!("File size: {} bytes", buffer.len());
+
+        self.decode_internal(Cursor::new(&buffer))
+    }
+
+    /// Read the headers of an image without decoding it
+    ///
+    /// Headers are parsed up to the first scan, after which the image information
+    /// can be queried using [`info`](Self::info), e.g. to reject images which are
+    /// too large before paying for a full decode.
+    ///
+    /// The next decode continues from where this stopped instead of parsing the
+    /// headers again, so it must be given the same image.
+    ///
+    /// # Errors
+    /// If the headers are invalid or describe an unsupported image
+    pub fn read_headers(&mut self, buf: &[u8]) -> Result<(), DecodeErrors>
+    {
+        let mut buf = Cursor::new(buf);
+
+        self.decode_headers(&mut buf)?;
+
+        self.resolve_deferred_height(&mut buf)?;
+
+        self.headers_end = Some(buf.position());
+
+        Ok(())
+    }
+
+    /// Parse headers up to the first scan, unless `read_headers` already did
+    pub(crate) fn prepare_headers(&mut self, buf: &mut Cursor<&[u8]>) -> Result<(), DecodeErrors>
+    {
+        if let Some(position) = self.headers_end.take()
+        {
+            buf.set_position(position);
+        }
+        else
+        {
+            self.decode_headers(buf)?;
+
+            self.resolve_deferred_height(buf)?;
+        }
+        self.check_crop()?;
+
+        self.check_colorspace()?;
+
+        self.check_memory()
+    }
+
+    /// Decode an image from a reader, e.g. a socket or a pipe
+    ///
+    /// Baseline images are decoded as data arrives, holding no more than the
+    /// compressed data of an MCU row at a time, other images are read into
+    /// memory first.
+    ///
+    /// # Errors
+    /// If the image is not a valid jpeg file or the reader returns an error
+    pub fn decode_reader<R: Read>(&mut self, reader: R) -> Result<Vec<u8>, DecodeErrors>
+    {
+        // headers are read from the stream, not from a buffer given to `read_headers`
+        self.headers_end = None;
+
+        let mut reader = BufReader::new(reader);
+
+        let offset = self.decode_headers(&mut reader)?;
+
+        let buffered = reader.buffer().to_vec();
+
+        let mut reader = reader.into_inner();
+
+        if !self.deferred_height && self.decodes_by_mcu_rows()
+        {
+            self.check_colorspace()?;
+
+            self.check_memory()?;
+
+            let mut source = StreamSource::new(reader, buffered, offset);
+
+            let mut pixels = vec![0; self.output_size()];
+
+            self.decode_mcu_ycbcr_baseline(&mut source, &mut pixels)?;
+
+            return Ok(pixels);
+        }
+
+        let mut data = buffered;
+
+        reader.read_to_end(&mut data)?;
+
+        // the data starts where the headers end
+        self.data_offset = offset;
+
+        let mut buf = Cursor::new(&data[..]);
 
-        self.decode_internal(buffer)
+        self.resolve_deferred_height(&mut buf)?;
+
+        self.check_crop()?;
+
+        self.check_colorspace()?;
+
+        self.check_memory()?;
+
+        self.decode_scans(&mut buf)
     }
 
     /// Returns the image information
     ///
-    /// This **must** be called after a subsequent call to `decode_file` or
-    /// `decode_buffer` otherwise it will return None
+    /// This **must** be called after a subsequent call to `read_headers`,
+    /// `decode_file` or `decode_buffer` otherwise it will return None
     ///
     #[must_use]
     pub fn info(&self) -> Option<ImageInfo>
@@ -224,8 +582,14 @@ impl Decoder
         {
             return None;
         }
+        let mut info = self.info.clone();
+
+        if self.swaps_dimensions()
+        {
+            std::mem::swap(&mut info.width, &mut info.height);
+        }
 
-        return Some(self.info.clone());
+        return Some(info);
     }
 
     /// Decode Decoder headers
@@ -238,16 +602,51 @@ impl Decoder
     ///  - SOF(O)
     ///  - DQT -> Quantization tables
     ///  - DHT -> Huffman tables
+    ///  - DAC -> Arithmetic conditioning tables
+    ///  - DHP -> Hierarchical progression
     ///  - SOS -> Start of Scan
     /// # Unsupported Headers
     ///  - SOF(n) -> Decoder images which are not baseline/progressive
-    ///  - DAC -> Images using Arithmetic tables
     ///  - JPG(n)
-    fn decode_headers<R>(&mut self, buf: &mut R) -> Result<(), DecodeErrors>
+    ///
+    /// Returns the number of bytes read
+    pub(crate) fn decode_headers<R>(&mut self, buf: &mut R) -> Result<usize, DecodeErrors>
     where
         R: Read + BufRead,
     {
-        let mut buf = buf;
+        let mut buf = PositionReader::new(buf);
+
+        self.scans_parsed = 0;
+
+        self.warnings.clear();
+
+        self.data_offset = 0;
+
+        self.info.exif = None;
+
+        self.icc_chunks.clear();
+
+        self.icc_profile = None;
+
+        self.adobe_transform = None;
+
+        // set by frame and DRI segments of the previous image
+        self.is_progressive = false;
+
+        self.restart_interval = 0;
+
+        self.todo = 0x7fff_ffff;
+
+        self.info.restart_interval = 0;
+
+        self.arith_dc_conditioning = [(0, 1); MAX_COMPONENTS];
+
+        self.arith_ac_conditioning = [5; MAX_COMPONENTS];
+
+        // set by the DHP segment and the frame header of the previous image
+        self.hierarchical = None;
+
+        self.deferred_height = false;
 
         // First two bytes should be jpeg soi marker
         let magic_bytes = read_u16_be(&mut buf)?;
@@ -259,6 +658,12 @@ impl Decoder
 
         let mut last_byte = 0;
 
+        // bytes since the end of the last segment which are not part of a marker, and
+        // where they start
+        let mut skipped = 0;
+
+        let mut skipped_from = 0;
+
         loop
         {
             // read a byte
@@ -268,6 +673,25 @@ impl Decoder
             // like OxFF(some marker data)
             if last_byte == 0xFF
             {
+                // any number of 0xFF fill bytes may precede a marker
+                if m == 0xFF
+                {
+                    continue;
+                }
+                // where the marker starts
+                let offset = buf.position() - 2;
+
+                if skipped != 0
+                {
+                    self.add_warning(
+                        WarningKind::SkippedBytes,
+                        Some(m),
+                        skipped_from,
+                        format!("Skipped {skipped} bytes before marker 0xFF{m:X}"),
+                    )?;
+
+                    skipped = 0;
+                }
                 let marker = Marker::from_u8(m);
 
                 // Check http://www.vip.sugovica.hu/Sardi/kepnezo/JPEG%20File%20Layout%20and%20Format.htm
@@ -276,23 +700,48 @@ impl Decoder
                 {
                     match m
                     {
-                        Marker::SOF(0 | 2) =>
+                        Marker::SOF(0 | 1 | 2 | 3 | 5 | 6 | 7 | 9 | 10) =>
                         {
                             let marker = {
                                 // choose marker
-                                if m == Marker::SOF(0)
+                                match m
                                 {
-                                    SOFMarkers::BaselineDct
-                                }
-                                else
-                                {
-                                    self.is_progressive = true;
-
-                                    SOFMarkers::ProgressiveDctHuffman
+                                    Marker::SOF(0) => SOFMarkers::BaselineDct,
+                                    Marker::SOF(1) => SOFMarkers::ExtendedSequentialHuffman,
+                                    Marker::SOF(2) =>
+                                    {
+                                        self.is_progressive = true;
+
+                                        SOFMarkers::ProgressiveDctHuffman
+                                    }
+                                    Marker::SOF(3) => SOFMarkers::LosslessHuffman,
+                                    Marker::SOF(5) => SOFMarkers::DifferentialSequentialHuffman,
+                                    Marker::SOF(6) =>
+                                    {
+                                        self.is_progressive = true;
+
+                                        SOFMarkers::DifferentialProgressiveHuffman
+                                    }
+                                    Marker::SOF(7) => SOFMarkers::DifferentialLosslessHuffman,
+                                    Marker::SOF(10) =>
+                                    {
+                                        self.is_progressive = true;
+
+                                        SOFMarkers::ProgressiveDctArithmetic
+                                    }
+                                    _ => SOFMarkers::ExtendedSequentialDctArithmetic,
                                 }
                             };
                             info!("Image encoding scheme =`{:?}`", marker);
 
+                            if marker.is_differential() && self.hierarchical.is_none()
+                            {
+                                return Err(DecodeErrors::SofError(
+                                    "Differential frame found outside a hierarchical image"
+                                        .to_string(),
+                                ));
+                            }
+
                             // get components
                             parse_start_of_frame(&mut buf, marker, self)?;
                         }
@@ -310,10 +759,10 @@ impl Decoder
                                 "Unsupported image format".to_string(),
                             ));
                         }
-                        // APP(0) segment
+                        // Application segments, metadata
                         Marker::APP(_) =>
                         {
-                            parse_app(&mut buf, m, &mut self.info)?;
+                            parse_app(&mut buf, m, offset, self)?;
                         }
                         // Quantization tables
                         Marker::DQT =>
@@ -335,32 +784,38 @@ impl Decoder
                             break;
                         }
 
-                        Marker::DAC | Marker::DNL =>
+                        // Arithmetic conditioning tables
+                        Marker::DAC =>
+                        {
+                            parse_dac(self, &mut buf)?;
+                        }
+                        // Hierarchical progression, frames follow
+                        Marker::DHP =>
                         {
-                            return Err(DecodeErrors::Format(format!(
-                                "Parsing of the following header `{:?}` is not supported,\
-                                cannot continue",
-                                m
-                            )));
+                            parse_dhp(self, &mut buf)?;
+                        }
+                        // Number of lines, only valid after the first scan
+                        // (see resolve_deferred_height)
+                        Marker::DNL =>
+                        {
+                            return Err(DecodeErrors::Format(
+                                "DNL segment found before the first scan".to_string(),
+                            ));
                         }
                         Marker::DRI =>
                         {
                             info!("DRI marker present");
-                            if read_u16_be(buf)? != 4
-                            {
-                                return Err(DecodeErrors::Format(
-                                    "Bad DRI length, Corrupt JPEG".to_string(),
-                                ));
-                            }
-                            self.restart_interval = usize::from(read_u16_be(buf)?);
-                            self.todo = self.restart_interval;
+                            parse_dri(self, &mut buf)?;
+                        }
+                        // no segment follows a restart marker
+                        Marker::RST(_) =>
+                        {
+                            self.add_skipped_marker(m.to_u8(), offset, "in the headers")?;
                         }
                         _ =>
                         {
-                            warn!(
-                                "Capabilities for processing marker \"{:?}\" not implemented",
-                                m
-                            );
+                            self.add_skipped_marker(m.to_u8(), offset, "in the headers")?;
+
                             let length = read_u16_be(&mut buf)?;
 
                             if length < 2
@@ -370,8 +825,7 @@ impl Decoder
                                     length
                                 )));
                             }
-                            warn!("Skipping {} bytes", length - 2);
-                            buf.consume((length - 2) as usize);
+                            skip_bytes(&mut buf, usize::from(length - 2))?;
                         }
                     }
                 }
@@ -387,16 +841,52 @@ impl Decoder
                             size
                         )));
                     }
-                    warn!("Extraneous marker 0xFF{:X} found. Size: {}", m, size - 2);
-                    warn!("Skipping {} bytes", (size - 2));
-                    buf.consume((size - 2) as usize);
+                    self.add_skipped_marker(m, offset, "in the headers")?;
+
+                    skip_bytes(&mut buf, usize::from(size - 2))?;
+                }
+            }
+            else if m != 0xFF
+            {
+                if skipped == 0
+                {
+                    skipped_from = buf.position() - 1;
                 }
+                skipped += 1;
             }
 
             last_byte = m;
         }
 
-        Ok(())
+        // the Adobe segment may follow the frame header
+        match self.info.components
+        {
+            // Adobe transform 0 means the components were stored as they are
+            3 if self.adobe_transform == Some(0) => self.input_colorspace = ColorSpace::RGB,
+            3 => self.input_colorspace = ColorSpace::YCbCr,
+            // Adobe transform 2 means Y, Cb and Cr were stored in place of C, M and Y
+            4 if self.adobe_transform == Some(2) => self.input_colorspace = ColorSpace::YCCK,
+            4 => self.input_colorspace = ColorSpace::CMYK,
+            _ => (),
+        }
+
+        if !self.icc_chunks.is_empty()
+        {
+            let mut chunks = std::mem::take(&mut self.icc_chunks);
+
+            match assemble_profile(&mut chunks)
+            {
+                Ok(profile) => self.icc_profile = Some(profile),
+                Err(err) => self.add_warning(
+                    WarningKind::InvalidIccProfile,
+                    Some(0xE2),
+                    chunks[0].offset,
+                    format!("Ignored ICC profile, {err}"),
+                )?,
+            }
+        }
+
+        Ok(buf.position())
     }
 
     /// Get the output colorspace the image pixels will be decoded into
@@ -406,19 +896,138 @@ impl Decoder
         return self.output_colorspace;
     }
 
-    fn decode_internal(&mut self, buf: Cursor<Vec<u8>>) -> Result<Vec<u8>, DecodeErrors>
+    fn decode_internal(&mut self, buf: Cursor<&[u8]>) -> Result<Vec<u8>, DecodeErrors>
     {
         let mut buf = buf;
 
-        self.decode_headers(&mut buf)?;
+        self.prepare_headers(&mut buf)?;
+
+        self.decode_scans(&mut buf)
+    }
+
+    /// Decode image data after headers have been parsed into a new buffer
+    pub(crate) fn decode_scans(&mut self, buf: &mut Cursor<&[u8]>) -> Result<Vec<u8>, DecodeErrors>
+    {
+        if let Some(pixels) = self.decode_whole_planes(buf)?
+        {
+            return Ok(pixels);
+        }
+        let mut pixels = vec![0; self.output_size()];
+
+        self.decode_mcu(buf, &mut pixels)?;
+
+        Ok(pixels)
+    }
+
+    /// Decode image data after headers have been parsed into a new buffer, as
+    /// stored, i.e. not turned per `set_apply_orientation`
+    ///
+    /// For readers handing out rows and previews, whose baseline images are never
+    /// turned either.
+    pub(crate) fn decode_scans_unturned(
+        &mut self, buf: &mut Cursor<&[u8]>,
+    ) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let apply_orientation = std::mem::replace(&mut self.apply_orientation, false);
+
+        let pixels = self.decode_scans(buf);
+
+        self.apply_orientation = apply_orientation;
+
+        pixels
+    }
+
+    /// Whether image data is decoded by the baseline decoder, one MCU row at a time
+    pub(crate) fn decodes_by_mcu_rows(&self) -> bool
+    {
+        self.hierarchical.is_none()
+            && !self.reconstructs_whole_planes()
+            && !self.is_progressive
+            && !self.info.sof.is_lossless()
+            && !self.info.sof.is_arithmetic()
+            && self.info.pixel_density <= 8
+    }
+
+    /// Decode hierarchical, lossless, scaled and cropped images, whose samples
+    /// are reconstructed a whole plane at a time, into 8 bit samples
+    ///
+    /// Returns `None` for images decoded MCU row by MCU row, or an error if the
+    /// samples do not fit into 8 bits.
+    #[allow(clippy::cast_possible_truncation)]
+    fn decode_whole_planes(
+        &mut self, buf: &mut Cursor<&[u8]>,
+    ) -> Result<Option<Vec<u8>>, DecodeErrors>
+    {
+        if self.info.pixel_density > 8
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Image has {} bits of precision, use `decode_buffer_u16` to decode it",
+                self.info.pixel_density
+            )));
+        }
+        let samples = if self.hierarchical.is_some()
+        {
+            let samples = self.decode_mcu_hierarchical(buf)?;
+
+            self.crop_output(&samples)
+        }
+        else if self.info.sof.is_lossless()
+        {
+            let samples = self.decode_mcu_lossless(buf)?;
+
+            self.crop_output(&samples)
+        }
+        else if self.reconstructs_whole_planes()
+        {
+            self.decode_mcu_scaled(buf)?
+        }
+        else
+        {
+            return Ok(None);
+        };
+        // samples fit into 8 bits
+        Ok(Some(samples.into_iter().map(|x| x as u8).collect()))
+    }
+
+    /// Decode image data after headers have been parsed, dispatching on the
+    /// coding process
+    fn decode_mcu(
+        &mut self, buf: &mut Cursor<&[u8]>, output: &mut [u8],
+    ) -> Result<(), DecodeErrors>
+    {
+        if self.info.sof.is_arithmetic()
+        {
+            self.decode_mcu_arithmetic(buf, output)
+        }
+        else if self.is_progressive
+        {
+            self.decode_mcu_ycbcr_progressive(buf, output)
+        }
+        else
+        {
+            self.decode_mcu_ycbcr_baseline(buf, output)
+        }
+    }
 
-        if self.is_progressive
+    /// Decode the coefficients of the whole image, dispatching on the coding
+    /// process
+    ///
+    /// Returns buffers laid out as `allocate_coefficient_buffers` lays them out.
+    pub(crate) fn decode_all_coefficients(
+        &mut self, buf: &mut Cursor<&[u8]>,
+    ) -> Result<[Vec<i16>; MAX_COMPONENTS], DecodeErrors>
+    {
+        if self.info.sof.is_arithmetic()
+        {
+            Ok(self.decode_arithmetic_coefficients(buf)?.0)
+        }
+        else if self.is_progressive
         {
-            self.decode_mcu_ycbcr_progressive(&mut buf)
+            Ok(self.decode_progressive_coefficients(buf)?.0)
         }
         else
         {
-            self.decode_mcu_ycbcr_baseline(&mut buf)
+            self.decode_sequential_coefficients(buf)
         }
     }
 
@@ -441,6 +1050,14 @@ impl Decoder
     ///   image(grayscale)
     ///
     /// - `ColorSpace::YCbCr`: Do not do colorspace conversion.
+    ///
+    /// - `ColorSpace::CMYK`: Decode CMYK and YCCK images to CMYK, 0 being no ink.
+    ///   Inverted CMYK, as written by Adobe applications, is inverted back.
+    ///
+    /// CMYK and YCCK images can only be decoded to `CMYK` or RGB(A/X), converting
+    /// to RGB leaves color management to the caller, see
+    /// [`icc_profile`](Self::icc_profile). Three component images cannot be
+    /// decoded to `CMYK` or `YCCK`, decoding them fails.
     #[allow(clippy::expect_used)]
     pub fn set_output_colorspace(&mut self, colorspace: ColorSpace)
     {
@@ -464,6 +1081,55 @@ impl Decoder
         }
     }
 
+    /// Check the image can be converted to the output colorspace
+    ///
+    /// Lossless images are not color converted, neither are hierarchical images
+    /// whose last frame is lossless, which the first frame header doesn't tell, so
+    /// those are checked once the last frame is decoded.
+    pub(crate) fn check_colorspace(&self) -> Result<(), DecodeErrors>
+    {
+        if self.info.sof.is_lossless() || self.hierarchical.is_some()
+        {
+            return Ok(());
+        }
+        self.check_color_conversion()
+    }
+
+    /// Check there is a conversion from the input to the output colorspace
+    pub(crate) fn check_color_conversion(&self) -> Result<(), DecodeErrors>
+    {
+        let supported = match self.input_colorspace
+        {
+            ColorSpace::GRAYSCALE => self.output_colorspace == ColorSpace::GRAYSCALE,
+            ColorSpace::YCbCr => matches!(
+                self.output_colorspace,
+                ColorSpace::GRAYSCALE
+                    | ColorSpace::YCbCr
+                    | ColorSpace::RGB
+                    | ColorSpace::RGBA
+                    | ColorSpace::RGBX
+            ),
+            ColorSpace::RGB => matches!(
+                self.output_colorspace,
+                ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX
+            ),
+            ColorSpace::CMYK | ColorSpace::YCCK => matches!(
+                self.output_colorspace,
+                ColorSpace::CMYK | ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX
+            ),
+            _ => false,
+        };
+
+        if !supported
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Cannot convert {:?} images to {:?}",
+                self.input_colorspace, self.output_colorspace
+            )));
+        }
+        Ok(())
+    }
+
     /// Set up-sampling routines in case an image is down sampled
     pub(crate) fn set_upsampling(&mut self) -> Result<(), DecodeErrors>
     {
@@ -521,6 +1187,117 @@ impl Decoder
         return Ok(());
     }
 
+    /// Decode images at a reduced size
+    ///
+    /// Images are scaled by `num/denom` rounded up to the nearest supported
+    /// factor, which are 1/8, 1/4, 1/2 and 1, like libjpeg's `scale_num` and
+    /// `scale_denom`. The IDCT then produces 1x1, 2x2 or 4x4 samples for every
+    /// block, and upsampling and color conversion are carried out at that size,
+    /// which is far cheaper than decoding the whole image and scaling it down.
+    ///
+    /// Lossless and hierarchical images are always decoded at their full size.
+    ///
+    /// Dimensions of scaled images are the image dimensions multiplied by the scale
+    /// and rounded up, see [`output_dimensions`](Self::output_dimensions).
+    pub fn set_scale(&mut self, num: u32, denom: u32)
+    {
+        // pick the smallest block size with size/8 >= num/denom
+        self.scale = [1_u8, 2, 4]
+            .into_iter()
+            .find(|size| u64::from(num) * 8 <= u64::from(*size) * u64::from(denom))
+            .map_or(8, usize::from);
+    }
+
+    /// Decode only a rectangle of the image
+    ///
+    /// `x` and `y` give the top left corner of the rectangle, in output
+    /// coordinates, i.e. after scaling via [`set_scale`](Self::set_scale), and the
+    /// output contains exactly its `width` x `height` pixels.
+    ///
+    /// Entropy decoding stops after the last MCU row overlapping the rectangle,
+    /// restart intervals that hold no MCU of interest are skipped without decoding
+    /// them, and IDCT, upsampling and color conversion are only carried out for
+    /// MCUs overlapping the rectangle and their neighbours, which upsampling looks
+    /// at. Lossless and hierarchical images are decoded whole and then cropped.
+    ///
+    /// Decoding fails if the rectangle is empty or does not fit in the image.
+    pub fn set_crop(&mut self, x: usize, y: usize, width: usize, height: usize)
+    {
+        self.crop = Some((x, y, width, height));
+    }
+
+    /// Return the width and height of the decoded image
+    ///
+    /// These are the image dimensions unless the image is scaled down or cropped,
+    /// see [`set_scale`](Self::set_scale) and [`set_crop`](Self::set_crop). Like `output_buffer_size`, this is only
+    /// known once headers have been read, otherwise `None` is returned.
+    #[must_use]
+    pub fn output_dimensions(&self) -> Option<(usize, usize)>
+    {
+        if self.info == ImageInfo::default()
+        {
+            return None;
+        }
+        let (width, height) = self.decoded_dimensions();
+
+        if self.swaps_dimensions()
+        {
+            return Some((height, width));
+        }
+        Some((width, height))
+    }
+
+    /// Set whether decoded images are turned upright per their Exif orientation
+    ///
+    /// Off by default. Images are rotated and mirrored as the orientation tag of
+    /// their Exif metadata says, see [`ExifData::orientation`](crate::ExifData::orientation), while they are written
+    /// to the output. Images rotated by 90 or 270 degrees have their width and height
+    /// swapped, as do [`info`](Self::info) and
+    /// [`output_dimensions`](Self::output_dimensions).
+    ///
+    /// This applies to `decode_buffer`, `decode_buffer_u16`, `decode_into`,
+    /// `decode_reader` and `decode_file`, the region set by `set_crop` is a region of
+    /// the image as stored. Previews, rows returned a few at a time, planes and
+    /// coefficients are never turned, not even for images those readers decode
+    /// whole.
+    pub fn set_apply_orientation(&mut self, apply: bool)
+    {
+        self.apply_orientation = apply;
+    }
+
+    /// Get whether decoded images are turned upright per their Exif orientation
+    #[must_use]
+    pub const fn get_apply_orientation(&self) -> bool
+    {
+        self.apply_orientation
+    }
+
+    /// How the output is turned, `None` if it is written as stored
+    pub(crate) fn output_orientation(&self) -> Option<Orientation>
+    {
+        if !self.apply_orientation
+        {
+            return None;
+        }
+        let orientation = self.info.exif.as_ref()?.orientation.filter(|x| *x != 1)?;
+
+        let (width, height) = self.decoded_dimensions();
+
+        Some(Orientation::new(orientation, width, height))
+    }
+
+    /// Whether the output is turned by 90 or 270 degrees
+    fn swaps_dimensions(&self) -> bool
+    {
+        self.apply_orientation
+            && self
+                .info
+                .exif
+                .as_ref()
+                .and_then(|x| x.orientation)
+                .is_some_and(|x| x >= 5)
+    }
+
     /// Set output colorspace to be RGBA
     /// equivalent of calling
     /// ```rust
@@ -536,18 +1313,30 @@ impl Decoder
     #[must_use]
     /// Get the width of the image as a u16
     ///
-    /// The width lies between 0 and 65535
+    /// The width lies between 0 and 65535, it is the height of the image as stored
+    /// if [`set_apply_orientation`](Self::set_apply_orientation) turns it by 90 or
+    /// 270 degrees, like the width [`info`](Self::info) reports.
     pub fn width(&self) -> u16
     {
+        if self.swaps_dimensions()
+        {
+            return self.info.height;
+        }
         self.info.width
     }
 
     /// Get the height of the image as a u16
     ///
-    /// The height lies between 0 and 65535
+    /// The height lies between 0 and 65535, it is the width of the image as stored
+    /// if [`set_apply_orientation`](Self::set_apply_orientation) turns it by 90 or
+    /// 270 degrees, like the height [`info`](Self::info) reports.
     #[must_use]
     pub fn height(&self) -> u16
     {
+        if self.swaps_dimensions()
+        {
+            return self.info.width;
+        }
         self.info.height
     }
 }
@@ -570,7 +1359,17 @@ pub struct ImageInfo
     /// Vertical sample
     pub y_density: u16,
     /// Number of components
-    pub(crate) components: u8,
+    pub components: u8,
+    /// Horizontal and vertical sampling factors of each component, in
+    /// frame header order
+    pub sampling_factors: Vec<(u8, u8)>,
+    /// Whether the image is progressive, i.e. its coefficients are spread
+    /// over multiple scans
+    pub progressive: bool,
+    /// Number of MCU's between restart markers, zero if the image has none
+    pub restart_interval: u16,
+    /// Exif metadata, if the image has an Exif APP1 segment that could be parsed
+    pub exif: Option<ExifData>,
 }
 
 impl ImageInfo
@@ -4,7 +4,6 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
-use crate::decoder::MAX_DIMENSIONS;
 use crate::misc::{
     START_OF_FRAME_EXT_AR, START_OF_FRAME_EXT_SEQ, START_OF_FRAME_LOS_SEQ,
     START_OF_FRAME_LOS_SEQ_AR, START_OF_FRAME_PROG_DCT_AR,
@@ -35,8 +34,22 @@ pub enum DecodeErrors
     MCUError(String),
     /// Exhausted data
     ExhaustedData,
-    /// Large image dimensions(Corrupted data)?
-    LargeDimensions(usize),
+    /// Image width is larger than the limit set via `DecoderOptions`, width and limit
+    TooLargeWidth(usize, usize),
+    /// Image height is larger than the limit set via `DecoderOptions`, height and
+    /// limit
+    TooLargeHeight(usize, usize),
+    /// Image has more pixels than the limit set via `DecoderOptions`, pixels and limit
+    TooManyPixels(usize, usize),
+    /// Decoding the image needs more memory than the limit set via `DecoderOptions`,
+    /// bytes needed and limit
+    TooLargeAllocation(usize, usize),
+    /// Image has more scans than the limit set via `DecoderOptions`
+    TooManyScans(usize),
+    /// Output buffer is smaller than the decoded image, expected and actual sizes
+    TooSmallOutput(usize, usize),
+    /// A warning of a kind set via `Decoder::set_warnings_as_errors`
+    Warning(DecodeWarning),
 }
 
 impl Debug for DecodeErrors
@@ -64,11 +77,28 @@ impl Debug for DecodeErrors
                 write!(f, "{:?}", image_type)
             }
             Self::ExhaustedData => write!(f, "Exhausted data in the image"),
-            Self::LargeDimensions(ref dimensions) => write!(
+            Self::TooLargeWidth(width, limit) =>
+            {
+                write!(f, "Image width {width} is larger than the limit of {limit}")
+            }
+            Self::TooLargeHeight(height, limit) =>
+            {
+                write!(f, "Image height {height} is larger than the limit of {limit}")
+            }
+            Self::TooManyPixels(pixels, limit) =>
+            {
+                write!(f, "Image has {pixels} pixels, more than the limit of {limit}")
+            }
+            Self::TooLargeAllocation(bytes, limit) => write!(
+                f,
+                "Decoding the image needs {bytes} bytes, more than the limit of {limit}"
+            ),
+            Self::TooManyScans(limit) => write!(f, "Image has more than {limit} scans"),
+            Self::TooSmallOutput(expected, found) => write!(
                 f,
-                "Too large dimensions {},library supports up to {}",
-                dimensions, MAX_DIMENSIONS
+                "Output buffer too small, expected at least {expected} bytes but found {found}"
             ),
+            Self::Warning(ref warning) => write!(f, "{warning}"),
         }
     }
 }
@@ -99,11 +129,28 @@ impl Display for DecodeErrors
             Self::MCUError(ref reason) => write!(f, "Error in decoding MCU. Reason {}", reason),
             Self::ExhaustedData => write!(f, "Exhausted data in the image"),
 
-            Self::LargeDimensions(ref dimensions) => write!(
+            Self::TooLargeWidth(width, limit) =>
+            {
+                write!(f, "Image width {width} is larger than the limit of {limit}")
+            }
+            Self::TooLargeHeight(height, limit) =>
+            {
+                write!(f, "Image height {height} is larger than the limit of {limit}")
+            }
+            Self::TooManyPixels(pixels, limit) =>
+            {
+                write!(f, "Image has {pixels} pixels, more than the limit of {limit}")
+            }
+            Self::TooLargeAllocation(bytes, limit) => write!(
                 f,
-                "Too large dimensions {},library supports up to {}",
-                dimensions, MAX_DIMENSIONS
+                "Decoding the image needs {bytes} bytes, more than the limit of {limit}"
             ),
+            Self::TooManyScans(limit) => write!(f, "Image has more than {limit} scans"),
+            Self::TooSmallOutput(expected, found) => write!(
+                f,
+                "Output buffer too small, expected at least {expected} bytes but found {found}"
+            ),
+            Self::Warning(ref warning) => write!(f, "{warning}"),
         }
     }
 }
@@ -126,6 +173,76 @@ impl From<std::io::Error> for DecodeErrors
     }
 }
 
+/// A problem with an image which did not stop it from being decoded
+///
+/// Collected while decoding, see [`Decoder::warnings`](crate::Decoder::warnings),
+/// warnings of kinds passed to
+/// [`Decoder::set_warnings_as_errors`](crate::Decoder::set_warnings_as_errors)
+/// fail the decode instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeWarning
+{
+    /// What kind of problem this is
+    pub kind: WarningKind,
+    /// Second byte of the marker the problem was found at, e.g. `0xD4` for RST4,
+    /// if any
+    pub marker: Option<u8>,
+    /// Offset from the start of the image of the byte the problem was found at
+    ///
+    /// Entropy coded data is read a few bytes ahead of the bits being decoded, so
+    /// for problems in it this is where reading had got to.
+    pub offset: usize,
+    /// What went wrong, in words
+    pub reason: String,
+}
+
+impl Display for DecodeWarning
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "{} at offset {}", self.reason, self.offset)
+    }
+}
+
+/// Kinds of problems reported as a [`DecodeWarning`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum WarningKind
+{
+    /// A marker the JPEG standard does not define was found outside of entropy
+    /// coded data, and its segment skipped
+    UnknownMarker,
+    /// A marker which cannot appear where it was found, e.g. SOI after the frame
+    /// header or a restart marker between scans, was skipped along with its
+    /// segment
+    UnexpectedMarker,
+    /// Bytes which are not part of any segment were found before a marker, and
+    /// skipped
+    SkippedBytes,
+    /// The entropy coded data of a scan or restart interval ended before its last
+    /// MCU, the MCUs left were not decoded, only reported in lenient mode
+    MissingData,
+    /// Entropy coded data could not be decoded, MCUs were not decoded up to the
+    /// next restart marker, only reported in lenient mode
+    CorruptData,
+    /// A restart marker was found where another one was expected, only reported in
+    /// lenient mode
+    UnexpectedRestartMarker,
+    /// A segment following a scan of a progressive image could not be parsed, the
+    /// image was rendered from the scans before it, only reported in lenient mode
+    SkippedScans,
+    /// The chunks of an ICC profile do not make up a whole profile, e.g. one is
+    /// missing or two claim the same place, the profile was ignored
+    InvalidIccProfile,
+    /// Exif metadata could not be read, the segment was ignored if its TIFF header
+    /// or first IFD is broken, otherwise the tags which could not be read were
+    /// skipped
+    InvalidExif,
+    /// The frame header left the image height to a DNL segment but none followed
+    /// the first scan, the height was taken from the number of lines in the scan
+    MissingDnl,
+}
+
 /// Contains Unsupported/Yet-to-be supported Decoder image encoding types.
 #[derive(Eq, PartialEq, Copy, Clone)]
 
@@ -0,0 +1,501 @@
+//! Exif metadata
+//!
+//! Cameras and phones store metadata in an APP1 segment starting with `Exif\0\0`,
+//! what follows is a TIFF file without an image: a header giving the byte order,
+//! then a chain of image file directories (IFDs), lists of 12 byte entries each
+//! holding a tag, its type, a count of values and either the values themselves if
+//! they fit in four bytes, or their offset from the start of the TIFF header.
+//!
+//! We read the first IFD, which describes the image, and the Exif and GPS IFDs it
+//! points to, which is where the tags people care about are. The IFD of the
+//! thumbnail and maker notes are left alone. Tags are kept as they were found so
+//! callers can read the ones we do not interpret, and the ones we do are decoded
+//! into [`ExifData`].
+//!
+//! Broken metadata should not stop an image from being decoded, so entries pointing
+//! outside of the segment or of types we do not know are skipped, and a segment
+//! that cannot be parsed at all is ignored, each with a
+//! [`WarningKind::InvalidExif`](crate::errors::WarningKind::InvalidExif) warning
+//! (see `parse_app`).
+use crate::errors::DecodeErrors;
+
+/// Tag of the image orientation, in the first IFD
+const ORIENTATION: u16 = 0x0112;
+
+/// Tags of the camera maker and model, in the first IFD
+const MAKE: u16 = 0x010F;
+
+const MODEL: u16 = 0x0110;
+
+/// Tag of the time the file was last changed, in the first IFD
+const DATE_TIME: u16 = 0x0132;
+
+/// Tag of the time the picture was taken, in the Exif IFD
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+/// Tags of the offsets of the Exif and GPS IFDs, in the first IFD
+const EXIF_POINTER: u16 = 0x8769;
+
+const GPS_POINTER: u16 = 0x8825;
+
+/// Tags of the GPS IFD
+const GPS_LATITUDE_REF: u16 = 0x0001;
+
+const GPS_LATITUDE: u16 = 0x0002;
+
+const GPS_LONGITUDE_REF: u16 = 0x0003;
+
+const GPS_LONGITUDE: u16 = 0x0004;
+
+const GPS_ALTITUDE_REF: u16 = 0x0005;
+
+const GPS_ALTITUDE: u16 = 0x0006;
+
+/// An unsigned fraction, numerator and denominator
+pub type Rational = (u32, u32);
+
+/// Exif metadata of an image
+///
+/// Found in [`ImageInfo::exif`](crate::ImageInfo::exif) once the headers have been
+/// read, for images with an Exif APP1 segment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExifData
+{
+    /// Whether values in `tags` and `raw` are big endian (`MM`), rather than little
+    /// endian (`II`)
+    pub big_endian: bool,
+    /// How the image should be rotated or flipped to be displayed upright, 1 to 8 as
+    /// defined by the TIFF standard, 1 being as stored
+    pub orientation: Option<u16>,
+    /// Manufacturer of the camera
+    pub make: Option<String>,
+    /// Model of the camera
+    pub model: Option<String>,
+    /// When the file was last changed, as `YYYY:MM:DD HH:MM:SS`
+    pub date_time: Option<String>,
+    /// When the picture was taken, as `YYYY:MM:DD HH:MM:SS`
+    pub date_time_original: Option<String>,
+    /// Where the picture was taken
+    pub gps: Option<GpsInfo>,
+    /// Every tag of the first, Exif and GPS IFDs, in the order they were found
+    pub tags: Vec<ExifTag>,
+    /// The TIFF data following `Exif\0\0`, offsets of tags are relative to its start
+    pub raw: Vec<u8>,
+}
+
+/// GPS position of an image
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GpsInfo
+{
+    /// `N` or `S` for the northern or southern hemisphere
+    pub latitude_ref: Option<char>,
+    /// Degrees, minutes and seconds
+    pub latitude: Option<[Rational; 3]>,
+    /// `E` or `W` for east or west of the prime meridian
+    pub longitude_ref: Option<char>,
+    /// Degrees, minutes and seconds
+    pub longitude: Option<[Rational; 3]>,
+    /// 0 if the altitude is above sea level, 1 if it is below
+    pub altitude_ref: Option<u8>,
+    /// Meters from sea level
+    pub altitude: Option<Rational>,
+}
+
+/// An IFD tags are read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExifIfd
+{
+    /// The first IFD, describing the image
+    Primary,
+    /// The Exif IFD, describing how the picture was taken
+    Exif,
+    /// The GPS IFD
+    Gps,
+}
+
+/// A tag of an IFD, as found in the file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExifTag
+{
+    /// IFD the tag was found in
+    pub ifd: ExifIfd,
+    /// Tag number, e.g. `0x0112` for the orientation
+    pub tag: u16,
+    /// TIFF type of the values, e.g. 2 for ASCII or 3 for SHORT
+    pub format: u16,
+    /// Number of values
+    pub count: u32,
+    /// Bytes of the values, in the byte order of the file
+    pub data: Vec<u8>,
+}
+
+impl ExifData
+{
+    /// Return the tag `tag` of `ifd`, if the image has it
+    #[must_use]
+    pub fn tag(&self, ifd: ExifIfd, tag: u16) -> Option<&ExifTag>
+    {
+        self.tags.iter().find(|x| x.ifd == ifd && x.tag == tag)
+    }
+
+    /// Parse the TIFF data of an Exif segment
+    ///
+    /// What had to be skipped is added to `problems`.
+    pub(crate) fn parse(raw: Vec<u8>, problems: &mut Vec<String>)
+        -> Result<ExifData, DecodeErrors>
+    {
+        let big_endian = match raw.get(..4)
+        {
+            Some(b"MM\x00\x2A") => true,
+            Some(b"II\x2A\x00") => false,
+            _ =>
+            {
+                return Err(DecodeErrors::Format(
+                    "Exif data does not start with a TIFF header".to_string(),
+                ))
+            }
+        };
+        let reader = TiffReader {
+            data: &raw,
+            big_endian,
+        };
+
+        let mut tags = vec![];
+
+        reader.read_ifd(
+            reader.u32(4)? as usize,
+            ExifIfd::Primary,
+            &mut tags,
+            problems,
+        )?;
+
+        // the first IFD points to the others
+        let pointer = |tag| {
+            tags.iter()
+                .find(|x: &&ExifTag| x.ifd == ExifIfd::Primary && x.tag == tag)
+                .and_then(|x| reader.value(x, 0))
+        };
+
+        let exif_ifd = pointer(EXIF_POINTER);
+
+        let gps_ifd = pointer(GPS_POINTER);
+
+        // a broken sub IFD leaves the tags found so far
+        if let Some(offset) = exif_ifd
+        {
+            if let Err(err) = reader.read_ifd(offset, ExifIfd::Exif, &mut tags, problems)
+            {
+                problems.push(format!("Skipped the rest of the Exif IFD, {err}"));
+            }
+        }
+        if let Some(offset) = gps_ifd
+        {
+            if let Err(err) = reader.read_ifd(offset, ExifIfd::Gps, &mut tags, problems)
+            {
+                problems.push(format!("Skipped the rest of the GPS IFD, {err}"));
+            }
+        }
+
+        let mut exif = ExifData {
+            big_endian,
+            tags,
+            ..ExifData::default()
+        };
+
+        exif.orientation = exif
+            .tag(ExifIfd::Primary, ORIENTATION)
+            .and_then(|x| reader.value(x, 0))
+            .and_then(|x| u16::try_from(x).ok())
+            .filter(|x| (1..=8).contains(x));
+
+        exif.make = exif.tag(ExifIfd::Primary, MAKE).and_then(ascii);
+
+        exif.model = exif.tag(ExifIfd::Primary, MODEL).and_then(ascii);
+
+        exif.date_time = exif.tag(ExifIfd::Primary, DATE_TIME).and_then(ascii);
+
+        exif.date_time_original = exif.tag(ExifIfd::Exif, DATE_TIME_ORIGINAL).and_then(ascii);
+
+        if gps_ifd.is_some()
+        {
+            let gps_tag = |tag| exif.tag(ExifIfd::Gps, tag);
+
+            let reference = |tag| gps_tag(tag).and_then(ascii).and_then(|x| x.chars().next());
+
+            let position = |tag| {
+                let tag = gps_tag(tag)?;
+
+                Some([
+                    reader.rational(tag, 0)?,
+                    reader.rational(tag, 1)?,
+                    reader.rational(tag, 2)?,
+                ])
+            };
+
+            exif.gps = Some(GpsInfo {
+                latitude_ref: reference(GPS_LATITUDE_REF),
+                latitude: position(GPS_LATITUDE),
+                longitude_ref: reference(GPS_LONGITUDE_REF),
+                longitude: position(GPS_LONGITUDE),
+                altitude_ref: gps_tag(GPS_ALTITUDE_REF).and_then(|x| x.data.first().copied()),
+                altitude: gps_tag(GPS_ALTITUDE).and_then(|x| reader.rational(x, 0)),
+            });
+        }
+
+        exif.raw = raw;
+
+        Ok(exif)
+    }
+}
+
+impl GpsInfo
+{
+    /// Latitude in degrees, negative in the southern hemisphere
+    #[must_use]
+    pub fn latitude_degrees(&self) -> Option<f64>
+    {
+        let degrees = to_degrees(self.latitude?)?;
+
+        Some(
+            if self.latitude_ref == Some('S')
+            {
+                -degrees
+            }
+            else
+            {
+                degrees
+            },
+        )
+    }
+
+    /// Longitude in degrees, negative west of the prime meridian
+    #[must_use]
+    pub fn longitude_degrees(&self) -> Option<f64>
+    {
+        let degrees = to_degrees(self.longitude?)?;
+
+        Some(
+            if self.longitude_ref == Some('W')
+            {
+                -degrees
+            }
+            else
+            {
+                degrees
+            },
+        )
+    }
+
+    /// Altitude in meters, negative below sea level
+    #[must_use]
+    pub fn altitude_meters(&self) -> Option<f64>
+    {
+        let meters = to_f64(self.altitude?)?;
+
+        Some(
+            if self.altitude_ref == Some(1)
+            {
+                -meters
+            }
+            else
+            {
+                meters
+            },
+        )
+    }
+}
+
+/// Reads values from TIFF data in its byte order
+struct TiffReader<'a>
+{
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl TiffReader<'_>
+{
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N], DecodeErrors>
+    {
+        offset
+            .checked_add(N)
+            .and_then(|end| self.data.get(offset..end))
+            .and_then(|x| x.try_into().ok())
+            .ok_or_else(|| DecodeErrors::Format(format!("Exif data ends before offset {offset}")))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, DecodeErrors>
+    {
+        let bytes = self.bytes(offset)?;
+
+        Ok(
+            if self.big_endian
+            {
+                u16::from_be_bytes(bytes)
+            }
+            else
+            {
+                u16::from_le_bytes(bytes)
+            },
+        )
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, DecodeErrors>
+    {
+        let bytes = self.bytes(offset)?;
+
+        Ok(
+            if self.big_endian
+            {
+                u32::from_be_bytes(bytes)
+            }
+            else
+            {
+                u32::from_le_bytes(bytes)
+            },
+        )
+    }
+
+    /// Append the tags of the IFD at `offset` to `tags`, and the ones skipped to
+    /// `problems`
+    fn read_ifd(
+        &self, offset: usize, ifd: ExifIfd, tags: &mut Vec<ExifTag>, problems: &mut Vec<String>,
+    ) -> Result<(), DecodeErrors>
+    {
+        let entries = self.u16(offset)?;
+
+        for entry in 0..usize::from(entries)
+        {
+            let start = offset + 2 + entry * 12;
+
+            let tag = self.u16(start)?;
+
+            let format = self.u16(start + 2)?;
+
+            let count = self.u32(start + 4)?;
+
+            let Some(size) = type_size(format).and_then(|x| x.checked_mul(count as usize))
+            else
+            {
+                problems.push(format!(
+                    "Skipped Exif tag 0x{tag:04X} of unknown type {format}"
+                ));
+
+                continue;
+            };
+
+            // values which do not fit in the entry are elsewhere
+            let position = if size <= 4
+            {
+                start + 8
+            }
+            else
+            {
+                self.u32(start + 8)? as usize
+            };
+
+            let Some(data) = position
+                .checked_add(size)
+                .and_then(|end| self.data.get(position..end))
+            else
+            {
+                problems.push(format!(
+                    "Skipped Exif tag 0x{tag:04X}, its values are past the end of the data"
+                ));
+
+                continue;
+            };
+
+            tags.push(ExifTag {
+                ifd,
+                tag,
+                format,
+                count,
+                data: data.to_vec(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Value `index` of a BYTE, SHORT or LONG tag
+    fn value(&self, tag: &ExifTag, index: usize) -> Option<usize>
+    {
+        let reader = TiffReader {
+            data: &tag.data,
+            big_endian: self.big_endian,
+        };
+
+        match tag.format
+        {
+            1 => tag.data.get(index).map(|x| usize::from(*x)),
+            3 => reader.u16(index * 2).ok().map(usize::from),
+            4 | 13 => reader.u32(index * 4).ok().map(|x| x as usize),
+            _ => None,
+        }
+    }
+
+    /// Value `index` of a RATIONAL tag
+    fn rational(&self, tag: &ExifTag, index: usize) -> Option<Rational>
+    {
+        if tag.format != 5
+        {
+            return None;
+        }
+        let reader = TiffReader {
+            data: &tag.data,
+            big_endian: self.big_endian,
+        };
+
+        let numerator = reader.u32(index * 8).ok()?;
+
+        let denominator = reader.u32(index * 8 + 4).ok()?;
+
+        Some((numerator, denominator))
+    }
+}
+
+/// Size in bytes of a value of a TIFF type
+const fn type_size(format: u16) -> Option<usize>
+{
+    match format
+    {
+        // BYTE, ASCII, SBYTE and UNDEFINED
+        1 | 2 | 6 | 7 => Some(1),
+        // SHORT and SSHORT
+        3 | 8 => Some(2),
+        // LONG, SLONG, FLOAT and IFD
+        4 | 9 | 11 | 13 => Some(4),
+        // RATIONAL, SRATIONAL and DOUBLE
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// The string of an ASCII tag, which may be padded with NULs or spaces
+fn ascii(tag: &ExifTag) -> Option<String>
+{
+    if tag.format != 2
+    {
+        return None;
+    }
+    let end = tag
+        .data
+        .iter()
+        .position(|x| *x == 0)
+        .unwrap_or(tag.data.len());
+
+    let string = String::from_utf8_lossy(&tag.data[..end])
+        .trim_end()
+        .to_string();
+
+    (!string.is_empty()).then_some(string)
+}
+
+fn to_f64((numerator, denominator): Rational) -> Option<f64>
+{
+    (denominator != 0).then(|| f64::from(numerator) / f64::from(denominator))
+}
+
+fn to_degrees([degrees, minutes, seconds]: [Rational; 3]) -> Option<f64>
+{
+    Some(to_f64(degrees)? + to_f64(minutes)? / 60.0 + to_f64(seconds)? / 3600.0)
+}
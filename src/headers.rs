@@ -7,12 +7,15 @@
 use std::cmp::max;
 use std::io::{BufRead, Read};
 
-use crate::components::Components;
-use crate::decoder::{Decoder, ImageInfo, MAX_DIMENSIONS};
-use crate::errors::DecodeErrors;
+use crate::components::{Components, SubSampRatios};
+use crate::decoder::{Decoder, MAX_COMPONENTS};
+use crate::errors::{DecodeErrors, WarningKind};
+use crate::exif::ExifData;
 use crate::huffman::HuffmanTable;
+use crate::icc::{IccChunk, ICC_SIGNATURE};
 use crate::marker::Marker;
-use crate::misc::{read_byte, read_u16_be, Aligned32, ColorSpace, SOFMarkers, UN_ZIGZAG};
+use crate::mcu_hierarchical::HierarchicalInfo;
+use crate::misc::{read_byte, read_u16_be, skip_bytes, Aligned32, ColorSpace, SOFMarkers, UN_ZIGZAG};
 
 ///**B.2.4.2 Huffman table-specification syntax**
 #[allow(clippy::similar_names)]
@@ -136,6 +139,13 @@ where
         // last 4 bits give us position
         let table_position = (qt_info & 0x0f) as usize;
 
+        if table_position >= decoder.qt_tables.len()
+        {
+            return Err(DecodeErrors::DqtError(format!(
+                "Invalid QT destination {table_position}, expected value between 0 and 3"
+            )));
+        }
+
         let precision_value = 64 * (precision + 1);
 
         let dct_table = match precision
@@ -155,10 +165,23 @@ where
             }
             1 =>
             {
-                // 16 bit quantization tables
-                return Err(DecodeErrors::DqtError(
-                    "Support for 16 bit quantization table is not complete".to_string(),
-                ));
+                // 16 bit quantization tables, stored in big endian
+                let mut qt_values = [0; 128];
+
+                buf.read_exact(&mut qt_values).map_err(|x| {
+                    DecodeErrors::Format(format!("Could not read symbols into the buffer\n{x}"))
+                })?;
+
+                length_read += 7 + precision_value as u16;
+
+                let mut qt_values_16 = [0; 64];
+
+                for (value, bytes) in qt_values_16.iter_mut().zip(qt_values.chunks_exact(2))
+                {
+                    *value = u16::from_be_bytes([bytes[0], bytes[1]]);
+                }
+                // carry out un zig-zag here
+                un_zig_zag(&qt_values_16)
             }
             _ =>
             {
@@ -175,8 +198,184 @@ where
     return Ok(());
 }
 
+///**B.2.4.3 Arithmetic conditioning table-specification syntax**
+pub(crate) fn parse_dac<R>(decoder: &mut Decoder, mut buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    // Read the length of the DAC segment
+    let dac_length = read_u16_be(&mut buf)?
+        .checked_sub(2)
+        .ok_or_else(|| DecodeErrors::Format("Invalid DAC length in image".to_string()))?;
+
+    // Each conditioning table takes two bytes
+    if dac_length % 2 != 0
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Invalid DAC length {}, expected a multiple of two",
+            dac_length
+        )));
+    }
+
+    for _ in 0..dac_length / 2
+    {
+        // top 4 bits are table class, lower 4 bits are table destination
+        let tc_tb = read_byte(&mut buf)?;
+
+        let conditioning = read_byte(&mut buf)?;
+
+        let table_class = tc_tb >> 4;
+
+        let index = usize::from(tc_tb & 0x0F);
+
+        if index >= MAX_COMPONENTS
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Invalid DAC table destination {}, expected value less than {}",
+                index, MAX_COMPONENTS
+            )));
+        }
+
+        match table_class
+        {
+            0 =>
+            {
+                // DC conditioning, lower 4 bits are L, top 4 bits are U
+                let lower = conditioning & 0x0F;
+
+                let upper = conditioning >> 4;
+
+                if lower > upper
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid DC conditioning, L({}) is greater than U({})",
+                        lower, upper
+                    )));
+                }
+                decoder.arith_dc_conditioning[index] = (lower, upper);
+            }
+            1 =>
+            {
+                // AC conditioning, Kx
+                if !(1..=63).contains(&conditioning)
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid AC conditioning Kx value {}, expected value between 1 and 63",
+                        conditioning
+                    )));
+                }
+                decoder.arith_ac_conditioning[index] = conditioning;
+            }
+            _ =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Invalid DAC table class {}, expected 0 or 1",
+                    table_class
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///**B.2.4.4 Restart interval definition syntax**
+pub(crate) fn parse_dri<R>(decoder: &mut Decoder, mut buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    if read_u16_be(&mut buf)? != 4
+    {
+        return Err(DecodeErrors::Format(
+            "Bad DRI length, Corrupt JPEG".to_string(),
+        ));
+    }
+    let restart_interval = read_u16_be(&mut buf)?;
+
+    decoder.info.restart_interval = restart_interval;
+    decoder.restart_interval = usize::from(restart_interval);
+    decoder.todo = decoder.restart_interval;
+
+    Ok(())
+}
+
+///**B.3.2 Define hierarchical progression syntax**
+///
+/// The segment has the same layout as a frame header, but describes the image at its
+/// final resolution, the frames following it carry their own component specifications.
+pub(crate) fn parse_dhp<R>(decoder: &mut Decoder, mut buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    let length = read_u16_be(&mut buf)?;
+
+    let precision = read_byte(&mut buf)?;
+
+    let height = read_u16_be(&mut buf)?;
+
+    let width = read_u16_be(&mut buf)?;
+
+    let num_components = read_byte(&mut buf)?;
+
+    if length != 8 + 3 * u16::from(num_components)
+    {
+        return Err(DecodeErrors::SofError(
+            "Bad DHP length, Corrupt JPEG".to_string(),
+        ));
+    }
+
+    decoder.check_dimensions(usize::from(width), usize::from(height))?;
+
+    if width == 0 || height == 0
+    {
+        return Err(DecodeErrors::ZeroError);
+    }
+    // skip component specifications, we use the ones in frame headers
+    buf.read_exact(&mut vec![0; 3 * usize::from(num_components)])?;
+
+    info!("Hierarchical image, final dimensions {width}x{height}");
+
+    decoder.hierarchical = Some(HierarchicalInfo {
+        width,
+        height,
+        precision,
+        components: num_components,
+    });
+
+    Ok(())
+}
+
+///**B.3.3 Expand reference components syntax**
+///
+/// Returns whether reference components should be expanded horizontally and
+/// vertically.
+pub(crate) fn parse_exp<R>(mut buf: &mut R) -> Result<(bool, bool), DecodeErrors>
+where
+    R: Read,
+{
+    if read_u16_be(&mut buf)? != 3
+    {
+        return Err(DecodeErrors::Format(
+            "Bad EXP length, Corrupt JPEG".to_string(),
+        ));
+    }
+    let expand = read_byte(&mut buf)?;
+
+    let (horizontal, vertical) = (expand >> 4, expand & 0x0F);
+
+    if horizontal > 1 || vertical > 1
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Invalid EXP expansion values Eh:{horizontal} Ev:{vertical}"
+        )));
+    }
+
+    Ok((horizontal == 1, vertical == 1))
+}
+
 /// Section:`B.2.2 Frame header syntax`
 
+#[allow(clippy::too_many_lines)]
 pub(crate) fn parse_start_of_frame<R>(
     buf: &mut R, sof: SOFMarkers, img: &mut Decoder,
 ) -> Result<(), DecodeErrors>
@@ -189,15 +388,24 @@ where
     let length = read_u16_be(&mut buf)
         .map_err(|_| DecodeErrors::Format("Cannot read SOF length, exhausted data".to_string()))?;
 
-    // usually 8, but can be 12 and 16, we currently support only 8
-    // so sorry about that 12 bit images
+    // usually 8, but extended sequential images may also be 12 bit
+    // and lossless images anything from 2 to 16 bits
     let dt_precision = read_byte(&mut buf)?;
 
-    if dt_precision != 8
+    let supported = match sof
+    {
+        _ if sof.is_lossless() => (2..=16).contains(&dt_precision),
+        SOFMarkers::ExtendedSequentialHuffman
+        | SOFMarkers::DifferentialSequentialHuffman
+        | SOFMarkers::DifferentialProgressiveHuffman => dt_precision == 8 || dt_precision == 12,
+        _ => dt_precision == 8,
+    };
+
+    if !supported
     {
         return Err(DecodeErrors::SofError(format!(
-            "The library can only parse 8-bit images, the image has {} bits of precision",
-            dt_precision
+            "The library cannot parse {:?} images with {} bits of precision",
+            sof, dt_precision
         )));
     }
 
@@ -219,17 +427,15 @@ where
     info!("Image width  :{}", img_width);
     info!("Image height :{}", img_height);
 
-    let dimensions = usize::from(img_width) * usize::from(img_height);
-    if dimensions > MAX_DIMENSIONS
-    {
-        return Err(DecodeErrors::LargeDimensions(dimensions));
-    }
+    img.check_dimensions(usize::from(img_width), usize::from(img_height))?;
 
     // Check image width or height is zero
-    if img_width == 0 || img_height == 0
+    if img_width == 0 || (img_height == 0 && img.hierarchical.is_some())
     {
         return Err(DecodeErrors::ZeroError);
     }
+    // a zero height is defined later on by a DNL segment
+    img.deferred_height = img_height == 0;
 
     // Number of components for the image.
     let num_components = read_byte(&mut buf)?;
@@ -259,21 +465,57 @@ where
 
     let mut components = Vec::with_capacity(num_components as usize);
 
-    let mut temp = [0; 3];
+    img.info.sampling_factors.clear();
+
+    let mut specs = vec![[0; 3]; usize::from(num_components)];
 
-    for _ in 0..num_components
+    for temp in &mut specs
     {
         // read 3 bytes for each component
-        buf.read_exact(&mut temp)
+        buf.read_exact(temp)
             .map_err(|x| DecodeErrors::Format(format!("Could not read component data\n{}", x)))?;
-        // create a component.
-        let component = Components::from(temp)?;
+    }
+
+    // Lossless images (e.g. DNG tiles) are free to number their components
+    // however they like, as are four component images (libjpeg numbers CMYK
+    // components 'C', 'M', 'Y' and 'K') and images stored as RGB (numbered 'R',
+    // 'G' and 'B'), so identify them by position.
+    let by_position =
+        sof.is_lossless() || num_components == 4 || specs.iter().any(|x| !(1..=3).contains(&x[0]));
+
+    for (position, temp) in (1..=num_components).zip(specs)
+    {
+        let mut component = if by_position
+        {
+            Components::from([position, temp[1], temp[2]])?
+        }
+        else
+        {
+            Components::from(temp)?
+        };
+        component.id = temp[0];
+
+        img.info
+            .sampling_factors
+            .push((temp[1] >> 4, temp[1] & 0x0f));
 
         components.push(component);
     }
 
     img.info.set_sof_marker(sof);
 
+    img.info.progressive = sof.is_progressive();
+
+    // raised to the sampling factors of the components below, a decoder may
+    // have decoded other images before
+    img.h_max = 1;
+
+    img.v_max = 1;
+
+    img.interleaved = false;
+
+    img.sub_sample_ratio = SubSampRatios::None;
+
     for component in &mut components
     {
         // compute interleaved image info
@@ -299,9 +541,19 @@ where
             // not equal to 1.
             img.interleaved = true;
         }
+        // initially stride contains its horizontal sub-sampling
+        component.width_stride *= img.mcu_x * 8;
+
+        if sof.is_lossless()
+        {
+            // lossless images do not use quantization tables
+            continue;
+        }
         // Extract quantization tables from the arrays into components
-        let qt_table = *img.qt_tables[component.quantization_table_number as usize]
-            .as_ref()
+        let qt_table = *img
+            .qt_tables
+            .get(usize::from(component.quantization_table_number))
+            .and_then(Option::as_ref)
             .ok_or_else(|| {
                 DecodeErrors::DqtError(format!(
                     "No quantization table for component {:?}",
@@ -310,13 +562,36 @@ where
             })?;
 
         component.quantization_table = Aligned32(qt_table);
-        // initially stride contains its horizontal sub-sampling
-        component.width_stride *= img.mcu_x * 8;
+    }
+
+    // four component images are up-sampled a whole plane at a time, which handles
+    // any sampling factors, see `mcu_scaled.rs`
+    if img.interleaved && !sof.is_lossless() && components.len() > 1 && components.len() != 4
+    {
+        // upsampling expects full resolution luma and chroma sampled once per MCU
+        let luma = &components[0];
+
+        let subsampled = luma.horizontal_sample == img.h_max
+            && luma.vertical_sample == img.v_max
+            && components[1..]
+                .iter()
+                .all(|x| x.horizontal_sample == 1 && x.vertical_sample == 1);
+
+        if !subsampled
+        {
+            return Err(DecodeErrors::SofError(format!(
+                "Unsupported sampling factors {:?}",
+                img.info.sampling_factors
+            )));
+        }
     }
 
     // delete quantization tables, we'll extract them from the components when
-    // needed
-    img.qt_tables = [None, None, None,None];
+    // needed, frames of hierarchical images may reuse them though
+    if img.hierarchical.is_none()
+    {
+        img.qt_tables = [None, None, None, None];
+    }
 
     img.components = components;
 
@@ -331,12 +606,13 @@ where
 {
     let mut buf = buf;
 
+    image.count_scan()?;
+
     // Scan header length
     let ls = read_u16_be(&mut buf)?;
 
     // Number of image components in scan
     let ns = read_byte(&mut buf)?;
-    image.num_scans = ns;
 
     if ls != 6 + 2 * u16::from(ns)
     {
@@ -346,14 +622,15 @@ where
     }
 
     // Check number of components.
-    // Currently ths library doesn't support images with more than 4 components
-    if !(1..4).contains(&ns)
+    // B.2.3, a scan holds at most 4 components
+    if !(1..=4).contains(&ns)
     {
         return Err(DecodeErrors::SosError(format!(
-            "Number of components in start of scan should be less than 3 but more than 0. Found {}",
+            "Number of components in start of scan should be between 1 and 4. Found {}",
             ns
         )));
     }
+    image.num_scans = ns;
 
     // consume spec parameters
     for i in 0..ns
@@ -365,23 +642,25 @@ where
         // top 4 bits contain dc huffman destination table
         // lower four bits contain ac huffman destination table
         let y = read_byte(&mut buf)?;
-        let mut j = 0;
-        while j < image.info.components
-        {
-            if image.components[j as usize].id == id
-            {
-                break;
-            }
-            j += 1;
-        }
-        image.components[usize::from(j)].dc_huff_table = usize::from((y >> 4) & 0xF);
 
-        image.components[usize::from(j)].ac_huff_table = usize::from(y & 0xF);
-        image.z_order[i as usize] = j as usize;
+        let j = image
+            .components
+            .iter()
+            .position(|x| x.id == id)
+            .ok_or_else(|| {
+                DecodeErrors::SosError(format!(
+                    "Scan refers to component {id}, which is not in the frame"
+                ))
+            })?;
+
+        image.components[j].dc_huff_table = usize::from((y >> 4) & 0xF);
+
+        image.components[j].ac_huff_table = usize::from(y & 0xF);
+        image.z_order[i as usize] = j;
     }
 
     // Collect the component spec parameters
-    if image.info.sof == SOFMarkers::ProgressiveDctHuffman
+    if image.info.sof.is_progressive()
     {
         // Extract progressive information
 
@@ -401,18 +680,52 @@ where
 
         // successive approximation bit position low
         image.succ_low = bit_approx & 0xF;
+
+        if image.spec_start > image.spec_end
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Invalid spectral selection {}..{}",
+                image.spec_start, image.spec_end
+            )));
+        }
+
+        // G.1.1.1.2, coefficients are at most 13 bits wide for 12 bit images
+        if image.succ_high > 13 || image.succ_low > 13
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Invalid successive approximation bit positions Ah={}, Al={}",
+                image.succ_high, image.succ_low
+            )));
+        }
+    }
+    else if image.info.sof.is_lossless()
+    {
+        // https://www.w3.org/Graphics/JPEG/itu-t81.pdf
+        // H.2.2, Ss holds the predictor, Se is unused and Al the point transform
+
+        // predictor selection value
+        image.spec_start = read_byte(&mut buf)?;
+
+        image.spec_end = read_byte(&mut buf)?;
+
+        let bit_approx = read_byte(&mut buf)?;
+
+        image.succ_high = bit_approx >> 4;
+
+        // point transform
+        image.succ_low = bit_approx & 0xF;
     }
     else
     {
         // ignore three bytes that contain progressive information
-        buf.consume(3);
+        skip_bytes(buf, 3)?;
     }
 
     Ok(())
 }
 
 pub(crate) fn parse_app<R>(
-    mut buf: &mut R, marker: Marker, info: &mut ImageInfo,
+    mut buf: &mut R, marker: Marker, offset: usize, decoder: &mut Decoder,
 ) -> Result<(), DecodeErrors>
 where
     R: BufRead + Read,
@@ -423,56 +736,125 @@ where
 
     match marker
     {
-        Marker::APP(0) =>
+        Marker::APP(0) if length >= 14 =>
         {
             // The only thing we need is the x and y pixel densities here
             // which are found 10 bytes away
-            buf.consume(8);
+            skip_bytes(buf, 8)?;
 
             let x_density = read_u16_be(&mut buf)?;
 
-            info.set_x(x_density);
+            decoder.info.set_x(x_density);
 
             let y_density = read_u16_be(&mut buf)?;
 
-            info.set_y(y_density);
+            decoder.info.set_y(y_density);
+
+            bytes_read += 12;
         }
-        Marker::APP(1) =>
+        Marker::APP(1) if length >= 8 =>
         {
-            if length >= 6
+            let mut buffer = [0_u8; 6];
+
+            buf.read_exact(&mut buffer)
+                .map_err(|x| DecodeErrors::Format(format!("Could not read Exif data\n{}", x)))?;
+
+            bytes_read += 6;
+
+            // https://web.archive.org/web/20190624045241if_/http://www.cipa.jp:80/std/documents/e/DC-008-Translation-2019-E.pdf
+            // 4.5.4 Basic Structure of Decoder Compressed Data
+            if &buffer == b"Exif\x00\x00"
             {
-                let mut buffer = [0_u8; 6];
+                let mut tiff = vec![0; length - bytes_read];
 
-                buf.read_exact(&mut buffer).map_err(|x| {
-                    DecodeErrors::Format(format!("Could not read Exif data\n{}", x))
-                })?;
+                buf.read_exact(&mut tiff)
+                    .map_err(|x| DecodeErrors::Format(format!("Could not read Exif data\n{x}")))?;
 
-                bytes_read += 6;
+                bytes_read = length;
 
-                // https://web.archive.org/web/20190624045241if_/http://www.cipa.jp:80/std/documents/e/DC-008-Translation-2019-E.pdf
-                // 4.5.4 Basic Structure of Decoder Compressed Data
-                if &buffer == b"Exif\x00\x00"
+                // the first Exif segment is the one that counts
+                if decoder.info.exif.is_none()
                 {
-                    buf.consume(length - bytes_read);
+                    let mut problems = vec![];
+
+                    match ExifData::parse(tiff, &mut problems)
+                    {
+                        Ok(exif) => decoder.info.exif = Some(exif),
+                        Err(err) => problems.push(format!("Ignored Exif data, {err}")),
+                    }
+                    for problem in problems
+                    {
+                        decoder.add_warning(
+                            WarningKind::InvalidExif,
+                            Some(0xE1),
+                            offset,
+                            problem,
+                        )?;
+                    }
                 }
             }
         }
+        Marker::APP(2) if length >= 16 =>
+        {
+            let mut buffer = [0_u8; 14];
+
+            buf.read_exact(&mut buffer)
+                .map_err(|x| DecodeErrors::Format(format!("Could not read ICC profile\n{x}")))?;
+
+            bytes_read += 14;
+
+            if &buffer[..12] == ICC_SIGNATURE
+            {
+                let mut data = vec![0; length - bytes_read];
+
+                buf.read_exact(&mut data).map_err(|x| {
+                    DecodeErrors::Format(format!("Could not read ICC profile\n{x}"))
+                })?;
+
+                bytes_read = length;
+
+                decoder.icc_chunks.push(IccChunk {
+                    seq: buffer[12],
+                    count: buffer[13],
+                    data,
+                    offset,
+                });
+            }
+        }
+        Marker::APP(14) if length >= 14 =>
+        {
+            // Adobe Technical Note #5116, the segment holds a version, two flags
+            // words and the color transform
+            let mut buffer = [0_u8; 12];
+
+            buf.read_exact(&mut buffer)
+                .map_err(|x| DecodeErrors::Format(format!("Could not read Adobe segment\n{x}")))?;
+
+            bytes_read += 12;
+
+            if &buffer[..5] == b"Adobe"
+            {
+                decoder.adobe_transform = Some(buffer[11]);
+            }
+        }
         _ =>
         {}
     }
+    // skip what we do not use, so it is not taken for markers
+    skip_bytes(buf, length.saturating_sub(bytes_read))?;
 
     Ok(())
 }
 
 /// Small utility function to print Un-zig-zagged quantization tables
 
-fn un_zig_zag(a: &[u8]) -> [i32; 64]
+fn un_zig_zag<T: Copy + Into<i32>>(a: &[T]) -> [i32; 64]
 {
     let mut output = [0; 64];
 
     for i in 0..64
     {
-        output[UN_ZIGZAG[i]] = i32::from(a[i]);
+        output[UN_ZIGZAG[i]] = a[i].into();
     }
 
     output
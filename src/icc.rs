@@ -0,0 +1,90 @@
+//! ICC colour profiles
+//!
+//! A segment holds at most 65533 bytes, profiles can be larger, so ICC.1 B.4 splits
+//! them into chunks, each in an APP2 segment starting with `ICC_PROFILE\0`, then a
+//! one based sequence number and the number of chunks, then the chunk itself.
+//!
+//! Chunks may be stored in any order, and other segments may sit between them, so
+//! they are collected while the headers are parsed (see `parse_app`) and put back
+//! together by sequence number once the headers are done. A profile whose chunks
+//! do not add up, e.g. one is missing or two claim the same place, is ignored with a
+//! [`WarningKind::InvalidIccProfile`](crate::errors::WarningKind::InvalidIccProfile)
+//! warning, colour managing an image with part of a profile is worse than not doing
+//! it at all.
+use crate::errors::DecodeErrors;
+use crate::Decoder;
+
+/// What APP2 segments holding ICC profile chunks start with
+pub(crate) const ICC_SIGNATURE: &[u8; 12] = b"ICC_PROFILE\0";
+
+/// A piece of an ICC profile, as found in an APP2 segment
+pub(crate) struct IccChunk
+{
+    /// Position of the chunk in the profile, starting at one
+    pub(crate) seq: u8,
+    /// Number of chunks the profile is split into
+    pub(crate) count: u8,
+    /// Bytes of the profile in this chunk
+    pub(crate) data: Vec<u8>,
+    /// Where its APP2 marker starts, for warnings about the profile
+    pub(crate) offset: usize,
+}
+
+/// Put the profile split into `chunks` back together
+///
+/// # Errors
+/// If the chunks do not make up a whole profile
+pub(crate) fn assemble_profile(chunks: &mut [IccChunk]) -> Result<Vec<u8>, DecodeErrors>
+{
+    let count = chunks[0].count;
+
+    if count == 0
+    {
+        return Err(DecodeErrors::Format(
+            "ICC profile split into zero chunks".to_string(),
+        ));
+    }
+    if let Some(chunk) = chunks.iter().find(|x| x.count != count)
+    {
+        return Err(DecodeErrors::Format(format!(
+            "ICC profile chunks disagree on their number, {} and {}",
+            count, chunk.count
+        )));
+    }
+
+    chunks.sort_unstable_by_key(|x| x.seq);
+
+    // sorted, the chunks should be numbered 1, 2, ... count
+    for (expected, chunk) in (1..=count).zip(chunks.iter())
+    {
+        if chunk.seq != expected
+        {
+            return Err(DecodeErrors::Format(format!(
+                "ICC profile chunk {expected} of {count} is missing or repeated"
+            )));
+        }
+    }
+    if chunks.len() != usize::from(count)
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Expected {count} ICC profile chunks, found {}",
+            chunks.len()
+        )));
+    }
+
+    Ok(chunks.iter().flat_map(|x| x.data.iter().copied()).collect())
+}
+
+impl Decoder
+{
+    /// Get the ICC colour profile of the image, if it has one
+    ///
+    /// Available after the headers have been decoded. Profiles split across several
+    /// APP2 segments are put back together, those with missing or repeated pieces
+    /// are ignored.
+    #[must_use]
+    pub fn icc_profile(&self) -> Option<&[u8]>
+    {
+        self.icc_profile.as_deref()
+    }
+}
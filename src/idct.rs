@@ -29,11 +29,14 @@ use crate::decoder::IDCTPtr;
 #[cfg(feature = "X86")]
 use crate::idct::avx2::dequantize_and_idct_avx2;
 use crate::idct::scalar::dequantize_and_idct_int;
+pub(crate) use crate::idct::scaled::dequantize_and_idct_scaled;
+pub(crate) use crate::idct::scalar::{dequantize_and_idct_12bit, dequantize_and_idct_precise};
 
 #[cfg(feature = "x86")]
 mod avx2;
 
 mod scalar;
+mod scaled;
 
 /// Choose an appropriate IDCT function
 
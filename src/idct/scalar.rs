@@ -45,7 +45,7 @@ pub fn dequantize_and_idct_int(
             if &vector[1..] == &[0_i16; 63]
             {
                 // okay then if you work, yaay, let's write you really quick
-                let coeff = [((vector[0] * (qt_table.0[0] as i16)) >> 3) + 128; 8];
+                let coeff = [clamp(((i32::from(vector[0]) * qt_table.0[0]) >> 3) + 128); 8];
 
                 macro_rules! store {
                     ($index:tt) => {
@@ -281,6 +281,141 @@ pub fn dequantize_and_idct_int(
     return out_vector;
 }
 
+/// Perform Integer IDCT on a single block of a 12 bit image
+/// and level shift (by adding 2048 to each element)
+///
+/// # Arguments
+/// - block: Coefficients of the block in natural order
+/// - `qt_table`: The quantization table for the block
+/// - output: Where to write the 8 rows of 8 samples
+/// - stride: Distance between two rows in `output`
+#[allow(clippy::cast_possible_truncation)]
+pub fn dequantize_and_idct_12bit(
+    block: &[i16; 64], qt_table: &Aligned32<[i32; 64]>, output: &mut [i16], stride: usize,
+)
+{
+    let samples = dequantize_and_idct_precise(block, qt_table);
+
+    for (row, out) in samples.chunks_exact(8).zip(output.chunks_mut(stride))
+    {
+        for (out, value) in out[..8].iter_mut().zip(row.iter())
+        {
+            *out = (value + 2048).clamp(0, 4095) as i16;
+        }
+    }
+}
+
+/// Perform Integer IDCT on a single block without level shifting or clamping
+/// the results
+///
+/// This is a port of the accurate integer IDCT (`jpeg_idct_islow`) found in libjpeg,
+/// using the constants libjpeg uses for 12 bit samples, the 8 bit routine above
+/// assumes coefficients and intermediate values fit in ranges only valid for
+/// 8 bit samples.
+///
+/// Returns the 64 samples of the block in natural order
+#[rustfmt::skip]
+#[allow(clippy::cast_possible_truncation, clippy::similar_names)]
+pub fn dequantize_and_idct_precise(block: &[i16; 64], qt_table: &Aligned32<[i32; 64]>) -> [i32; 64]
+{
+    const CONST_BITS: i64 = 13;
+
+    const PASS1_BITS: i64 = 1;
+
+    /// Carry out a one dimensional IDCT on 8 values, returning them in
+    /// natural order without descaling
+    #[inline]
+    fn idct_1d(v: [i64; 8]) -> [i64; 8]
+    {
+        // Even part
+        let z1 = (v[2] + v[6]) * 4433;
+
+        let tmp2 = z1 + v[6] * -15137;
+
+        let tmp3 = z1 + v[2] * 6270;
+
+        let tmp0 = (v[0] + v[4]) << CONST_BITS;
+
+        let tmp1 = (v[0] - v[4]) << CONST_BITS;
+
+        let tmp10 = tmp0 + tmp3;
+
+        let tmp13 = tmp0 - tmp3;
+
+        let tmp11 = tmp1 + tmp2;
+
+        let tmp12 = tmp1 - tmp2;
+
+        // Odd part
+        let (mut tmp0, mut tmp1, mut tmp2, mut tmp3) = (v[7], v[5], v[3], v[1]);
+
+        let z5 = (tmp0 + tmp1 + tmp2 + tmp3) * 9633;
+
+        let z1 = (tmp0 + tmp3) * -7373;
+
+        let z2 = (tmp1 + tmp2) * -20995;
+
+        let z3 = (tmp0 + tmp2) * -16069 + z5;
+
+        let z4 = (tmp1 + tmp3) * -3196 + z5;
+
+        tmp0 = tmp0 * 2446 + z1 + z3;
+
+        tmp1 = tmp1 * 16819 + z2 + z4;
+
+        tmp2 = tmp2 * 25172 + z2 + z3;
+
+        tmp3 = tmp3 * 12299 + z1 + z4;
+
+        [
+            tmp10 + tmp3, tmp11 + tmp2, tmp12 + tmp1, tmp13 + tmp0,
+            tmp13 - tmp0, tmp12 - tmp1, tmp11 - tmp2, tmp10 - tmp3,
+        ]
+    }
+
+    /// Equivalent of libjpeg's `DESCALE`, a right shift with rounding
+    #[inline]
+    const fn descale(x: i64, n: i64) -> i64
+    {
+        (x + (1 << (n - 1))) >> n
+    }
+
+    let mut workspace = [0_i64; 64];
+
+    // Pass 1: process columns from input, store into work array.
+    for column in 0..8
+    {
+        let mut values = [0; 8];
+
+        for (row, value) in values.iter_mut().enumerate()
+        {
+            let pos = row * 8 + column;
+
+            *value = i64::from(block[pos]) * i64::from(qt_table.0[pos]);
+        }
+        let values = idct_1d(values);
+
+        for (row, value) in values.iter().enumerate()
+        {
+            workspace[row * 8 + column] = descale(*value, CONST_BITS - PASS1_BITS);
+        }
+    }
+
+    let mut output = [0; 64];
+
+    // Pass 2: process rows from work array, store into output array.
+    for (row, out) in workspace.chunks_exact(8).zip(output.chunks_exact_mut(8))
+    {
+        let values = idct_1d(row.try_into().unwrap());
+
+        for (out, value) in out.iter_mut().zip(values.iter())
+        {
+            *out = descale(*value, CONST_BITS + PASS1_BITS + 3) as i32;
+        }
+    }
+    output
+}
+
 #[inline]
 #[allow(clippy::cast_possible_truncation)]
 /// Multiply a number by 4096
@@ -0,0 +1,172 @@
+//! Reduced size IDCT routines
+//!
+//! These produce 4x4, 2x2 or 1x1 samples from a block of 8x8 coefficients by only
+//! using the low frequency coefficients, which is the same as carrying out a full IDCT
+//! and scaling the block down, at a fraction of the cost.
+//!
+//! This is a port of `jidctred.c` found in libjpeg.
+#![allow(clippy::cast_possible_truncation, clippy::similar_names)]
+
+use crate::idct::dequantize_and_idct_precise;
+use crate::misc::Aligned32;
+
+const CONST_BITS: i64 = 13;
+
+const PASS1_BITS: i64 = 2;
+
+const FIX_0_211164243: i64 = 1730;
+const FIX_0_509795579: i64 = 4176;
+const FIX_0_601344887: i64 = 4926;
+const FIX_0_720959822: i64 = 5906;
+const FIX_0_765366865: i64 = 6270;
+const FIX_0_850430095: i64 = 6967;
+const FIX_0_899976223: i64 = 7373;
+const FIX_1_061594337: i64 = 8697;
+const FIX_1_272758580: i64 = 10426;
+const FIX_1_451774981: i64 = 11893;
+const FIX_1_847759065: i64 = 15137;
+const FIX_2_172734803: i64 = 17799;
+const FIX_2_562915447: i64 = 20995;
+const FIX_3_624509785: i64 = 29692;
+
+/// Divide by `2^n`, rounding to the nearest value
+#[inline]
+fn descale(x: i64, n: i64) -> i64
+{
+    (x + (1 << (n - 1))) >> n
+}
+
+/// One dimensional IDCT producing 4 values, scaled up by `2^(CONST_BITS+1)`
+#[inline]
+fn idct_1d_4(v: &[i64; 8]) -> [i64; 4]
+{
+    // Even part
+    let tmp0 = v[0] << (CONST_BITS + 1);
+
+    let tmp2 = v[2] * FIX_1_847759065 - v[6] * FIX_0_765366865;
+
+    let tmp10 = tmp0 + tmp2;
+
+    let tmp12 = tmp0 - tmp2;
+
+    // Odd part
+    let (z1, z2, z3, z4) = (v[7], v[5], v[3], v[1]);
+
+    let tmp0 =
+        -z1 * FIX_0_211164243 + z2 * FIX_1_451774981 - z3 * FIX_2_172734803 + z4 * FIX_1_061594337;
+
+    let tmp2 =
+        -z1 * FIX_0_509795579 - z2 * FIX_0_601344887 + z3 * FIX_0_899976223 + z4 * FIX_2_562915447;
+
+    [tmp10 + tmp2, tmp12 + tmp0, tmp12 - tmp0, tmp10 - tmp2]
+}
+
+/// One dimensional IDCT producing 2 values, scaled up by `2^(CONST_BITS+2)`
+#[inline]
+fn idct_1d_2(v: &[i64; 8]) -> [i64; 2]
+{
+    // Even part
+    let tmp10 = v[0] << (CONST_BITS + 2);
+
+    // Odd part
+    let tmp0 = -v[7] * FIX_0_720959822 + v[5] * FIX_0_850430095 - v[3] * FIX_1_272758580
+        + v[1] * FIX_3_624509785;
+
+    [tmp10 + tmp0, tmp10 - tmp0]
+}
+
+/// Perform a reduced size IDCT on a single block and level shift the results
+///
+/// # Arguments
+/// - block: Coefficients of the block in natural order
+/// - `qt_table`: The quantization table for the block
+/// - size: Width and height of the output, one of 1, 2, 4 or 8
+/// - output: Where to write the `size` rows of `size` samples
+/// - stride: Distance between two rows in `output`
+/// - `max_sample`: Largest value a sample can take, 255 for 8 bit images
+pub fn dequantize_and_idct_scaled(
+    block: &[i16; 64], qt_table: &Aligned32<[i32; 64]>, size: usize, output: &mut [i16],
+    stride: usize, max_sample: i32,
+)
+{
+    let center = i64::from(max_sample + 1) / 2;
+
+    let max_sample = i64::from(max_sample);
+
+    let mut samples = [0_i64; 64];
+
+    if size == 8
+    {
+        // full size, used for sub-sampled components of scaled images
+        for (sample, value) in samples
+            .iter_mut()
+            .zip(dequantize_and_idct_precise(block, qt_table))
+        {
+            *sample = i64::from(value);
+        }
+    }
+    else if size == 1
+    {
+        // the DC coefficient is the average of the block
+        samples[0] = descale(i64::from(block[0]) * i64::from(qt_table.0[0]), 3);
+    }
+    else
+    {
+        // extra bits the 1D routines scale their results up by
+        let shift = if size == 4 { 1 } else { 2 };
+
+        let idct_1d = |v: &[i64; 8], out: &mut [i64]| {
+            if size == 4
+            {
+                out.copy_from_slice(&idct_1d_4(v));
+            }
+            else
+            {
+                out.copy_from_slice(&idct_1d_2(v));
+            }
+        };
+
+        // Pass 1: process columns, storing the first `size` rows of each
+        let mut workspace = [0_i64; 64];
+
+        let mut column = [0_i64; 8];
+
+        let mut out = [0_i64; 4];
+
+        for x in 0..8
+        {
+            for (y, value) in column.iter_mut().enumerate()
+            {
+                *value = i64::from(block[y * 8 + x]) * i64::from(qt_table.0[y * 8 + x]);
+            }
+            idct_1d(&column, &mut out[..size]);
+
+            for (y, value) in out[..size].iter().enumerate()
+            {
+                workspace[y * 8 + x] = descale(*value, CONST_BITS - PASS1_BITS + shift);
+            }
+        }
+        // Pass 2: process the rows we kept
+        for y in 0..size
+        {
+            let row: &[i64; 8] = workspace[y * 8..y * 8 + 8].try_into().unwrap();
+
+            idct_1d(row, &mut out[..size]);
+
+            for (x, value) in out[..size].iter().enumerate()
+            {
+                samples[y * size + x] = descale(*value, CONST_BITS + PASS1_BITS + 3 + shift);
+            }
+        }
+    }
+
+    for (row, out) in samples[..size * size]
+        .chunks_exact(size)
+        .zip(output.chunks_mut(stride))
+    {
+        for (out, value) in out[..size].iter_mut().zip(row.iter())
+        {
+            *out = (value + center).clamp(0, max_sample) as i16;
+        }
+    }
+}
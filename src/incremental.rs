@@ -0,0 +1,686 @@
+//! Decoding images as their data arrives
+//!
+//! Network clients receive images a chunk at a time, [`IncrementalDecoder`] is given
+//! those chunks as they come in, keeps the data it has not decoded yet, and decodes as
+//! much of the image as that data allows every time it is called.
+//!
+//! - Headers are parsed once every segment up to and including the first SOS has
+//!   arrived, so they are never parsed twice.
+//! - Baseline images are decoded an MCU row at a time, like [`ScanlineReader`]. We
+//!   can't tell how many bytes an MCU row is coded in before decoding it, so the
+//!   bitstream, DC predictions and restart count are saved before every row and
+//!   restored if decoding ran past the end of the data, the row is then decoded again
+//!   once more data has arrived.
+//! - Huffman coded progressive images are decoded a scan at a time, once the marker
+//!   ending the scan has arrived, and previews can be rendered after every scan like
+//!   [`ProgressiveReader`] renders them.
+//! - Other images are decoded once their EOI marker has arrived.
+//!
+//! Markers are found by walking the segments in the data, skipping them by their
+//! lengths, so bytes that look like markers inside a segment are never mistaken for
+//! one.
+//!
+//! [`ScanlineReader`]: crate::ScanlineReader
+//! [`ProgressiveReader`]: crate::ProgressiveReader
+use std::io::Cursor;
+
+use crate::bitstream::BitStream;
+use crate::errors::{DecodeErrors, DecodeWarning, WarningKind};
+use crate::marker::Marker;
+use crate::mcu::BaselineLayout;
+use crate::progressive::ProgressiveScans;
+use crate::worker::post_process;
+use crate::{Decoder, ImageInfo};
+
+/// What an [`IncrementalDecoder`] did with the data it holds
+#[derive(Debug, PartialEq, Eq)]
+pub enum FeedStatus<'a>
+{
+    /// Everything the data given so far allows has been decoded, feed more
+    NeedMoreData,
+    /// The headers have been parsed, [`info`](IncrementalDecoder::info) describes
+    /// the image
+    ///
+    /// The height of images which define it in a DNL segment is only known once
+    /// their first scan has arrived.
+    Headers,
+    /// The next rows of the image, laid out as `decode_buffer` lays them out
+    ///
+    /// Concatenating every batch of rows gives the decoded image, images which are
+    /// not decoded an MCU row at a time come in a single batch.
+    Rows(&'a [u8]),
+    /// A scan of a progressive image has been decoded, see
+    /// [`preview`](IncrementalDecoder::preview)
+    Scan,
+    /// Every row of the image has been returned
+    Finished,
+}
+
+/// Where the decoder is in the image
+enum State
+{
+    /// Waiting for the headers
+    Headers,
+    /// Decoding a baseline image an MCU row at a time
+    Rows
+    {
+        layout: BaselineLayout,
+        stream: BitStream,
+        /// Rows of MCUs still to be decoded
+        mcu_rows: usize,
+    },
+    /// Decoding a progressive image a scan at a time
+    Scans(Box<ProgressiveScans>),
+    /// Waiting for the whole image
+    Whole,
+    /// Returning an image decoded whole
+    Decoded,
+    /// Decoding failed, the decoder may be halfway through updating its state
+    Failed,
+}
+
+/// What decoding did, the [`FeedStatus`] returned without borrowing the pixels
+enum Progress
+{
+    NeedMoreData,
+    Headers,
+    /// The first bytes of `pixels` are the next rows
+    Rows(usize),
+    Scan,
+    Finished,
+}
+
+/// Decodes an image from data given to it a chunk at a time
+///
+/// Every call to [`feed`](Self::feed) adds data and decodes as far as it can, returning
+/// one [`FeedStatus`] at a time. Call it with an empty slice to get the next one until
+/// it asks for more data, and call [`finish`](Self::finish) once the data has ended.
+///
+/// # Example
+/// ```no_run
+/// use zune_jpeg::{Decoder, FeedStatus, IncrementalDecoder};
+///
+/// let data = std::fs::read("image.jpg").unwrap();
+///
+/// let mut decoder = IncrementalDecoder::new(Decoder::new());
+///
+/// let mut pixels = vec![];
+///
+/// for chunk in data.chunks(4096)
+/// {
+///     let mut status = decoder.feed(chunk).unwrap();
+///
+///     while status != FeedStatus::NeedMoreData
+///     {
+///         if let FeedStatus::Rows(rows) = status
+///         {
+///             pixels.extend_from_slice(rows);
+///         }
+///         status = decoder.feed(&[]).unwrap();
+///     }
+/// }
+/// ```
+pub struct IncrementalDecoder
+{
+    decoder: Decoder,
+    /// Data received, decoding continues from `position`
+    data: Vec<u8>,
+    position: usize,
+    /// Bytes decoded and dropped from the start of `data`
+    discarded: usize,
+    state: State,
+    /// No more data will arrive
+    end_of_input: bool,
+    block_smoothing: bool,
+    /// The last batch of rows, the last preview, or the whole image
+    pixels: Vec<u8>,
+    /// Bytes in a batch of rows of an image decoded an MCU row at a time
+    batch_size: usize,
+    /// Bytes of the image not yet returned
+    remaining: usize,
+}
+
+impl IncrementalDecoder
+{
+    /// Create a decoder which decodes an image with the options set on `decoder`
+    #[must_use]
+    pub fn new(decoder: Decoder) -> IncrementalDecoder
+    {
+        IncrementalDecoder {
+            decoder,
+            data: vec![],
+            position: 0,
+            discarded: 0,
+            state: State::Headers,
+            end_of_input: false,
+            block_smoothing: false,
+            pixels: vec![],
+            batch_size: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Add the next chunk of the image and decode as far as the data allows
+    ///
+    /// This stops at every [`FeedStatus`] other than `NeedMoreData`, call it again
+    /// with an empty chunk to carry on decoding.
+    ///
+    /// # Errors
+    /// If the image is not a valid jpeg image, every call after an error fails too
+    pub fn feed(&mut self, data: &[u8]) -> Result<FeedStatus<'_>, DecodeErrors>
+    {
+        self.data.extend_from_slice(data);
+
+        let progress = match self.state
+        {
+            State::Headers => self.parse_headers(),
+            State::Rows { .. } => self.decode_row(),
+            State::Scans(_) => self.decode_scan(),
+            State::Whole => self.decode_whole(),
+            State::Decoded => Ok(self.decoded_rows()),
+            State::Failed =>
+            {
+                return Err(DecodeErrors::Format(
+                    "Image failed to decode on an earlier call".to_string(),
+                ))
+            }
+        };
+
+        let progress = match progress
+        {
+            Ok(progress) => progress,
+            Err(error) =>
+            {
+                self.state = State::Failed;
+
+                return Err(error);
+            }
+        };
+
+        Ok(match progress
+        {
+            Progress::NeedMoreData => FeedStatus::NeedMoreData,
+            Progress::Headers => FeedStatus::Headers,
+            Progress::Rows(length) => FeedStatus::Rows(&self.pixels[..length]),
+            Progress::Scan => FeedStatus::Scan,
+            Progress::Finished => FeedStatus::Finished,
+        })
+    }
+
+    /// Tell the decoder no more data will arrive and carry on decoding
+    ///
+    /// Call this until it returns [`FeedStatus::Finished`]. Truncated images are
+    /// decoded as `decode_buffer` would decode them, except that scans of a
+    /// progressive image that did not arrive whole are left out.
+    ///
+    /// # Errors
+    /// If the image is not a valid jpeg image, or its headers are incomplete
+    pub fn finish(&mut self) -> Result<FeedStatus<'_>, DecodeErrors>
+    {
+        self.end_of_input = true;
+
+        self.feed(&[])
+    }
+
+    /// Render the image from the scans of a progressive image decoded so far
+    ///
+    /// Returns `None` for images which are not decoded a scan at a time, before the
+    /// first scan has been decoded, and once the decoded image has been returned as
+    /// rows.
+    pub fn preview(&mut self) -> Option<&[u8]>
+    {
+        let State::Scans(scans) = &mut self.state
+        else
+        {
+            return None;
+        };
+
+        if scans.scans == 0
+        {
+            return None;
+        }
+        scans.render(&mut self.decoder, &mut self.pixels);
+
+        Some(&self.pixels)
+    }
+
+    /// Estimate AC coefficients not decoded yet in previews, like
+    /// [`ProgressiveReader::set_block_smoothing`](crate::ProgressiveReader::set_block_smoothing)
+    ///
+    /// Off by default.
+    pub fn set_block_smoothing(&mut self, smoothing: bool)
+    {
+        self.block_smoothing = smoothing;
+
+        if let State::Scans(scans) = &mut self.state
+        {
+            scans.block_smoothing = smoothing;
+        }
+    }
+
+    /// Information about the image, once its headers have been parsed
+    #[must_use]
+    pub fn info(&self) -> Option<ImageInfo>
+    {
+        match self.state
+        {
+            State::Headers => None,
+            _ => Some(self.decoder.info.clone()),
+        }
+    }
+
+    /// Problems with the image found so far, see [`Decoder::warnings`]
+    #[must_use]
+    pub fn warnings(&self) -> &[DecodeWarning]
+    {
+        self.decoder.warnings()
+    }
+
+    /// Parse the headers if they have all arrived, and choose how to decode the image
+    fn parse_headers(&mut self) -> Result<Progress, DecodeErrors>
+    {
+        // fail on data that is not a jpeg image rather than wait for headers
+        let is_jpeg = self.data.len() < 2 || self.data[..2] == [0xFF, 0xD8];
+
+        let end = match headers_end(&self.data)
+        {
+            Some(end) => end,
+            None if is_jpeg && !self.end_of_input => return Ok(Progress::NeedMoreData),
+            None => self.data.len(),
+        };
+
+        let mut reader = Cursor::new(&self.data[..end]);
+
+        self.decoder.decode_headers(&mut reader)?;
+
+        self.position = usize::try_from(reader.position()).unwrap_or(end);
+
+        let decoder = &mut self.decoder;
+
+        if !decoder.deferred_height
+        {
+            decoder.check_crop()?;
+
+            decoder.check_colorspace()?;
+
+            decoder.check_memory()?;
+        }
+
+        self.state = if decoder.deferred_height
+        {
+            State::Whole
+        }
+        else if decoder.decodes_by_mcu_rows()
+        {
+            let layout = decoder.baseline_layout()?;
+
+            let output_size = decoder.output_size();
+
+            let (_, height) = decoder.decoded_dimensions();
+
+            let row_size = output_size / height;
+
+            // the same number of rows the baseline decoder writes at a time
+            self.batch_size = row_size * 8 * decoder.h_max * decoder.v_max;
+
+            self.remaining = output_size;
+
+            self.pixels = vec![0; layout.chunk_size];
+
+            State::Rows {
+                mcu_rows: layout.mcu_height,
+                layout,
+                stream: BitStream::new(),
+            }
+        }
+        else if decoder.renders_progressive_scans()
+        {
+            decoder.set_upsampling()?;
+
+            let mut scans = ProgressiveScans::new(decoder);
+
+            scans.block_smoothing = self.block_smoothing;
+
+            State::Scans(Box::new(scans))
+        }
+        else
+        {
+            State::Whole
+        };
+
+        Ok(Progress::Headers)
+    }
+
+    /// Decode the next MCU row of a baseline image
+    fn decode_row(&mut self) -> Result<Progress, DecodeErrors>
+    {
+        let State::Rows {
+            layout,
+            stream,
+            mcu_rows,
+        } = &mut self.state
+        else
+        {
+            unreachable!();
+        };
+
+        if self.remaining == 0
+        {
+            return Ok(Progress::Finished);
+        }
+        let length = self.batch_size.min(self.remaining);
+
+        let decoder = &mut self.decoder;
+
+        // saved to decode the row again if its data has not all arrived
+        let saved_stream = stream.clone();
+
+        let saved_predictions: Vec<i32> = decoder.components.iter().map(|x| x.dc_pred).collect();
+
+        let saved_todo = decoder.todo;
+
+        let saved_warnings = decoder.warnings.len();
+
+        let data = &self.data[self.position..];
+
+        let mut reader = Cursor::new(data);
+
+        decoder.data_offset = self.discarded + self.position;
+
+        let result = decoder.decode_baseline_row(layout, &mut reader, stream, *mcu_rows == 1);
+
+        // the bitstream makes up zeroes past the end of the data
+        let used = usize::try_from(reader.position()).unwrap_or(usize::MAX);
+
+        // lenient decoders give up on data which has not arrived yet
+        let missing = decoder.warnings[saved_warnings..]
+            .iter()
+            .any(|x| x.kind == WarningKind::MissingData)
+            || matches!(&result, Err(DecodeErrors::Warning(x)) if x.kind == WarningKind::MissingData);
+
+        if (used > data.len() || missing) && !self.end_of_input
+        {
+            *stream = saved_stream;
+
+            for (component, prediction) in decoder.components.iter_mut().zip(saved_predictions)
+            {
+                component.dc_pred = prediction;
+            }
+            decoder.todo = saved_todo;
+
+            decoder.warnings.truncate(saved_warnings);
+
+            return Ok(Progress::NeedMoreData);
+        }
+        let mut coefficients = result?;
+
+        self.position += used.min(data.len());
+
+        // discard what has been decoded
+        self.data.drain(..self.position);
+
+        self.discarded += self.position;
+
+        self.position = 0;
+
+        post_process(
+            &mut coefficients,
+            &decoder.components,
+            decoder.idct_func,
+            decoder.color_convert_16,
+            decoder.input_colorspace,
+            decoder.output_colorspace,
+            &mut self.pixels,
+            usize::from(decoder.info.width),
+        );
+
+        *mcu_rows -= 1;
+        self.remaining -= length;
+
+        Ok(Progress::Rows(length))
+    }
+
+    /// Decode the next scan of a progressive image if it has all arrived
+    fn decode_scan(&mut self) -> Result<Progress, DecodeErrors>
+    {
+        let State::Scans(scans) = &mut self.state
+        else
+        {
+            unreachable!();
+        };
+
+        let data = &self.data[self.position..];
+
+        let complete = holds_next_scan(data, scans.scans, scans.marker);
+
+        if !complete && !self.end_of_input
+        {
+            return Ok(Progress::NeedMoreData);
+        }
+
+        // scans that did not arrive whole are left out
+        if complete
+        {
+            let mut reader = Cursor::new(data);
+
+            self.decoder.data_offset = self.discarded + self.position;
+
+            let decoded = scans.next_scan(&mut self.decoder, &mut reader)?;
+
+            self.position += usize::try_from(reader.position())
+                .unwrap_or(usize::MAX)
+                .min(data.len());
+
+            // discard what has been decoded
+            self.data.drain(..self.position);
+
+            self.discarded += self.position;
+
+            self.position = 0;
+
+            if decoded
+            {
+                return Ok(Progress::Scan);
+            }
+        }
+        scans.render(&mut self.decoder, &mut self.pixels);
+
+        self.remaining = self.pixels.len();
+
+        self.state = State::Decoded;
+
+        Ok(self.decoded_rows())
+    }
+
+    /// Decode the image once it has all arrived
+    fn decode_whole(&mut self) -> Result<Progress, DecodeErrors>
+    {
+        if !self.end_of_input && !holds_eoi(&self.data[self.position..])
+        {
+            return Ok(Progress::NeedMoreData);
+        }
+        let mut reader = Cursor::new(&self.data[..]);
+
+        reader.set_position(self.position as u64);
+
+        self.decoder.data_offset = self.discarded;
+
+        self.decoder.resolve_deferred_height(&mut reader)?;
+
+        self.decoder.check_crop()?;
+
+        self.decoder.check_colorspace()?;
+
+        self.decoder.check_memory()?;
+
+        self.pixels = self.decoder.decode_scans_unturned(&mut reader)?;
+
+        self.data = vec![];
+
+        self.position = 0;
+
+        self.remaining = self.pixels.len();
+
+        self.state = State::Decoded;
+
+        Ok(self.decoded_rows())
+    }
+
+    /// Return the rows of an image decoded whole, if they have not been returned
+    fn decoded_rows(&mut self) -> Progress
+    {
+        if self.remaining == 0
+        {
+            return Progress::Finished;
+        }
+        self.remaining = 0;
+
+        Progress::Rows(self.pixels.len())
+    }
+}
+
+/// Find the next marker at or after `position`, skipping entropy coded data
+///
+/// Returns the byte identifying the marker and the position after it.
+fn find_marker(data: &[u8], position: usize) -> Option<(u8, usize)>
+{
+    let mut position = position;
+
+    while let Some(offset) = data.get(position..)?.iter().position(|x| *x == 0xFF)
+    {
+        position += offset + 1;
+
+        // markers may be preceded by any number of fill bytes
+        while data.get(position) == Some(&0xFF)
+        {
+            position += 1;
+        }
+
+        match data.get(position)
+        {
+            // stuffed zero byte or a restart marker, we are still inside a scan
+            Some(0x00 | 0xD0..=0xD7) => position += 1,
+            Some(marker) => return Some((*marker, position + 1)),
+            None => return None,
+        }
+    }
+
+    None
+}
+
+/// Whether a marker is followed by a segment with a length
+fn has_segment(marker: u8) -> bool
+{
+    // TEM, restart markers, SOI and EOI stand alone
+    !matches!(marker, 0x01 | 0xD0..=0xD9)
+}
+
+/// Position after the segment whose length is at `position`, if it has all arrived
+fn segment_end(data: &[u8], position: usize) -> Option<usize>
+{
+    let length = data.get(position..position + 2)?;
+
+    let end = position + usize::from(u16::from_be_bytes([length[0], length[1]]));
+
+    (end <= data.len()).then_some(end)
+}
+
+/// Position after the first SOS segment, if the headers have all arrived
+fn headers_end(data: &[u8]) -> Option<usize>
+{
+    // skip SOI
+    let mut position = 2;
+
+    loop
+    {
+        let (marker, start) = find_marker(data, position)?;
+
+        position = if has_segment(marker)
+        {
+            segment_end(data, start)?
+        }
+        else
+        {
+            start
+        };
+
+        if marker == 0xDA
+        {
+            return Some(position);
+        }
+    }
+}
+
+/// Whether the EOI marker is in `data`, which starts inside a scan
+fn holds_eoi(data: &[u8]) -> bool
+{
+    let mut position = 0;
+
+    while let Some((marker, start)) = find_marker(data, position)
+    {
+        if marker == 0xD9
+        {
+            return true;
+        }
+        if !has_segment(marker)
+        {
+            position = start;
+        }
+        else if let Some(end) = segment_end(data, start)
+        {
+            position = end;
+        }
+        else
+        {
+            return false;
+        }
+    }
+    false
+}
+
+/// Whether `data` holds the whole of the next scan of a progressive image
+///
+/// `marker` is the marker the previous scan stopped at, `data` starting after it.
+/// The header of the first scan is parsed with the other headers, so `data` starts
+/// with its entropy coded data.
+fn holds_next_scan(data: &[u8], scans: usize, marker: Option<Marker>) -> bool
+{
+    if scans == 0
+    {
+        // the scan ends at the first marker
+        return find_marker(data, 0).is_some();
+    }
+    let mut marker = marker;
+
+    let mut position = 0;
+
+    loop
+    {
+        let next = match marker
+        {
+            Some(Marker::SOS) =>
+            {
+                return segment_end(data, position)
+                    .and_then(|end| find_marker(data, end))
+                    .is_some();
+            }
+            // segments `decode_next_progressive_scan` parses before the next scan
+            Some(Marker::DHT | Marker::DNL) =>
+            {
+                segment_end(data, position).and_then(|end| find_marker(data, end))
+            }
+            // decoding stops at any other marker
+            _ => return true,
+        };
+
+        let Some((next, end)) = next
+        else
+        {
+            return false;
+        };
+
+        marker = Marker::from_u8(next);
+
+        position = end;
+    }
+}
@@ -0,0 +1,253 @@
+//! Decoding truncated and corrupt images
+//!
+//! Images cut short by an interrupted upload, or with a few bytes of their entropy
+//! coded data flipped, fail to decode in strict mode, which is the default, or
+//! decode to garbage past the damage. In lenient mode the decoder conceals such
+//! errors the way libjpeg does, and returns what it could decode along with a list
+//! of the problems it ran into, see
+//! [`DecodeWarning`](crate::errors::DecodeWarning).
+//!
+//! Once an MCU fails to decode, or runs past the end of its data (the bitstream
+//! makes up zeroes past the end, see `BitStream::overread`), the bitstream loses
+//! sync, and no MCU is decoded until the next restart marker, where decoding
+//! resumes with the DC predictions reset. MCUs which are not decoded keep the
+//! coefficients they had, zeroes for baseline images, which are gray, and those of
+//! earlier scans for progressive images.
+//!
+//! Restart markers are numbered modulo 8, so when one is lost with the data around
+//! it, the next one tells how many restart intervals were lost, see
+//! `resync_to_restart`. A progressive image whose data ends between scans, or has a
+//! corrupt segment between them, is rendered from the scans before that.
+//!
+//! Lenient mode applies to Huffman coded baseline and progressive images decoded at
+//! their full size, other images fail to decode as in strict mode.
+use std::io::Cursor;
+
+use crate::bitstream::BitStream;
+use crate::errors::{DecodeErrors, WarningKind};
+use crate::marker::Marker;
+use crate::mcu_prog::get_marker;
+use crate::Decoder;
+
+impl Decoder
+{
+    /// Set whether corrupt and truncated images fail to decode
+    ///
+    /// Strict mode is the default. In lenient mode, entropy coded data which cannot
+    /// be decoded is skipped up to the next restart marker, missing data is made up
+    /// with zeroes, and MCUs which could not be decoded are left gray, or as earlier
+    /// scans of a progressive image left them. The image is then returned as if
+    /// nothing went wrong, [`warnings`](Self::warnings) lists what was concealed.
+    ///
+    /// This applies to Huffman coded baseline and progressive images decoded at their
+    /// full size, errors in headers, and limits set via
+    /// [`set_options`](Self::set_options), fail in both modes.
+    pub fn set_strict_mode(&mut self, strict: bool)
+    {
+        self.strict = strict;
+    }
+
+    /// Get whether corrupt and truncated images fail to decode
+    #[must_use]
+    pub const fn get_strict_mode(&self) -> bool
+    {
+        self.strict
+    }
+
+    /// Handle the end of a restart interval of a baseline or progressive image
+    ///
+    /// Strict decoders expect the restart marker ending it, see `handle_rst`, lenient
+    /// ones resynchronize on the next restart marker, see `resync_to_restart`.
+    pub(crate) fn start_restart_interval(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream,
+    ) -> Result<(), DecodeErrors>
+    {
+        if self.strict
+        {
+            return self.handle_rst(reader, stream);
+        }
+        self.reset_restart_count();
+
+        self.resync_to_restart(reader, stream)
+    }
+
+    /// Handle the result of decoding an MCU
+    ///
+    /// Strict decoders fail with the error. Lenient ones record it, or record that
+    /// the MCU ran past the end of the data, and leave out MCUs up to the next
+    /// restart marker.
+    pub(crate) fn check_decoded_mcu(
+        &mut self, reader: &Cursor<&[u8]>, stream: &mut BitStream,
+        decoded: Result<(), DecodeErrors>,
+    ) -> Result<(), DecodeErrors>
+    {
+        if self.strict
+        {
+            return decoded;
+        }
+
+        if let Err(error) = decoded
+        {
+            let reason = format!("Corrupt entropy coded data: {error}");
+
+            self.lose_sync(
+                stream,
+                WarningKind::CorruptData,
+                None,
+                position(reader),
+                reason,
+            )?;
+        }
+        else if stream.overread(reader)
+        {
+            let reason = "Entropy coded data ended early".to_string();
+
+            self.lose_sync(
+                stream,
+                WarningKind::MissingData,
+                None,
+                position(reader),
+                reason,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Handle a segment between the scans of a progressive image which could not be
+    /// parsed
+    ///
+    /// Strict decoders fail with the error, lenient ones record it and return
+    /// `false`, so the image is rendered from the scans decoded so far. Limits on the
+    /// number of scans are enforced in both modes.
+    pub(crate) fn skip_remaining_scans(
+        &mut self, reader: &Cursor<&[u8]>, marker: Marker, error: DecodeErrors,
+    ) -> Result<bool, DecodeErrors>
+    {
+        if self.strict || matches!(error, DecodeErrors::TooManyScans(_))
+        {
+            return Err(error);
+        }
+        self.add_warning(
+            WarningKind::SkippedScans,
+            Some(marker.to_u8()),
+            position(reader),
+            format!("Skipped the remaining scans: {error}"),
+        )?;
+
+        Ok(false)
+    }
+
+    /// Stop decoding MCUs until the next restart marker
+    fn lose_sync(
+        &mut self, stream: &mut BitStream, kind: WarningKind, marker: Option<u8>, offset: usize,
+        reason: String,
+    ) -> Result<(), DecodeErrors>
+    {
+        self.add_warning(
+            kind,
+            marker,
+            offset,
+            reason + ", skipped to the next restart marker",
+        )?;
+
+        stream.lost_sync = true;
+
+        Ok(())
+    }
+
+    /// Find the restart marker ending a restart interval in lenient mode
+    ///
+    /// This follows libjpeg's `jpeg_resync_to_restart`. Invalid markers in corrupt
+    /// data and restart markers we have passed are skipped. A restart marker one or
+    /// two numbers ahead of the expected one means the ones before it were lost, so
+    /// it is kept for later and this restart interval is left out. Any other restart
+    /// marker is taken to be the expected one, and any other marker, or the end of
+    /// the data, ends the scan.
+    pub(crate) fn resync_to_restart(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream,
+    ) -> Result<(), DecodeErrors>
+    {
+        loop
+        {
+            let marker = get_marker(reader, stream);
+
+            // where the marker starts
+            let offset = position(reader).saturating_sub(2);
+
+            let Some(Marker::RST(n)) = marker
+            else
+            {
+                if let Some(Marker::Unknown(_)) = marker
+                {
+                    continue;
+                }
+                if !stream.lost_sync
+                {
+                    let (marker, offset, reason) = match marker
+                    {
+                        Some(marker) => (
+                            Some(marker.to_u8()),
+                            offset,
+                            format!("Entropy coded data ended early at marker {marker:?}"),
+                        ),
+                        None => (
+                            None,
+                            position(reader),
+                            "Entropy coded data ended early".to_string(),
+                        ),
+                    };
+                    self.lose_sync(stream, WarningKind::MissingData, marker, offset, reason)?;
+                }
+                // leave the marker to whoever parses what follows the scan
+                stream.marker = marker;
+
+                return Ok(());
+            };
+
+            let expected = stream.next_restart;
+
+            if n != expected && !stream.lost_sync
+            {
+                self.add_warning(
+                    WarningKind::UnexpectedRestartMarker,
+                    Some(0xD0 + n),
+                    offset,
+                    format!("Expected restart marker RST{expected}, found RST{n}"),
+                )?;
+            }
+
+            match n.wrapping_sub(expected) & 7
+            {
+                1 | 2 =>
+                {
+                    stream.marker = Some(Marker::RST(n));
+
+                    stream.lost_sync = true;
+
+                    stream.next_restart = (expected + 1) & 7;
+
+                    return Ok(());
+                }
+                6 | 7 => (),
+                _ =>
+                {
+                    stream.reset();
+
+                    stream.next_restart = (n + 1) & 7;
+
+                    self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Position of `reader` in its data, which it may have been read past
+fn position(reader: &Cursor<&[u8]>) -> usize
+{
+    usize::try_from(reader.position())
+        .unwrap_or(usize::MAX)
+        .min(reader.get_ref().len())
+}
@@ -34,6 +34,20 @@
 //! let image_info = decoder.info().unwrap();
 //! println!("{},{}",image_info.width,image_info.height)
 //! ```
+//!
+//! ## Read image information without decoding the image
+//! ```no_run
+//! use zune_jpeg::Decoder;
+//! let data = std::fs::read("a_jpeg_file").unwrap();
+//! let mut decoder = Decoder::new();
+//! decoder.read_headers(&data).unwrap();
+//! let image_info = decoder.info().unwrap();
+//! if usize::from(image_info.width) * usize::from(image_info.height) < 10_000_000
+//! {
+//!     // continues from where read_headers stopped
+//!     let pixels = decoder.decode_buffer(&data).unwrap();
+//! }
+//! ```
 
 #![allow(
     clippy::needless_return,
@@ -55,21 +69,47 @@
 #[macro_use]
 extern crate log;
 
-pub use crate::decoder::Decoder;
-pub use crate::misc::ColorSpace;
+pub use crate::coefficients::ComponentCoefficients;
+pub use crate::decoder::{Decoder, ImageInfo};
+pub use crate::exif::{ExifData, ExifIfd, ExifTag, GpsInfo, Rational};
+pub use crate::incremental::{FeedStatus, IncrementalDecoder};
+pub use crate::misc::{ColorSpace, SOFMarkers};
+pub use crate::options::DecoderOptions;
+pub use crate::planar::Plane;
+pub use crate::progressive::ProgressiveReader;
+pub use crate::scanlines::ScanlineReader;
 
+mod arithmetic;
 mod bitstream;
+mod coefficients;
 mod color_convert;
 mod components;
 mod decoder;
 pub mod errors;
+mod exif;
 mod headers;
 mod huffman;
+mod icc;
 mod idct;
+mod incremental;
+mod lenient;
 mod marker;
 mod mcu;
+mod mcu_arith;
+mod mcu_crop;
+mod mcu_dnl;
+mod mcu_extended;
+mod mcu_hierarchical;
+mod mcu_lossless;
 mod mcu_prog;
+mod mcu_scaled;
+mod mcu_source;
 mod misc;
+mod options;
+mod planar;
+mod progressive;
+mod scanlines;
 mod unsafe_utils;
 mod upsampler;
+mod warnings;
 mod worker;
@@ -37,25 +37,44 @@ pub enum Marker
     DNL,
     /// Define restart interval
     DRI,
+    /// Define hierarchical progression
+    DHP,
+    /// Expand reference component(s)
+    EXP,
     /// Reserved for application segments
     APP(u8),
     /// Comment
     COM,
+    /// Any other marker, found where only its second byte can be kept
+    Unknown(u8),
 }
 
 impl Marker
 {
     pub fn from_u8(n: u8) -> Option<Marker>
     {
-        use self::Marker::{APP, COM, DAC, DHT, DNL, DQT, DRI, EOI, RST, SOF, SOI, SOS};
+        use self::Marker::{
+            APP, COM, DAC, DHP, DHT, DNL, DQT, DRI, EOI, EXP, RST, SOF, SOI, SOS,
+        };
 
         match n
         {
             0xFE => Some(COM),
             0xC0 => Some(SOF(0)),
+            0xC1 => Some(SOF(1)),
             0xC2 => Some(SOF(2)),
+            0xC3 => Some(SOF(3)),
             0xC4 => Some(DHT),
+            0xC5 => Some(SOF(5)),
+            0xC6 => Some(SOF(6)),
+            0xC7 => Some(SOF(7)),
+            0xC9 => Some(SOF(9)),
+            0xCA => Some(SOF(10)),
+            0xCB => Some(SOF(11)),
             0xCC => Some(DAC),
+            0xCD => Some(SOF(13)),
+            0xCE => Some(SOF(14)),
+            0xCF => Some(SOF(15)),
             0xD0 => Some(RST(0)),
             0xD1 => Some(RST(1)),
             0xD2 => Some(RST(2)),
@@ -70,10 +89,37 @@ impl Marker
             0xDB => Some(DQT),
             0xDC => Some(DNL),
             0xDD => Some(DRI),
-            0xE0 => Some(APP(0)),
-            0xE1 => Some(APP(1)),
-            0xEE => Some(APP(14)),
+            0xDE => Some(DHP),
+            0xDF => Some(EXP),
+            0xE0..=0xEF => Some(APP(n - 0xE0)),
             _ => None,
         }
     }
+
+    /// The second byte of the marker, the inverse of `from_u8`
+    pub const fn to_u8(self) -> u8
+    {
+        use self::Marker::{
+            Unknown, APP, COM, DAC, DHP, DHT, DNL, DQT, DRI, EOI, EXP, RST, SOF, SOI, SOS,
+        };
+
+        match self
+        {
+            SOF(n) => 0xC0 + n,
+            DHT => 0xC4,
+            DAC => 0xCC,
+            RST(n) => 0xD0 + n,
+            SOI => 0xD8,
+            EOI => 0xD9,
+            SOS => 0xDA,
+            DQT => 0xDB,
+            DNL => 0xDC,
+            DRI => 0xDD,
+            DHP => 0xDE,
+            EXP => 0xDF,
+            APP(n) => 0xE0 + n,
+            COM => 0xFE,
+            Unknown(n) => n,
+        }
+    }
 }
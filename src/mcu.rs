@@ -61,13 +61,36 @@ use crate::bitstream::BitStream;
 use crate::components::{ComponentID, SubSampRatios};
 use crate::errors::DecodeErrors;
 use crate::marker::Marker;
-use crate::worker::post_process;
+use crate::mcu_prog::get_marker;
+use crate::mcu_source::McuRowSource;
+use crate::worker::{post_process, McuRows};
 use crate::Decoder;
 
 /// The size of a DC block for a MCU.
 
 pub const DCT_BLOCK: usize = 64;
 
+/// How a baseline image is split into rows of MCUs for decoding
+pub(crate) struct BaselineLayout
+{
+    /// MCUs in a row
+    pub(crate) mcu_width: usize,
+    /// Rows to decode, each row being `bias` MCU rows
+    pub(crate) mcu_height: usize,
+    /// MCUs in the last row, which holds a single MCU row if the image has an
+    /// odd number of MCU rows and two are decoded together
+    last_row_mcus: usize,
+    /// MCU rows decoded together before post processing, 2 for 4:2:0 images
+    pub(crate) bias: usize,
+    component_capacity: usize,
+    width_stride: usize,
+    is_hv: bool,
+    /// Output bytes of a row
+    pub(crate) chunk_size: usize,
+    /// Blocks coded in a row
+    pub(crate) row_blocks: usize,
+}
+
 impl Decoder
 {
     /// Check for existence of DC and AC Huffman Tables
@@ -115,24 +138,18 @@ impl Decoder
         Ok(())
     }
 
-    /// Decode MCUs and carry out post processing.
+    /// Work out how MCU rows of a baseline image are decoded and post processed
     ///
-    /// This is the main decoder loop for the library, the hot path.
-    ///
-    /// Because of this, we pull in some very crazy optimization tricks hence readability is a pinch
-    /// here.
+    /// This also sets up upsampling functions and checks that Huffman tables exist,
+    /// so it must be called before decoding any row.
     #[allow(clippy::similar_names)]
-    #[inline(never)]
     #[rustfmt::skip]
-    pub(crate) fn decode_mcu_ycbcr_baseline(
-        &mut self, reader: &mut Cursor<Vec<u8>>,
-    ) -> Result<Vec<u8>, DecodeErrors>
+    pub(crate) fn baseline_layout(&mut self) -> Result<BaselineLayout, DecodeErrors>
     {
-        let mut scoped_pools = scoped_threadpool::Pool::new(num_cpus::get() as u32);
-        info!("Created {} worker threads", scoped_pools.thread_count());
-
         let (mcu_width, mcu_height);
         let mut bias = 1;
+        // two MCU rows are decoded at a time
+        let mut paired = false;
 
         if self.interleaved
         {
@@ -147,12 +164,16 @@ impl Decoder
                 // To make it complete we multiply width by 2 and divide mcu_height by 2
                 mcu_width = self.mcu_x * 2;
 
-                mcu_height = self.mcu_y / 2;
+                mcu_height = self.mcu_y.div_ceil(2);
+
+                paired = true;
             } else if self.sub_sample_ratio == SubSampRatios::HV
             {
                 mcu_width = self.mcu_x;
 
-                mcu_height = self.mcu_y / 2;
+                mcu_height = self.mcu_y.div_ceil(2);
+
+                paired = true;
 
                 bias = 2;
                 // V;
@@ -168,18 +189,68 @@ impl Decoder
 
             mcu_height = ((self.info.height + 7) / 8) as usize;
         }
+        let component_capacity = mcu_width * DCT_BLOCK;
+
+        // Halfway width size, used for vertical sub-sampling to write |Y2| in the right position.
+        let width_stride = (component_capacity * self.components[0].vertical_sample * self.components[0].horizontal_sample * bias) >> 1;
+
+        // check dc and AC tables
+        self.check_tables()?;
+
+        // restart intervals count from the start of the scan
+        self.reset_restart_count();
+
+        // Split output into different blocks each containing enough space for an MCU width
+        let chunk_size = usize::from(self.info.width) * self.output_colorspace.num_components() * 8 * self.h_max * self.v_max;
+
+        // blocks in every row we decode, for the source to know how much data to have ready
+        let row_blocks = mcu_width * bias * self.components.iter()
+            .map(|x| x.horizontal_sample * x.vertical_sample)
+            .sum::<usize>();
+
+        // rows of two MCU rows end with a single one if there is an odd number of them
+        let last_row_mcus = if paired && self.mcu_y % 2 == 1
+        {
+            self.mcu_x
+        } else {
+            mcu_width * bias
+        };
+
+        Ok(BaselineLayout {
+            mcu_width,
+            mcu_height,
+            last_row_mcus,
+            bias,
+            component_capacity,
+            width_stride,
+            is_hv: self.sub_sample_ratio == SubSampRatios::HV,
+            chunk_size,
+            row_blocks,
+        })
+    }
+
+    /// Decode MCUs and carry out post processing.
+    ///
+    /// This is the main decoder loop for the library, the hot path.
+    ///
+    /// Because of this, we pull in some very crazy optimization tricks hence readability is a pinch
+    /// here.
+    #[inline(never)]
+    #[rustfmt::skip]
+    pub(crate) fn decode_mcu_ycbcr_baseline<S: McuRowSource>(
+        &mut self, source: &mut S, global_channel: &mut [u8],
+    ) -> Result<(), DecodeErrors>
+    {
+        let mut scoped_pools = scoped_threadpool::Pool::new(num_cpus::get() as u32);
+        info!("Created {} worker threads", scoped_pools.thread_count());
+
+        let layout = self.baseline_layout()?;
 
         let mut stream = BitStream::new();
-        // Size of our output image(width*height)
-        let capacity = usize::from(self.info.width + 7) * usize::from(self.info.height + 7);
 
-        let component_capacity = mcu_width * DCT_BLOCK;
         // Create an Arc of components to prevent cloning on every MCU width
         let global_component = Arc::new(self.components.clone());
 
-        // Storage for decoded pixels
-        let mut global_channel = vec![0; capacity * self.output_colorspace.num_components()];
-
         // things needed for post processing that we can remove out of the loop
         let input = self.input_colorspace;
 
@@ -187,169 +258,225 @@ impl Decoder
 
         let idct_func = self.idct_func;
 
-
         let color_convert_16 = self.color_convert_16;
 
-        let width = usize::from(self.width());
-
-        let h_max = self.h_max;
-
-        let v_max = self.v_max;
-        // Halfway width size, used for vertical sub-sampling to write |Y2| in the right position.
-        let width_stride = (component_capacity * self.components[0].vertical_sample * self.components[0].horizontal_sample * bias) >> 1;
-
-        let hv_width_stride = width_stride >> 1;
-        // check dc and AC tables
-        self.check_tables()?;
-
-        let is_hv = self.sub_sample_ratio == SubSampRatios::HV;
+        let width = usize::from(self.info.width);
 
-        // Split output into different blocks each containing enough space for an MCU width
-        let mut chunks =
-            global_channel.chunks_exact_mut(width * output.num_components() * 8 * h_max * v_max);
+        let chunk_size = layout.chunk_size;
 
-        let mut tmp = [0; DCT_BLOCK];
+        let mut chunks = McuRows::new(global_channel, chunk_size, width, output.num_components(), self.output_orientation());
 
         // Argument for scoped threadpools, see file docs.
         scoped_pools.scoped::<_, Result<(), DecodeErrors>>(|scope| {
-            for _ in 0..mcu_height
+            for row in 0..layout.mcu_height
             {
-                // faster to memset than a later memcpy
+                self.data_offset = source.offset();
 
-                // We allocate on every mcu_height since this is sent to a separate
-                // thread (that's how we're multi-threaded and thread safe).
+                let mut reader = source.next_row(layout.row_blocks)?;
 
-                let mut temporary = [vec![], vec![], vec![]];
-                for (pos, comp) in self.components.iter().enumerate()
-                {
-                    // multiply capacity with sampling factor, it  should be 1*1 for un-sampled images
+                let last = row + 1 == layout.mcu_height;
 
-                    // Allocate only needed components.
-                    if min(self.output_colorspace.num_components() - 1, pos) == pos
-                    {
-                        let len = component_capacity * comp.vertical_sample * comp.horizontal_sample * bias;
+                let mut temporary = self.decode_baseline_row(&layout, &mut reader, &mut stream, last)?;
 
-                        temporary[pos] = vec![0; len];
-                    }
-                }
-                // Bias only affects 4:2:0(chroma quartered) sub-sampled images.
-                // since we want to fetch two MCU rows before we send it to post process
-                for v in 0..bias
-                {
-                    for j in 0..mcu_width
-                    {
-                        // iterate over components
-
-                        for pos in 0..self.input_colorspace.num_components()
-                        {
-                            let component = &mut self.components[pos];
-                            // Safety:The tables were confirmed to exist in self.check_tables();
-                            let dc_table = unsafe {
-                                self.dc_huffman_tables
-                                    .get_unchecked(component.dc_huff_table)
-                                    .as_ref()
-                                    .unwrap_or_else(|| std::hint::unreachable_unchecked())
-                            };
-                            let ac_table = unsafe {
-                                self.ac_huffman_tables
-                                    .get_unchecked(component.ac_huff_table)
-                                    .as_ref()
-                                    .unwrap_or_else(|| std::hint::unreachable_unchecked())
-                            };
-                            // If image is interleaved iterate over scan  components,
-                            // otherwise if it-s non-interleaved, these routines iterate in
-                            // trivial scanline order(Y,Cb,Cr)
-                            for v_samp in 0..component.vertical_sample
-                            {
-                                for h_samp in 0..component.horizontal_sample
-                                {
-                                    // only decode needed components
-                                    if min(self.output_colorspace.num_components() - 1, pos) == pos
-                                    {
-                                        // The spec  https://www.w3.org/Graphics/JPEG/itu-t81.pdf page 26
-
-                                        // Get position to write
-                                        // This is complex, don't even try to understand it. ~author
-                                        let is_y =
-                                            usize::from(component.component_id == ComponentID::Y);
-
-                                        // This only affects 4:2:0 images.
-                                        let y_offset = is_y
-                                            * v
-                                            * (hv_width_stride
-                                            + (hv_width_stride * (component.vertical_sample - 1)));
-
-                                        let another_stride =
-                                            (width_stride * v_samp * usize::from(!is_hv))
-                                                + hv_width_stride * v_samp * usize::from(is_hv);
-
-                                        let yet_another_stride = usize::from(is_hv)
-                                            * (width_stride >> 2)
-                                            * v
-                                            * usize::from(component.component_id != ComponentID::Y);
-
-                                        // offset calculator.
-                                        let start = (j * 64 * component.horizontal_sample)
-                                            + (h_samp * 64)
-                                            + another_stride
-                                            + y_offset
-                                            + yet_another_stride;
-
-                                        // Get the location we will be writing to.
-
-                                        // It will always be zero since it's initialized per MCU height.
-
-                                        let tmp: &mut [i16; 64] = temporary.get_mut(pos).unwrap().get_mut(start..start + 64).unwrap().try_into().unwrap();
-
-                                        stream.decode_mcu_block(reader, dc_table, ac_table, tmp, &mut component.dc_pred)?;
-                                    }
-                                    else
-                                    {
-                                        // component not needed, decode and discard bits
-                                        stream.decode_mcu_block(reader, dc_table, ac_table, &mut tmp, &mut component.dc_pred)?;
-                                    }
-                                }
-                            }
-                            self.todo -= 1;
-                            // after every interleaved MCU that's a mcu, count down restart markers.
-                            if self.todo == 0
-                            {
-                                self.handle_rst(&mut stream)?;
-                            }
-                        }
-                    }
-                }
+                let position = reader.position();
+
+                source.consumed(position);
                 // Clone things, to make multithreading safe
                 let component = global_component.clone();
 
                 let next_chunk = chunks.next().unwrap();
 
                 scope.execute(move || {
-                    post_process(&mut temporary, &component,
-                                 idct_func, color_convert_16,
-                                 input, output, next_chunk,
-                                  width);
+                    next_chunk.write(chunk_size, |next_chunk| {
+                        post_process(&mut temporary, &component,
+                                     idct_func, color_convert_16,
+                                     input, output, next_chunk,
+                                     width);
+                    });
                 });
             }
             //everything is okay
             Ok(())
         })?;
+        chunks.finish();
+
         info!("Finished decoding image");
-        // remove excess allocation for images.
-        global_channel.truncate(
-            usize::from(self.width())
-                * usize::from(self.height())
-                * self.output_colorspace.num_components(),
-        );
-        return Ok(global_channel);
+
+        return Ok(());
     }
-    // handle RST markers.
-    // No-op if not using restarts
-    // this routine is shared with mcu_prog
+
+    /// Decode the coefficients of the next MCU row (two rows for 4:2:0 images)
+    ///
+    /// Returns the coefficients of every component needed for the output colorspace,
+    /// ready to be given to `post_process`. `last` is set for the last row of the
+    /// image, which may hold fewer MCUs.
+    #[allow(clippy::similar_names)]
+    #[rustfmt::skip]
+    pub(crate) fn decode_baseline_row(
+        &mut self, layout: &BaselineLayout, reader: &mut Cursor<&[u8]>, stream: &mut BitStream,
+        last: bool,
+    ) -> Result<[Vec<i16>; 3], DecodeErrors>
+    {
+        // We allocate on every mcu_height since this is sent to a separate
+        // thread (that's how we're multi-threaded and thread safe).
+
+        let mut temporary = [vec![], vec![], vec![]];
+        for (pos, comp) in self.components.iter().enumerate()
+        {
+            // multiply capacity with sampling factor, it  should be 1*1 for un-sampled images
+
+            // Allocate only needed components.
+            if min(self.output_colorspace.num_components() - 1, pos) == pos
+            {
+                let len = layout.component_capacity * comp.vertical_sample * comp.horizontal_sample * layout.bias;
+
+                temporary[pos] = vec![0; len];
+            }
+        }
+        let mcus = if last { layout.last_row_mcus } else { layout.mcu_width * layout.bias };
+
+        // Bias only affects 4:2:0(chroma quartered) sub-sampled images.
+        // since we want to fetch two MCU rows before we send it to post process
+        for v in 0..layout.bias
+        {
+            for j in 0..layout.mcu_width
+            {
+                // the image ended with the first of the two MCU rows, the
+                // coefficients of the other are left zeroed
+                if v * layout.mcu_width + j == mcus
+                {
+                    return Ok(temporary);
+                }
+                // the previous MCU ended a restart interval
+                if self.todo == 0
+                {
+                    self.start_restart_interval(reader, stream)?;
+                }
+
+                // MCUs which are not decoded are left zeroed, which is gray
+                if !stream.lost_sync
+                {
+                    let decoded = self.decode_baseline_mcu(layout, reader, stream, &mut temporary, v, j);
+
+                    self.check_decoded_mcu(reader, stream, decoded)?;
+                }
+                self.todo = self.todo.saturating_sub(1);
+            }
+        }
+        Ok(temporary)
+    }
+
+    /// Decode the MCU at column `j` of the `v`th MCU row of those decoded together
+    /// into `temporary`
+    #[allow(clippy::similar_names)]
+    #[rustfmt::skip]
+    #[inline]
+    fn decode_baseline_mcu(
+        &mut self, layout: &BaselineLayout, reader: &mut Cursor<&[u8]>, stream: &mut BitStream,
+        temporary: &mut [Vec<i16>; 3], v: usize, j: usize,
+    ) -> Result<(), DecodeErrors>
+    {
+        let is_hv = layout.is_hv;
+
+        let width_stride = layout.width_stride;
+
+        let hv_width_stride = width_stride >> 1;
+
+        let mut tmp = [0; DCT_BLOCK];
+        // faster to memset than a later memcpy
+
+        // iterate over components
+        for pos in 0..self.input_colorspace.num_components()
+        {
+            let component = &mut self.components[pos];
+            // Safety:The tables were confirmed to exist in self.check_tables();
+            let dc_table = unsafe {
+                self.dc_huffman_tables
+                    .get_unchecked(component.dc_huff_table)
+                    .as_ref()
+                    .unwrap_or_else(|| std::hint::unreachable_unchecked())
+            };
+            let ac_table = unsafe {
+                self.ac_huffman_tables
+                    .get_unchecked(component.ac_huff_table)
+                    .as_ref()
+                    .unwrap_or_else(|| std::hint::unreachable_unchecked())
+            };
+            // If image is interleaved iterate over scan  components,
+            // otherwise if it-s non-interleaved, these routines iterate in
+            // trivial scanline order(Y,Cb,Cr)
+            for v_samp in 0..component.vertical_sample
+            {
+                for h_samp in 0..component.horizontal_sample
+                {
+                    // only decode needed components
+                    if min(self.output_colorspace.num_components() - 1, pos) == pos
+                    {
+                        // The spec  https://www.w3.org/Graphics/JPEG/itu-t81.pdf page 26
+
+                        // Get position to write
+                        // This is complex, don't even try to understand it. ~author
+                        let is_y =
+                            usize::from(component.component_id == ComponentID::Y);
+
+                        // This only affects 4:2:0 images.
+                        let y_offset = is_y
+                            * v
+                            * (hv_width_stride
+                            + (hv_width_stride * (component.vertical_sample - 1)));
+
+                        let another_stride =
+                            (width_stride * v_samp * usize::from(!is_hv))
+                                + hv_width_stride * v_samp * usize::from(is_hv);
+
+                        let yet_another_stride = usize::from(is_hv)
+                            * (width_stride >> 2)
+                            * v
+                            * usize::from(component.component_id != ComponentID::Y);
+
+                        // offset calculator.
+                        let start = (j * 64 * component.horizontal_sample)
+                            + (h_samp * 64)
+                            + another_stride
+                            + y_offset
+                            + yet_another_stride;
+
+                        // Get the location we will be writing to.
+
+                        // It will always be zero since it's initialized per MCU height.
+
+                        let tmp: &mut [i16; 64] = temporary.get_mut(pos).unwrap().get_mut(start..start + 64).unwrap().try_into().unwrap();
+
+                        stream.decode_mcu_block(reader, dc_table, ac_table, tmp, &mut component.dc_pred)?;
+                    }
+                    else
+                    {
+                        // component not needed, decode and discard bits
+                        stream.decode_mcu_block(reader, dc_table, ac_table, &mut tmp, &mut component.dc_pred)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle the restart marker ending a restart interval
+    ///
+    /// This is called before decoding the first MCU of every restart interval but the
+    /// first, and is shared by every Huffman coded image. The bitstream may not have
+    /// reached the marker yet if the restart interval ended on a byte boundary, so
+    /// search for it first.
     #[cold]
-    pub(crate) fn handle_rst(&mut self, stream: &mut BitStream) -> Result<(), DecodeErrors>
+    pub(crate) fn handle_rst(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream,
+    ) -> Result<(), DecodeErrors>
     {
-        self.todo = self.restart_interval;
+        self.reset_restart_count();
+
+        if stream.marker.is_none()
+        {
+            stream.marker = get_marker(reader, stream);
+        }
 
         if let Some(marker) = stream.marker
         {
@@ -357,15 +484,18 @@ impl Decoder
             // Read stream and see what marker is stored there
             match marker
             {
-                Marker::RST(_) =>
+                Marker::RST(n) =>
                 {
                     // reset stream
                     stream.reset();
+
+                    stream.next_restart = (n + 1) & 7;
+
                     // Initialize dc predictions to zero for all components
                     self.components.iter_mut().for_each(|x| x.dc_pred = 0);
                     // Start iterating again. from position.
                 }
-                Marker::EOI =>
+                Marker::EOI | Marker::DNL =>
                 {
                     // silent pass
                 }
@@ -378,6 +508,12 @@ impl Decoder
                 }
             }
         }
+        else
+        {
+            // the data ended before the marker, nothing left in the bitstream belongs
+            // to the next interval
+            stream.reset();
+        }
 
         Ok(())
     }
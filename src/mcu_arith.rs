@@ -0,0 +1,413 @@
+//! Routines for decoding arithmetic coded images
+//!
+//! Arithmetic coded images, both sequential and progressive, may be split into multiple
+//! scans, each carrying one or more components, so like progressive images we decode
+//! coefficients of the whole image into a buffer first, and once we hit EOI we carry out
+//! IDCT, upsampling and color conversion using the same routines progressive decoding
+//! uses (see `mcu_prog.rs`).
+//!
+//! For progressive images, every scan refines the coefficients accumulated by the previous
+//! ones, in the same way Huffman coded progressive scans do.
+//!
+//! This means we need memory for the coefficients of the whole image, but unlike Huffman
+//! coded images, arithmetic decoding is way slower than post-processing so we don't
+//! lose much by not overlapping the two.
+use std::io::{BufRead, Cursor};
+
+use crate::arithmetic::ArithmeticDecoder;
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::headers::{parse_dac, parse_dri, parse_huffman, parse_sos};
+use crate::marker::Marker;
+use crate::misc::{read_byte, read_u16_be};
+use crate::warnings::marker_offset;
+use crate::Decoder;
+
+impl Decoder
+{
+    /// Decode an arithmetic coded image
+    ///
+    /// This expects the headers up to and including the first SOS to have been parsed.
+    pub(crate) fn decode_mcu_arithmetic(
+        &mut self, reader: &mut Cursor<&[u8]>, output: &mut [u8],
+    ) -> Result<(), DecodeErrors>
+    {
+        let (block, mcu_width) = self.decode_arithmetic_coefficients(reader)?;
+
+        self.set_upsampling()?;
+
+        self.finish_progressive_decoding(&block, mcu_width, output, self.output_orientation());
+
+        Ok(())
+    }
+
+    /// Decode the coefficients of every scan of an arithmetic coded image
+    ///
+    /// Returns the coefficients and the number of coefficients in a single MCU row
+    pub(crate) fn decode_arithmetic_coefficients(
+        &mut self, reader: &mut Cursor<&[u8]>,
+    ) -> Result<([Vec<i16>; MAX_COMPONENTS], usize), DecodeErrors>
+    {
+        let (mut block, mcu_width) = self.allocate_coefficient_buffers();
+
+        let mut stream = ArithmeticDecoder::new();
+
+        // there may be multiple scans in the stream, this decodes the first one.
+        self.decode_arithmetic_scan(reader, &mut stream, &mut block)?;
+
+        // Markers are handed back to us by the arithmetic decoder, or we
+        // search for them if the decoder finished before hitting one.
+        while let Some(byte) = next_marker(reader, &mut stream)
+        {
+            match Marker::from_u8(byte)
+            {
+                Some(Marker::EOI) => break,
+                Some(Marker::SOS) =>
+                {
+                    parse_sos(reader, self)?;
+
+                    self.decode_arithmetic_scan(reader, &mut stream, &mut block)?;
+                }
+                Some(Marker::DAC) =>
+                {
+                    parse_dac(self, reader)?;
+                }
+                Some(Marker::DHT) =>
+                {
+                    parse_huffman(self, reader)?;
+                }
+                Some(Marker::DRI) =>
+                {
+                    parse_dri(self, reader)?;
+                }
+                Some(Marker::RST(_)) =>
+                {
+                    // A stray restart marker, no segment follows it.
+                    self.add_skipped_marker(byte, marker_offset(reader), "between scans")?;
+                }
+                _ =>
+                {
+                    self.add_skipped_marker(byte, marker_offset(reader), "between scans")?;
+
+                    let length = read_u16_be(reader)?;
+
+                    if length < 2
+                    {
+                        return Err(DecodeErrors::Format(format!(
+                            "Found a marker with invalid length:{length}\n"
+                        )));
+                    }
+                    reader.consume(usize::from(length - 2));
+                }
+            }
+        }
+
+        Ok((block, mcu_width))
+    }
+
+    /// Decode a single arithmetic coded scan into `buffer`
+    #[allow(clippy::similar_names)]
+    fn decode_arithmetic_scan(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut ArithmeticDecoder,
+        buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+    ) -> Result<(), DecodeErrors>
+    {
+        stream.reset();
+        stream.set_conditioning(self.arith_dc_conditioning, self.arith_ac_conditioning);
+
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+
+        self.reset_restart_count();
+
+        if self.info.sof.is_progressive()
+        {
+            self.check_progressive_params()?;
+        }
+
+        for k in 0..usize::from(self.num_scans)
+        {
+            let component = &self.components[self.z_order[k]];
+
+            if component.dc_huff_table >= MAX_COMPONENTS
+                || component.ac_huff_table >= MAX_COMPONENTS
+            {
+                return Err(DecodeErrors::SosError(format!(
+                    "Invalid arithmetic conditioning table for component {:?}",
+                    component.component_id
+                )));
+            }
+        }
+
+        if self.num_scans == 1
+        {
+            // non interleaved data, process one block at a time in trivial scanline order
+            let k = self.z_order[0];
+
+            let (blocks_x, blocks_y) = self.component_blocks(k);
+
+            let stride = self.components[k].width_stride / 8;
+
+            for i in 0..blocks_y
+            {
+                for j in 0..blocks_x
+                {
+                    let start = 64 * (j + i * stride);
+
+                    let data: &mut [i16; 64] = buffer[k]
+                        .get_mut(start..start + 64)
+                        .ok_or(DecodeErrors::ExhaustedData)?
+                        .try_into()
+                        .unwrap();
+
+                    self.decode_arithmetic_block(reader, stream, k, data)?;
+
+                    self.todo -= 1;
+
+                    if self.todo == 0
+                    {
+                        self.handle_rst_arithmetic(reader, stream);
+                    }
+                }
+            }
+        }
+        else
+        {
+            // Interleaved scan, components are decoded in MCU order
+            for i in 0..self.mcu_y
+            {
+                for j in 0..self.mcu_x
+                {
+                    for k in 0..usize::from(self.num_scans)
+                    {
+                        let n = self.z_order[k];
+
+                        let component = &self.components[n];
+
+                        let (h_samp_max, v_samp_max, width_stride) = (
+                            component.horizontal_sample,
+                            component.vertical_sample,
+                            component.width_stride,
+                        );
+
+                        for v_samp in 0..v_samp_max
+                        {
+                            for h_samp in 0..h_samp_max
+                            {
+                                let x2 = j * h_samp_max + h_samp;
+
+                                let y2 = i * v_samp_max + v_samp;
+
+                                let position = 64 * (x2 + y2 * width_stride / 8);
+
+                                let data: &mut [i16; 64] = buffer[n]
+                                    .get_mut(position..position + 64)
+                                    .ok_or(DecodeErrors::ExhaustedData)?
+                                    .try_into()
+                                    .unwrap();
+
+                                self.decode_arithmetic_block(reader, stream, n, data)?;
+                            }
+                        }
+                    }
+                    self.todo -= 1;
+                    // after every interleaved MCU, count down restart markers.
+                    if self.todo == 0
+                    {
+                        self.handle_rst_arithmetic(reader, stream);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode a single block of component `k` for the current scan
+    ///
+    /// For progressive images, this carries out the pass selected by the scan's spectral
+    /// selection and successive approximation parameters.
+    #[inline]
+    fn decode_arithmetic_block(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut ArithmeticDecoder, k: usize,
+        data: &mut [i16; 64],
+    ) -> Result<(), DecodeErrors>
+    {
+        let component = &mut self.components[k];
+
+        if !self.info.sof.is_progressive()
+        {
+            stream.decode_mcu_block(
+                reader,
+                k,
+                component.dc_huff_table,
+                component.ac_huff_table,
+                data,
+                &mut component.dc_pred,
+            )
+        }
+        else if self.spec_start == 0
+        {
+            if self.succ_high == 0
+            {
+                // first scan for this block
+                stream.decode_dc_first(
+                    reader,
+                    k,
+                    component.dc_huff_table,
+                    &mut data[0],
+                    &mut component.dc_pred,
+                    self.succ_low,
+                )
+            }
+            else
+            {
+                // refining scans for this block
+                stream.decode_dc_refine(reader, &mut data[0], self.succ_low);
+
+                Ok(())
+            }
+        }
+        else if self.succ_high == 0
+        {
+            stream.decode_ac_first(
+                reader,
+                component.ac_huff_table,
+                self.spec_start,
+                self.spec_end,
+                self.succ_low,
+                data,
+            )
+        }
+        else
+        {
+            stream.decode_ac_refine(
+                reader,
+                component.ac_huff_table,
+                self.spec_start,
+                self.spec_end,
+                self.succ_low,
+                data,
+            )
+        }
+    }
+
+    /// Validate spectral selection and successive approximation values of a
+    /// progressive scan
+    fn check_progressive_params(&self) -> Result<(), DecodeErrors>
+    {
+        if self.spec_start > self.spec_end
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Invalid spectral selection start {} greater than end {}",
+                self.spec_start, self.spec_end
+            )));
+        }
+        if self.spec_start == 0 && self.spec_end != 0
+        {
+            return Err(DecodeErrors::SosError(
+                "Can't merge DC and AC corrupt jpeg".to_string(),
+            ));
+        }
+        if self.spec_start != 0 && self.num_scans != 1
+        {
+            return Err(DecodeErrors::SosError(
+                "AC scans cannot be interleaved, corrupt jpeg".to_string(),
+            ));
+        }
+        // 13 is the largest value, see table G.2
+        if self.succ_high > 13 || self.succ_low > 13
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Invalid successive approximation values Ah:{} Al:{}",
+                self.succ_high, self.succ_low
+            )));
+        }
+        Ok(())
+    }
+
+    /// Number of blocks in the x and y direction for component `k` in a
+    /// non-interleaved scan
+    pub(crate) fn component_blocks(&self, k: usize) -> (usize, usize)
+    {
+        let component = &self.components[k];
+
+        let width =
+            (usize::from(self.info.width) * component.horizontal_sample).div_ceil(self.h_max);
+
+        let height =
+            (usize::from(self.info.height) * component.vertical_sample).div_ceil(self.v_max);
+
+        (width.div_ceil(8), height.div_ceil(8))
+    }
+
+    /// Set the number of MCU's left before we expect a restart marker
+    pub(crate) fn reset_restart_count(&mut self)
+    {
+        self.todo = if self.restart_interval == 0
+        {
+            // no restart markers
+            usize::MAX
+        }
+        else
+        {
+            self.restart_interval
+        };
+    }
+
+    /// Handle RST markers for arithmetic coded images
+    ///
+    /// On finding a restart marker the decoder statistics and DC predictions are
+    /// reset, any other marker is handed back to the decoder to be processed once the
+    /// scan is finished.
+    #[cold]
+    fn handle_rst_arithmetic(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut ArithmeticDecoder,
+    )
+    {
+        self.reset_restart_count();
+
+        if let Some(marker) = next_marker(reader, stream)
+        {
+            if let Some(Marker::RST(_)) = Marker::from_u8(marker)
+            {
+                stream.reset();
+
+                self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+            }
+            else
+            {
+                // Not a restart marker, probably end of the scan.
+                stream.set_marker(marker);
+            }
+        }
+    }
+}
+
+/// Get the next marker from the stream
+///
+/// If the arithmetic decoder stopped at a marker, that one is returned, otherwise we
+/// read until we find a marker or the end of the stream is reached (which returns `None`)
+fn next_marker(reader: &mut Cursor<&[u8]>, stream: &mut ArithmeticDecoder) -> Option<u8>
+{
+    if let Some(marker) = stream.take_marker()
+    {
+        return Some(marker);
+    }
+
+    loop
+    {
+        if read_byte(reader).ok()? == 0xFF
+        {
+            let mut r = read_byte(reader).ok()?;
+            // 0xFF 0xFF(some images may be like that)
+            while r == 0xFF
+            {
+                r = read_byte(reader).ok()?;
+            }
+
+            if r != 0
+            {
+                return Some(r);
+            }
+        }
+    }
+}
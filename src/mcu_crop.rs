@@ -0,0 +1,332 @@
+//! Routines for decoding a region of an image
+//!
+//! When only a rectangle of the image is wanted (see `Decoder::set_crop`), we work out
+//! the window of MCUs overlapping it, widened by an MCU on each side for sub-sampled
+//! images, since up-sampling looks at neighbouring samples, and reconstruct only
+//! that window, see `mcu_scaled.rs`.
+//!
+//! Huffman coded sequential images whose only scan holds every component have their
+//! coefficients decoded up to the last MCU of the window and no further, keeping only
+//! the coefficients of the window. Restart intervals that hold no MCU of the window
+//! are skipped by searching for the next RST marker, the DC predictions start from
+//! zero after it, so nothing in them is needed to decode what follows.
+//!
+//! Other images have coefficients for the whole image decoded as usual.
+use std::io::Cursor;
+
+use crate::bitstream::BitStream;
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::Decoder;
+
+/// A rectangle of MCUs, in units of MCUs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct McuWindow
+{
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl McuWindow
+{
+    /// Whether the MCU in column `x` and row `y` lies in the window
+    fn contains(&self, x: usize, y: usize) -> bool
+    {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    /// Whether any of the `count` MCUs starting at the `start`th MCU of the
+    /// image, in raster order, lie in the window
+    fn overlaps(&self, start: usize, count: usize, mcus_per_row: usize) -> bool
+    {
+        let end = start + count - 1;
+
+        let (first_row, last_row) = (start / mcus_per_row, end / mcus_per_row);
+
+        for row in first_row.max(self.y)..=last_row.min(self.y + self.height - 1)
+        {
+            let first_column = if row == first_row
+            {
+                start % mcus_per_row
+            }
+            else
+            {
+                0
+            };
+
+            let last_column = if row == last_row
+            {
+                end % mcus_per_row
+            }
+            else
+            {
+                mcus_per_row - 1
+            };
+
+            if first_column < self.x + self.width && last_column >= self.x
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Decoder
+{
+    /// Region of the output to decode as `(x, y, width, height)`, the whole
+    /// output unless the image is cropped
+    pub(crate) fn output_region(&self) -> (usize, usize, usize, usize)
+    {
+        let (width, height) = self.uncropped_dimensions();
+
+        self.crop.unwrap_or((0, 0, width, height))
+    }
+
+    /// Check the region set via `set_crop` is not empty and fits in the image
+    pub(crate) fn check_crop(&self) -> Result<(), DecodeErrors>
+    {
+        if let Some((x, y, width, height)) = self.crop
+        {
+            let (image_width, image_height) = self.uncropped_dimensions();
+
+            if width == 0
+                || height == 0
+                || x.saturating_add(width) > image_width
+                || y.saturating_add(height) > image_height
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Crop region {width}x{height}+{x}+{y} does not fit in the {image_width}x{image_height} image"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Window of MCUs needed to reconstruct the output region
+    pub(crate) fn crop_window(&self) -> McuWindow
+    {
+        let (x, y, width, height) = self.output_region();
+
+        // width and height of an MCU in the output
+        let size = if self.is_scaled() { self.scale } else { 8 };
+
+        let (mcu_width, mcu_height) = (self.h_max * size, self.v_max * size);
+
+        let (mut start_x, mut end_x) = (x / mcu_width, (x + width).div_ceil(mcu_width));
+
+        let (mut start_y, mut end_y) = (y / mcu_height, (y + height).div_ceil(mcu_height));
+
+        // up-sampled samples near the edges of the region depend on samples of the
+        // MCUs next to it
+        let components = &self.components[..self.input_colorspace.num_components()];
+
+        if components.iter().any(|x| x.horizontal_sample != self.h_max)
+        {
+            start_x = start_x.saturating_sub(1);
+
+            end_x = (end_x + 1).min(self.mcu_x);
+        }
+        if components.iter().any(|x| x.vertical_sample != self.v_max)
+        {
+            start_y = start_y.saturating_sub(1);
+
+            end_y = (end_y + 1).min(self.mcu_y);
+        }
+
+        McuWindow {
+            x: start_x,
+            y: start_y,
+            width: end_x - start_x,
+            height: end_y - start_y,
+        }
+    }
+
+    /// Whether coefficients of only the MCUs in the crop window can be decoded,
+    /// see the module docs
+    pub(crate) fn decodes_crop_window(&self) -> bool
+    {
+        let scan_components = usize::from(self.num_scans);
+
+        self.crop.is_some()
+            && !self.is_progressive
+            && !self.info.sof.is_arithmetic()
+            && scan_components == self.input_colorspace.num_components()
+            // a single component scan has blocks, not MCUs, in it
+            && (scan_components > 1 || (self.h_max == 1 && self.v_max == 1))
+    }
+
+    /// Decode the coefficients of MCUs in `window` from the first scan of a
+    /// Huffman coded sequential image
+    ///
+    /// Returns buffers laid out like `allocate_coefficient_buffers` lays them out,
+    /// for an image made of the MCUs in the window.
+    pub(crate) fn decode_window_coefficients(
+        &mut self, reader: &mut Cursor<&[u8]>, window: McuWindow,
+    ) -> Result<[Vec<i16>; MAX_COMPONENTS], DecodeErrors>
+    {
+        self.check_scan_tables()?;
+
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+
+        self.reset_restart_count();
+
+        let mut block = [vec![], vec![], vec![], vec![]];
+
+        for (buffer, component) in block.iter_mut().zip(self.components.iter())
+        {
+            let blocks = window.width
+                * component.horizontal_sample
+                * window.height
+                * component.vertical_sample;
+
+            *buffer = vec![0; blocks * 64];
+        }
+
+        // blocks of MCUs outside the window are decoded into this and dropped
+        let mut scratch = [0; 64];
+
+        let mut stream = BitStream::new();
+
+        let interval = self.restart_interval;
+
+        // nothing after the last MCU of the window is needed
+        let end = (window.y + window.height - 1) * self.mcu_x + window.x + window.width;
+
+        let mut mcu = 0;
+
+        while mcu < end
+        {
+            if mcu != 0 && self.todo == 0
+            {
+                self.handle_rst(reader, &mut stream)?;
+            }
+
+            if interval != 0 && mcu % interval == 0 && !window.overlaps(mcu, interval, self.mcu_x)
+            {
+                // jump to the RST marker ending this interval, `handle_rst` then
+                // resets the DC predictions as if we had decoded it
+                self.handle_rst(reader, &mut stream)?;
+
+                mcu += interval;
+
+                continue;
+            }
+
+            let (i, j) = (mcu / self.mcu_x, mcu % self.mcu_x);
+
+            let inside = window.contains(j, i);
+
+            for k in 0..usize::from(self.num_scans)
+            {
+                let n = self.z_order[k];
+
+                let component = &mut self.components[n];
+
+                // tables were confirmed to exist above
+                let dc_table = self.dc_huffman_tables[component.dc_huff_table]
+                    .as_ref()
+                    .unwrap();
+
+                let ac_table = self.ac_huffman_tables[component.ac_huff_table]
+                    .as_ref()
+                    .unwrap();
+
+                let stride = window.width * component.horizontal_sample;
+
+                for v_samp in 0..component.vertical_sample
+                {
+                    for h_samp in 0..component.horizontal_sample
+                    {
+                        let data: &mut [i16; 64] = if inside
+                        {
+                            let x2 = (j - window.x) * component.horizontal_sample + h_samp;
+
+                            let y2 = (i - window.y) * component.vertical_sample + v_samp;
+
+                            let position = 64 * (x2 + y2 * stride);
+
+                            block[n]
+                                .get_mut(position..position + 64)
+                                .ok_or(DecodeErrors::ExhaustedData)?
+                                .try_into()
+                                .unwrap()
+                        }
+                        else
+                        {
+                            &mut scratch
+                        };
+
+                        stream.decode_mcu_block(
+                            reader,
+                            dc_table,
+                            ac_table,
+                            data,
+                            &mut component.dc_pred,
+                        )?;
+                    }
+                }
+            }
+            self.todo = self.todo.saturating_sub(1);
+
+            mcu += 1;
+        }
+        Ok(block)
+    }
+
+    /// Crop samples of the whole output to the region set via `set_crop`, and turn
+    /// them per `output_orientation`
+    pub(crate) fn crop_output(&self, samples: &[u16]) -> Vec<u16>
+    {
+        let (width, height) = self.uncropped_dimensions();
+
+        let components = samples.len() / (width * height);
+
+        let region = self.output_region();
+
+        let Some(orientation) = self.output_orientation()
+        else
+        {
+            return crop_samples(samples, width, components, region);
+        };
+
+        let (x, y, crop_width, crop_height) = region;
+
+        let mut output = vec![0; crop_width * crop_height * components];
+
+        for (line, row) in samples
+            .chunks_exact(width * components)
+            .skip(y)
+            .take(crop_height)
+            .enumerate()
+        {
+            let row = &row[x * components..(x + crop_width) * components];
+
+            orientation.write_rows(row, line, components, &mut output);
+        }
+        output
+    }
+}
+
+/// Copy the samples of a rectangle out of an image
+///
+/// # Arguments
+/// - samples: The image, rows are `width` pixels of `components` samples each
+/// - `(x,y,width,height)`: The rectangle to copy, in pixels
+pub(crate) fn crop_samples<T: Copy>(
+    samples: &[T], width: usize, components: usize,
+    (x, y, crop_width, crop_height): (usize, usize, usize, usize),
+) -> Vec<T>
+{
+    let row_size = width * components;
+
+    samples
+        .chunks_exact(row_size)
+        .skip(y)
+        .take(crop_height)
+        .flat_map(|row| &row[x * components..(x + crop_width) * components])
+        .copied()
+        .collect()
+}
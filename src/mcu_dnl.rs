@@ -0,0 +1,273 @@
+//! Support for images whose height is defined by a DNL segment
+//!
+//! Encoders which do not know how many lines they will write when they emit the frame
+//! header (mostly fax machines and scanners) may set the height to zero and append a
+//! DNL (define number of lines) segment to the first scan, see B.2.5 in the spec.
+//!
+//! Our decoders allocate memory for the whole image up front, so we resolve the height
+//! before decoding anything, first by looking for the DNL segment ending the first scan,
+//! and if the scan ends without one, by decoding and discarding MCU rows until the scan
+//! runs out of data, which is reported as a [`MissingDnl`](WarningKind::MissingDnl)
+//! warning.
+use std::io::Cursor;
+
+use crate::bitstream::BitStream;
+use crate::errors::{DecodeErrors, WarningKind};
+use crate::Decoder;
+
+impl Decoder
+{
+    /// Resolve the height of an image whose frame header set it to zero
+    ///
+    /// This expects the headers up to and including the first SOS to have been parsed,
+    /// `reader` is left at the start of the first scan.
+    pub(crate) fn resolve_deferred_height(
+        &mut self, reader: &mut Cursor<&[u8]>,
+    ) -> Result<(), DecodeErrors>
+    {
+        if !self.deferred_height
+        {
+            return Ok(());
+        }
+
+        let position = reader.position();
+
+        let scan = usize::try_from(position)
+            .ok()
+            .and_then(|x| reader.get_ref().get(x..))
+            .unwrap_or(&[]);
+
+        let height = if let Some(height) = find_dnl(scan)?
+        {
+            info!("Image height defined by DNL segment: {height}");
+
+            height
+        }
+        else
+        {
+            self.add_warning(
+                WarningKind::MissingDnl,
+                None,
+                usize::try_from(position).unwrap_or(usize::MAX),
+                "No DNL segment found after the first scan, counting lines in the scan".to_string(),
+            )?;
+
+            let height = self.count_scan_lines(reader)?;
+
+            reader.set_position(position);
+
+            height
+        };
+
+        self.check_dimensions(usize::from(self.info.width), usize::from(height))?;
+
+        self.info.set_height(height);
+
+        self.mcu_y = usize::from(height).div_ceil(self.mcu_height);
+
+        self.deferred_height = false;
+
+        Ok(())
+    }
+
+    /// Count the lines stored in the first scan of a sequential Huffman coded image
+    ///
+    /// MCUs are decoded and discarded until the scan runs out of data, the result is
+    /// a multiple of the MCU height.
+    fn count_scan_lines(&mut self, reader: &mut Cursor<&[u8]>) -> Result<u16, DecodeErrors>
+    {
+        if !self.info.sof.is_sequential_dct() || self.info.sof.is_arithmetic()
+        {
+            return Err(DecodeErrors::Format(
+                "Image height is not set and no DNL segment follows the first scan".to_string(),
+            ));
+        }
+
+        let scan_components = &self.z_order[..usize::from(self.num_scans)];
+
+        for &k in scan_components
+        {
+            let component = &self.components[k];
+
+            if !matches!(
+                self.dc_huffman_tables.get(component.dc_huff_table),
+                Some(Some(_))
+            ) || !matches!(
+                self.ac_huffman_tables.get(component.ac_huff_table),
+                Some(Some(_))
+            )
+            {
+                return Err(DecodeErrors::HuffmanDecode(format!(
+                    "No Huffman table for component {:?}",
+                    component.component_id
+                )));
+            }
+        }
+
+        let (mcus_per_row, lines_per_row) = if self.num_scans == 1
+        {
+            // non interleaved, MCUs are single blocks
+            let component = &self.components[self.z_order[0]];
+
+            let width =
+                (usize::from(self.info.width) * component.horizontal_sample).div_ceil(self.h_max);
+
+            (
+                width.div_ceil(8),
+                8 * self.v_max / component.vertical_sample,
+            )
+        }
+        else
+        {
+            (self.mcu_x, self.mcu_height)
+        };
+
+        let max_mcus = mcus_per_row * (usize::from(u16::MAX) / lines_per_row);
+
+        let end = reader.get_ref().len() as u64;
+
+        let todo = self.todo;
+
+        let mut stream = BitStream::new();
+
+        let mut block = [0; 64];
+
+        let mut mcus = 0;
+
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+
+        self.reset_restart_count();
+
+        while mcus < max_mcus
+        {
+            if mcus != 0 && self.todo == 0 && self.handle_rst(reader, &mut stream).is_err()
+            {
+                // the scan was ended by a marker other than a restart marker
+                break;
+            }
+
+            let decoded = self.skip_mcu(reader, &mut stream, &mut block);
+
+            // an MCU which ran past the end of the scan was not stored in it
+            if !decoded || stream.overread(reader) || reader.position() > end
+            {
+                break;
+            }
+            mcus += 1;
+
+            self.todo = self.todo.saturating_sub(1);
+        }
+
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+
+        self.todo = todo;
+
+        // encoders write whole MCU rows, anything less is padding we misread as an MCU
+        let rows = mcus / mcus_per_row;
+
+        if rows == 0
+        {
+            return Err(DecodeErrors::Format(
+                "Image height is not set and the first scan does not contain a single MCU row"
+                    .to_string(),
+            ));
+        }
+        info!("First scan contains {rows} MCU rows");
+
+        Ok(u16::try_from(rows * lines_per_row).unwrap_or(u16::MAX))
+    }
+
+    /// Decode and discard a single MCU of the current scan
+    ///
+    /// Returns false if the MCU could not be decoded, the Huffman tables are expected
+    /// to exist.
+    fn skip_mcu(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream, block: &mut [i16; 64],
+    ) -> bool
+    {
+        let mut decoded = true;
+
+        for &k in &self.z_order[..usize::from(self.num_scans)]
+        {
+            let component = &mut self.components[k];
+
+            let dc_table = self.dc_huffman_tables[component.dc_huff_table]
+                .as_ref()
+                .unwrap();
+
+            let ac_table = self.ac_huffman_tables[component.ac_huff_table]
+                .as_ref()
+                .unwrap();
+
+            let blocks = if self.num_scans == 1
+            {
+                1
+            }
+            else
+            {
+                component.horizontal_sample * component.vertical_sample
+            };
+
+            for _ in 0..blocks
+            {
+                decoded &= stream
+                    .decode_mcu_block(reader, dc_table, ac_table, block, &mut component.dc_pred)
+                    .is_ok();
+            }
+        }
+        decoded
+    }
+}
+
+/// Find the DNL segment ending the scan at the start of `data`
+///
+/// Returns the number of lines it defines, or `None` if the scan is ended by any other
+/// marker or by the end of the data.
+fn find_dnl(data: &[u8]) -> Result<Option<u16>, DecodeErrors>
+{
+    let mut position = 0;
+
+    while let Some(offset) = data[position..].iter().position(|x| *x == 0xFF)
+    {
+        position += offset + 1;
+
+        // markers may be preceded by any number of fill bytes
+        while data.get(position) == Some(&0xFF)
+        {
+            position += 1;
+        }
+
+        match data.get(position)
+        {
+            // stuffed zero byte or a restart marker, we are still inside the scan
+            Some(0x00 | 0xD0..=0xD7) => position += 1,
+            Some(0xDC) =>
+            {
+                let segment = data
+                    .get(position + 1..position + 5)
+                    .ok_or(DecodeErrors::ExhaustedData)?;
+
+                let length = u16::from_be_bytes([segment[0], segment[1]]);
+
+                if length != 4
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid DNL segment length {length}, expected 4"
+                    )));
+                }
+
+                let lines = u16::from_be_bytes([segment[2], segment[3]]);
+
+                if lines == 0
+                {
+                    return Err(DecodeErrors::ZeroError);
+                }
+
+                return Ok(Some(lines));
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(None)
+}
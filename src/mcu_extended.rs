@@ -0,0 +1,653 @@
+//! Routines for decoding extended sequential (12 bit) images
+//!
+//! 12 bit samples do not fit the 8 bit pipeline used for baseline images, the IDCT
+//! would overflow and the color convert routines saturate to 255, so these images
+//! take a separate, simpler route.
+//!
+//! Like progressive images, we decode coefficients for the whole image first, since
+//! extended sequential images may carry each component in its own scan, and once we hit
+//! EOI we carry out IDCT, upsampling and color conversion on whole planes, writing
+//! samples out as `u16`'s.
+//!
+//! Up-sampling uses the same triangle filter as libjpeg's `fancy_upsampling`
+//! for the common (2,1),(1,2) and (2,2) ratios, and pixel replication for everything else.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use std::io::{BufRead, Cursor};
+
+use crate::bitstream::BitStream;
+use crate::components::Components;
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::headers::{parse_dri, parse_huffman, parse_sos};
+use crate::idct::dequantize_and_idct_12bit;
+use crate::marker::Marker;
+use crate::mcu_prog::get_marker;
+use crate::misc::{read_u16_be, ColorSpace};
+use crate::warnings::marker_offset;
+use crate::worker::Orientation;
+use crate::Decoder;
+
+impl Decoder
+{
+    /// Decode an extended sequential image whose precision is greater than 8 bits
+    ///
+    /// This expects the headers up to and including the first SOS to have been parsed.
+    pub(crate) fn decode_mcu_extended(
+        &mut self, reader: &mut Cursor<&[u8]>,
+    ) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let block = self.decode_sequential_coefficients(reader)?;
+
+        Ok(self.finish_extended_decoding(&block))
+    }
+
+    /// Decode the coefficients of every scan of a Huffman coded sequential image
+    ///
+    /// This expects the headers up to and including the first SOS to have been parsed.
+    pub(crate) fn decode_sequential_coefficients(
+        &mut self, reader: &mut Cursor<&[u8]>,
+    ) -> Result<[Vec<i16>; MAX_COMPONENTS], DecodeErrors>
+    {
+        let (mut block, _) = self.allocate_coefficient_buffers();
+
+        let mut stream = BitStream::new();
+
+        // there may be multiple scans in the stream, this decodes the first one.
+        self.decode_extended_scan(reader, &mut stream, &mut block)?;
+
+        while let Some(marker) = get_marker(reader, &mut stream)
+        {
+            match marker
+            {
+                Marker::EOI => break,
+                Marker::SOS =>
+                {
+                    parse_sos(reader, self)?;
+
+                    stream.reset();
+
+                    self.decode_extended_scan(reader, &mut stream, &mut block)?;
+                }
+                Marker::DHT =>
+                {
+                    parse_huffman(self, reader)?;
+                }
+                Marker::DRI =>
+                {
+                    parse_dri(self, reader)?;
+                }
+                Marker::RST(_) =>
+                {
+                    // A stray restart marker, no segment follows it.
+                    self.add_skipped_marker(
+                        marker.to_u8(),
+                        marker_offset(reader),
+                        "between scans",
+                    )?;
+                }
+                _ =>
+                {
+                    self.add_skipped_marker(
+                        marker.to_u8(),
+                        marker_offset(reader),
+                        "between scans",
+                    )?;
+
+                    let length = read_u16_be(reader)?;
+
+                    if length < 2
+                    {
+                        return Err(DecodeErrors::Format(format!(
+                            "Found a marker with invalid length:{length}\n"
+                        )));
+                    }
+                    reader.consume(usize::from(length - 2));
+                }
+            }
+        }
+
+        Ok(block)
+    }
+
+    /// Decode a single Huffman coded scan into `buffer`
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn decode_extended_scan(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream,
+        buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+    ) -> Result<(), DecodeErrors>
+    {
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+
+        self.reset_restart_count();
+
+        self.check_scan_tables()?;
+
+        if self.num_scans == 1
+        {
+            // non interleaved data, process one block at a time in trivial scanline order
+            let k = self.z_order[0];
+
+            let (blocks_x, blocks_y) = self.component_blocks(k);
+
+            let stride = self.components[k].width_stride / 8;
+
+            let mut first = true;
+
+            for i in 0..blocks_y
+            {
+                for j in 0..blocks_x
+                {
+                    if !first && self.todo == 0
+                    {
+                        self.handle_rst(reader, stream)?;
+                    }
+                    first = false;
+
+                    let start = 64 * (j + i * stride);
+
+                    let data: &mut [i16; 64] = buffer[k]
+                        .get_mut(start..start + 64)
+                        .ok_or(DecodeErrors::ExhaustedData)?
+                        .try_into()
+                        .unwrap();
+
+                    let component = &mut self.components[k];
+
+                    // tables were confirmed to exist above
+                    let dc_table = self.dc_huffman_tables[component.dc_huff_table]
+                        .as_ref()
+                        .unwrap();
+
+                    let ac_table = self.ac_huffman_tables[component.ac_huff_table]
+                        .as_ref()
+                        .unwrap();
+
+                    stream.decode_mcu_block(
+                        reader,
+                        dc_table,
+                        ac_table,
+                        data,
+                        &mut component.dc_pred,
+                    )?;
+
+                    self.todo = self.todo.saturating_sub(1);
+                }
+            }
+        }
+        else
+        {
+            // Interleaved scan, components are decoded in MCU order
+            for i in 0..self.mcu_y
+            {
+                for j in 0..self.mcu_x
+                {
+                    if (i != 0 || j != 0) && self.todo == 0
+                    {
+                        self.handle_rst(reader, stream)?;
+                    }
+
+                    for k in 0..usize::from(self.num_scans)
+                    {
+                        let n = self.z_order[k];
+
+                        let component = &mut self.components[n];
+
+                        let dc_table = self.dc_huffman_tables[component.dc_huff_table]
+                            .as_ref()
+                            .unwrap();
+
+                        let ac_table = self.ac_huffman_tables[component.ac_huff_table]
+                            .as_ref()
+                            .unwrap();
+
+                        for v_samp in 0..component.vertical_sample
+                        {
+                            for h_samp in 0..component.horizontal_sample
+                            {
+                                let x2 = j * component.horizontal_sample + h_samp;
+
+                                let y2 = i * component.vertical_sample + v_samp;
+
+                                let position = 64 * (x2 + y2 * component.width_stride / 8);
+
+                                let data: &mut [i16; 64] = buffer[n]
+                                    .get_mut(position..position + 64)
+                                    .ok_or(DecodeErrors::ExhaustedData)?
+                                    .try_into()
+                                    .unwrap();
+
+                                stream.decode_mcu_block(
+                                    reader,
+                                    dc_table,
+                                    ac_table,
+                                    data,
+                                    &mut component.dc_pred,
+                                )?;
+                            }
+                        }
+                    }
+                    self.todo = self.todo.saturating_sub(1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the Huffman tables used by the current scan exist
+    pub(crate) fn check_scan_tables(&self) -> Result<(), DecodeErrors>
+    {
+        for k in 0..usize::from(self.num_scans)
+        {
+            let component = &self.components[self.z_order[k]];
+
+            let dc_table = self.dc_huffman_tables.get(component.dc_huff_table);
+
+            let ac_table = self.ac_huffman_tables.get(component.ac_huff_table);
+
+            if !matches!(dc_table, Some(Some(_))) || !matches!(ac_table, Some(Some(_)))
+            {
+                return Err(DecodeErrors::HuffmanDecode(format!(
+                    "No Huffman table for component {:?}",
+                    component.component_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Carry out IDCT, up-sampling and color conversion on decoded coefficients
+    fn finish_extended_decoding(&self, block: &[Vec<i16>; MAX_COMPONENTS]) -> Vec<u16>
+    {
+        let width = usize::from(self.info.width);
+
+        let height = usize::from(self.info.height);
+
+        let num_components = self.needed_components();
+
+        let mut planes = [vec![], vec![], vec![], vec![]];
+
+        for (pos, plane) in planes.iter_mut().enumerate().take(num_components)
+        {
+            let component = &self.components[pos];
+
+            let samples = idct_plane(&block[pos], component);
+
+            // dimensions of this component, B.2.2 in the spec
+            let comp_width = (width * component.horizontal_sample).div_ceil(self.h_max);
+
+            let comp_height = (height * component.vertical_sample).div_ceil(self.v_max);
+
+            *plane = upsample_plane(
+                &samples,
+                component.width_stride,
+                (comp_width, comp_height),
+                (
+                    self.h_max / component.horizontal_sample,
+                    self.v_max / component.vertical_sample,
+                ),
+                (width, height),
+            );
+        }
+
+        self.color_convert_planes(&planes, self.output_orientation())
+    }
+
+    /// Color convert full resolution component planes, writing out samples in
+    /// the output colorspace, turned by `orientation` if any
+    pub(crate) fn color_convert_planes(
+        &self, planes: &[Vec<i16>; MAX_COMPONENTS], orientation: Option<Orientation>,
+    ) -> Vec<u16>
+    {
+        let channels = self.output_colorspace.num_components();
+
+        let mut output = vec![0; planes[0].len() * channels];
+
+        let Some(orientation) = orientation
+        else
+        {
+            self.color_convert_samples(planes, 0, &mut output);
+
+            return output;
+        };
+
+        // lines are converted one at a time and written to where they end up
+        let (width, _) = self.decoded_dimensions();
+
+        let mut line = vec![0; width * channels];
+
+        for y in 0..planes[0].len() / width.max(1)
+        {
+            self.color_convert_samples(planes, y * width, &mut line);
+
+            orientation.write_rows(&line, y, channels, &mut output);
+        }
+        output
+    }
+
+    /// Color convert as many pixels of `planes` as fit in `output`, starting at
+    /// pixel `start`
+    fn color_convert_samples(
+        &self, planes: &[Vec<i16>; MAX_COMPONENTS], start: usize, output: &mut [u16],
+    )
+    {
+        let max_sample = (1_i32 << self.info.pixel_density) - 1;
+
+        let planes: [&[i16]; MAX_COMPONENTS] =
+            std::array::from_fn(|k| planes[k].get(start..).unwrap_or(&[]));
+
+        match (self.input_colorspace, self.output_colorspace)
+        {
+            (ColorSpace::YCbCr | ColorSpace::GRAYSCALE, ColorSpace::GRAYSCALE) =>
+            {
+                for (out, y) in output.iter_mut().zip(planes[0].iter())
+                {
+                    *out = *y as u16;
+                }
+            }
+            (ColorSpace::YCbCr, ColorSpace::YCbCr)
+            | (ColorSpace::RGB, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+            {
+                // samples are copied as they are
+                let stride = self.output_colorspace.num_components();
+
+                for (((out, y), cb), cr) in output
+                    .chunks_exact_mut(stride)
+                    .zip(planes[0].iter())
+                    .zip(planes[1].iter())
+                    .zip(planes[2].iter())
+                {
+                    out[0] = *y as u16;
+                    out[1] = *cb as u16;
+                    out[2] = *cr as u16;
+
+                    if let Some(alpha) = out.get_mut(3)
+                    {
+                        *alpha = max_sample as u16;
+                    }
+                }
+            }
+            (ColorSpace::YCbCr, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+            {
+                let stride = self.output_colorspace.num_components();
+
+                for (((out, y), cb), cr) in output
+                    .chunks_exact_mut(stride)
+                    .zip(planes[0].iter())
+                    .zip(planes[1].iter())
+                    .zip(planes[2].iter())
+                {
+                    ycbcr_to_rgb(*y, *cb, *cr, max_sample, out);
+                }
+            }
+            (
+                ColorSpace::CMYK | ColorSpace::YCCK,
+                ColorSpace::CMYK | ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX,
+            ) =>
+            {
+                let stride = self.output_colorspace.num_components();
+
+                // Adobe applications store CMYK inverted, 0 being full ink
+                let inverted = self.adobe_transform.is_some();
+
+                let mut ink = [0; 4];
+
+                for (i, out) in output.chunks_exact_mut(stride).enumerate()
+                {
+                    if self.input_colorspace == ColorSpace::YCCK
+                    {
+                        // Y, Cb and Cr were converted from R, G and B, the complements
+                        // of C, M and Y as stored
+                        ycbcr_to_rgb(
+                            planes[0][i],
+                            planes[1][i],
+                            planes[2][i],
+                            max_sample,
+                            &mut ink,
+                        );
+
+                        for x in &mut ink[..3]
+                        {
+                            *x = max_sample as u16 - *x;
+                        }
+
+                        ink[3] = planes[3][i] as u16;
+                    }
+                    else
+                    {
+                        for (ink, plane) in ink.iter_mut().zip(planes)
+                        {
+                            *ink = plane[i] as u16;
+                        }
+                    }
+
+                    if inverted
+                    {
+                        for x in &mut ink
+                        {
+                            *x = max_sample as u16 - *x;
+                        }
+                    }
+
+                    if self.output_colorspace == ColorSpace::CMYK
+                    {
+                        out.copy_from_slice(&ink);
+                    }
+                    else
+                    {
+                        cmyk_to_rgb(ink, max_sample, out);
+                    }
+                }
+            }
+            // `check_colorspace` rejects the others
+            _ => unreachable!(
+                "Cannot convert {:?} to {:?}",
+                self.input_colorspace, self.output_colorspace
+            ),
+        }
+    }
+}
+
+/// Carry out IDCT on all blocks of a component
+///
+/// Returns a plane of samples whose width is `component.width_stride`
+fn idct_plane(coefficients: &[i16], component: &Components) -> Vec<i16>
+{
+    let stride = component.width_stride;
+
+    let mut plane = vec![0; coefficients.len()];
+
+    // each row of blocks takes 8 rows of samples
+    for (in_row, out_row) in coefficients
+        .chunks_exact(stride * 8)
+        .zip(plane.chunks_exact_mut(stride * 8))
+    {
+        for (x, block) in in_row.chunks_exact(64).enumerate()
+        {
+            dequantize_and_idct_12bit(
+                block.try_into().unwrap(),
+                &component.quantization_table,
+                &mut out_row[x * 8..],
+                stride,
+            );
+        }
+    }
+    plane
+}
+
+/// Up-sample a component plane to the full image resolution
+///
+/// # Arguments
+/// - input: Samples of the component, rows are `stride` samples apart
+/// - `(in_width,in_height)`: Dimensions of the component
+/// - `(h_ratio,v_ratio)`: Up-sampling ratios in the horizontal and vertical direction
+/// - `(width,height)`: Dimensions of the image, output rows are `width` samples apart
+pub(crate) fn upsample_plane(
+    input: &[i16], stride: usize, (in_width, in_height): (usize, usize),
+    (h_ratio, v_ratio): (usize, usize), (width, height): (usize, usize),
+) -> Vec<i16>
+{
+    let mut output = vec![0; width * height];
+
+    // Row `y` of the input, rows past the end of the component
+    // are replaced with the last row, as libjpeg does
+    let row = |y: usize| -> &[i16] {
+        let y = y.min(in_height - 1);
+
+        &input[y * stride..y * stride + in_width]
+    };
+
+    let mut scratch = vec![0_i32; in_width];
+
+    let mut upsampled = vec![0_i16; in_width * 2];
+
+    for (y, out) in output.chunks_exact_mut(width).enumerate()
+    {
+        let near = row(y / v_ratio);
+
+        match (h_ratio, v_ratio)
+        {
+            (1, 1) => out.copy_from_slice(&near[..width]),
+            (2, 1) =>
+            {
+                for (sum, near) in scratch.iter_mut().zip(near)
+                {
+                    *sum = i32::from(*near) * 4;
+                }
+                upsample_row_h2(&scratch, &mut upsampled);
+
+                out.copy_from_slice(&upsampled[..width]);
+            }
+            (1 | 2, 2) =>
+            {
+                // upper output rows are mixed with the row above, lower ones with the row
+                // below
+                let far = if y % 2 == 0
+                {
+                    row((y / 2).saturating_sub(1))
+                }
+                else
+                {
+                    row(y / 2 + 1)
+                };
+
+                for ((sum, near), far) in scratch.iter_mut().zip(near).zip(far)
+                {
+                    *sum = i32::from(*near) * 3 + i32::from(*far);
+                }
+
+                if h_ratio == 1
+                {
+                    let bias = if y % 2 == 0 { 1 } else { 2 };
+
+                    for (out, sum) in out.iter_mut().zip(scratch.iter())
+                    {
+                        *out = ((sum + bias) >> 2) as i16;
+                    }
+                }
+                else
+                {
+                    upsample_row_h2(&scratch, &mut upsampled);
+
+                    out.copy_from_slice(&upsampled[..width]);
+                }
+            }
+            _ =>
+            {
+                // pixel replication for unusual ratios
+                for (x, out) in out.iter_mut().enumerate()
+                {
+                    *out = near[(x / h_ratio).min(in_width - 1)];
+                }
+            }
+        }
+    }
+    output
+}
+
+/// Horizontally up-sample a row of column sums (samples pre-multiplied by 4)
+/// with a triangle filter, writing `2*input.len()` values to `output`
+fn upsample_row_h2(input: &[i32], output: &mut [i16])
+{
+    if input.len() == 1
+    {
+        output[0] = ((input[0] * 4 + 8) >> 4) as i16;
+        output[1] = output[0];
+
+        return;
+    }
+    let last = input.len() - 1;
+
+    output[0] = ((input[0] * 4 + 8) >> 4) as i16;
+
+    output[1] = ((input[0] * 3 + input[1] + 7) >> 4) as i16;
+
+    for x in 1..last
+    {
+        output[x * 2] = ((input[x] * 3 + input[x - 1] + 8) >> 4) as i16;
+
+        output[x * 2 + 1] = ((input[x] * 3 + input[x + 1] + 7) >> 4) as i16;
+    }
+
+    output[last * 2] = ((input[last] * 3 + input[last - 1] + 8) >> 4) as i16;
+
+    output[last * 2 + 1] = ((input[last] * 4 + 7) >> 4) as i16;
+}
+
+/// Convert a single YCbCr pixel to RGB(A/X)
+///
+/// This uses the same fixed point constants as libjpeg, any fourth component is set to
+/// `max_sample`, the largest value a sample can take.
+fn ycbcr_to_rgb(y: i16, cb: i16, cr: i16, max_sample: i32, output: &mut [u16])
+{
+    const SCALE_BITS: i32 = 16;
+
+    const ONE_HALF: i32 = 1 << (SCALE_BITS - 1);
+
+    let y = i32::from(y);
+
+    // center of the sample range, used for level shifting chroma values
+    let center = (max_sample + 1) / 2;
+
+    let cb = i32::from(cb) - center;
+
+    let cr = i32::from(cr) - center;
+
+    let r = y + ((91881 * cr + ONE_HALF) >> SCALE_BITS);
+
+    let g = y + ((-22554 * cb + ONE_HALF - 46802 * cr) >> SCALE_BITS);
+
+    let b = y + ((116_130 * cb + ONE_HALF) >> SCALE_BITS);
+
+    output[0] = r.clamp(0, max_sample) as u16;
+
+    output[1] = g.clamp(0, max_sample) as u16;
+
+    output[2] = b.clamp(0, max_sample) as u16;
+
+    if let Some(alpha) = output.get_mut(3)
+    {
+        *alpha = max_sample as u16;
+    }
+}
+
+/// Convert a single CMYK pixel, 0 being no ink, to RGB(A/X)
+///
+/// Inks are taken to filter out their share of the light, there is no color
+/// management, any fourth component is set to `max_sample`.
+fn cmyk_to_rgb([c, m, y, k]: [u16; 4], max_sample: i32, output: &mut [u16])
+{
+    let white = i32::from(max_sample as u16 - k);
+
+    for (out, ink) in output.iter_mut().zip([c, m, y])
+    {
+        let light = (max_sample - i32::from(ink)) * white;
+
+        *out = ((light + max_sample / 2) / max_sample) as u16;
+    }
+
+    if let Some(alpha) = output.get_mut(3)
+    {
+        *alpha = max_sample as u16;
+    }
+}
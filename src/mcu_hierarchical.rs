@@ -0,0 +1,618 @@
+//! Routines for decoding hierarchical images
+//!
+//! Hierarchical images (Annex J of the spec) are coded as a sequence of frames, the first
+//! one codes the image at a (usually) low resolution, and each following differential
+//! frame codes the difference between the image at its resolution and the frame before
+//! it, which may be expanded by two horizontally and/or vertically first (the EXP marker).
+//!
+//! Frames may use any of the Huffman coded processes, so we decode each frame into
+//! whole component planes, add them to the expanded reference and carry on to the next
+//! frame. Once the last frame is done, components are up-sampled and color converted
+//! like extended sequential images, or written out as is if the last frame was lossless.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use std::io::{BufRead, Cursor};
+
+use crate::bitstream::BitStream;
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::headers::{
+    parse_dqt, parse_dri, parse_exp, parse_huffman, parse_sos, parse_start_of_frame,
+};
+use crate::idct::dequantize_and_idct_precise;
+use crate::marker::Marker;
+use crate::mcu_extended::upsample_plane;
+use crate::mcu_prog::get_marker;
+use crate::misc::{read_u16_be, SOFMarkers};
+use crate::warnings::marker_offset;
+use crate::Decoder;
+
+/// Final dimensions and precision of a hierarchical image, from its DHP segment
+#[derive(Clone, Copy)]
+pub(crate) struct HierarchicalInfo
+{
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    pub(crate) precision: u8,
+    pub(crate) components: u8,
+}
+
+/// What the scans of a single frame are decoded into
+enum FrameBuffer
+{
+    /// Coefficients of DCT based frames, laid out as `allocate_coefficient_buffers` does
+    Coefficients([Vec<i16>; MAX_COMPONENTS]),
+    /// Samples of lossless frames, laid out as `lossless_plane_dimensions` does
+    Samples(Vec<Vec<u16>>),
+}
+
+/// A decoded component of a frame
+struct Plane
+{
+    samples: Vec<i32>,
+    width: usize,
+    height: usize,
+}
+
+impl Plane
+{
+    /// Up-sample the plane by two in the directions given by `expand` (J.1.1.2), then
+    /// crop it or extend it by replicating edge samples to `(width,height)`
+    fn expand(
+        &self, (horizontal, vertical): (bool, bool), (width, height): (usize, usize),
+    ) -> Vec<i32>
+    {
+        let sample = |x: usize, y: usize| {
+            self.samples[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+        };
+
+        let mut output = Vec::with_capacity(width * height);
+
+        for y in 0..height
+        {
+            // odd output rows and columns are the average of their two neighbours,
+            // for even ones both neighbours are the same sample.
+            let (y0, y1) = if vertical
+            {
+                (y / 2, y.div_ceil(2))
+            }
+            else
+            {
+                (y, y)
+            };
+
+            for x in 0..width
+            {
+                let (x0, x1) = if horizontal
+                {
+                    (x / 2, x.div_ceil(2))
+                }
+                else
+                {
+                    (x, x)
+                };
+
+                let upper = (sample(x0, y0) + sample(x1, y0)) >> 1;
+
+                let lower = (sample(x0, y1) + sample(x1, y1)) >> 1;
+
+                output.push((upper + lower) >> 1);
+            }
+        }
+        output
+    }
+}
+
+impl Decoder
+{
+    /// Decode a hierarchical image
+    ///
+    /// This expects the headers up to and including the first SOS of the first frame to
+    /// have been parsed.
+    pub(crate) fn decode_mcu_hierarchical(
+        &mut self, reader: &mut Cursor<&[u8]>,
+    ) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let mut stream = BitStream::new();
+
+        let mut reference: Option<Vec<Plane>> = None;
+
+        let mut expand = (false, false);
+
+        loop
+        {
+            self.check_hierarchical_frame()?;
+
+            let (planes, mut marker) = self.decode_frame(reader, &mut stream)?;
+
+            let planes = if self.info.sof.is_differential()
+            {
+                let reference = reference.take().ok_or_else(|| {
+                    DecodeErrors::Format(
+                        "Differential frame found before the first frame".to_string(),
+                    )
+                })?;
+
+                self.add_reference(planes, &reference, expand)?
+            }
+            else
+            {
+                planes
+            };
+
+            reference = Some(planes);
+
+            expand = (false, false);
+
+            // parse segments up to the first scan of the next frame
+            let mut new_frame = false;
+
+            loop
+            {
+                match marker
+                {
+                    None | Some(Marker::EOI) =>
+                    {
+                        if new_frame
+                        {
+                            // the planes are those of the previous frame
+                            return Err(DecodeErrors::Format(
+                                "Hierarchical image ended in a frame without scans".to_string(),
+                            ));
+                        }
+                        return self.finish_hierarchical_decoding(&reference.unwrap_or_default());
+                    }
+                    Some(Marker::SOS) =>
+                    {
+                        if !new_frame
+                        {
+                            return Err(DecodeErrors::SosError(
+                                "Scan found outside a frame".to_string(),
+                            ));
+                        }
+                        parse_sos(reader, self)?;
+
+                        break;
+                    }
+                    Some(Marker::SOF(n)) =>
+                    {
+                        // SOF(n) markers are 0xFFC0 + n
+                        let sof = SOFMarkers::from_int(0xFFC0 + u16::from(n)).ok_or_else(|| {
+                            DecodeErrors::Format(format!(
+                                "Unsupported frame SOF({n}) in hierarchical image"
+                            ))
+                        })?;
+                        info!("Hierarchical frame encoding scheme =`{sof:?}`");
+
+                        self.is_progressive = sof.is_progressive();
+
+                        parse_start_of_frame(reader, sof, self)?;
+
+                        new_frame = true;
+                    }
+                    Some(Marker::EXP) =>
+                    {
+                        expand = parse_exp(reader)?;
+                    }
+                    Some(Marker::DQT) =>
+                    {
+                        parse_dqt(self, reader)?;
+                    }
+                    Some(Marker::DHT) =>
+                    {
+                        parse_huffman(self, reader)?;
+                    }
+                    Some(Marker::DRI) =>
+                    {
+                        parse_dri(self, reader)?;
+                    }
+                    // a stray restart marker, no segment follows it
+                    Some(Marker::RST(n)) =>
+                    {
+                        self.add_skipped_marker(0xD0 + n, marker_offset(reader), "between frames")?;
+                    }
+                    Some(other) =>
+                    {
+                        self.skip_segment(reader, other)?;
+                    }
+                }
+                marker = get_marker(reader, &mut stream);
+            }
+        }
+    }
+
+    /// Check that the frame whose header was just parsed fits in the image
+    /// described by the DHP segment
+    fn check_hierarchical_frame(&self) -> Result<(), DecodeErrors>
+    {
+        let hierarchy = self.hierarchical.ok_or_else(|| {
+            DecodeErrors::Format("No DHP segment found in hierarchical image".to_string())
+        })?;
+
+        if self.info.sof.is_arithmetic()
+        {
+            return Err(DecodeErrors::Format(
+                "Arithmetic coded hierarchical images are not supported".to_string(),
+            ));
+        }
+        if self.info.pixel_density != hierarchy.precision
+        {
+            return Err(DecodeErrors::SofError(format!(
+                "Frame precision {} differs from the image precision {}",
+                self.info.pixel_density, hierarchy.precision
+            )));
+        }
+        if self.info.width > hierarchy.width || self.info.height > hierarchy.height
+        {
+            return Err(DecodeErrors::SofError(format!(
+                "Frame dimensions {}x{} are larger than the image dimensions {}x{}",
+                self.info.width, self.info.height, hierarchy.width, hierarchy.height
+            )));
+        }
+        if self.components.len() != usize::from(hierarchy.components)
+        {
+            return Err(DecodeErrors::SofError(format!(
+                "Frame has {} components, expected {}",
+                self.components.len(),
+                hierarchy.components
+            )));
+        }
+        if !self.info.sof.is_lossless() && !matches!(self.components.len(), 1 | 3)
+        {
+            return Err(DecodeErrors::Format(format!(
+                "DCT frames with {} components are not supported",
+                self.components.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Decode all scans of the current frame into component planes
+    ///
+    /// Returns the planes and the marker that ended the frame, `None` if we ran out of data
+    fn decode_frame(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream,
+    ) -> Result<(Vec<Plane>, Option<Marker>), DecodeErrors>
+    {
+        let mut buffer = if self.info.sof.is_lossless()
+        {
+            FrameBuffer::Samples(
+                (0..self.components.len())
+                    .map(|k| {
+                        let (width, height) = self.lossless_plane_dimensions(k);
+
+                        vec![0; width * height]
+                    })
+                    .collect(),
+            )
+        }
+        else
+        {
+            FrameBuffer::Coefficients(self.allocate_coefficient_buffers().0)
+        };
+
+        stream.reset();
+
+        // there may be multiple scans in the frame, this decodes the first one.
+        self.decode_frame_scan(reader, stream, &mut buffer)?;
+
+        let marker = loop
+        {
+            match get_marker(reader, stream)
+            {
+                Some(Marker::SOS) =>
+                {
+                    parse_sos(reader, self)?;
+
+                    stream.reset();
+
+                    self.decode_frame_scan(reader, stream, &mut buffer)?;
+                }
+                Some(Marker::DQT) =>
+                {
+                    // tables for the next frame, this one already has its tables
+                    parse_dqt(self, reader)?;
+                }
+                Some(Marker::DHT) =>
+                {
+                    parse_huffman(self, reader)?;
+                }
+                Some(Marker::DRI) =>
+                {
+                    parse_dri(self, reader)?;
+                }
+                Some(Marker::RST(n)) =>
+                {
+                    // A stray restart marker, no segment follows it.
+                    self.add_skipped_marker(0xD0 + n, marker_offset(reader), "between scans")?;
+                }
+                Some(marker @ (Marker::APP(_) | Marker::COM)) =>
+                {
+                    self.skip_segment(reader, marker)?;
+                }
+                // anything else ends the frame
+                marker => break marker,
+            }
+        };
+
+        let planes = match buffer
+        {
+            FrameBuffer::Coefficients(block) => self.coefficients_to_planes(&block),
+            FrameBuffer::Samples(samples) => self.samples_to_planes(&samples),
+        };
+
+        Ok((planes, marker))
+    }
+
+    /// Decode a single scan of the current frame into `buffer`
+    fn decode_frame_scan(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream, buffer: &mut FrameBuffer,
+    ) -> Result<(), DecodeErrors>
+    {
+        match buffer
+        {
+            FrameBuffer::Samples(planes) => self.decode_lossless_scan(reader, stream, planes),
+            FrameBuffer::Coefficients(block) if self.info.sof.is_progressive() =>
+            {
+                stream.update_progressive_params(
+                    self.succ_high,
+                    self.succ_low,
+                    self.spec_start,
+                    self.spec_end,
+                );
+
+                self.reset_restart_count();
+
+                self.parse_entropy_coded_data(reader, stream, block)
+                    .map(|_| ())
+            }
+            FrameBuffer::Coefficients(block) => self.decode_extended_scan(reader, stream, block),
+        }
+    }
+
+    /// Dimensions of component `k` of the current frame, B.2.2 in the spec
+    fn component_dimensions(&self, k: usize) -> (usize, usize)
+    {
+        let component = &self.components[k];
+
+        (
+            (usize::from(self.info.width) * component.horizontal_sample).div_ceil(self.h_max),
+            (usize::from(self.info.height) * component.vertical_sample).div_ceil(self.v_max),
+        )
+    }
+
+    /// Carry out IDCT on the coefficients of a DCT based frame
+    ///
+    /// Samples of differential frames are differences, so they are neither level shifted
+    /// nor clamped.
+    fn coefficients_to_planes(&self, block: &[Vec<i16>; MAX_COMPONENTS]) -> Vec<Plane>
+    {
+        let max_sample = (1_i32 << self.info.pixel_density) - 1;
+
+        let differential = self.info.sof.is_differential();
+
+        (0..self.components.len())
+            .map(|k| {
+                let component = &self.components[k];
+
+                let (width, height) = self.component_dimensions(k);
+
+                let mut samples = vec![0; width * height];
+
+                let blocks = block[k]
+                    .chunks_exact(component.width_stride * 8)
+                    .take(height.div_ceil(8));
+
+                for (i, row) in blocks.enumerate()
+                {
+                    for (j, coefficients) in
+                        row.chunks_exact(64).take(width.div_ceil(8)).enumerate()
+                    {
+                        let values = dequantize_and_idct_precise(
+                            coefficients.try_into().unwrap(),
+                            &component.quantization_table,
+                        );
+
+                        for (y, values) in values.chunks_exact(8).enumerate()
+                        {
+                            let y = i * 8 + y;
+
+                            if y >= height
+                            {
+                                break;
+                            }
+                            for (x, value) in values.iter().enumerate()
+                            {
+                                let x = j * 8 + x;
+
+                                if x >= width
+                                {
+                                    break;
+                                }
+                                samples[y * width + x] = if differential
+                                {
+                                    *value
+                                }
+                                else
+                                {
+                                    (*value + (max_sample + 1) / 2).clamp(0, max_sample)
+                                };
+                            }
+                        }
+                    }
+                }
+                Plane {
+                    samples,
+                    width,
+                    height,
+                }
+            })
+            .collect()
+    }
+
+    /// Crop samples of a lossless frame to the dimensions of each component
+    fn samples_to_planes(&self, samples: &[Vec<u16>]) -> Vec<Plane>
+    {
+        samples
+            .iter()
+            .enumerate()
+            .map(|(k, plane)| {
+                let (width, height) = self.component_dimensions(k);
+
+                let (stride, _) = self.lossless_plane_dimensions(k);
+
+                let samples = plane
+                    .chunks_exact(stride)
+                    .take(height)
+                    .flat_map(|row| row[..width].iter().map(|x| i32::from(*x)))
+                    .collect();
+
+                Plane {
+                    samples,
+                    width,
+                    height,
+                }
+            })
+            .collect()
+    }
+
+    /// Add the (expanded) reference frame to the differences decoded from a
+    /// differential frame
+    fn add_reference(
+        &self, planes: Vec<Plane>, reference: &[Plane], expand: (bool, bool),
+    ) -> Result<Vec<Plane>, DecodeErrors>
+    {
+        if planes.len() != reference.len()
+        {
+            return Err(DecodeErrors::Format(
+                "Frames of hierarchical image have a different number of components".to_string(),
+            ));
+        }
+        let max_sample = (1_i32 << self.info.pixel_density) - 1;
+
+        let lossless = self.info.sof.is_lossless();
+
+        Ok(planes
+            .into_iter()
+            .zip(reference)
+            .map(|(mut plane, reference)| {
+                let expanded = reference.expand(expand, (plane.width, plane.height));
+
+                for (sample, reference) in plane.samples.iter_mut().zip(expanded)
+                {
+                    // lossless frames are reconstructed modulo 2^16 (J.2.2)
+                    *sample = if lossless
+                    {
+                        (*sample + reference) & 0xFFFF
+                    }
+                    else
+                    {
+                        (*sample + reference).clamp(0, max_sample)
+                    };
+                }
+                plane
+            })
+            .collect())
+    }
+
+    /// Write out the planes of the last frame
+    fn finish_hierarchical_decoding(&self, planes: &[Plane]) -> Result<Vec<u16>, DecodeErrors>
+    {
+        if let Some(hierarchy) = self.hierarchical
+        {
+            if (self.info.width, self.info.height) != (hierarchy.width, hierarchy.height)
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Hierarchical image ended at {}x{}, expected {}x{}",
+                    self.info.width, self.info.height, hierarchy.width, hierarchy.height
+                )));
+            }
+        }
+        let width = usize::from(self.info.width);
+
+        let height = usize::from(self.info.height);
+
+        if self.info.sof.is_lossless()
+        {
+            // Like lossless images, samples are not color converted and components
+            // are up-sampled by pixel replication
+            let num_components = planes.len();
+
+            let mut output = vec![0; width * height * num_components];
+
+            for (k, plane) in planes.iter().enumerate()
+            {
+                let component = &self.components[k];
+
+                for (y, row) in output.chunks_exact_mut(width * num_components).enumerate()
+                {
+                    let in_row = &plane.samples
+                        [(y * component.vertical_sample / self.v_max) * plane.width..];
+
+                    for (x, pixel) in row.chunks_exact_mut(num_components).enumerate()
+                    {
+                        pixel[k] = in_row[x * component.horizontal_sample / self.h_max] as u16;
+                    }
+                }
+            }
+            return Ok(output);
+        }
+
+        // not checked up front, in case the last frame was lossless
+        self.check_color_conversion()?;
+
+        let num_components = self.needed_components();
+
+        let mut upsampled = [vec![], vec![], vec![], vec![]];
+
+        for (k, (out, plane)) in upsampled
+            .iter_mut()
+            .zip(planes)
+            .enumerate()
+            .take(num_components)
+        {
+            let component = &self.components[k];
+
+            let samples: Vec<i16> = plane.samples.iter().map(|x| *x as i16).collect();
+
+            *out = upsample_plane(
+                &samples,
+                plane.width,
+                (plane.width, plane.height),
+                (
+                    self.h_max / component.horizontal_sample,
+                    self.v_max / component.vertical_sample,
+                ),
+                (width, height),
+            );
+        }
+
+        // turned once cropped
+        Ok(self.color_convert_planes(&upsampled, None))
+    }
+
+    /// Skip a segment we have no use for, warning about it unless it is an
+    /// application segment or a comment
+    fn skip_segment(
+        &mut self, reader: &mut Cursor<&[u8]>, marker: Marker,
+    ) -> Result<(), DecodeErrors>
+    {
+        self.add_skipped_marker(
+            marker.to_u8(),
+            marker_offset(reader),
+            "in hierarchical image",
+        )?;
+
+        let length = read_u16_be(reader)?;
+
+        if length < 2
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Found a marker with invalid length:{length}\n"
+            )));
+        }
+        reader.consume(usize::from(length - 2));
+
+        Ok(())
+    }
+}
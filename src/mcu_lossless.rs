@@ -0,0 +1,383 @@
+//! Routines for decoding lossless (SOF3) images
+//!
+//! Lossless images do not use the DCT, instead each sample is predicted from
+//! its already decoded neighbours and the Huffman coded difference between the
+//! prediction and the actual value is stored in the bitstream (Annex H of the spec).
+//!
+//! These images come mostly from DNG camera raw files and medical (DICOM) images and can
+//! have anywhere between 2 and 16 bits per sample, so samples are written out as `u16`'s.
+//!
+//! Samples are not color converted, each component is written out as is, and components
+//! with smaller sampling factors are up-sampled by pixel replication.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use std::io::{BufRead, Cursor};
+
+use crate::bitstream::BitStream;
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::headers::{parse_dri, parse_huffman, parse_sos};
+use crate::marker::Marker;
+use crate::mcu_prog::get_marker;
+use crate::misc::read_u16_be;
+use crate::warnings::marker_offset;
+use crate::Decoder;
+
+impl Decoder
+{
+    /// Decode a lossless image
+    ///
+    /// This expects the headers up to and including the first SOS to have been parsed.
+    pub(crate) fn decode_mcu_lossless(
+        &mut self, reader: &mut Cursor<&[u8]>,
+    ) -> Result<Vec<u16>, DecodeErrors>
+    {
+        // Planes are as big as the MCU's covering the image, since
+        // interleaved scans also code samples past the image edges.
+        let mut planes: Vec<Vec<u16>> = (0..self.components.len())
+            .map(|k| {
+                let (width, height) = self.lossless_plane_dimensions(k);
+
+                vec![0; width * height]
+            })
+            .collect();
+
+        let mut stream = BitStream::new();
+
+        // there may be multiple scans in the stream, this decodes the first one.
+        self.decode_lossless_scan(reader, &mut stream, &mut planes)?;
+
+        while let Some(marker) = get_marker(reader, &mut stream)
+        {
+            match marker
+            {
+                Marker::EOI => break,
+                Marker::SOS =>
+                {
+                    parse_sos(reader, self)?;
+
+                    stream.reset();
+
+                    self.decode_lossless_scan(reader, &mut stream, &mut planes)?;
+                }
+                Marker::DHT =>
+                {
+                    parse_huffman(self, reader)?;
+                }
+                Marker::DRI =>
+                {
+                    parse_dri(self, reader)?;
+                }
+                Marker::RST(_) =>
+                {
+                    // A stray restart marker, no segment follows it.
+                    self.add_skipped_marker(
+                        marker.to_u8(),
+                        marker_offset(reader),
+                        "between scans",
+                    )?;
+                }
+                _ =>
+                {
+                    self.add_skipped_marker(
+                        marker.to_u8(),
+                        marker_offset(reader),
+                        "between scans",
+                    )?;
+
+                    let length = read_u16_be(reader)?;
+
+                    if length < 2
+                    {
+                        return Err(DecodeErrors::Format(format!(
+                            "Found a marker with invalid length:{length}\n"
+                        )));
+                    }
+                    reader.consume(usize::from(length - 2));
+                }
+            }
+        }
+
+        Ok(self.finish_lossless_decoding(&planes))
+    }
+
+    /// Width and height of the plane holding samples of component `k`
+    pub(crate) fn lossless_plane_dimensions(&self, k: usize) -> (usize, usize)
+    {
+        let component = &self.components[k];
+
+        // In lossless mode, a data unit is a single sample
+        let mcu_x = usize::from(self.info.width).div_ceil(self.h_max);
+
+        let mcu_y = usize::from(self.info.height).div_ceil(self.v_max);
+
+        (
+            mcu_x * component.horizontal_sample,
+            mcu_y * component.vertical_sample,
+        )
+    }
+
+    /// Decode a single lossless scan into `planes`
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn decode_lossless_scan(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream, planes: &mut [Vec<u16>],
+    ) -> Result<(), DecodeErrors>
+    {
+        let predictor = self.spec_start;
+
+        let point_transform = self.succ_low;
+
+        let precision = self.info.pixel_density;
+
+        // differential frames of hierarchical images may also code
+        // differences without prediction
+        let first_predictor = u8::from(!self.info.sof.is_differential());
+
+        if !(first_predictor..=7).contains(&predictor)
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Invalid lossless predictor {predictor}, expected a value between {first_predictor} and 7"
+            )));
+        }
+        if point_transform >= precision
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Point transform {point_transform} is too large for {precision} bit samples"
+            )));
+        }
+
+        for k in 0..usize::from(self.num_scans)
+        {
+            let component = &self.components[self.z_order[k]];
+
+            if !matches!(
+                self.dc_huffman_tables.get(component.dc_huff_table),
+                Some(Some(_))
+            )
+            {
+                return Err(DecodeErrors::HuffmanDecode(format!(
+                    "No Huffman table for component {:?}",
+                    component.component_id
+                )));
+            }
+        }
+
+        // Prediction for the first sample of the scan and of each restart interval
+        let initial = 1_i32 << (precision - point_transform - 1);
+
+        // The first line of a scan and of each restart interval is predicted
+        // from the left sample only.
+        let mut first_line = [0_usize; MAX_COMPONENTS];
+
+        self.reset_restart_count();
+
+        if self.num_scans == 1
+        {
+            // non interleaved data, samples are in trivial scanline order
+            let k = self.z_order[0];
+
+            let component = &self.components[k];
+
+            let width =
+                (usize::from(self.info.width) * component.horizontal_sample).div_ceil(self.h_max);
+
+            let height =
+                (usize::from(self.info.height) * component.vertical_sample).div_ceil(self.v_max);
+
+            let stride = self.lossless_plane_dimensions(k).0;
+
+            for y in 0..height
+            {
+                for x in 0..width
+                {
+                    if (x != 0 || y != 0) && self.todo == 0
+                    {
+                        self.handle_rst(reader, stream)?;
+
+                        first_line[k] = y;
+                    }
+
+                    let table = self.dc_huffman_tables[self.components[k].dc_huff_table]
+                        .as_ref()
+                        .unwrap();
+
+                    let diff = stream.decode_lossless_diff(reader, table)?;
+
+                    let prediction = predict(
+                        &planes[k],
+                        stride,
+                        (x, y),
+                        first_line[k],
+                        predictor,
+                        initial,
+                    );
+
+                    planes[k][y * stride + x] = (prediction + diff) as u16;
+
+                    self.todo = self.todo.saturating_sub(1);
+                }
+            }
+        }
+        else
+        {
+            // Interleaved scan, components are decoded in MCU order
+            let mcu_x = usize::from(self.info.width).div_ceil(self.h_max);
+
+            let mcu_y = usize::from(self.info.height).div_ceil(self.v_max);
+
+            for i in 0..mcu_y
+            {
+                for j in 0..mcu_x
+                {
+                    if (i != 0 || j != 0) && self.todo == 0
+                    {
+                        self.handle_rst(reader, stream)?;
+
+                        for k in 0..usize::from(self.num_scans)
+                        {
+                            let n = self.z_order[k];
+
+                            first_line[n] = i * self.components[n].vertical_sample;
+                        }
+                    }
+
+                    for k in 0..usize::from(self.num_scans)
+                    {
+                        let n = self.z_order[k];
+
+                        let (stride, _) = self.lossless_plane_dimensions(n);
+
+                        let component = &self.components[n];
+
+                        let table = self.dc_huffman_tables[component.dc_huff_table]
+                            .as_ref()
+                            .unwrap();
+
+                        for v_samp in 0..component.vertical_sample
+                        {
+                            for h_samp in 0..component.horizontal_sample
+                            {
+                                let x = j * component.horizontal_sample + h_samp;
+
+                                let y = i * component.vertical_sample + v_samp;
+
+                                let diff = stream.decode_lossless_diff(reader, table)?;
+
+                                let prediction = predict(
+                                    &planes[n],
+                                    stride,
+                                    (x, y),
+                                    first_line[n],
+                                    predictor,
+                                    initial,
+                                );
+
+                                planes[n][y * stride + x] = (prediction + diff) as u16;
+                            }
+                        }
+                    }
+                    self.todo = self.todo.saturating_sub(1);
+                }
+            }
+        }
+
+        // undo the point transform for components in this scan
+        if point_transform != 0
+        {
+            for k in 0..usize::from(self.num_scans)
+            {
+                planes[self.z_order[k]]
+                    .iter_mut()
+                    .for_each(|x| *x <<= point_transform);
+            }
+        }
+        Ok(())
+    }
+
+    /// Interleave decoded planes into the output, up-sampling components if needed
+    fn finish_lossless_decoding(&self, planes: &[Vec<u16>]) -> Vec<u16>
+    {
+        let width = usize::from(self.info.width);
+
+        let height = usize::from(self.info.height);
+
+        let num_components = planes.len();
+
+        let mut output = vec![0; width * height * num_components];
+
+        for (k, plane) in planes.iter().enumerate()
+        {
+            let component = &self.components[k];
+
+            let (stride, _) = self.lossless_plane_dimensions(k);
+
+            for (y, row) in output.chunks_exact_mut(width * num_components).enumerate()
+            {
+                let in_row = &plane[(y * component.vertical_sample / self.v_max) * stride..];
+
+                for (x, pixel) in row.chunks_exact_mut(num_components).enumerate()
+                {
+                    pixel[k] = in_row[x * component.horizontal_sample / self.h_max];
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Predict the sample at `(x,y)` from its neighbours, see H.1.2.1
+///
+/// # Arguments
+/// - `plane`: Samples decoded so far, rows are `stride` samples apart
+/// - `first_line`: The first line of the current scan or restart interval
+/// - `predictor`: Predictor selection value from the SOS header, zero means no prediction
+/// - `initial`: Prediction for the first sample in `first_line`
+///
+/// The result is not bounded, adding the difference and truncating to 16 bits gives the
+/// sample value.
+#[inline]
+fn predict(
+    plane: &[u16], stride: usize, (x, y): (usize, usize), first_line: usize, predictor: u8,
+    initial: i32,
+) -> i32
+{
+    if predictor == 0
+    {
+        return 0;
+    }
+    let position = y * stride + x;
+
+    if y == first_line
+    {
+        if x == 0
+        {
+            return initial;
+        }
+        // only the sample to the left is available
+        return i32::from(plane[position - 1]);
+    }
+
+    // sample above
+    let rb = i32::from(plane[position - stride]);
+
+    if x == 0
+    {
+        return rb;
+    }
+    // sample to the left
+    let ra = i32::from(plane[position - 1]);
+
+    // sample above and to the left
+    let rc = i32::from(plane[position - stride - 1]);
+
+    match predictor
+    {
+        1 => ra,
+        2 => rb,
+        3 => rc,
+        4 => ra + rb - rc,
+        5 => ra + ((rb - rc) >> 1),
+        6 => rb + ((ra - rc) >> 1),
+        _ => (ra + rb) >> 1,
+    }
+}
@@ -26,16 +26,18 @@
 //!
 //! So here we use a different scheme. Just decode everything and then finally use threads when post processing.
 
-use std::io::Cursor;
+use std::io::{BufRead, Cursor};
 use std::sync::Arc;
 
 use crate::bitstream::BitStream;
-use crate::components::{ComponentID, SubSampRatios};
+use crate::components::SubSampRatios;
+use crate::decoder::MAX_COMPONENTS;
 use crate::errors::DecodeErrors;
 use crate::headers::{parse_huffman, parse_sos};
 use crate::marker::Marker;
-use crate::misc::read_byte;
-use crate::worker::post_process_prog;
+use crate::mcu_crop::McuWindow;
+use crate::misc::{read_byte, read_u16_be};
+use crate::worker::{post_process_prog, McuRows, Orientation};
 use crate::Decoder;
 
 impl Decoder
@@ -45,11 +47,131 @@ impl Decoder
     /// This routine decodes a progressive image, stopping if it finds any error.
     #[rustfmt::skip]
     pub(crate) fn decode_mcu_ycbcr_progressive(
-        &mut self, reader: &mut Cursor<Vec<u8>>,
-    ) -> Result<Vec<u8>, DecodeErrors>
+        &mut self, reader: &mut Cursor<&[u8]>, output: &mut [u8],
+    ) -> Result<(), DecodeErrors>
+    {
+        let (block, mcu_width) = self.decode_progressive_coefficients(reader)?;
+
+        self.set_upsampling()?;
+
+        self.finish_progressive_decoding(&block, mcu_width, output, self.output_orientation());
+
+        return Ok(());
+    }
+
+    /// Decode the coefficients of every scan of a progressive image
+    ///
+    /// Returns the coefficients and the number of coefficients in a single MCU row
+    #[rustfmt::skip]
+    pub(crate) fn decode_progressive_coefficients(
+        &mut self, reader: &mut Cursor<&[u8]>,
+    ) -> Result<([Vec<i16>; MAX_COMPONENTS], usize), DecodeErrors>
     {
         // memory location for decoded pixels for components
-        let mut block = [vec![], vec![], vec![]];
+        let (mut block, mcu_width) = self.allocate_coefficient_buffers();
+
+        let mut stream = BitStream::new_progressive(self.succ_high, self.succ_low,
+                                                    self.spec_start, self.spec_end);
+
+        // there are multiple scans in the stream, this should resolve the first scan
+        self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
+
+        // extract marker, the image may end without one
+        let Some(mut marker) = get_marker(reader, &mut stream)
+        else
+        {
+            return Ok((block, mcu_width));
+        };
+
+        // if marker is EOI, we are done, otherwise continue scanning.
+        while self.decode_next_progressive_scan(reader, &mut stream, &mut block, &mut marker)? {}
+
+        return Ok((block, mcu_width));
+    }
+
+    /// Parse the segments following `marker` up to the next scan, and decode that scan
+    /// into `block`
+    ///
+    /// `marker` is the marker the previous scan stopped at, it is updated to the one
+    /// this scan stopped at. Returns `false` if EOI, or a marker that cannot appear
+    /// between scans, was found before another scan, or in lenient mode if the
+    /// segments could not be parsed.
+    pub(crate) fn decode_next_progressive_scan(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream,
+        block: &mut [Vec<i16>; MAX_COMPONENTS], marker: &mut Marker,
+    ) -> Result<bool, DecodeErrors>
+    {
+        loop
+        {
+            match *marker
+            {
+                Marker::DHT =>
+                {
+                    if let Err(error) = parse_huffman(self, reader)
+                    {
+                        return self.skip_remaining_scans(reader, *marker, error);
+                    }
+                }
+                Marker::SOS =>
+                {
+                    if let Err(error) = parse_sos(reader, self)
+                    {
+                        return self.skip_remaining_scans(reader, *marker, error);
+                    }
+
+                    stream.update_progressive_params(
+                        self.succ_high,
+                        self.succ_low,
+                        self.spec_start,
+                        self.spec_end,
+                    );
+
+                    // after every SOS, marker, parse data for that scan.
+                    if let Err(error) = self.parse_entropy_coded_data(reader, stream, block)
+                    {
+                        return self.skip_remaining_scans(reader, *marker, error);
+                    }
+
+                    // extract marker, might either indicate end of image or we continue
+                    // scanning, data ending without one ends the image too
+                    *marker = get_marker(reader, stream).unwrap_or(Marker::EOI);
+
+                    stream.reset();
+
+                    return Ok(true);
+                }
+                Marker::DNL =>
+                {
+                    // the height was resolved before decoding, skip the segment
+                    let length = match read_u16_be(reader)
+                    {
+                        Ok(length) => length,
+                        Err(error) => return self.skip_remaining_scans(reader, *marker, error),
+                    };
+
+                    reader.consume(usize::from(length.saturating_sub(2)));
+                }
+                _ =>
+                {
+                    return Ok(false);
+                }
+            }
+            let Some(next) = get_marker(reader, stream)
+            else
+            {
+                return Ok(false);
+            };
+            *marker = next;
+        }
+    }
+
+    /// Allocate buffers large enough to hold the coefficients of the whole image
+    ///
+    /// Returns the buffers and the number of coefficients in a single MCU row
+    #[rustfmt::skip]
+    pub(crate) fn allocate_coefficient_buffers(&self) -> ([Vec<i16>; MAX_COMPONENTS], usize)
+    {
+        let mut block = [vec![], vec![], vec![], vec![]];
 
         let mut mcu_width;
 
@@ -75,55 +197,30 @@ impl Decoder
 
             block[i] = vec![0; len];
         }
+        (block, mcu_width)
+    }
 
-        let mut stream = BitStream::new_progressive(self.succ_high, self.succ_low,
-                                                    self.spec_start, self.spec_end);
-
-        // there are multiple scans in the stream, this should resolve the first scan
-        self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
-
-        // extract marker
-        let mut marker = stream.marker.take().unwrap();
-        // if marker is EOI, we are done, otherwise continue scanning.
-        'eoi: while marker != Marker::EOI
+    /// Turn the coefficients of the whole image into pixels, turned by `orientation`
+    /// if any
+    ///
+    /// `set_upsampling` must have been called before.
+    #[allow(clippy::cast_possible_truncation)]
+    #[rustfmt::skip]
+    pub(crate) fn finish_progressive_decoding(&mut self, block: &[Vec<i16>; MAX_COMPONENTS], mcu_width: usize, out_vector: &mut [u8], orientation: Option<Orientation>) {
+        if self.sub_sample_ratio == SubSampRatios::HV
         {
-            match marker
-            {
-                Marker::DHT => {
-                    parse_huffman(self, reader)?;
-                }
-                Marker::SOS =>
-                    {
-                        parse_sos(reader, self)?;
-
-                        stream.update_progressive_params(self.succ_high, self.succ_low,
-                                                         self.spec_start, self.spec_end);
+            // post processing expects 4:2:0 coefficients in the order the baseline
+            // decoder writes them in, not in rows of blocks, so reconstruct whole planes
+            let whole_image = McuWindow { x: 0, y: 0, width: self.mcu_x, height: self.mcu_y };
 
-                        // after every SOS, marker, parse data for that scan.
-                        self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
-                        // extract marker, might either indicate end of image or we continue
-                        // scanning(hence the continue statement to determine).
-                        marker = get_marker(reader, &mut stream).unwrap();
+            let samples = self.finish_scaled_decoding(block, whole_image, whole_image, orientation);
 
-                        stream.reset();
+            // samples fit into 8 bits
+            out_vector.iter_mut().zip(samples).for_each(|(out, sample)| *out = sample as u8);
 
-                        continue 'eoi;
-                    }
-                _ =>
-                    {
-                        break 'eoi;
-                    }
-            }
-            marker = get_marker(reader, &mut stream).unwrap();
+            return;
         }
 
-        return Ok(self.finish_progressive_decoding(&block, mcu_width));
-    }
-
-    #[rustfmt::skip]
-    fn finish_progressive_decoding(&mut self, block: &[Vec<i16>; 3], mcu_width: usize) -> Vec<u8> {
-        self.set_upsampling().unwrap();
-
         let mut mcu_width = mcu_width;
 
         if self.sub_sample_ratio == SubSampRatios::H
@@ -135,10 +232,6 @@ impl Decoder
         let cb = &block[1];
         let cr = &block[2];
 
-        let capacity = usize::from(self.info.width) * usize::from(self.info.height);
-
-        let mut out_vector = vec![0_u8; capacity * self.output_colorspace.num_components()];
-
 
         // Things we need for multithreading.
         let h_max = self.h_max;
@@ -159,12 +252,12 @@ impl Decoder
 
         let color_convert_16 = self.color_convert_16;
 
-        let width = usize::from(self.width());
+        let width = usize::from(self.info.width);
 
         // Divide the output into small blocks and send to threads/
         let chunks_size = width * self.output_colorspace.num_components() * 8 * h_max * v_max;
 
-        let out_chunks = out_vector.chunks_exact_mut(chunks_size);
+        let mut out_chunks = McuRows::new(out_vector, chunks_size, width, output.num_components(), orientation);
 
         // Chunk sizes. Each determine how many pixels go per thread.
         let y_chunk_size =
@@ -186,45 +279,52 @@ impl Decoder
             // open threads.
             pool.scoped(|scope| {
                 for (((y, cb), cr), out) in
-                y_chunk.zip(cb_chunk).zip(cr_chunk).zip(out_chunks)
+                y_chunk.zip(cb_chunk).zip(cr_chunk).zip(out_chunks.by_ref())
                 {
                     let component = components.clone();
 
                     scope.execute(move || {
-                        post_process_prog(&[y, cb, cr], &component, idct_func, color_convert_16,
-                                           input, output, out, width,
-                        );
+                        out.write(chunks_size, |out| {
+                            post_process_prog(&[y, cb, cr], &component, idct_func, color_convert_16,
+                                               input, output, out, width,
+                            );
+                        });
                     });
                 }
             });
         } else {
             // one component
             pool.scoped(|scope| {
-                for (y,  out) in y_chunk.zip(out_chunks)
+                for (y,  out) in y_chunk.zip(out_chunks.by_ref())
                 {
                     let component = components.clone();
                    scope.execute(move || {
-                        post_process_prog(&[y, &[], &[]], &component, idct_func, color_convert_16,
-                                           input, output, out, width,
-                        );
+                        out.write(chunks_size, |out| {
+                            post_process_prog(&[y, &[], &[]], &component, idct_func, color_convert_16,
+                                               input, output, out, width,
+                            );
+                        });
                     });
                 }
             });
 
         }
-        debug!("Finished decoding image");
+        out_chunks.finish();
 
-        return out_vector;
+        debug!("Finished decoding image");
     }
 
 
     #[rustfmt::skip]
-    fn parse_entropy_coded_data(
-        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream, buffer: &mut [Vec<i16>; 3],
+    pub(crate) fn parse_entropy_coded_data(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream, buffer: &mut [Vec<i16>; MAX_COMPONENTS],
     ) -> Result<bool, DecodeErrors>
     {
         stream.reset();
+        stream.next_restart = 0;
         self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        // restart intervals count from the start of the scan
+        self.reset_restart_count();
 
         if self.num_scans == 1
         {
@@ -239,21 +339,10 @@ impl Decoder
 
             let k = self.z_order[0];
 
-            let (mcu_width, mcu_height);
-            // For Y channel  or non interleaved scans , mcu's is the image dimensions divided
-            // by 8
-            if self.components[k].component_id == ComponentID::Y || !self.interleaved
-            {
-                mcu_width = ((self.info.width + 7) / 8) as usize;
+            // blocks covering the component, for full resolution components or non
+            // interleaved images that's the image dimensions divided by 8
+            let (mcu_width, mcu_height) = self.component_blocks(k);
 
-                mcu_height = ((self.info.height + 7) / 8) as usize;
-            } else {
-                // For other channels, in an interleaved mcu, number of MCU's
-                // are determined by some weird maths done in headers.rs->parse_sos()
-                mcu_width = self.mcu_x;
-
-                mcu_height = self.mcu_y;
-            }
             let mut i = 0;
 
             let mut j = 0;
@@ -262,67 +351,22 @@ impl Decoder
             {
                 while j < mcu_width
                 {
-                    let start = 64 * (j + i * (self.components[k].width_stride / 8));
-
-                    let data: &mut [i16; 64] = buffer.get_mut(k).unwrap().get_mut(start..start + 64)
-                        .unwrap().try_into().unwrap();
-
-                    if self.spec_start == 0
+                    // the previous block ended a restart interval
+                    if self.todo == 0
                     {
-                        let pos = self.components[k].dc_huff_table;
-
-                        let dc_table = self.dc_huffman_tables.get(pos).unwrap().as_ref().unwrap();
-
-                        let dc_pred = &mut self.components[k].dc_pred;
-                        if self.succ_high == 0
-                        {
-                            // first scan for this mcu
-                            stream.decode_prog_dc_first(reader, dc_table, &mut data[0], dc_pred)?;
-                        } else {
-                            // refining scans for this MCU
-                            stream.decode_prog_dc_refine(reader, &mut data[0]);
-                        }
-                    } else {
-                        let pos = self.components[k].ac_huff_table;
+                        self.start_restart_interval(reader, stream)?;
+                    }
 
-                        let ac_table = self.ac_huffman_tables.get(pos).unwrap().as_ref().unwrap();
+                    // blocks which are not decoded keep what earlier scans left in them
+                    if !stream.lost_sync
+                    {
+                        let decoded = self.decode_progressive_block(reader, stream, buffer, k, &mut i, &mut j, (mcu_width, mcu_height))?;
 
-                        if self.succ_high == 0
-                        {
-                            // first scan for this MCU
-                            if stream.eob_run > 0
-                            {
-                                // EOB runs indicate the whole block is empty, but unlike for baseline
-                                // EOB in progressive tell us the number of proceeding blocks currently zero.
-
-                                // other decoders use a check in decode_mcu_first decrement and return if it's an
-                                // eob run(since the array is expected to contain zeroes). but that's a function call overhead(if not inlined) and a branch check
-                                // we do it a bit differently
-                                // we can use divisors to determine how many MCU's to skip
-                                // which is more faster than a decrement and return since EOB runs can be
-                                // as big as 10,000
-
-                                i += (j + stream.eob_run as usize - 1) / mcu_width;
-
-                                j = (j + stream.eob_run as usize - 1) % mcu_width;
-
-                                stream.eob_run = 0;
-                            } else {
-                                stream.decode_mcu_ac_first(reader, ac_table, data)?;
-                            }
-                        } else {
-                            // refinement scan
-                            stream.decode_mcu_ac_refine(reader, ac_table, data)?;
-                        }
+                        self.check_decoded_mcu(reader, stream, decoded)?;
                     }
                     j += 1;
 
-                    self.todo -= 1;
-
-                    if self.todo == 0
-                    {
-                        self.handle_rst(stream)?;
-                    }
+                    self.todo = self.todo.saturating_sub(1);
                 }
                 j = 0;
                 i += 1;
@@ -342,54 +386,165 @@ impl Decoder
             {
                 for j in 0..self.mcu_x
                 {
-                    // process scan n elements in order
-                    for k in 0..self.num_scans
+                    // the previous MCU ended a restart interval
+                    if self.todo == 0
                     {
-                        let n = self.z_order[k as usize];
+                        self.start_restart_interval(reader, stream)?;
+                    }
 
-                        let component = &mut self.components[n];
+                    if !stream.lost_sync
+                    {
+                        let decoded = self.decode_progressive_dc_mcu(reader, stream, buffer, i, j)?;
 
-                        let huff_table = self.dc_huffman_tables.get(component.dc_huff_table)
-                            .expect("No DC table for component").as_ref().unwrap();
+                        self.check_decoded_mcu(reader, stream, decoded)?;
+                    }
+                    self.todo = self.todo.saturating_sub(1);
+                }
+            }
+        }
+        return Ok(true);
+    }
 
-                        for v_samp in 0..component.vertical_sample
-                        {
-                            for h_samp in 0..component.horizontal_sample
-                            {
-                                let x2 = j * component.horizontal_sample + h_samp;
+    /// Decode block `(j, i)` of component `k` from a non interleaved scan
+    ///
+    /// The block is skipped if it is part of an EOB run, `i` and `j` are then moved
+    /// to the last block of the run. The outer result is an error in the image
+    /// headers, the inner one an error in the entropy coded data.
+    #[allow(clippy::too_many_arguments)]
+    #[rustfmt::skip]
+    fn decode_progressive_block(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream, buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+        k: usize, i: &mut usize, j: &mut usize, (mcu_width, mcu_height): (usize, usize),
+    ) -> Result<Result<(), DecodeErrors>, DecodeErrors>
+    {
+        let start = 64 * (*j + *i * (self.components[k].width_stride / 8));
 
-                                let y2 = i * component.vertical_sample + v_samp;
+        let data: &mut [i16; 64] = buffer[k].get_mut(start..start + 64)
+            .ok_or(DecodeErrors::ExhaustedData)?.try_into().unwrap();
 
-                                let position = 64 * (x2 + y2 * component.width_stride / 8);
+        if self.spec_start == 0
+        {
+            let pos = self.components[k].dc_huff_table;
 
-                                // data will contain the position for this coefficient in our array.
-                                let data = &mut buffer[n as usize][position];
+            let dc_table = self.dc_huffman_tables.get(pos).and_then(Option::as_ref)
+                .ok_or_else(|| DecodeErrors::HuffmanDecode(format!(
+                    "No DC table for component {:?}", self.components[k].component_id
+                )))?;
 
-                                if self.succ_high == 0
-                                {
-                                    stream.decode_prog_dc_first(reader, huff_table, data, &mut component.dc_pred)?;
-                                } else {
-                                    stream.decode_prog_dc_refine(reader, data);
-                                }
-                            }
-                        }
-                        self.todo -= 1;
-                        // after every scan that's a mcu, count down restart markers.
-                        if self.todo == 0 {
-                            self.handle_rst(stream)?;
+            let dc_pred = &mut self.components[k].dc_pred;
+            if self.succ_high == 0
+            {
+                // first scan for this mcu
+                return Ok(stream.decode_prog_dc_first(reader, dc_table, &mut data[0], dc_pred));
+            }
+            // refining scans for this MCU
+            stream.decode_prog_dc_refine(reader, &mut data[0]);
+
+            return Ok(Ok(()));
+        }
+        let pos = self.components[k].ac_huff_table;
+
+        let ac_table = self.ac_huffman_tables.get(pos).and_then(Option::as_ref)
+            .ok_or_else(|| DecodeErrors::HuffmanDecode(format!(
+                "No AC table for component {:?}", self.components[k].component_id
+            )))?;
+
+        if self.succ_high != 0
+        {
+            // refinement scan
+            return Ok(stream.decode_mcu_ac_refine(reader, ac_table, data).map(|_| ()));
+        }
+        // first scan for this MCU
+        if stream.eob_run > 0
+        {
+            // EOB runs indicate the whole block is empty, but unlike for baseline
+            // EOB in progressive tell us the number of proceeding blocks currently zero.
+
+            // other decoders use a check in decode_mcu_first decrement and return if it's an
+            // eob run(since the array is expected to contain zeroes). but that's a function call overhead(if not inlined) and a branch check
+            // we do it a bit differently
+            // we can use divisors to determine how many MCU's to skip
+            // which is more faster than a decrement and return since EOB runs can be
+            // as big as 10,000
+            let run = stream.eob_run as usize;
+
+            *i += (*j + run - 1) / mcu_width;
+
+            *j = (*j + run - 1) % mcu_width;
+
+            stream.eob_run = 0;
+
+            // blocks of the run count towards the restart interval too
+            self.todo = self.todo.saturating_sub(run - 1);
+
+            if *i >= mcu_height
+            {
+                return Ok(Err(DecodeErrors::HuffmanDecode(
+                    "EOB run extends past the end of the scan".to_string(),
+                )));
+            }
+            return Ok(Ok(()));
+        }
+        Ok(stream.decode_mcu_ac_first(reader, ac_table, data).map(|_| ()))
+    }
+
+    /// Decode the DC coefficients of MCU `(j, i)` of an interleaved scan
+    ///
+    /// The outer result is an error in the image headers, the inner one an error in
+    /// the entropy coded data.
+    #[rustfmt::skip]
+    fn decode_progressive_dc_mcu(
+        &mut self, reader: &mut Cursor<&[u8]>, stream: &mut BitStream, buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+        i: usize, j: usize,
+    ) -> Result<Result<(), DecodeErrors>, DecodeErrors>
+    {
+        // process scan n elements in order
+        for k in 0..self.num_scans
+        {
+            let n = self.z_order[k as usize];
+
+            let component = &mut self.components[n];
+
+            let huff_table = self.dc_huffman_tables.get(component.dc_huff_table)
+                .and_then(Option::as_ref)
+                .ok_or_else(|| DecodeErrors::HuffmanDecode(format!(
+                    "No DC table for component {:?}", component.component_id
+                )))?;
+
+            for v_samp in 0..component.vertical_sample
+            {
+                for h_samp in 0..component.horizontal_sample
+                {
+                    let x2 = j * component.horizontal_sample + h_samp;
+
+                    let y2 = i * component.vertical_sample + v_samp;
+
+                    let position = 64 * (x2 + y2 * component.width_stride / 8);
+
+                    // data will contain the position for this coefficient in our array.
+                    let data = buffer[n as usize].get_mut(position)
+                        .ok_or(DecodeErrors::ExhaustedData)?;
+
+                    if self.succ_high == 0
+                    {
+                        if let Err(error) = stream.decode_prog_dc_first(reader, huff_table, data, &mut component.dc_pred)
+                        {
+                            return Ok(Err(error));
                         }
+                    } else {
+                        stream.decode_prog_dc_refine(reader, data);
                     }
                 }
             }
         }
-        return Ok(true);
+        Ok(Ok(()))
     }
 }
 
 ///Get a marker from the bit-stream.
 ///
 /// This reads until it gets a marker or end of file is encountered
-fn get_marker(reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream) -> Option<Marker>
+pub(crate) fn get_marker(reader: &mut Cursor<&[u8]>, stream: &mut BitStream) -> Option<Marker>
 {
     if let Some(marker) = stream.marker
     {
@@ -414,7 +569,7 @@ fn get_marker(reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream) -> Option<Ma
 
             if r != 0
             {
-                return Some(Marker::from_u8(r).unwrap());
+                return Some(Marker::from_u8(r).unwrap_or(Marker::Unknown(r)));
             }
 
             if reader.position() >= len
@@ -0,0 +1,242 @@
+//! Routines for decoding images at a reduced size
+//!
+//! When an image is scaled down by 1/2, 1/4 or 1/8 (see `Decoder::set_scale`), every
+//! block of coefficients goes through a reduced size IDCT producing 4x4, 2x2 or 1x1
+//! samples instead of 8x8, so the image is never reconstructed at its full size.
+//!
+//! The coefficients of the whole image are decoded first, with the routines used by
+//! the image's coding process, then like extended sequential images (see
+//! `mcu_extended.rs`) we carry out IDCT, up-sampling and color conversion on whole
+//! planes, at the reduced size.
+//!
+//! Cropped images take the same route, at any scale, only reconstructing the planes
+//! of the window of MCUs around the region, see `mcu_crop.rs`.
+//!
+//! So do CMYK and YCCK images at full size, the MCU row routines post process at
+//! most three components. Up-sampling whole planes also copes with the black
+//! component being sampled like luma, which is how libjpeg writes YCCK.
+use std::io::Cursor;
+
+use crate::components::Components;
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::idct::dequantize_and_idct_scaled;
+use crate::mcu_crop::{crop_samples, McuWindow};
+use crate::mcu_extended::upsample_plane;
+use crate::worker::Orientation;
+use crate::Decoder;
+
+impl Decoder
+{
+    /// Decode a DCT image at the size set by `set_scale`, limited to the region set
+    /// by `set_crop`
+    ///
+    /// This expects the headers up to and including the first SOS to have been parsed.
+    pub(crate) fn decode_mcu_scaled(
+        &mut self, reader: &mut Cursor<&[u8]>,
+    ) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let window = self.crop_window();
+
+        let orientation = self.output_orientation();
+
+        if self.decodes_crop_window()
+        {
+            let block = self.decode_window_coefficients(reader, window)?;
+
+            return Ok(self.finish_scaled_decoding(&block, window, window, orientation));
+        }
+
+        let block = self.decode_all_coefficients(reader)?;
+
+        let whole_image = McuWindow {
+            x: 0,
+            y: 0,
+            width: self.mcu_x,
+            height: self.mcu_y,
+        };
+
+        Ok(self.finish_scaled_decoding(&block, whole_image, window, orientation))
+    }
+
+    /// Carry out IDCT, up-sampling and color conversion for the MCUs in `window`
+    /// and return samples of the output region, turned by `orientation` if any
+    ///
+    /// `block` holds the coefficients of the MCUs in `buffer`, which contains `window`.
+    pub(crate) fn finish_scaled_decoding(
+        &self, block: &[Vec<i16>; MAX_COMPONENTS], buffer: McuWindow, window: McuWindow,
+        orientation: Option<Orientation>,
+    ) -> Vec<u16>
+    {
+        let size = if self.is_scaled() { self.scale } else { 8 };
+
+        let (width, height) = self.uncropped_dimensions();
+
+        let (x, y, region_width, region_height) = self.output_region();
+
+        // the part of the output the window covers
+        let (window_x, window_y) = (window.x * self.h_max * size, window.y * self.v_max * size);
+
+        let window_width = ((window.x + window.width) * self.h_max * size).min(width) - window_x;
+
+        let window_height = ((window.y + window.height) * self.v_max * size).min(height) - window_y;
+
+        let region = (x - window_x, y - window_y, region_width, region_height);
+
+        let max_sample = (1_i32 << self.info.pixel_density) - 1;
+
+        let num_components = self.needed_components();
+
+        let mut planes = [vec![], vec![], vec![], vec![]];
+
+        for (pos, plane) in planes.iter_mut().enumerate().take(num_components)
+        {
+            let component = &self.components[pos];
+
+            let (h_samp, v_samp) = (component.horizontal_sample, component.vertical_sample);
+
+            // Like libjpeg, sub-sampled components get larger IDCTs where that saves
+            // us from up-sampling them, e.g. chroma of a 2x2 sampled image scaled by
+            // 1/2 is reconstructed with 8x8 IDCTs at the output size.
+            let mut comp_size = size;
+
+            while comp_size < 8
+                && (self.h_max * size).is_multiple_of(h_samp * comp_size * 2)
+                && (self.v_max * size).is_multiple_of(v_samp * comp_size * 2)
+            {
+                comp_size *= 2;
+            }
+
+            // blocks of this component in the window
+            let blocks = (
+                (window.x - buffer.x) * h_samp,
+                (window.y - buffer.y) * v_samp,
+                window.width * h_samp,
+                window.height * v_samp,
+            );
+
+            let samples = idct_plane_scaled(
+                &block[pos],
+                buffer.width * h_samp,
+                blocks,
+                component,
+                comp_size,
+                max_sample,
+            );
+
+            // dimensions of this component at the reduced size, and of the part of it
+            // the window covers
+            let comp_width =
+                (usize::from(self.info.width) * h_samp * comp_size).div_ceil(self.h_max * 8);
+
+            let comp_height =
+                (usize::from(self.info.height) * v_samp * comp_size).div_ceil(self.v_max * 8);
+
+            let stride = blocks.2 * comp_size;
+
+            let window_comp_width = stride.min(comp_width - blocks.0 * comp_size);
+
+            let window_comp_height = (blocks.3 * comp_size).min(comp_height - blocks.1 * comp_size);
+
+            let ratios = (
+                (self.h_max * size) / (h_samp * comp_size),
+                (self.v_max * size) / (v_samp * comp_size),
+            );
+
+            let upsampled = if size == 1
+            {
+                // samples are block averages, libjpeg doesn't bother with a
+                // triangle filter for those, and neither do we
+                replicate_plane(
+                    &samples,
+                    stride,
+                    (window_comp_width, window_comp_height),
+                    ratios,
+                    (window_width, window_height),
+                )
+            }
+            else
+            {
+                upsample_plane(
+                    &samples,
+                    stride,
+                    (window_comp_width, window_comp_height),
+                    ratios,
+                    (window_width, window_height),
+                )
+            };
+
+            *plane = if region == (0, 0, window_width, window_height)
+            {
+                upsampled
+            }
+            else
+            {
+                crop_samples(&upsampled, window_width, 1, region)
+            };
+        }
+
+        self.color_convert_planes(&planes, orientation)
+    }
+}
+
+/// Carry out reduced size IDCT on a rectangle of blocks of a component
+///
+/// # Arguments
+/// - coefficients: Blocks of the component, rows are `blocks_per_row` blocks long
+/// - `(x,y,width,height)`: The rectangle of blocks to carry out IDCT on
+///
+/// Returns a plane of samples whose width is `width * size`
+pub(crate) fn idct_plane_scaled(
+    coefficients: &[i16], blocks_per_row: usize,
+    (x, y, width, height): (usize, usize, usize, usize), component: &Components, size: usize,
+    max_sample: i32,
+) -> Vec<i16>
+{
+    let stride = width * size;
+
+    let mut plane = vec![0; stride * height * size];
+
+    // each row of blocks takes `size` rows of samples
+    for (row, out_row) in plane.chunks_exact_mut(stride * size).enumerate()
+    {
+        let start = ((y + row) * blocks_per_row + x) * 64;
+
+        let in_row = &coefficients[start..start + width * 64];
+
+        for (x, block) in in_row.chunks_exact(64).enumerate()
+        {
+            dequantize_and_idct_scaled(
+                block.try_into().unwrap(),
+                &component.quantization_table,
+                size,
+                &mut out_row[x * size..],
+                stride,
+                max_sample,
+            );
+        }
+    }
+    plane
+}
+
+/// Up-sample a component plane to the output size by replicating samples
+///
+/// Arguments are the same as for `upsample_plane`
+fn replicate_plane(
+    input: &[i16], stride: usize, (in_width, in_height): (usize, usize),
+    (h_ratio, v_ratio): (usize, usize), (width, height): (usize, usize),
+) -> Vec<i16>
+{
+    let mut output = vec![0; width * height];
+
+    for (y, out) in output.chunks_exact_mut(width).enumerate()
+    {
+        let row = &input[(y / v_ratio).min(in_height - 1) * stride..];
+
+        for (x, out) in out.iter_mut().enumerate()
+        {
+            *out = row[(x / h_ratio).min(in_width - 1)];
+        }
+    }
+    output
+}
@@ -0,0 +1,148 @@
+//! Sources of entropy coded data for the baseline decoder
+//!
+//! The baseline decoder works one MCU row at a time, so it does not need the whole
+//! stream in memory, before decoding a row it asks its source for the data the row
+//! is in, and after decoding it tells the source how much of it was used.
+//!
+//! Images in memory are their own source, while images read from a `Read` source keep
+//! a window of the stream large enough to hold any MCU row, discarding what was used
+//! and reading more before every row.
+use std::io::{Cursor, Read};
+
+use crate::errors::DecodeErrors;
+
+/// Largest number of bytes a single 8 bit block may be coded in
+///
+/// That's 16 bits for the DC code and 11 for its value, and 16 bits for the
+/// code and 10 for the value of each of the 63 AC coefficients, doubled because
+/// every byte may be followed by a stuffed zero byte.
+const MAX_BLOCK_BYTES: usize = 2 * (16 + 11 + 63 * (16 + 10)) / 8 + 1;
+
+/// Bytes kept past the end of an MCU row for restart markers and bitstream
+/// refills reading ahead
+const ROW_SLACK: usize = 64;
+
+/// Provides entropy coded data to the baseline decoder
+pub(crate) trait McuRowSource
+{
+    /// Return data holding the next `blocks` blocks, positioned where the
+    /// previous row ended
+    ///
+    /// # Errors
+    /// If the underlying reader returns an error
+    fn next_row(&mut self, blocks: usize) -> Result<Cursor<&[u8]>, DecodeErrors>;
+
+    /// Record where in the data returned by `next_row` decoding stopped
+    fn consumed(&mut self, position: u64);
+
+    /// Offset in the image of the data the next call to `next_row` returns
+    fn offset(&self) -> usize;
+}
+
+impl McuRowSource for Cursor<&[u8]>
+{
+    fn next_row(&mut self, _: usize) -> Result<Cursor<&[u8]>, DecodeErrors>
+    {
+        // we already have everything
+        let mut reader = Cursor::new(*self.get_ref());
+
+        reader.set_position(self.position());
+
+        Ok(reader)
+    }
+
+    fn consumed(&mut self, position: u64)
+    {
+        self.set_position(position);
+    }
+
+    fn offset(&self) -> usize
+    {
+        0
+    }
+}
+
+/// Entropy coded data pulled from a `Read` source as it is needed
+pub(crate) struct StreamSource<R: Read>
+{
+    reader: R,
+    /// Data read but not yet decoded starts at `position`
+    window: Vec<u8>,
+    position: usize,
+    /// Offset in the image of the start of `window`
+    offset: usize,
+    /// The reader has no more data
+    finished: bool,
+}
+
+impl<R: Read> StreamSource<R>
+{
+    /// Create a new source, `buffered` contains data already read from `reader`,
+    /// found at `offset` in the image
+    pub(crate) fn new(reader: R, buffered: Vec<u8>, offset: usize) -> StreamSource<R>
+    {
+        StreamSource {
+            reader,
+            window: buffered,
+            position: 0,
+            offset,
+            finished: false,
+        }
+    }
+}
+
+impl<R: Read> McuRowSource for StreamSource<R>
+{
+    fn next_row(&mut self, blocks: usize) -> Result<Cursor<&[u8]>, DecodeErrors>
+    {
+        // discard what the previous rows used
+        let used = self.position.min(self.window.len());
+
+        self.window.drain(..used);
+
+        self.offset += used;
+
+        self.position = 0;
+
+        let wanted = blocks * MAX_BLOCK_BYTES + ROW_SLACK;
+
+        if self.window.len() < wanted && !self.finished
+        {
+            let start = self.window.len();
+
+            self.window.resize(wanted, 0);
+
+            let mut filled = start;
+
+            while filled < wanted
+            {
+                match self.reader.read(&mut self.window[filled..])
+                {
+                    Ok(0) =>
+                    {
+                        self.finished = true;
+
+                        break;
+                    }
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            self.window.truncate(filled);
+        }
+
+        Ok(Cursor::new(&self.window))
+    }
+
+    fn consumed(&mut self, position: u64)
+    {
+        self.position = usize::try_from(position).unwrap_or(usize::MAX);
+    }
+
+    fn offset(&self) -> usize
+    {
+        // the rows used are discarded by `next_row`
+        self.offset + self.position.min(self.window.len())
+    }
+}
@@ -2,7 +2,7 @@
 #![allow(dead_code)]
 
 use std::fmt;
-use std::io::{BufReader, Read};
+use std::io::{BufRead, BufReader, Read};
 
 use crate::errors::DecodeErrors;
 
@@ -22,6 +22,15 @@ pub const START_OF_FRAME_PROG_DCT: u16 = 0xffc2;
 
 pub const START_OF_FRAME_LOS_SEQ: u16 = 0xffc3;
 
+/// Start of differential sequential DCT Huffman coding
+pub const START_OF_FRAME_DIFF_SEQ: u16 = 0xffc5;
+
+/// Start of differential progressive DCT Huffman coding
+pub const START_OF_FRAME_DIFF_PROG_DCT: u16 = 0xffc6;
+
+/// Start of differential lossless Huffman coding
+pub const START_OF_FRAME_DIFF_LOS: u16 = 0xffc7;
+
 /// Start of extended sequential DCT arithmetic coding
 
 pub const START_OF_FRAME_EXT_AR: u16 = 0xffc9;
@@ -93,7 +102,7 @@ pub enum ColorSpace
     GRAYSCALE,
     /// YCbCr, (also known as YUV)
     YCbCr,
-    /// C/M/Y/K
+    /// C/M/Y/K, as output 0 is no ink and 255 full ink
     CMYK,
     /// Y/Cb/Cr/K
     YCCK,
@@ -155,6 +164,12 @@ pub enum SOFMarkers
     ProgressiveDctArithmetic,
     /// Lossless ( sequential), arithmetic coding
     LosslessArithmetic,
+    /// Differential sequential DCT, Huffman coding
+    DifferentialSequentialHuffman,
+    /// Differential progressive DCT, Huffman coding
+    DifferentialProgressiveHuffman,
+    /// Differential lossless (sequential), Huffman coding
+    DifferentialLosslessHuffman,
 }
 
 impl Default for SOFMarkers
@@ -169,6 +184,7 @@ impl SOFMarkers
 {
     /// Check if a certain marker is sequential DCT or not
 
+    #[must_use]
     pub fn is_sequential_dct(self) -> bool
     {
         matches!(
@@ -176,28 +192,61 @@ impl SOFMarkers
             Self::BaselineDct
                 | Self::ExtendedSequentialHuffman
                 | Self::ExtendedSequentialDctArithmetic
+                | Self::DifferentialSequentialHuffman
         )
     }
 
     /// Check if a marker is a Lossles type or not
 
+    #[must_use]
     pub fn is_lossless(self) -> bool
     {
-        matches!(self, Self::LosslessHuffman | Self::LosslessArithmetic)
+        matches!(
+            self,
+            Self::LosslessHuffman | Self::LosslessArithmetic | Self::DifferentialLosslessHuffman
+        )
     }
 
     /// Check whether a marker is a progressive marker or not
 
+    #[must_use]
     pub fn is_progressive(self) -> bool
     {
         matches!(
             self,
-            Self::ProgressiveDctHuffman | Self::ProgressiveDctArithmetic
+            Self::ProgressiveDctHuffman
+                | Self::ProgressiveDctArithmetic
+                | Self::DifferentialProgressiveHuffman
+        )
+    }
+
+    /// Check whether a marker starts a differential frame of a hierarchical image
+    #[must_use]
+    pub fn is_differential(self) -> bool
+    {
+        matches!(
+            self,
+            Self::DifferentialSequentialHuffman
+                | Self::DifferentialProgressiveHuffman
+                | Self::DifferentialLosslessHuffman
+        )
+    }
+
+    /// Check whether a marker uses arithmetic coding or not
+    #[must_use]
+    pub fn is_arithmetic(self) -> bool
+    {
+        matches!(
+            self,
+            Self::ExtendedSequentialDctArithmetic
+                | Self::ProgressiveDctArithmetic
+                | Self::LosslessArithmetic
         )
     }
 
     /// Create a marker from an integer
 
+    #[must_use]
     pub fn from_int(int: u16) -> Option<SOFMarkers>
     {
         match int
@@ -209,6 +258,9 @@ impl SOFMarkers
             START_OF_FRAME_LOS_SEQ_AR => Some(Self::LosslessArithmetic),
             START_OF_FRAME_EXT_SEQ => Some(Self::ExtendedSequentialHuffman),
             START_OF_FRAME_EXT_AR => Some(Self::ExtendedSequentialDctArithmetic),
+            START_OF_FRAME_DIFF_SEQ => Some(Self::DifferentialSequentialHuffman),
+            START_OF_FRAME_DIFF_PROG_DCT => Some(Self::DifferentialProgressiveHuffman),
+            START_OF_FRAME_DIFF_LOS => Some(Self::DifferentialLosslessHuffman),
             _ => None,
         }
     }
@@ -233,6 +285,18 @@ impl fmt::Debug for SOFMarkers
             }
             Self::ProgressiveDctArithmetic => write!(f, "Progressive DCT, arithmetic coding"),
             Self::LosslessArithmetic => write!(f, "Lossless (sequential) arithmetic coding"),
+            Self::DifferentialSequentialHuffman =>
+            {
+                write!(f, "Differential sequential DCT, Huffman coding")
+            }
+            Self::DifferentialProgressiveHuffman =>
+            {
+                write!(f, "Differential progressive DCT, Huffman coding")
+            }
+            Self::DifferentialLosslessHuffman =>
+            {
+                write!(f, "Differential lossless (sequential) Huffman coding")
+            }
         }
     }
 }
@@ -285,16 +349,83 @@ where
 {
     let mut tmp: [u8; 2] = [0, 0];
 
-    if reader.read(&mut tmp)? != 2
-    {
-        return Err(DecodeErrors::ExhaustedData);
-    };
+    // a single read may return less than it could when the reader is buffered
+    reader
+        .read_exact(&mut tmp)
+        .map_err(|_| DecodeErrors::ExhaustedData)?;
 
     let v = u16::from_be_bytes(tmp);
 
     Ok(v)
 }
 
+/// Skip `length` bytes from a buffer
+///
+/// Unlike `BufRead::consume`, this skips past the bytes a buffered reader currently
+/// holds, if fewer than `length` bytes are left, all of them are skipped.
+///
+/// # Errors
+/// If the reader returns an error
+pub(crate) fn skip_bytes<R>(reader: &mut R, length: usize) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    std::io::copy(&mut reader.take(length as u64), &mut std::io::sink())?;
+
+    Ok(())
+}
+
+/// A reader counting the bytes read through it
+///
+/// Headers may be read from any `BufRead`, this keeps track of where in the image
+/// they are, for the offsets of warnings.
+pub(crate) struct PositionReader<R>
+{
+    inner: R,
+    position: usize,
+}
+
+impl<R> PositionReader<R>
+{
+    pub(crate) const fn new(inner: R) -> PositionReader<R>
+    {
+        PositionReader { inner, position: 0 }
+    }
+
+    /// Number of bytes read so far
+    pub(crate) const fn position(&self) -> usize
+    {
+        self.position
+    }
+}
+
+impl<R: Read> Read for PositionReader<R>
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        let read = self.inner.read(buf)?;
+
+        self.position += read;
+
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for PositionReader<R>
+{
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]>
+    {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize)
+    {
+        self.position += amt;
+
+        self.inner.consume(amt);
+    }
+}
+
 /// Read `buf.len()*2` data from the underlying `u8` buffer and convert it into
 /// u16, and store it into `buf`
 ///
@@ -0,0 +1,263 @@
+//! Limits on the resources a decode may use
+//!
+//! Image headers say how large an image is, and the decoder sizes its buffers from
+//! them before it has seen any image data, so a few hundred bytes of hostile input
+//! can ask for gigabytes of memory, or for thousands of progressive scans each
+//! walking over the whole image. [`DecoderOptions`] bounds both, and every limit
+//! is checked as soon as the headers that could exceed it have been parsed, before
+//! anything sized from them is allocated.
+use crate::errors::DecodeErrors;
+use crate::Decoder;
+
+/// Largest number of pixels an image may have by default
+pub(crate) const MAX_DIMENSIONS: usize = 1 << 27;
+
+/// Limits on the images a [`Decoder`] accepts
+///
+/// Images exceeding a limit are rejected with an error naming it, see
+/// [`Decoder::set_options`].
+///
+/// # Example
+/// ```
+/// use zune_jpeg::{Decoder, DecoderOptions};
+///
+/// let options = DecoderOptions::default()
+///     .set_max_pixels(4096 * 4096)
+///     .set_max_memory(256 << 20)
+///     .set_max_scans(64);
+///
+/// let decoder = Decoder::new_with_options(options);
+/// ```
+#[allow(clippy::struct_field_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecoderOptions
+{
+    max_width: usize,
+    max_height: usize,
+    max_pixels: usize,
+    max_memory: usize,
+    max_scans: usize,
+}
+
+impl Default for DecoderOptions
+{
+    /// Images of up to 2^27 pixels, with no other limits
+    fn default() -> Self
+    {
+        DecoderOptions {
+            max_width: usize::from(u16::MAX),
+            max_height: usize::from(u16::MAX),
+            max_pixels: MAX_DIMENSIONS,
+            max_memory: usize::MAX,
+            max_scans: usize::MAX,
+        }
+    }
+}
+
+impl DecoderOptions
+{
+    /// Set the largest width an image, or a frame of a hierarchical image, may have
+    #[must_use]
+    pub const fn set_max_width(mut self, width: usize) -> Self
+    {
+        self.max_width = width;
+
+        self
+    }
+
+    /// Set the largest height an image, or a frame of a hierarchical image, may have
+    #[must_use]
+    pub const fn set_max_height(mut self, height: usize) -> Self
+    {
+        self.max_height = height;
+
+        self
+    }
+
+    /// Set the largest number of pixels, width times height, an image may have
+    #[must_use]
+    pub const fn set_max_pixels(mut self, pixels: usize) -> Self
+    {
+        self.max_pixels = pixels;
+
+        self
+    }
+
+    /// Set the largest number of bytes the buffers sized from the image headers may
+    /// take up
+    ///
+    /// Those are the output image, and for images which are not decoded an MCU row
+    /// at a time (progressive, arithmetic coded, lossless, hierarchical, scaled and
+    /// cropped images), the DCT coefficients and samples of every component. This is
+    /// an estimate made before decoding, small allocations like tables are not
+    /// counted.
+    #[must_use]
+    pub const fn set_max_memory(mut self, bytes: usize) -> Self
+    {
+        self.max_memory = bytes;
+
+        self
+    }
+
+    /// Set the largest number of scans an image may have, counting the scans of
+    /// every frame of hierarchical images
+    #[must_use]
+    pub const fn set_max_scans(mut self, scans: usize) -> Self
+    {
+        self.max_scans = scans;
+
+        self
+    }
+
+    /// Largest width of an image
+    #[must_use]
+    pub const fn get_max_width(&self) -> usize
+    {
+        self.max_width
+    }
+
+    /// Largest height of an image
+    #[must_use]
+    pub const fn get_max_height(&self) -> usize
+    {
+        self.max_height
+    }
+
+    /// Largest number of pixels of an image
+    #[must_use]
+    pub const fn get_max_pixels(&self) -> usize
+    {
+        self.max_pixels
+    }
+
+    /// Largest number of bytes the decoder may allocate for an image
+    #[must_use]
+    pub const fn get_max_memory(&self) -> usize
+    {
+        self.max_memory
+    }
+
+    /// Largest number of scans of an image
+    #[must_use]
+    pub const fn get_max_scans(&self) -> usize
+    {
+        self.max_scans
+    }
+}
+
+impl Decoder
+{
+    /// Create a new decoder which rejects images exceeding the limits in `options`
+    #[must_use]
+    pub fn new_with_options(options: DecoderOptions) -> Decoder
+    {
+        let mut decoder = Decoder::new();
+
+        decoder.set_options(options);
+
+        decoder
+    }
+
+    /// Set the limits images must fit in
+    ///
+    /// Images exceeding them fail to decode with `TooLargeWidth`,
+    /// `TooLargeHeight`, `TooManyPixels`, `TooLargeAllocation` or `TooManyScans`.
+    pub fn set_options(&mut self, options: DecoderOptions)
+    {
+        self.options = options;
+    }
+
+    /// Get the limits images must fit in
+    #[must_use]
+    pub const fn get_options(&self) -> DecoderOptions
+    {
+        self.options
+    }
+
+    /// Check the dimensions of a frame against the limits
+    ///
+    /// A zero height, to be defined by a DNL segment, passes.
+    pub(crate) fn check_dimensions(&self, width: usize, height: usize) -> Result<(), DecodeErrors>
+    {
+        let options = &self.options;
+
+        if width > options.max_width
+        {
+            return Err(DecodeErrors::TooLargeWidth(width, options.max_width));
+        }
+        if height > options.max_height
+        {
+            return Err(DecodeErrors::TooLargeHeight(height, options.max_height));
+        }
+        if width * height > options.max_pixels
+        {
+            return Err(DecodeErrors::TooManyPixels(
+                width * height,
+                options.max_pixels,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Count a scan, failing if the image has more than the limit
+    pub(crate) fn count_scan(&mut self) -> Result<(), DecodeErrors>
+    {
+        self.scans_parsed += 1;
+
+        if self.scans_parsed > self.options.max_scans
+        {
+            return Err(DecodeErrors::TooManyScans(self.options.max_scans));
+        }
+        Ok(())
+    }
+
+    /// Check the memory the image needs against the limit
+    ///
+    /// This must be called once the headers have been parsed and the height is
+    /// known, see `set_max_memory` for what is counted.
+    pub(crate) fn check_memory(&self) -> Result<(), DecodeErrors>
+    {
+        let bytes = self.memory_estimate();
+
+        if bytes > self.options.max_memory
+        {
+            return Err(DecodeErrors::TooLargeAllocation(
+                bytes,
+                self.options.max_memory,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Bytes taken up by the largest buffers decoding the image allocates
+    fn memory_estimate(&self) -> usize
+    {
+        let mut bytes = self.output_size();
+
+        if self.info.pixel_density > 8
+        {
+            // decoded to 16 bit samples
+            bytes *= 2;
+        }
+
+        if !self.decodes_by_mcu_rows()
+        {
+            // samples of every component, padded to whole blocks
+            let samples: usize = self
+                .components
+                .iter()
+                .map(|x| {
+                    let blocks_wide = self.mcu_x * x.horizontal_sample;
+
+                    let blocks_high = self.mcu_y * x.vertical_sample;
+
+                    blocks_wide * blocks_high * 64
+                })
+                .sum();
+
+            // a 16 bit coefficient and a 16 bit sample for each
+            bytes += samples * 4;
+        }
+        bytes
+    }
+}
@@ -0,0 +1,111 @@
+//! Raw planar output
+//!
+//! Decoding to `ColorSpace::YCbCr` up-samples chroma to the image size, video encoders
+//! and GPU shaders would rather have each component at its own resolution, e.g. the
+//! three planes of a 4:2:0 image, which is what libjpeg calls `raw_data_out`.
+//!
+//! Coefficients of the whole image are decoded and every block goes through the IDCT,
+//! like for scaled images (see `mcu_scaled.rs`), but there the pipeline stops, no
+//! up-sampling or color conversion is carried out.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use std::io::Cursor;
+
+use crate::errors::DecodeErrors;
+use crate::mcu_scaled::idct_plane_scaled;
+use crate::Decoder;
+
+/// A single component of an image, at its native resolution
+///
+/// Returned by [`Decoder::decode_planar`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plane
+{
+    /// Samples of the component, `height` rows `stride` bytes apart
+    pub data: Vec<u8>,
+    /// Number of samples in a row
+    pub width: usize,
+    /// Number of rows
+    pub height: usize,
+    /// Distance between two rows in bytes, at least `width`, rows are padded to
+    /// whole blocks
+    pub stride: usize,
+}
+
+impl Decoder
+{
+    /// Decode a buffer into one plane per component, at the sampling resolution of
+    /// each component
+    ///
+    /// Samples are left in the image's colorspace, e.g. Y, Cb and Cr planes for
+    /// color images, a 4:2:0 image has Cb and Cr planes half the width and height
+    /// of the Y plane. The output colorspace is ignored, as is the region set by
+    /// `set_crop`, but the planes are scaled by [`set_scale`](Self::set_scale).
+    ///
+    /// # Errors
+    /// If the image is not a valid jpeg file, or is not an 8 bit DCT image, i.e. a
+    /// lossless, hierarchical or 12 bit image
+    pub fn decode_planar(&mut self, buf: &[u8]) -> Result<Vec<Plane>, DecodeErrors>
+    {
+        let mut buf = Cursor::new(buf);
+
+        self.prepare_headers(&mut buf)?;
+
+        if self.info.sof.is_lossless() || self.hierarchical.is_some()
+        {
+            return Err(DecodeErrors::Format(
+                "Planar output is only supported for DCT images".to_string(),
+            ));
+        }
+        if self.info.pixel_density > 8
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Image has {} bits of precision, planar output is only supported for 8 bit images",
+                self.info.pixel_density
+            )));
+        }
+
+        let block = self.decode_all_coefficients(&mut buf)?;
+
+        let size = if self.is_scaled() { self.scale } else { 8 };
+
+        let mut planes = vec![];
+
+        for (pos, coefficients) in block
+            .iter()
+            .enumerate()
+            .take(self.input_colorspace.num_components())
+        {
+            let component = &self.components[pos];
+
+            let (h_samp, v_samp) = (component.horizontal_sample, component.vertical_sample);
+
+            // blocks of the component, padded to whole MCUs
+            let blocks = (0, 0, self.mcu_x * h_samp, self.mcu_y * v_samp);
+
+            let samples = idct_plane_scaled(coefficients, blocks.2, blocks, component, size, 255);
+
+            // dimensions of this component at the output size
+            let width = (usize::from(self.info.width) * h_samp * size).div_ceil(self.h_max * 8);
+
+            let height = (usize::from(self.info.height) * v_samp * size).div_ceil(self.v_max * 8);
+
+            let stride = blocks.2 * size;
+
+            // samples were clamped to 0..=255 by the IDCT
+            let data = samples[..stride * height]
+                .iter()
+                .map(|x| *x as u8)
+                .collect();
+
+            planes.push(Plane {
+                data,
+                width,
+                height,
+                stride,
+            });
+        }
+
+        Ok(planes)
+    }
+}
@@ -0,0 +1,429 @@
+//! Progressive previews
+//!
+//! Progressive images spread their coefficients over several scans, the first ones
+//! carrying a coarse version of the whole image. [`ProgressiveReader`] decodes one scan
+//! at a time into the coefficient buffers the progressive decoder accumulates them in,
+//! and renders the image from whatever has been decoded so far when asked to, so a
+//! viewer on a slow connection can show intermediate images.
+//!
+//! Early scans leave most AC coefficients at zero, so every block renders as a flat
+//! square. Like libjpeg's `do_block_smoothing`, we can estimate the lowest AC
+//! coefficients not yet decoded from the DC coefficients of neighbouring blocks
+//! (see `smooth_blocks`), which gives much smoother previews.
+//!
+//! Other images are decoded whole by the first call to `next_scan`.
+#![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+
+use std::io::Cursor;
+
+use crate::bitstream::BitStream;
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::marker::Marker;
+use crate::mcu_crop::McuWindow;
+use crate::mcu_prog::get_marker;
+use crate::{Decoder, ImageInfo};
+
+/// Positions of the AC coefficients estimated by block smoothing, in natural order
+const Q01: usize = 1;
+const Q10: usize = 8;
+const Q20: usize = 16;
+const Q11: usize = 9;
+const Q02: usize = 2;
+
+/// Decodes a progressive image scan by scan, rendering previews on demand
+///
+/// Created by [`Decoder::progressive_scans`].
+///
+/// # Example
+/// ```no_run
+/// use zune_jpeg::Decoder;
+///
+/// let data = std::fs::read("image.jpg").unwrap();
+///
+/// let mut decoder = Decoder::new();
+///
+/// let mut scans = decoder.progressive_scans(&data).unwrap();
+///
+/// scans.set_block_smoothing(true);
+///
+/// while scans.next_scan().unwrap()
+/// {
+///     let preview = scans.render();
+///     // show the preview
+/// }
+/// ```
+pub struct ProgressiveReader<'a>
+{
+    decoder: &'a mut Decoder,
+    data: Cursor<&'a [u8]>,
+    scans: ProgressiveScans,
+    /// The last rendered image, or the whole image
+    pixels: Vec<u8>,
+}
+
+/// Coefficients of a progressive image and the state needed to decode its next scan
+///
+/// Shared by [`ProgressiveReader`] and the incremental decoder, which keep the image
+/// data in different places.
+pub(crate) struct ProgressiveScans
+{
+    stream: BitStream,
+    /// Coefficients decoded so far
+    block: [Vec<i16>; MAX_COMPONENTS],
+    /// Number of coefficients in a single MCU row
+    mcu_width: usize,
+    /// Marker the last scan stopped at, `None` before the first scan and once the
+    /// image is finished
+    pub(crate) marker: Option<Marker>,
+    /// Number of scans decoded
+    pub(crate) scans: usize,
+    /// Lowest bit known of each coefficient of each component, -1 for coefficients
+    /// no scan has carried yet, like libjpeg's `coef_bits`
+    coef_bits: [[i8; 64]; MAX_COMPONENTS],
+    pub(crate) block_smoothing: bool,
+}
+
+impl Decoder
+{
+    /// Decode a progressive image one scan at a time
+    ///
+    /// The returned reader decodes a scan every time
+    /// [`next_scan`](ProgressiveReader::next_scan) is called, and can render the
+    /// image from the scans decoded so far. Images that are not Huffman coded
+    /// progressive images are decoded whole by the first call to `next_scan`.
+    ///
+    /// # Errors
+    /// If the image headers are invalid
+    pub fn progressive_scans<'a>(
+        &'a mut self, buf: &'a [u8],
+    ) -> Result<ProgressiveReader<'a>, DecodeErrors>
+    {
+        let mut data = Cursor::new(buf);
+
+        self.prepare_headers(&mut data)?;
+
+        if self.renders_progressive_scans()
+        {
+            self.set_upsampling()?;
+        }
+        let scans = ProgressiveScans::new(self);
+
+        Ok(ProgressiveReader {
+            decoder: self,
+            data,
+            scans,
+            pixels: vec![],
+        })
+    }
+
+    /// Whether the image can be decoded scan by scan by `ProgressiveScans`
+    pub(crate) fn renders_progressive_scans(&self) -> bool
+    {
+        self.is_progressive
+            && !self.info.sof.is_arithmetic()
+            && self.hierarchical.is_none()
+            && self.info.pixel_density <= 8
+    }
+}
+
+impl ProgressiveReader<'_>
+{
+    /// Decode the next scan
+    ///
+    /// Returns `false` once every scan has been decoded.
+    ///
+    /// # Errors
+    /// If the image data is corrupt
+    pub fn next_scan(&mut self) -> Result<bool, DecodeErrors>
+    {
+        if self.decoder.renders_progressive_scans()
+        {
+            return self.scans.next_scan(self.decoder, &mut self.data);
+        }
+        if self.scans.scans != 0
+        {
+            return Ok(false);
+        }
+        self.pixels = self.decoder.decode_scans_unturned(&mut self.data)?;
+
+        self.scans.scans += 1;
+
+        Ok(true)
+    }
+
+    /// Render the image from the coefficients of the scans decoded so far
+    ///
+    /// Pixels are laid out as `decode_buffer` lays them out, once every scan has been
+    /// decoded, this returns what `decode_buffer` would have returned.
+    pub fn render(&mut self) -> &[u8]
+    {
+        if self.decoder.renders_progressive_scans()
+        {
+            self.scans.render(self.decoder, &mut self.pixels);
+        }
+        &self.pixels
+    }
+
+    /// Estimate AC coefficients not decoded yet when rendering, see the module docs
+    ///
+    /// Off by default.
+    pub fn set_block_smoothing(&mut self, smoothing: bool)
+    {
+        self.scans.block_smoothing = smoothing;
+    }
+
+    /// Number of scans decoded so far
+    #[must_use]
+    pub fn scans_decoded(&self) -> usize
+    {
+        self.scans.scans
+    }
+
+    /// Information about the image being decoded
+    #[must_use]
+    pub fn info(&self) -> ImageInfo
+    {
+        self.decoder.info.clone()
+    }
+}
+
+impl ProgressiveScans
+{
+    /// Set up decoding of an image whose headers have been parsed
+    ///
+    /// Coefficient buffers are only allocated for images which
+    /// `renders_progressive_scans`
+    pub(crate) fn new(decoder: &Decoder) -> ProgressiveScans
+    {
+        let (block, mcu_width) = if decoder.renders_progressive_scans()
+        {
+            decoder.allocate_coefficient_buffers()
+        }
+        else
+        {
+            ([vec![], vec![], vec![], vec![]], 0)
+        };
+
+        let stream = BitStream::new_progressive(
+            decoder.succ_high,
+            decoder.succ_low,
+            decoder.spec_start,
+            decoder.spec_end,
+        );
+
+        ProgressiveScans {
+            stream,
+            block,
+            mcu_width,
+            marker: None,
+            scans: 0,
+            coef_bits: [[-1; 64]; MAX_COMPONENTS],
+            block_smoothing: false,
+        }
+    }
+
+    /// Decode the next scan from `data`, which must continue where the previous
+    /// scan stopped
+    ///
+    /// Returns `false` once every scan has been decoded.
+    pub(crate) fn next_scan(
+        &mut self, decoder: &mut Decoder, data: &mut Cursor<&[u8]>,
+    ) -> Result<bool, DecodeErrors>
+    {
+        if self.scans == 0
+        {
+            // the first SOS was parsed with the headers
+            decoder.parse_entropy_coded_data(data, &mut self.stream, &mut self.block)?;
+
+            self.marker = get_marker(data, &mut self.stream);
+        }
+        else
+        {
+            let Some(mut marker) = self.marker
+            else
+            {
+                return Ok(false);
+            };
+
+            let scan_found = decoder.decode_next_progressive_scan(
+                data,
+                &mut self.stream,
+                &mut self.block,
+                &mut marker,
+            )?;
+
+            if !scan_found
+            {
+                self.marker = None;
+
+                return Ok(false);
+            }
+            self.marker = Some(marker);
+        }
+
+        // the scan parameters are still those of the scan we just decoded
+        for k in 0..usize::from(decoder.num_scans)
+        {
+            if let Some(bits) = self.coef_bits.get_mut(decoder.z_order[k])
+            {
+                let (start, end) = (decoder.spec_start, decoder.spec_end);
+
+                for bit in &mut bits[usize::from(start)..=usize::from(end)]
+                {
+                    *bit = decoder.succ_low as i8;
+                }
+            }
+        }
+        self.scans += 1;
+
+        Ok(true)
+    }
+
+    /// Render the image from the coefficients decoded so far into `pixels`
+    pub(crate) fn render(&self, decoder: &mut Decoder, pixels: &mut Vec<u8>)
+    {
+        let smoothed = if self.block_smoothing
+        {
+            Some(self.smoothed_coefficients(decoder))
+        }
+        else
+        {
+            None
+        };
+
+        let block = smoothed.as_ref().unwrap_or(&self.block);
+
+        if decoder.reconstructs_whole_planes()
+        {
+            let whole_image = McuWindow {
+                x: 0,
+                y: 0,
+                width: decoder.mcu_x,
+                height: decoder.mcu_y,
+            };
+
+            // previews are not turned
+            let samples =
+                decoder.finish_scaled_decoding(block, whole_image, decoder.crop_window(), None);
+
+            // samples fit into 8 bits
+            *pixels = samples.into_iter().map(|x| x as u8).collect();
+        }
+        else
+        {
+            // not every byte of the output is written for every image, start from zeroes
+            // so the result matches `decode_buffer`
+            pixels.clear();
+
+            pixels.resize(decoder.output_size(), 0);
+
+            // previews are not turned
+            decoder.finish_progressive_decoding(block, self.mcu_width, pixels, None);
+        }
+    }
+
+    /// Copy of the coefficients with block smoothing applied to every component
+    /// that benefits from it
+    fn smoothed_coefficients(&self, decoder: &Decoder) -> [Vec<i16>; MAX_COMPONENTS]
+    {
+        let mut block = self.block.clone();
+
+        for ((coefficients, component), coef_bits) in block
+            .iter_mut()
+            .zip(decoder.components.iter())
+            .zip(self.coef_bits.iter())
+        {
+            let table = &component.quantization_table.0;
+
+            // libjpeg only smooths once the DC coefficients are known, while some of
+            // the coefficients it estimates are not fully known
+            let useful =
+                coef_bits[0] >= 0 && [Q01, Q10, Q20, Q11, Q02].iter().any(|x| coef_bits[*x] != 0);
+
+            let tables_valid = [0, Q01, Q10, Q20, Q11, Q02].iter().all(|x| table[*x] != 0);
+
+            if useful && tables_valid && !coefficients.is_empty()
+            {
+                *coefficients =
+                    smooth_blocks(coefficients, component.width_stride / 8, table, coef_bits);
+            }
+        }
+        block
+    }
+}
+
+/// Estimate the low frequency AC coefficients of every block from the DC
+/// coefficients of the 3x3 blocks around it
+///
+/// This is libjpeg's block smoothing (see `decompress_smooth_data` in `jdcoefct.c`).
+/// A coefficient is only estimated if it is zero and not yet fully known, estimates
+/// of coefficients known down to bit `Al` are kept below `2^Al` so they stay within
+/// what later refinement scans could add.
+///
+/// # Arguments
+/// - coefficients: Blocks of a component, in natural order, `blocks_wide` to a row
+/// - table: Quantization table of the component
+/// - `coef_bits`: Lowest known bit of each coefficient, -1 if not seen yet
+fn smooth_blocks(
+    coefficients: &[i16], blocks_wide: usize, table: &[i32; 64], coef_bits: &[i8; 64],
+) -> Vec<i16>
+{
+    let mut output = coefficients.to_vec();
+
+    let blocks_high = coefficients.len() / (64 * blocks_wide);
+
+    // DC coefficient of a block, blocks past the edges are replaced by the edge blocks
+    let dc = |x: usize, y: usize, dx: isize, dy: isize| -> i64 {
+        let x = x.saturating_add_signed(dx).min(blocks_wide - 1);
+
+        let y = y.saturating_add_signed(dy).min(blocks_high - 1);
+
+        i64::from(coefficients[64 * (y * blocks_wide + x)])
+    };
+
+    let q00 = i64::from(table[0]);
+
+    for y in 0..blocks_high
+    {
+        for x in 0..blocks_wide
+        {
+            let (dc1, dc2, dc3) = (dc(x, y, -1, -1), dc(x, y, 0, -1), dc(x, y, 1, -1));
+
+            let (dc4, dc5, dc6) = (dc(x, y, -1, 0), dc(x, y, 0, 0), dc(x, y, 1, 0));
+
+            let (dc7, dc8, dc9) = (dc(x, y, -1, 1), dc(x, y, 0, 1), dc(x, y, 1, 1));
+
+            let estimates = [
+                (Q01, 36 * q00 * (dc4 - dc6)),
+                (Q10, 36 * q00 * (dc2 - dc8)),
+                (Q20, 9 * q00 * (dc2 + dc8 - 2 * dc5)),
+                (Q11, 5 * q00 * (dc1 - dc3 - dc7 + dc9)),
+                (Q02, 9 * q00 * (dc4 + dc6 - 2 * dc5)),
+            ];
+
+            let out = &mut output[64 * (y * blocks_wide + x)..][..64];
+
+            for (position, num) in estimates
+            {
+                let al = coef_bits[position];
+
+                if al == 0 || out[position] != 0
+                {
+                    continue;
+                }
+                let q = i64::from(table[position]);
+
+                let mut pred = ((q << 7) + num.abs()) / (q << 8);
+
+                if al > 0 && pred >= (1 << al)
+                {
+                    pred = (1 << al) - 1;
+                }
+
+                out[position] = if num < 0 { -pred } else { pred } as i16;
+            }
+        }
+    }
+    output
+}
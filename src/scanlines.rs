@@ -0,0 +1,188 @@
+//! Row by row decoding
+//!
+//! Baseline images are decoded an MCU row at a time, so instead of building the whole
+//! image we can hand out output rows as soon as the MCU row they belong to has been
+//! post processed, which is what [`ScanlineReader`] does.
+//!
+//! Chroma upsampling only looks at samples within the rows decoded together (two MCU
+//! rows for 4:2:0 images), so a batch of rows is only returned once that whole group
+//! has been decoded, and rows come out exactly as `decode_buffer` would write them.
+//!
+//! Other images are decoded whole when the reader is created and their rows are
+//! returned in batches of the same height.
+use std::io::Cursor;
+
+use crate::bitstream::BitStream;
+use crate::errors::DecodeErrors;
+use crate::mcu::BaselineLayout;
+use crate::worker::post_process;
+use crate::{Decoder, ImageInfo};
+
+/// Returns output rows of an image as they are decoded
+///
+/// Created by [`Decoder::scanlines`]. Each call to [`next_rows`](Self::next_rows)
+/// returns the next batch of whole rows, laid out as `decode_buffer` lays them out,
+/// so concatenating the batches gives the decoded image.
+///
+/// # Example
+/// ```no_run
+/// use zune_jpeg::Decoder;
+///
+/// let data = std::fs::read("image.jpg").unwrap();
+///
+/// let mut decoder = Decoder::new();
+///
+/// let mut scanlines = decoder.scanlines(&data).unwrap();
+///
+/// while let Some(rows) = scanlines.next_rows().unwrap()
+/// {
+///     // hand rows to the next stage
+/// }
+/// ```
+pub struct ScanlineReader<'a>
+{
+    decoder: &'a mut Decoder,
+    data: Cursor<&'a [u8]>,
+    /// Set for images decoded an MCU row at a time
+    layout: Option<BaselineLayout>,
+    stream: BitStream,
+    /// Rows of MCUs still to be decoded
+    mcu_rows: usize,
+    /// Output of the last decoded MCU row, or the whole image
+    pixels: Vec<u8>,
+    /// Bytes in a single output row
+    row_size: usize,
+    /// Rows in every batch but perhaps the last
+    rows_per_batch: usize,
+    /// Bytes of the image not yet returned
+    remaining: usize,
+}
+
+impl Decoder
+{
+    /// Decode an image row by row
+    ///
+    /// The returned reader decodes baseline images one MCU row at a time, holding a
+    /// single row of output in memory, other images are decoded when this is
+    /// called.
+    ///
+    /// # Errors
+    /// If the image headers are invalid, or the image could not be decoded
+    pub fn scanlines<'a>(&'a mut self, buf: &'a [u8]) -> Result<ScanlineReader<'a>, DecodeErrors>
+    {
+        let mut data = Cursor::new(buf);
+
+        self.prepare_headers(&mut data)?;
+
+        // the same number of rows the baseline decoder writes at a time
+        let rows_per_batch = 8 * self.h_max * self.v_max;
+
+        let (layout, mcu_rows, pixels, remaining) = if self.decodes_by_mcu_rows()
+        {
+            let layout = self.baseline_layout()?;
+
+            let mcu_rows = layout.mcu_height;
+
+            let pixels = vec![0; layout.chunk_size];
+
+            (Some(layout), mcu_rows, pixels, self.output_size())
+        }
+        else
+        {
+            // sized once decoded, a hierarchical image ending in lossless frames is
+            // not color converted, which its first frame header doesn't tell
+            let pixels = self.decode_scans_unturned(&mut data)?;
+
+            let remaining = pixels.len();
+
+            (None, 0, pixels, remaining)
+        };
+
+        let (_, height) = self.decoded_dimensions();
+
+        let row_size = remaining / height;
+
+        Ok(ScanlineReader {
+            decoder: self,
+            data,
+            layout,
+            stream: BitStream::new(),
+            mcu_rows,
+            pixels,
+            row_size,
+            rows_per_batch,
+            remaining,
+        })
+    }
+}
+
+impl ScanlineReader<'_>
+{
+    /// Decode and return the next batch of rows
+    ///
+    /// Returns `None` once every row of the image has been returned.
+    ///
+    /// # Errors
+    /// If the image data is corrupt
+    pub fn next_rows(&mut self) -> Result<Option<&[u8]>, DecodeErrors>
+    {
+        if self.remaining == 0
+        {
+            return Ok(None);
+        }
+        let length = (self.row_size * self.rows_per_batch).min(self.remaining);
+
+        let start = match &self.layout
+        {
+            Some(layout) =>
+            {
+                let decoder = &mut *self.decoder;
+
+                let last = self.mcu_rows == 1;
+
+                let mut coefficients =
+                    decoder.decode_baseline_row(layout, &mut self.data, &mut self.stream, last)?;
+
+                post_process(
+                    &mut coefficients,
+                    &decoder.components,
+                    decoder.idct_func,
+                    decoder.color_convert_16,
+                    decoder.input_colorspace,
+                    decoder.output_colorspace,
+                    &mut self.pixels,
+                    usize::from(decoder.info.width),
+                );
+
+                self.mcu_rows -= 1;
+                0
+            }
+            None => self.pixels.len() - self.remaining,
+        };
+
+        self.remaining -= length;
+
+        Ok(Some(&self.pixels[start..start + length]))
+    }
+
+    /// Number of bytes in a single output row
+    #[must_use]
+    pub fn row_size(&self) -> usize
+    {
+        self.row_size
+    }
+
+    /// Number of rows returned by every call to `next_rows`, except perhaps the last
+    #[must_use]
+    pub fn rows_per_batch(&self) -> usize
+    {
+        self.rows_per_batch
+    }
+
+    /// Information about the image being decoded
+    #[must_use]
+    pub fn info(&self) -> ImageInfo
+    {
+        self.decoder.info.clone()
+    }
+}
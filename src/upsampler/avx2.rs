@@ -259,7 +259,10 @@ pub unsafe fn upsample_hv_avx(input: &[i16], output_len: usize) -> Vec<i16>
             // Safety , the assert statement above ensures none of these goes out of bounds.
             prev = (3 * (*input.get_unchecked(pos)) + (*input.get_unchecked(pos + stride)) + 2) >> 2;
 
-            pixel_far = (3 * (*input.get_unchecked(pos + 16)) + (*input.get_unchecked(pos + stride + 16)) + 2) >> 2;
+            // the last row of the input has nothing after it, repeat its last pixel
+            let next = if input.len() > pos + stride + 16 { pos + 16 } else { pos + 15 };
+
+            pixel_far = (3 * (*input.get_unchecked(next)) + (*input.get_unchecked(next + stride)) + 2) >> 2;
         }
         // now there are some more data left, since we actually don't write
         // to the end of the array.
@@ -0,0 +1,104 @@
+//! Problems with an image which do not stop it from being decoded
+//!
+//! Decoders skip over a few things the standard does not allow, like markers it
+//! does not define or stray bytes between segments, and in lenient mode over
+//! damaged entropy coded data (see the `lenient` module). Each of these is recorded
+//! as a [`DecodeWarning`], with the marker and the offset in the image it was found
+//! at, so callers can tell how healthy a file is, and is logged too.
+//!
+//! Callers who would rather not accept some of these can turn their kinds into
+//! errors via [`set_warnings_as_errors`](Decoder::set_warnings_as_errors), the
+//! decode then fails with [`DecodeErrors::Warning`] at the first one.
+//!
+//! Application segments and comments are fine where a decoder skips them, they are
+//! just of no use to it, so they are not warned about.
+use std::io::Cursor;
+
+use crate::errors::{DecodeErrors, DecodeWarning, WarningKind};
+use crate::marker::Marker;
+use crate::Decoder;
+
+impl Decoder
+{
+    /// Problems with the last image decoded, or whose headers were read
+    ///
+    /// This is empty if nothing went wrong.
+    #[must_use]
+    pub fn warnings(&self) -> &[DecodeWarning]
+    {
+        &self.warnings
+    }
+
+    /// Set which kinds of warnings fail the decode
+    ///
+    /// The decode fails with [`DecodeErrors::Warning`] at the first warning of
+    /// one of these kinds, nothing is promoted by default.
+    pub fn set_warnings_as_errors(&mut self, kinds: &[WarningKind])
+    {
+        self.fatal_warnings = kinds.to_vec();
+    }
+
+    /// Get the kinds of warnings which fail the decode
+    #[must_use]
+    pub fn get_warnings_as_errors(&self) -> &[WarningKind]
+    {
+        &self.fatal_warnings
+    }
+
+    /// Record a warning, or fail if its kind was promoted to an error
+    ///
+    /// `offset` is relative to `data_offset`.
+    pub(crate) fn add_warning(
+        &mut self, kind: WarningKind, marker: Option<u8>, offset: usize, reason: String,
+    ) -> Result<(), DecodeErrors>
+    {
+        let warning = DecodeWarning {
+            kind,
+            marker,
+            offset: self.data_offset + offset,
+            reason,
+        };
+
+        if self.fatal_warnings.contains(&kind)
+        {
+            return Err(DecodeErrors::Warning(warning));
+        }
+        warn!("{warning}");
+
+        self.warnings.push(warning);
+
+        Ok(())
+    }
+
+    /// Record a marker skipped, along with its segment if it has one, because it
+    /// could not be made sense of where it was found at `offset`
+    pub(crate) fn add_skipped_marker(
+        &mut self, marker: u8, offset: usize, place: &str,
+    ) -> Result<(), DecodeErrors>
+    {
+        match Marker::from_u8(marker)
+        {
+            Some(Marker::APP(_) | Marker::COM) => Ok(()),
+            Some(known) => self.add_warning(
+                WarningKind::UnexpectedMarker,
+                Some(marker),
+                offset,
+                format!("Skipped marker {known:?} {place}"),
+            ),
+            None => self.add_warning(
+                WarningKind::UnknownMarker,
+                Some(marker),
+                offset,
+                format!("Skipped unknown marker 0xFF{marker:X} {place}"),
+            ),
+        }
+    }
+}
+
+/// Offset of the marker `reader` has just read
+pub(crate) fn marker_offset(reader: &Cursor<&[u8]>) -> usize
+{
+    usize::try_from(reader.position())
+        .unwrap_or(usize::MAX)
+        .saturating_sub(2)
+}
@@ -1,7 +1,11 @@
 use std::cmp::min;
 use std::convert::TryInto;
+use std::slice::ChunksMut;
+use std::sync::mpsc::{channel, Receiver, Sender};
 
-use crate::color_convert::{ycbcr_to_grayscale, ycbcr_to_ycbcr};
+use crate::color_convert::{
+    rgb_to_rgb_16_scalar, rgb_to_rgba_16_scalar, ycbcr_to_grayscale, ycbcr_to_ycbcr,
+};
 use crate::components::Components;
 use crate::decoder::{ColorConvert16Ptr, IDCTPtr};
 use crate::misc::ColorSpace;
@@ -83,6 +87,247 @@ pub(crate) fn post_process(
                        input_colorspace, output_colorspace, output,  width);
 }
 
+/// Write an MCU row to `output` using `process`
+///
+/// The last MCU row of an image whose height is not a multiple of the MCU height
+/// does not fit into its part of the output, so it is written to a scratch row
+/// of `row_size` bytes first and the lines inside the image are copied over.
+pub(crate) fn write_mcu_row<F>(output: &mut [u8], row_size: usize, process: F)
+where
+    F: FnOnce(&mut [u8]),
+{
+    if output.len() >= row_size
+    {
+        process(output);
+    }
+    else
+    {
+        let mut scratch = vec![0; row_size];
+
+        process(&mut scratch);
+
+        output.copy_from_slice(&scratch[..output.len()]);
+    }
+}
+
+/// Where pixels of an image turned upright per its Exif orientation go
+///
+/// Pixel `(x, y)` of the image as stored is pixel `origin + x * dx + y * dy` of the
+/// turned image, counting pixels row by row.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Orientation
+{
+    width: usize,
+    height: usize,
+    origin: isize,
+    dx: isize,
+    dy: isize,
+}
+
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+impl Orientation
+{
+    /// Turn a `width` x `height` image with Exif orientation `orientation`, 2 to 8
+    ///
+    /// Orientations 5 to 8 swap the width and height.
+    pub(crate) fn new(orientation: u16, width: usize, height: usize) -> Orientation
+    {
+        let (w, h) = (width as isize, height as isize);
+
+        let (origin, dx, dy) = match orientation
+        {
+            // mirrored horizontally
+            2 => (w - 1, -1, w),
+            // rotated by 180 degrees
+            3 => (w * h - 1, -1, -w),
+            // mirrored vertically
+            4 => ((h - 1) * w, 1, -w),
+            // mirrored along the top left to bottom right diagonal
+            5 => (0, h, 1),
+            // rotated by 90 degrees clockwise
+            6 => (h - 1, h, -1),
+            // mirrored along the top right to bottom left diagonal
+            7 => (w * h - 1, -h, -1),
+            // rotated by 90 degrees counter clockwise
+            8 => ((w - 1) * h, -h, 1),
+            _ => (0, 1, w),
+        };
+
+        Orientation {
+            width,
+            height,
+            origin,
+            dx,
+            dy,
+        }
+    }
+
+    /// Write `rows`, lines of the image as stored starting at line `first_line`, to
+    /// the turned image `output`
+    ///
+    /// Lines past the bottom of the image are left out.
+    pub(crate) fn write_rows<T: Copy>(
+        &self, rows: &[T], first_line: usize, channels: usize, output: &mut [T],
+    )
+    {
+        let stride = self.width * channels;
+
+        let lines = self.height.saturating_sub(first_line);
+
+        for (y, line) in rows.chunks_exact(stride).take(lines).enumerate()
+        {
+            let mut position = self.origin + (first_line + y) as isize * self.dy;
+
+            for pixel in line.chunks_exact(channels)
+            {
+                let start = position as usize * channels;
+
+                output[start..start + channels].copy_from_slice(pixel);
+
+                position += self.dx;
+            }
+        }
+    }
+}
+
+/// The MCU rows of an output buffer, handed to the threads writing them
+///
+/// Unless the image is turned, every MCU row has its own chunk of the output.
+/// Turned rows end up all over the output, which threads cannot share, so they
+/// are written to a scratch row and sent back, and the decoding thread copies
+/// them to where they belong as it hands out the following rows, and once all
+/// threads are done, see [`finish`](Self::finish).
+pub(crate) enum McuRows<'a>
+{
+    Chunks(ChunksMut<'a, u8>),
+    Turned
+    {
+        output: &'a mut [u8],
+        orientation: Orientation,
+        /// Lines of the image in an MCU row
+        lines: usize,
+        channels: usize,
+        /// MCU rows handed out so far, and the number of MCU rows
+        next: usize,
+        rows: usize,
+        sender: Sender<(usize, Vec<u8>)>,
+        receiver: Receiver<(usize, Vec<u8>)>,
+    },
+}
+
+/// Where a thread writes an MCU row
+pub(crate) enum McuRow<'a>
+{
+    Chunk(&'a mut [u8]),
+    Turned(usize, Sender<(usize, Vec<u8>)>),
+}
+
+impl<'a> McuRows<'a>
+{
+    /// Split `output`, an image `width` pixels of `channels` bytes wide, into MCU
+    /// rows of `row_size` bytes, to be turned by `orientation` if any
+    pub(crate) fn new(
+        output: &'a mut [u8], row_size: usize, width: usize, channels: usize,
+        orientation: Option<Orientation>,
+    ) -> McuRows<'a>
+    {
+        let Some(orientation) = orientation
+        else
+        {
+            return McuRows::Chunks(output.chunks_mut(row_size));
+        };
+        let lines = row_size / (width * channels).max(1);
+
+        let (sender, receiver) = channel();
+
+        McuRows::Turned {
+            rows: orientation.height.div_ceil(lines.max(1)),
+            output,
+            orientation,
+            lines,
+            channels,
+            next: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Write the turned MCU rows threads have sent back
+    ///
+    /// Call this once the threads writing rows have finished.
+    pub(crate) fn finish(&mut self)
+    {
+        if let McuRows::Turned {
+            output,
+            orientation,
+            lines,
+            channels,
+            receiver,
+            ..
+        } = self
+        {
+            for (row, pixels) in receiver.try_iter()
+            {
+                orientation.write_rows(&pixels, row * *lines, *channels, output);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for McuRows<'a>
+{
+    type Item = McuRow<'a>;
+
+    fn next(&mut self) -> Option<McuRow<'a>>
+    {
+        match self
+        {
+            McuRows::Chunks(chunks) => chunks.next().map(McuRow::Chunk),
+            McuRows::Turned {
+                next, rows, sender, ..
+            } =>
+            {
+                if *next == *rows
+                {
+                    return None;
+                }
+                let row = McuRow::Turned(*next, sender.clone());
+
+                *next += 1;
+
+                // don't hold on to rows which are done
+                self.finish();
+
+                Some(row)
+            }
+        }
+    }
+}
+
+impl McuRow<'_>
+{
+    /// Write the MCU row using `process`, which writes `row_size` bytes, see
+    /// `write_mcu_row`
+    pub(crate) fn write<F>(self, row_size: usize, process: F)
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        match self
+        {
+            McuRow::Chunk(output) => write_mcu_row(output, row_size, process),
+            McuRow::Turned(row, sender) =>
+            {
+                let mut scratch = vec![0; row_size];
+
+                process(&mut scratch);
+
+                // the receiver outlives the threads
+                let _ = sender.send((row, scratch));
+            }
+        }
+    }
+}
+
 #[rustfmt::skip]
 pub(crate) fn post_process_prog(
     block: &[&[i16]; 3], /*The difference with post process*/
@@ -160,9 +405,23 @@ pub(crate) fn post_process_inner(
             color_convert_ycbcr(unprocessed, width, h_samp, v_samp,
                 output_colorspace, color_convert_16,  output);
         }
-        // For the other components we do nothing(currently)
-        _ =>
-        {}
+        (ColorSpace::RGB, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+        {
+            // samples are copied as they are
+            let copy_16: ColorConvert16Ptr = if output_colorspace == ColorSpace::RGB
+            {
+                rgb_to_rgb_16_scalar
+            }
+            else
+            {
+                rgb_to_rgba_16_scalar
+            };
+
+            color_convert_ycbcr(unprocessed, width, h_samp, v_samp,
+                output_colorspace, copy_16,  output);
+        }
+        // `check_colorspace` rejects the others
+        _ => unreachable!("Cannot convert {input_colorspace:?} to {output_colorspace:?}"),
     }
 }
 
@@ -222,21 +481,30 @@ let  e= (y_width.len()/16).saturating_sub(1);
             (color_convert_16)(y.try_into().unwrap(), cb.try_into().unwrap(), cr.try_into().unwrap(), &mut out, &mut position);
         }
         
-        // we have more pixels in the end that can't be handled by the main loop.
-        // move pointer back a little bit to get last 16 bytes,
-        // color convert, and overwrite
-        // This means some values will be color converted twice.
-    
-        let diff = 64-(stride-position);
-    
-        position -= diff;
-    
-        (color_convert_16)(
-            y_width.rchunks_exact(16).next().unwrap().try_into().unwrap(),
-            cb_width.rchunks_exact(16).next().unwrap().try_into().unwrap(),
-            cr_width.rchunks_exact(16).next().unwrap().try_into().unwrap(),
-        &mut out,&mut position);
+        // the pixels left at the end of the row don't fill the output for a run of
+        // 16, convert them into a scratch buffer and copy the ones inside the row
+        let channels = output_colorspace.num_components();
+
+        for x in (16 * e..width).step_by(16)
+        {
+            let mut samples = [[0; 16]; 3];
+
+            for (samples, plane) in samples.iter_mut().zip([y_width, cb_width, cr_width])
+            {
+                let plane_end = min(x + 16, plane.len());
 
+                samples[..plane_end - x].copy_from_slice(&plane[x..plane_end]);
+            }
+            let mut scratch = [0; 64];
+
+            let mut scratch_position = 0;
+
+            (color_convert_16)(&samples[0], &samples[1], &samples[2], &mut scratch, &mut scratch_position);
+
+            let bytes = min(16, width - x) * channels;
+
+            out[x * channels..x * channels + bytes].copy_from_slice(&scratch[..bytes]);
+        }
         start += stride;
 
         end += stride;
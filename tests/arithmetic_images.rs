@@ -0,0 +1,113 @@
+//! Arithmetic coded images
+//!
+//! The images were created by transcoding Huffman coded files with
+//! `jpegtran -arithmetic`, which keeps the DCT coefficients intact, so
+//! decoding both should give us the same pixels.
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn decode(name: &str, colorspace: ColorSpace) -> (Vec<u8>, u16, u16)
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/arithmetic/" + name;
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    let pixels = decoder.decode_file(&path).expect("Test failed decoding");
+
+    (pixels, decoder.width(), decoder.height())
+}
+
+#[test]
+fn sof9_no_sampling()
+{
+    let (arithmetic, width, height) = decode("sof9_1x1.jpg", ColorSpace::RGB);
+
+    let (huffman, ..) = decode("huffman_1x1.jpg", ColorSpace::RGB);
+
+    assert_eq!((width, height), (480, 256));
+
+    assert!(arithmetic == huffman);
+}
+
+#[test]
+fn sof9_horizontal_sampling()
+{
+    let (arithmetic, width, height) = decode("sof9_2x1.jpg", ColorSpace::RGB);
+
+    let (huffman, ..) = decode("huffman_2x1.jpg", ColorSpace::RGB);
+
+    assert_eq!((width, height), (480, 256));
+
+    assert!(arithmetic == huffman);
+}
+
+#[test]
+fn sof9_grayscale()
+{
+    let (arithmetic, width, height) = decode("sof9_gray.jpg", ColorSpace::GRAYSCALE);
+
+    let (huffman, ..) = decode("huffman_gray.jpg", ColorSpace::GRAYSCALE);
+
+    assert_eq!((width, height), (480, 256));
+
+    assert!(arithmetic == huffman);
+}
+
+#[test]
+fn sof9_restart_markers()
+{
+    // same coefficients as sof9_2x1.jpg with a restart interval of 7 MCU's
+    let (restart, ..) = decode("sof9_rst.jpg", ColorSpace::RGB);
+
+    let (no_restart, ..) = decode("sof9_2x1.jpg", ColorSpace::RGB);
+
+    assert!(restart == no_restart);
+}
+
+#[test]
+fn sof10_no_sampling()
+{
+    let (progressive, width, height) = decode("sof10_1x1.jpg", ColorSpace::RGB);
+
+    let (sequential, ..) = decode("sof9_1x1.jpg", ColorSpace::RGB);
+
+    assert_eq!((width, height), (480, 256));
+
+    assert!(progressive == sequential);
+}
+
+#[test]
+fn sof10_grayscale()
+{
+    let (progressive, ..) = decode("sof10_gray.jpg", ColorSpace::GRAYSCALE);
+
+    let (sequential, ..) = decode("sof9_gray.jpg", ColorSpace::GRAYSCALE);
+
+    assert!(progressive == sequential);
+}
+
+#[test]
+fn sof10_refinement_and_restart_markers()
+{
+    // DC and AC successive approximation with a restart interval of 3 MCU's
+    let (progressive, ..) = decode("sof10_rst.jpg", ColorSpace::RGB);
+
+    let (sequential, ..) = decode("sof9_2x1.jpg", ColorSpace::RGB);
+
+    assert!(progressive == sequential);
+}
+
+#[test]
+fn test_arithmetic_coding_image()
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/test-arithmetic-coding.jpg";
+
+    let mut decoder = Decoder::new();
+
+    let pixels = decoder.decode_file(&path).expect("Test failed decoding");
+
+    assert_eq!((decoder.width(), decoder.height()), (1920, 1080));
+
+    assert_eq!(pixels.len(), 1920 * 1080 * 3);
+}
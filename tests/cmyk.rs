@@ -0,0 +1,280 @@
+//! Four component CMYK and YCCK images
+//!
+//! The images in `tests/inputs/cmyk` were written with libjpeg, which adds an Adobe segment,
+//! `cmyk*.jpg` storing CMYK (transform 0) and `ycck*.jpg` YCCK (transform 2), with
+//! Y and K 2x2 sampled. Progressive and arithmetic coded files hold the same
+//! coefficients as the baseline ones. The `.pam` files are what libjpeg-turbo
+//! decodes the baseline images to, inverted CMYK as stored in the file.
+//!
+//! `rgb*.jpg` are three component images libjpeg stored as RGB, with an Adobe
+//! segment saying so (transform 0), `rgb.ppm` is what it decodes them to.
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/cmyk/" + name).unwrap()
+}
+
+/// Read the samples of a PAM file, inverted so 0 is no ink
+fn read_reference(name: &str) -> Vec<u8>
+{
+    let data = read(name);
+
+    let start = data.windows(7).position(|x| x == b"ENDHDR\n").unwrap() + 7;
+
+    data[start..].iter().map(|x| 255 - x).collect()
+}
+
+fn decode(data: &[u8], colorspace: ColorSpace) -> Vec<u8>
+{
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    decoder.decode_buffer(data).unwrap()
+}
+
+/// Check `pixels` are at most `tolerance` away from `expected`, our IDCT doesn't round
+/// the same way as libjpeg's
+fn assert_close(pixels: &[u8], expected: &[u8], tolerance: u8)
+{
+    assert_eq!(pixels.len(), expected.len());
+
+    let difference = pixels
+        .iter()
+        .zip(expected)
+        .map(|(x, y)| x.abs_diff(*y))
+        .max();
+
+    assert!(difference <= Some(tolerance), "{difference:?}");
+}
+
+/// `data` without its Adobe segment
+fn without_adobe(data: &[u8]) -> Vec<u8>
+{
+    let start = data
+        .windows(9)
+        .position(|x| x[..2] == [0xFF, 0xEE] && &x[4..] == b"Adobe")
+        .unwrap();
+
+    let length = usize::from(u16::from_be_bytes([data[start + 2], data[start + 3]]));
+
+    let mut data = data.to_vec();
+
+    data.drain(start..start + 2 + length);
+
+    data
+}
+
+#[test]
+fn cmyk_adobe_inverted()
+{
+    let pixels = decode(&read("cmyk.jpg"), ColorSpace::CMYK);
+
+    assert_close(&pixels, &read_reference("cmyk.pam"), 1);
+
+    // the same coefficients, coded progressively
+    assert!(decode(&read("cmyk_prog.jpg"), ColorSpace::CMYK) == pixels);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(ColorSpace::CMYK);
+
+    decoder.decode_buffer(&read("cmyk.jpg")).unwrap();
+
+    assert_eq!(decoder.output_dimensions(), Some((45, 29)));
+
+    assert_eq!(decoder.info().unwrap().components, 4);
+}
+
+#[test]
+fn cmyk_without_adobe_segment()
+{
+    let data = read("cmyk.jpg");
+
+    let inverted = decode(&data, ColorSpace::CMYK);
+
+    // samples are taken as they are stored
+    let pixels = decode(&without_adobe(&data), ColorSpace::CMYK);
+
+    assert!(pixels.iter().zip(&inverted).all(|(x, y)| *x == 255 - y));
+}
+
+#[test]
+fn cmyk_ycck()
+{
+    let pixels = decode(&read("ycck.jpg"), ColorSpace::CMYK);
+
+    // color conversion scales up chroma errors of the IDCT
+    assert_close(&pixels, &read_reference("ycck.pam"), 3);
+
+    for name in ["ycck_prog.jpg", "ycck_arith.jpg"]
+    {
+        assert!(decode(&read(name), ColorSpace::CMYK) == pixels, "{name}");
+    }
+}
+
+#[test]
+fn cmyk_to_rgb()
+{
+    for name in ["cmyk.jpg", "ycck.jpg"]
+    {
+        let data = read(name);
+
+        let cmyk = decode(&data, ColorSpace::CMYK);
+
+        // RGB is the default output
+        let rgb = decode(&data, ColorSpace::RGB);
+
+        let expected: Vec<u8> = cmyk
+            .chunks_exact(4)
+            .flat_map(|x| {
+                let white = 255 - u32::from(x[3]);
+
+                x[..3]
+                    .iter()
+                    .map(move |ink| (((255 - u32::from(*ink)) * white + 127) / 255) as u8)
+            })
+            .collect();
+
+        assert!(rgb == expected, "{name}");
+
+        let rgba = decode(&data, ColorSpace::RGBA);
+
+        assert!(rgba
+            .chunks_exact(4)
+            .zip(rgb.chunks_exact(3))
+            .all(|(x, y)| x[..3] == *y && x[3] == 255));
+    }
+}
+
+#[test]
+fn cmyk_other_routes()
+{
+    let data = read("ycck.jpg");
+
+    let pixels = decode(&data, ColorSpace::RGB);
+
+    let mut decoder = Decoder::new();
+
+    assert!(decoder.decode_reader(&data[..]).unwrap() == pixels);
+
+    let samples = decoder.decode_buffer_u16(&data).unwrap();
+
+    assert!(samples.iter().map(|x| *x as u8).eq(pixels.iter().copied()));
+
+    // a region of the image
+    decoder.set_crop(10, 5, 20, 12);
+
+    let region: Vec<u8> = pixels
+        .chunks_exact(45 * 3)
+        .skip(5)
+        .take(12)
+        .flat_map(|x| &x[30..90])
+        .copied()
+        .collect();
+
+    assert!(decoder.decode_buffer(&data).unwrap() == region);
+
+    // images with fewer components decode as usual afterwards
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    let other =
+        std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/lenient/2x2.jpg")
+            .unwrap();
+
+    assert!(
+        decoder.decode_buffer(&other).unwrap() == Decoder::new().decode_buffer(&other).unwrap()
+    );
+}
+
+#[test]
+fn cmyk_unsupported_output()
+{
+    for colorspace in [ColorSpace::GRAYSCALE, ColorSpace::YCbCr, ColorSpace::YCCK]
+    {
+        let mut decoder = Decoder::new();
+
+        decoder.set_output_colorspace(colorspace);
+
+        assert!(decoder.decode_buffer(&read("cmyk.jpg")).is_err());
+    }
+}
+
+#[test]
+fn cmyk_output_of_other_images()
+{
+    // only four component images have ink to decode, whatever the coding process
+    for name in [
+        "scaled/2x2.jpg",
+        "arithmetic/sof9_2x1.jpg",
+        "coefficients/2x2_progressive.jpg",
+        "extended/sof1_2x1.jpg",
+    ]
+    {
+        let data = std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name)
+            .unwrap();
+
+        for colorspace in [ColorSpace::CMYK, ColorSpace::YCCK]
+        {
+            let mut decoder = Decoder::new();
+
+            decoder.read_headers(&data).unwrap();
+
+            decoder.set_output_colorspace(colorspace);
+
+            assert!(decoder.decode_buffer_u16(&data).is_err(), "{name}");
+
+            assert!(decoder.decode_buffer(&data).is_err(), "{name}");
+
+            assert!(decoder.scanlines(&data).is_err(), "{name}");
+        }
+    }
+}
+
+#[test]
+fn adobe_rgb()
+{
+    let data = read("rgb.jpg");
+
+    let pixels = decode(&data, ColorSpace::RGB);
+
+    let reference = read("rgb.ppm");
+
+    let reference = &reference[reference.len() - 45 * 29 * 3..];
+
+    assert_close(&pixels, reference, 1);
+
+    assert!(decode(&read("rgb_prog.jpg"), ColorSpace::RGB) == pixels);
+
+    let rgba = decode(&data, ColorSpace::RGBA);
+
+    assert!(rgba
+        .chunks_exact(4)
+        .zip(pixels.chunks_exact(3))
+        .all(|(x, y)| x[..3] == *y && x[3] == 255));
+
+    // whole planes are converted on their own path
+    let mut decoder = Decoder::new();
+
+    decoder.set_crop(10, 5, 20, 12);
+
+    let region: Vec<u8> = reference
+        .chunks_exact(45 * 3)
+        .skip(5)
+        .take(12)
+        .flat_map(|x| &x[30..90])
+        .copied()
+        .collect();
+
+    assert_close(&decoder.decode_buffer(&data).unwrap(), &region, 1);
+
+    // samples are not luma and chroma
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(ColorSpace::YCbCr);
+
+    assert!(decoder.decode_buffer(&data).is_err());
+}
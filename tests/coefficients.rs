@@ -0,0 +1,111 @@
+//! Reading quantized DCT coefficients
+//!
+//! The files in `tests/inputs/coefficients` and `crop/2x2_rst.jpg` are the same 480x256
+//! image encoded with the same tables as a progressive, an arithmetic coded and a
+//! baseline image, so they hold the same coefficients.
+use zune_jpeg::{ComponentCoefficients, Decoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+fn coefficients(name: &str) -> Vec<ComponentCoefficients>
+{
+    Decoder::new().decode_coefficients(&read(name)).unwrap()
+}
+
+#[test]
+fn coefficients_match_across_coding_processes()
+{
+    let baseline = coefficients("crop/2x2_rst.jpg");
+
+    assert!(coefficients("coefficients/2x2_progressive.jpg") == baseline);
+
+    assert!(coefficients("coefficients/2x2_arithmetic.jpg") == baseline);
+}
+
+#[test]
+fn coefficients_grid()
+{
+    let components = coefficients("crop/2x2_rst.jpg");
+
+    let grids: Vec<_> = components
+        .iter()
+        .map(|x| {
+            (
+                x.blocks_wide,
+                x.blocks_high,
+                x.horizontal_sample,
+                x.vertical_sample,
+            )
+        })
+        .collect();
+
+    assert_eq!(grids, [(60, 32, 2, 2), (30, 16, 1, 1), (30, 16, 1, 1)]);
+
+    for component in &components
+    {
+        assert_eq!(
+            component.coefficients.len(),
+            component.blocks_wide * component.blocks_high * 64
+        );
+    }
+}
+
+#[test]
+fn coefficients_quantization_tables()
+{
+    let data = read("crop/2x2_rst.jpg");
+
+    let components = coefficients("crop/2x2_rst.jpg");
+
+    // the first table of the first DQT segment, in zig-zag order
+    let start = data.windows(2).position(|x| x == [0xFF, 0xDB]).unwrap() + 5;
+
+    let table = &data[start..start + 64];
+
+    let luma = &components[0].quantization_table;
+
+    // the first three zig-zag positions are (0,0), (1,0) and (0,1)
+    assert_eq!(
+        [luma[0], luma[1], luma[8]],
+        [table[0], table[1], table[2]].map(u16::from)
+    );
+}
+
+#[test]
+fn coefficients_dc_is_block_average()
+{
+    let data = read("crop/2x2_rst.jpg");
+
+    let components = coefficients("crop/2x2_rst.jpg");
+
+    let planes = Decoder::new().decode_planar(&data).unwrap();
+
+    let luma = &components[0];
+
+    // average of the first luma block, from its DC coefficient and from the pixels
+    let dc = i32::from(luma.coefficients[0]) * i32::from(luma.quantization_table[0]);
+
+    let sum: i32 = planes[0]
+        .data
+        .chunks_exact(planes[0].stride)
+        .take(8)
+        .flat_map(|row| &row[..8])
+        .map(|x| i32::from(*x))
+        .sum();
+
+    assert!((dc / 8 + 128 - sum / 64).abs() <= 1);
+}
+
+#[test]
+fn coefficients_unsupported_images()
+{
+    assert!(Decoder::new()
+        .decode_coefficients(&read("lossless/rgb8_p6_rst.jpg"))
+        .is_err());
+
+    // 12 bit images have coefficients too
+    assert_eq!(coefficients("extended/sof1_gray.jpg").len(), 1);
+}
@@ -0,0 +1,213 @@
+//! Decoding a rectangle of an image
+//!
+//! `crop/2x2_rst.jpg` is a 480x256 2x2 sampled image with a restart marker every
+//! three MCUs, so restart intervals line up with MCU rows.
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+fn decode_crop(
+    data: &[u8], colorspace: ColorSpace, denom: u32,
+    (x, y, width, height): (usize, usize, usize, usize),
+) -> Vec<u8>
+{
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    decoder.set_scale(1, denom);
+
+    decoder.set_crop(x, y, width, height);
+
+    let pixels = decoder.decode_buffer(data).unwrap();
+
+    assert_eq!(decoder.output_dimensions(), Some((width, height)));
+
+    pixels
+}
+
+/// Copy a rectangle out of a decoded image
+fn slice(
+    pixels: &[u8], width: usize, components: usize, (x, y, w, h): (usize, usize, usize, usize),
+) -> Vec<u8>
+{
+    pixels
+        .chunks_exact(width * components)
+        .skip(y)
+        .take(h)
+        .flat_map(|row| &row[x * components..(x + w) * components])
+        .copied()
+        .collect()
+}
+
+/// Crops of an image should match the same rectangle cropped out of the whole output
+fn compare(name: &str, colorspace: ColorSpace, denom: u32)
+{
+    let data = read(name);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_scale(1, denom);
+
+    decoder.read_headers(&data).unwrap();
+
+    let (width, height) = decoder.output_dimensions().unwrap();
+
+    let whole = decode_crop(&data, colorspace, denom, (0, 0, width, height));
+
+    for region in [
+        (1, 1, width - 2, height - 2),
+        (width / 3, height / 4, width / 3, height / 2),
+        (width - 5, height - 3, 5, 3),
+        (0, height / 2, 3, 1),
+        (width / 2, 0, 1, height),
+    ]
+    {
+        let pixels = decode_crop(&data, colorspace, denom, region);
+
+        assert!(
+            pixels == slice(&whole, width, colorspace.num_components(), region),
+            "{name} at 1/{denom} cropped to {region:?}"
+        );
+    }
+}
+
+#[test]
+fn crop_baseline()
+{
+    compare("crop/2x2_rst.jpg", ColorSpace::RGB, 1);
+
+    compare("dnl/gray_rst.jpg", ColorSpace::GRAYSCALE, 1);
+
+    compare("dnl/2x1.jpg", ColorSpace::YCbCr, 1);
+
+    compare("medium_horiz_samp_2500x1786.jpg", ColorSpace::RGB, 1);
+}
+
+#[test]
+fn crop_other_images()
+{
+    compare("dnl/progressive.jpg", ColorSpace::RGB, 1);
+
+    compare("arithmetic/sof9_2x1.jpg", ColorSpace::RGB, 1);
+}
+
+#[test]
+fn crop_scaled()
+{
+    for denom in [2, 4, 8]
+    {
+        compare("crop/2x2_rst.jpg", ColorSpace::RGB, denom);
+
+        compare("dnl/progressive.jpg", ColorSpace::RGB, denom);
+    }
+}
+
+#[test]
+fn crop_skips_restart_intervals()
+{
+    let mut data = read("crop/2x2_rst.jpg");
+
+    let region = (100, 64, 200, 100);
+
+    let reference = decode_crop(&data, ColorSpace::RGB, 1, region);
+
+    // corrupt the first restart interval, which lies above the region
+    let start = data.windows(2).position(|x| x == [0xFF, 0xDA]).unwrap() + 14;
+
+    let end = data.windows(2).position(|x| x == [0xFF, 0xD0]).unwrap();
+
+    data[start..end].fill(0x55);
+
+    assert!(decode_crop(&data, ColorSpace::RGB, 1, region) == reference);
+}
+
+#[test]
+fn crop_whole_plane_images()
+{
+    // lossless images are decoded whole and cropped afterwards
+    let data = read("lossless/rgb8_p6_rst.jpg");
+
+    let mut decoder = Decoder::new();
+
+    let whole = decoder.decode_buffer(&data).unwrap();
+
+    let width = usize::from(decoder.width());
+
+    let region = (3, 5, 20, 7);
+
+    assert!(decode_crop(&data, ColorSpace::RGB, 1, region) == slice(&whole, width, 3, region));
+}
+
+#[test]
+fn crop_decode_into_and_scanlines()
+{
+    let data = read("crop/2x2_rst.jpg");
+
+    let region = (37, 21, 90, 40);
+
+    let reference = decode_crop(&data, ColorSpace::RGB, 1, region);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_crop(37, 21, 90, 40);
+
+    decoder.read_headers(&data).unwrap();
+
+    assert_eq!(decoder.output_buffer_size(), Some(90 * 40 * 3));
+
+    let mut out = vec![0; 90 * 40 * 3];
+
+    decoder.decode_into(&data, &mut out).unwrap();
+
+    assert!(out == reference);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_crop(37, 21, 90, 40);
+
+    let mut scanlines = decoder.scanlines(&data).unwrap();
+
+    assert_eq!(scanlines.row_size(), 90 * 3);
+
+    let mut pixels = vec![];
+
+    while let Some(rows) = scanlines.next_rows().unwrap()
+    {
+        pixels.extend_from_slice(rows);
+    }
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn crop_out_of_bounds()
+{
+    let data = read("crop/2x2_rst.jpg");
+
+    for (x, y, width, height) in [
+        (0, 0, 0, 10),
+        (0, 0, 481, 10),
+        (400, 200, 81, 10),
+        (0, 250, 10, 7),
+    ]
+    {
+        let mut decoder = Decoder::new();
+
+        decoder.set_crop(x, y, width, height);
+
+        assert!(decoder.decode_buffer(&data).is_err());
+    }
+
+    // crops are in output coordinates
+    let mut decoder = Decoder::new();
+
+    decoder.set_scale(1, 2);
+
+    decoder.set_crop(0, 0, 241, 10);
+
+    assert!(decoder.decode_buffer(&data).is_err());
+}
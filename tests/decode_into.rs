@@ -0,0 +1,135 @@
+//! Decoding into caller provided buffers
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// Decode an image with `decode_into` and `decode_buffer`, the results should match
+fn compare(name: &str, colorspace: ColorSpace)
+{
+    let data = read(name);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    decoder.read_headers(&data).unwrap();
+
+    let size = decoder.output_buffer_size().unwrap();
+
+    // the end of the buffer should be left alone
+    let mut out = vec![0xAA; size + 16];
+
+    decoder.decode_into(&data, &mut out).unwrap();
+
+    let mut reference = Decoder::new();
+
+    reference.set_output_colorspace(colorspace);
+
+    let pixels = reference.decode_buffer(&data).unwrap();
+
+    assert_eq!(pixels.len(), size);
+
+    assert!(out[..size] == pixels);
+
+    assert!(out[size..].iter().all(|x| *x == 0xAA));
+}
+
+#[test]
+fn decode_into_baseline()
+{
+    compare("dnl/gray.jpg", ColorSpace::GRAYSCALE);
+
+    compare("dnl/2x1_rst.jpg", ColorSpace::RGB);
+
+    compare("dnl/2x1.jpg", ColorSpace::RGBA);
+}
+
+#[test]
+fn decode_into_progressive()
+{
+    compare("dnl/progressive.jpg", ColorSpace::RGB);
+}
+
+#[test]
+fn decode_into_arithmetic()
+{
+    compare("arithmetic/sof9_2x1.jpg", ColorSpace::RGB);
+
+    compare("arithmetic/sof10_gray.jpg", ColorSpace::GRAYSCALE);
+}
+
+#[test]
+fn decode_into_lossless()
+{
+    compare("lossless/rgb8_p6_rst.jpg", ColorSpace::RGB);
+}
+
+#[test]
+fn decode_into_without_headers()
+{
+    let data = read("dnl/2x1.jpg");
+
+    let mut decoder = Decoder::new();
+
+    assert!(decoder.output_buffer_size().is_none());
+
+    let mut out = vec![0; 48 * 32 * 3];
+
+    decoder.decode_into(&data, &mut out).unwrap();
+
+    assert!(out == Decoder::new().decode_buffer(&data).unwrap());
+}
+
+#[test]
+fn decode_into_too_small()
+{
+    let data = read("dnl/2x1.jpg");
+
+    let mut out = vec![0; 48 * 32 * 3 - 1];
+
+    let result = Decoder::new().decode_into(&data, &mut out);
+
+    assert!(matches!(
+        result,
+        Err(DecodeErrors::TooSmallOutput(4608, 4607))
+    ));
+}
+
+#[test]
+fn decode_into_reused_decoder()
+{
+    // images of different kinds, sampling factors and restart intervals one after
+    // the other, nothing of the previous image should carry over
+    let names = [
+        "coefficients/2x2_progressive.jpg",
+        "scaled/2x2.jpg",
+        "medium_no_samp_2500x1786.jpg",
+        "dnl/2x1_rst.jpg",
+        "arithmetic/sof9_2x1.jpg",
+        "scaled/2x2.jpg",
+    ];
+
+    let mut decoder = Decoder::new();
+
+    let mut out = vec![];
+
+    for name in names
+    {
+        let data = read(name);
+
+        decoder.read_headers(&data).unwrap();
+
+        out.resize(decoder.output_buffer_size().unwrap(), 0);
+
+        decoder.decode_into(&data, &mut out).unwrap();
+
+        assert!(
+            out == Decoder::new().decode_buffer(&data).unwrap(),
+            "{name}"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Decoding from `Read` sources
+use std::io::Read;
+
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// A reader returning a few bytes at a time, like a slow socket would
+struct Trickle
+{
+    data: Vec<u8>,
+    position: usize,
+    chunk: usize,
+}
+
+impl Read for Trickle
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        let end = (self.position + self.chunk)
+            .min(self.data.len())
+            .min(self.position + buf.len());
+
+        let n = end - self.position;
+
+        buf[..n].copy_from_slice(&self.data[self.position..end]);
+
+        self.position = end;
+
+        Ok(n)
+    }
+}
+
+/// Decode an image with `decode_reader` and `decode_buffer`, the results should match
+fn compare(name: &str, colorspace: ColorSpace)
+{
+    let data = read(name);
+
+    for chunk in [1, 7, 4096]
+    {
+        let mut decoder = Decoder::new();
+
+        decoder.set_output_colorspace(colorspace);
+
+        let pixels = decoder
+            .decode_reader(Trickle {
+                data: data.clone(),
+                position: 0,
+                chunk,
+            })
+            .unwrap();
+
+        let mut reference = Decoder::new();
+
+        reference.set_output_colorspace(colorspace);
+
+        assert!(pixels == reference.decode_buffer(&data).unwrap());
+
+        assert_eq!(decoder.width(), reference.width());
+
+        assert_eq!(decoder.height(), reference.height());
+    }
+}
+
+#[test]
+fn reader_baseline()
+{
+    compare("dnl/gray.jpg", ColorSpace::GRAYSCALE);
+
+    compare("dnl/2x1.jpg", ColorSpace::RGBA);
+}
+
+#[test]
+fn reader_restart_markers()
+{
+    compare("dnl/2x1_rst.jpg", ColorSpace::RGB);
+
+    compare("dnl/gray_rst.jpg", ColorSpace::GRAYSCALE);
+}
+
+#[test]
+fn reader_large_baseline()
+{
+    compare("medium_horiz_samp_2500x1786.jpg", ColorSpace::RGB);
+}
+
+#[test]
+fn reader_other_images()
+{
+    // these are read into memory before decoding
+    compare("dnl/progressive.jpg", ColorSpace::RGB);
+
+    compare("dnl/dnl_gray.jpg", ColorSpace::GRAYSCALE);
+
+    compare("arithmetic/sof9_gray.jpg", ColorSpace::GRAYSCALE);
+}
+
+#[test]
+fn reader_truncated()
+{
+    let data = read("dnl/gray.jpg");
+
+    let result = Decoder::new().decode_reader(&data[..100]);
+
+    assert!(result.is_err());
+}
@@ -0,0 +1,140 @@
+//! Limits on the images a decoder accepts
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{Decoder, DecoderOptions, FeedStatus, IncrementalDecoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+fn decode(name: &str, options: DecoderOptions) -> Result<Vec<u8>, DecodeErrors>
+{
+    Decoder::new_with_options(options).decode_buffer(&read(name))
+}
+
+/// The image up to the end of its first SOS segment, without any image data
+fn headers(name: &str) -> Vec<u8>
+{
+    let data = read(name);
+
+    let sos = data.windows(2).position(|x| x == [0xFF, 0xDA]).unwrap();
+
+    let length = usize::from(u16::from_be_bytes([data[sos + 2], data[sos + 3]]));
+
+    data[..sos + 2 + length].to_vec()
+}
+
+#[test]
+fn options_dimensions()
+{
+    // 2500x1786
+    let name = "medium_no_samp_2500x1786.jpg";
+
+    let result = decode(name, DecoderOptions::default().set_max_width(2000));
+
+    assert!(matches!(
+        result,
+        Err(DecodeErrors::TooLargeWidth(2500, 2000))
+    ));
+
+    let result = decode(name, DecoderOptions::default().set_max_height(1000));
+
+    assert!(matches!(
+        result,
+        Err(DecodeErrors::TooLargeHeight(1786, 1000))
+    ));
+
+    let result = decode(name, DecoderOptions::default().set_max_pixels(1 << 20));
+
+    assert!(matches!(
+        result,
+        Err(DecodeErrors::TooManyPixels(4_465_000, 1_048_576))
+    ));
+
+    let options = DecoderOptions::default()
+        .set_max_width(2500)
+        .set_max_height(1786)
+        .set_max_pixels(2500 * 1786);
+
+    assert!(decode(name, options).is_ok());
+}
+
+#[test]
+fn options_dimensions_of_other_headers()
+{
+    // the final size of a hierarchical image is in its DHP segment
+    let result = decode(
+        "hierarchical/color.jpg",
+        DecoderOptions::default().set_max_width(32),
+    );
+
+    assert!(matches!(result, Err(DecodeErrors::TooLargeWidth(40, 32))));
+
+    // and the height of this one in a DNL segment
+    let result = decode(
+        "dnl/dnl_gray.jpg",
+        DecoderOptions::default().set_max_height(40),
+    );
+
+    assert!(matches!(result, Err(DecodeErrors::TooLargeHeight(45, 40))));
+}
+
+#[test]
+fn options_memory()
+{
+    // a 480x256 image, the progressive one keeps the coefficients of the whole image
+    // in memory
+    let options = DecoderOptions::default().set_max_memory(1 << 20);
+
+    assert!(decode("crop/2x2_rst.jpg", options).is_ok());
+
+    let result = decode("coefficients/2x2_progressive.jpg", options);
+
+    assert!(matches!(
+        result,
+        Err(DecodeErrors::TooLargeAllocation(bytes, 1_048_576)) if bytes > 1 << 20
+    ));
+
+    // the limit is checked before any image data is needed
+    let result = Decoder::new_with_options(options)
+        .decode_buffer(&headers("coefficients/2x2_progressive.jpg"));
+
+    assert!(matches!(result, Err(DecodeErrors::TooLargeAllocation(..))));
+}
+
+#[test]
+fn options_scans()
+{
+    // 10 scans
+    let name = "coefficients/2x2_progressive.jpg";
+
+    let result = decode(name, DecoderOptions::default().set_max_scans(4));
+
+    assert!(matches!(result, Err(DecodeErrors::TooManyScans(4))));
+
+    assert!(decode(name, DecoderOptions::default().set_max_scans(10)).is_ok());
+
+    assert!(decode(
+        "crop/2x2_rst.jpg",
+        DecoderOptions::default().set_max_scans(1)
+    )
+    .is_ok());
+}
+
+#[test]
+fn options_incremental()
+{
+    let options = DecoderOptions::default().set_max_memory(1 << 20);
+
+    let mut decoder = IncrementalDecoder::new(Decoder::new_with_options(options));
+
+    let result = decoder.feed(&headers("coefficients/2x2_progressive.jpg"));
+
+    assert!(matches!(result, Err(DecodeErrors::TooLargeAllocation(..))));
+
+    let mut decoder = IncrementalDecoder::new(Decoder::new_with_options(options));
+
+    let result = decoder.feed(&headers("crop/2x2_rst.jpg"));
+
+    assert!(matches!(result, Ok(FeedStatus::Headers)));
+}
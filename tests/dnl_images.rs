@@ -0,0 +1,111 @@
+//! Images whose height is defined after the first scan
+//!
+//! The images were created by setting the height in the frame header of a normal
+//! `cjpeg` file to zero and, unless the name starts with `no_dnl`, adding a DNL
+//! segment after the first scan, decoding them should give us the pixels of the
+//! original file.
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn decode(name: &str, colorspace: ColorSpace) -> (Vec<u8>, u16, u16)
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/dnl/" + name;
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    let pixels = decoder.decode_file(&path).expect("Test failed decoding");
+
+    (pixels, decoder.width(), decoder.height())
+}
+
+#[test]
+fn dnl_grayscale()
+{
+    let (pixels, width, height) = decode("dnl_gray.jpg", ColorSpace::GRAYSCALE);
+
+    let (reference, ..) = decode("gray.jpg", ColorSpace::GRAYSCALE);
+
+    assert_eq!((width, height), (61, 45));
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn dnl_horizontal_sampling_restart_markers()
+{
+    let (pixels, width, height) = decode("dnl_2x1_rst.jpg", ColorSpace::RGB);
+
+    let (reference, ..) = decode("2x1_rst.jpg", ColorSpace::RGB);
+
+    assert_eq!((width, height), (50, 37));
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn dnl_progressive()
+{
+    let (pixels, width, height) = decode("dnl_progressive.jpg", ColorSpace::RGB);
+
+    let (reference, ..) = decode("progressive.jpg", ColorSpace::RGB);
+
+    assert_eq!((width, height), (50, 37));
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn dnl_u16()
+{
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/dnl/dnl_gray.jpg";
+
+    let pixels = Decoder::new()
+        .decode_buffer_u16(&std::fs::read(path).unwrap())
+        .unwrap();
+
+    let (reference, ..) = decode("gray.jpg", ColorSpace::GRAYSCALE);
+
+    assert!(pixels.into_iter().eq(reference.into_iter().map(u16::from)));
+}
+
+#[test]
+fn no_dnl_grayscale_restart_markers()
+{
+    // height is recovered from the number of MCU rows in the scan
+    let (pixels, width, height) = decode("no_dnl_gray_rst.jpg", ColorSpace::GRAYSCALE);
+
+    let (reference, ..) = decode("gray_rst.jpg", ColorSpace::GRAYSCALE);
+
+    assert_eq!((width, height), (64, 48));
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn no_dnl_horizontal_sampling()
+{
+    let (pixels, width, height) = decode("no_dnl_2x1.jpg", ColorSpace::RGB);
+
+    let (reference, ..) = decode("2x1.jpg", ColorSpace::RGB);
+
+    assert_eq!((width, height), (48, 32));
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn grayscale_output_of_padded_mcu_rows()
+{
+    // 50 pixels wide rows are padded to 64 by the 16 pixel wide MCUs
+    for name in ["2x1_rst.jpg", "dnl_2x1_rst.jpg"]
+    {
+        let (pixels, width, height) = decode(name, ColorSpace::GRAYSCALE);
+
+        let (ycbcr, ..) = decode(name, ColorSpace::YCbCr);
+
+        assert_eq!(pixels.len(), usize::from(width) * usize::from(height));
+
+        assert!(pixels.iter().eq(ycbcr.iter().step_by(3)), "{name}");
+    }
+}
@@ -0,0 +1,217 @@
+//! Parsing Exif metadata
+//!
+//! `exif/big_endian.jpg` is `lenient/2x2.jpg` with a big endian Exif segment
+//! holding orientation 6, a camera make and model, capture times and a GPS
+//! position, `medium_no_samp_2500x1786.jpg` has a little endian one written by GIMP.
+use zune_jpeg::errors::{DecodeWarning, WarningKind};
+use zune_jpeg::{Decoder, ExifData, ExifIfd};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+fn exif(data: &[u8]) -> Option<ExifData>
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(data).unwrap();
+
+    decoder.info().unwrap().exif
+}
+
+/// Reasons given by the warnings about the Exif segment of `data`, which is at
+/// offset 2
+fn warnings(data: &[u8]) -> Vec<String>
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(data).unwrap();
+
+    let warnings: &[DecodeWarning] = decoder.warnings();
+
+    assert!(warnings
+        .iter()
+        .all(|x| (x.kind, x.marker, x.offset) == (WarningKind::InvalidExif, Some(0xE1), 2)));
+
+    warnings.iter().map(|x| x.reason.clone()).collect()
+}
+
+#[test]
+fn exif_big_endian()
+{
+    let exif = exif(&read("exif/big_endian.jpg")).unwrap();
+
+    assert!(exif.big_endian);
+
+    assert_eq!(exif.orientation, Some(6));
+
+    assert_eq!(exif.make.as_deref(), Some("Zune"));
+
+    assert_eq!(exif.model.as_deref(), Some("Test Camera 2"));
+
+    assert_eq!(exif.date_time.as_deref(), Some("2022:05:26 10:11:12"));
+
+    assert_eq!(
+        exif.date_time_original.as_deref(),
+        Some("2022:05:25 09:08:07")
+    );
+
+    assert!(exif.raw.starts_with(b"MM\x00\x2A"));
+}
+
+#[test]
+fn exif_little_endian()
+{
+    let exif = exif(&read("medium_no_samp_2500x1786.jpg")).unwrap();
+
+    assert!(!exif.big_endian);
+
+    assert_eq!(exif.orientation, Some(1));
+
+    assert_eq!(exif.make, None);
+
+    assert_eq!(exif.date_time.as_deref(), Some("2021:10:28 20:24:49"));
+
+    assert_eq!(exif.gps, None);
+
+    // the Exif IFD is read too, though nothing in it is interpreted
+    assert_eq!(exif.tag(ExifIfd::Exif, 0xA001).unwrap().data, [1, 0]);
+}
+
+#[test]
+fn exif_gps()
+{
+    let gps = exif(&read("exif/big_endian.jpg")).unwrap().gps.unwrap();
+
+    assert_eq!(gps.latitude_ref, Some('N'));
+
+    assert_eq!(gps.latitude, Some([(51, 1), (30, 1), (3600, 100)]));
+
+    assert_eq!(gps.longitude_ref, Some('W'));
+
+    assert!((gps.latitude_degrees().unwrap() - 51.51).abs() < 1e-9);
+
+    assert!((gps.longitude_degrees().unwrap() + 0.127_777_777).abs() < 1e-6);
+
+    assert!((gps.altitude_meters().unwrap() - 25.05).abs() < 1e-9);
+}
+
+#[test]
+fn exif_raw_tags()
+{
+    let exif = exif(&read("exif/big_endian.jpg")).unwrap();
+
+    let orientation = exif.tag(ExifIfd::Primary, 0x0112).unwrap();
+
+    assert_eq!((orientation.format, orientation.count), (3, 1));
+
+    // values are left in the byte order of the file
+    assert_eq!(orientation.data, [0, 6]);
+
+    assert_eq!(exif.tag(ExifIfd::Exif, 0x9000).unwrap().data, b"0232");
+
+    assert_eq!(exif.tag(ExifIfd::Gps, 0x0000).unwrap().data, [2, 3, 0, 0]);
+
+    assert!(exif.tag(ExifIfd::Gps, 0x0112).is_none());
+}
+
+#[test]
+fn exif_missing()
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&read("exif/big_endian.jpg")).unwrap();
+
+    assert!(decoder.info().unwrap().exif.is_some());
+
+    // nothing is left over from the previous image
+    decoder.read_headers(&read("lenient/2x2.jpg")).unwrap();
+
+    assert_eq!(decoder.info().unwrap().exif, None);
+}
+
+#[test]
+fn exif_invalid()
+{
+    let data = read("exif/big_endian.jpg");
+
+    let tiff = data.windows(6).position(|x| x == b"Exif\0\0").unwrap() + 6;
+
+    let pixels = Decoder::new().decode_buffer(&data).unwrap();
+
+    // broken metadata does not stop the image from being decoded
+    let mut broken = data.clone();
+
+    broken[tiff] = b'X';
+
+    assert_eq!(exif(&broken), None);
+
+    assert_eq!(
+        warnings(&broken),
+        ["Ignored Exif data, Exif data does not start with a TIFF header"]
+    );
+
+    assert!(Decoder::new().decode_buffer(&broken).unwrap() == pixels);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_warnings_as_errors(&[WarningKind::InvalidExif]);
+
+    assert!(decoder.decode_buffer(&broken).is_err());
+
+    // the first IFD is past the end of the segment
+    let mut broken = data.clone();
+
+    broken[tiff + 4..tiff + 8].copy_from_slice(&[0x7F, 0, 0, 0]);
+
+    assert_eq!(exif(&broken), None);
+
+    assert_eq!(warnings(&broken).len(), 1);
+
+    // the GPS IFD is past the end of the segment, the other tags are kept
+    let mut broken = data.clone();
+
+    let gps = broken[tiff..]
+        .windows(4)
+        .position(|x| x == [0x88, 0x25, 0x00, 0x04])
+        .unwrap();
+
+    broken[tiff + gps + 8..tiff + gps + 12].copy_from_slice(&[0x7F, 0, 0, 0]);
+
+    let exif_data = exif(&broken).unwrap();
+
+    assert_eq!(exif_data.gps.unwrap().latitude, None);
+
+    assert_eq!(exif_data.orientation, Some(6));
+
+    assert_eq!(
+        warnings(&broken),
+        [format!(
+            "Skipped the rest of the GPS IFD, Exif data ends before offset {}",
+            0x7F00_0000
+        )]
+    );
+
+    // a tag whose values are past the end is skipped, the others are kept
+    let mut broken = data;
+
+    let model = tiff + 8 + 2 + 12;
+
+    assert_eq!(broken[model..model + 2], [0x01, 0x10]);
+
+    broken[model + 8..model + 12].copy_from_slice(&[0, 1, 0, 0]);
+
+    let exif = exif(&broken).unwrap();
+
+    assert_eq!(exif.model, None);
+
+    assert_eq!(exif.make.as_deref(), Some("Zune"));
+
+    assert_eq!(exif.orientation, Some(6));
+
+    assert_eq!(
+        warnings(&broken),
+        ["Skipped Exif tag 0x0110, its values are past the end of the data"]
+    );
+}
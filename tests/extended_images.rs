@@ -0,0 +1,145 @@
+//! Extended sequential (SOF1) images
+//!
+//! The 12 bit images were created with a 12 bit build of libjpeg's `cjpeg`, and the
+//! `.ppm` files next to them are what a 12 bit `djpeg` decodes them to, we should
+//! match those exactly.
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn path(name: &str) -> String
+{
+    env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/extended/" + name
+}
+
+/// Read samples from a binary 16 bit PPM/PGM file
+fn read_reference(name: &str) -> Vec<u16>
+{
+    let data = std::fs::read(path(name)).unwrap();
+
+    // skip the three header lines (magic, dimensions and max value)
+    let mut newlines = 0;
+
+    let start = data
+        .iter()
+        .position(|x| {
+            newlines += usize::from(*x == b'\n');
+            newlines == 3
+        })
+        .unwrap();
+
+    data[start + 1..]
+        .chunks_exact(2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .collect()
+}
+
+fn decode(name: &str, colorspace: ColorSpace) -> (Vec<u16>, u16, u16)
+{
+    let data = std::fs::read(path(name)).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    let pixels = decoder
+        .decode_buffer_u16(&data)
+        .expect("Test failed decoding");
+
+    (pixels, decoder.width(), decoder.height())
+}
+
+#[test]
+fn sof1_12bit_grayscale()
+{
+    let (pixels, width, height) = decode("sof1_gray.jpg", ColorSpace::GRAYSCALE);
+
+    assert_eq!((width, height), (96, 64));
+
+    assert!(pixels == read_reference("sof1_gray.ppm"));
+}
+
+#[test]
+fn sof1_12bit_long_dc_values()
+{
+    // high frequency noise leaves few bits in the bit buffer after a block's last
+    // coefficient, followed by DC differences of up to 12 bits
+    let (pixels, width, height) = decode("sof1_long_dc.jpg", ColorSpace::GRAYSCALE);
+
+    assert_eq!((width, height), (64, 32));
+
+    assert!(pixels == read_reference("sof1_long_dc.ppm"));
+}
+
+#[test]
+fn sof1_12bit_horizontal_sampling()
+{
+    let (pixels, ..) = decode("sof1_2x1.jpg", ColorSpace::RGB);
+
+    assert!(pixels == read_reference("sof1_2x1.ppm"));
+}
+
+#[test]
+fn sof1_12bit_vertical_sampling()
+{
+    let (pixels, ..) = decode("sof1_1x2.jpg", ColorSpace::RGB);
+
+    assert!(pixels == read_reference("sof1_1x2.ppm"));
+}
+
+#[test]
+fn sof1_12bit_non_interleaved_scans()
+{
+    // 2x2 sampling, each component in its own scan
+    let (pixels, ..) = decode("sof1_2x2_scans.jpg", ColorSpace::RGB);
+
+    assert!(pixels == read_reference("sof1_2x2_scans.ppm"));
+}
+
+#[test]
+fn sof1_12bit_16bit_quantization_tables_and_restart_markers()
+{
+    let (pixels, ..) = decode("sof1_rst.jpg", ColorSpace::RGB);
+
+    assert!(pixels == read_reference("sof1_rst.ppm"));
+}
+
+#[test]
+fn sof1_12bit_rgba()
+{
+    let (rgba, ..) = decode("sof1_2x1.jpg", ColorSpace::RGBA);
+
+    let (rgb, ..) = decode("sof1_2x1.jpg", ColorSpace::RGB);
+
+    for (rgba, rgb) in rgba.chunks_exact(4).zip(rgb.chunks_exact(3))
+    {
+        assert_eq!(&rgba[..3], rgb);
+
+        assert_eq!(rgba[3], 4095);
+    }
+}
+
+#[test]
+fn sof1_12bit_needs_u16_output()
+{
+    let mut decoder = Decoder::new();
+
+    assert!(decoder.decode_file(path("sof1_gray.jpg")).is_err());
+}
+
+#[test]
+fn sof1_8bit_16bit_quantization_tables()
+{
+    let (wide, ..) = decode("sof1_8bit.jpg", ColorSpace::RGB);
+
+    let mut decoder = Decoder::new();
+
+    let narrow = decoder
+        .decode_file(path("sof1_8bit.jpg"))
+        .expect("Test failed decoding");
+
+    assert!(wide
+        .iter()
+        .zip(narrow.iter())
+        .all(|(a, b)| *a == u16::from(*b)));
+
+    assert_eq!(wide.len(), 96 * 64 * 3);
+}
@@ -0,0 +1,139 @@
+//! Hierarchical images
+//!
+//! Each image comes with a `.raw` file holding the samples we expect to decode as big
+//! endian `u16`'s. Images whose last frame is lossless should give back the samples they
+//! were encoded from, for the others the encoder computed what a decoder reconstructs.
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn path(name: &str) -> String
+{
+    env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/hierarchical/" + name
+}
+
+fn read_reference(name: &str) -> Vec<u16>
+{
+    std::fs::read(path(name))
+        .unwrap()
+        .chunks_exact(2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .collect()
+}
+
+fn decode(name: &str) -> (Vec<u16>, u16, u16)
+{
+    let data = std::fs::read(path(name)).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    let pixels = decoder
+        .decode_buffer_u16(&data)
+        .expect("Test failed decoding");
+
+    (pixels, decoder.width(), decoder.height())
+}
+
+/// Decode an 8 bit image through `decode_buffer`, widening samples for comparison
+fn decode_8bit(name: &str, colorspace: ColorSpace) -> Vec<u16>
+{
+    let data = std::fs::read(path(name)).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    decoder
+        .decode_buffer(&data)
+        .expect("Test failed decoding")
+        .into_iter()
+        .map(u16::from)
+        .collect()
+}
+
+#[test]
+fn hierarchical_lossless_16bit()
+{
+    // three lossless frames, each twice as big as the one before it
+    let (pixels, width, height) = decode("lossless_16bit.jpg");
+
+    assert_eq!((width, height), (61, 47));
+
+    assert!(pixels == read_reference("lossless_16bit.raw"));
+}
+
+#[test]
+fn hierarchical_sequential_dct()
+{
+    // baseline frame, expanded and corrected by a differential sequential frame
+    // with restart markers
+    let pixels = decode_8bit("dct_gray.jpg", ColorSpace::GRAYSCALE);
+
+    assert!(pixels == read_reference("dct_gray.raw"));
+}
+
+#[test]
+fn hierarchical_dct_with_lossless_final_frame()
+{
+    let pixels = decode_8bit("dct_gray_lossless.jpg", ColorSpace::GRAYSCALE);
+
+    assert!(pixels == read_reference("dct_gray_lossless.raw"));
+}
+
+#[test]
+fn hierarchical_progressive_horizontal_expansion()
+{
+    // progressive frames, the second one redefines the quantization table
+    let pixels = decode_8bit("progressive_gray.jpg", ColorSpace::GRAYSCALE);
+
+    assert!(pixels == read_reference("progressive_gray.raw"));
+}
+
+#[test]
+fn hierarchical_color_conversion()
+{
+    let pixels = decode_8bit("color.jpg", ColorSpace::RGB);
+
+    assert!(pixels == read_reference("color.raw"));
+
+    let (wide, ..) = decode("color.jpg");
+
+    assert!(wide == pixels);
+}
+
+#[test]
+fn hierarchical_sampling_factors_lossless_final_frame()
+{
+    // luma is sampled 2x1, chroma components are replicated
+    let pixels = decode_8bit("color_2x1_lossless.jpg", ColorSpace::RGB);
+
+    assert!(pixels == read_reference("color_2x1_lossless.raw"));
+}
+
+#[test]
+fn hierarchical_missing_frames()
+{
+    // the DHP segment describes a bigger image than the only frame
+    let mut decoder = Decoder::new();
+
+    assert!(decoder.decode_file(path("incomplete.jpg")).is_err());
+}
+
+#[test]
+fn hierarchical_decoder_reuse()
+{
+    // a decoder which decoded a hierarchical image should decode others as usual
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(ColorSpace::GRAYSCALE);
+
+    decoder.decode_file(path("dct_gray.jpg")).unwrap();
+
+    decoder.set_output_colorspace(ColorSpace::RGB);
+
+    let data =
+        std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/scaled/2x2.jpg")
+            .unwrap();
+
+    let pixels = decoder.decode_buffer(&data).unwrap();
+
+    assert!(pixels == Decoder::new().decode_buffer(&data).unwrap());
+}
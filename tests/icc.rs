@@ -0,0 +1,219 @@
+//! Reassembling ICC profiles split across APP2 segments
+//!
+//! `icc/three_chunks.jpg` was written by `cjpeg -icc` with a 150000 byte profile,
+//! which it split into three chunks.
+use zune_jpeg::errors::{DecodeErrors, WarningKind};
+use zune_jpeg::Decoder;
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// The APP2 segments of `data` holding ICC profile chunks, markers included
+fn chunks(data: &[u8]) -> Vec<std::ops::Range<usize>>
+{
+    data.windows(16)
+        .enumerate()
+        .filter(|(_, x)| x[..2] == [0xFF, 0xE2] && &x[4..16] == b"ICC_PROFILE\0")
+        .map(|(i, x)| i..i + 2 + usize::from(u16::from_be_bytes([x[2], x[3]])))
+        .collect()
+}
+
+/// `data` with its ICC profile chunks replaced by `segments`
+fn with_chunks(data: &[u8], segments: &[Vec<u8>]) -> Vec<u8>
+{
+    let ranges = chunks(data);
+
+    let mut out = data[..ranges[0].start].to_vec();
+
+    segments.iter().for_each(|x| out.extend_from_slice(x));
+
+    out.extend_from_slice(&data[ranges.last().unwrap().end..]);
+
+    out
+}
+
+fn profile(data: &[u8]) -> Option<Vec<u8>>
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(data).unwrap();
+
+    decoder.icc_profile().map(<[u8]>::to_vec)
+}
+
+#[test]
+fn icc_three_chunks()
+{
+    let data = read("icc/three_chunks.jpg");
+
+    let ranges = chunks(&data);
+
+    assert_eq!(ranges.len(), 3);
+
+    let profile = profile(&data).unwrap();
+
+    assert_eq!(profile.len(), 150_000);
+
+    assert_eq!(&profile[36..40], b"acsp");
+
+    let expected: Vec<u8> = ranges
+        .iter()
+        .flat_map(|x| data[x.start + 18..x.end].iter().copied())
+        .collect();
+
+    assert!(profile == expected);
+}
+
+#[test]
+fn icc_chunks_out_of_order()
+{
+    let data = read("icc/three_chunks.jpg");
+
+    let segments: Vec<Vec<u8>> = chunks(&data)
+        .into_iter()
+        .map(|x| data[x].to_vec())
+        .collect();
+
+    let reordered = with_chunks(
+        &data,
+        &[
+            segments[2].clone(),
+            segments[0].clone(),
+            segments[1].clone(),
+        ],
+    );
+
+    assert!(profile(&reordered) == profile(&data));
+}
+
+#[test]
+fn icc_invalid_chunks()
+{
+    let data = read("icc/three_chunks.jpg");
+
+    let segments: Vec<Vec<u8>> = chunks(&data)
+        .into_iter()
+        .map(|x| data[x].to_vec())
+        .collect();
+
+    let pixels = Decoder::new().decode_buffer(&data).unwrap();
+
+    // a missing chunk
+    let missing = with_chunks(&data, &[segments[0].clone(), segments[2].clone()]);
+
+    assert_eq!(profile(&missing), None);
+
+    // a repeated one, in place of another
+    let repeated = with_chunks(
+        &data,
+        &[
+            segments[0].clone(),
+            segments[0].clone(),
+            segments[2].clone(),
+        ],
+    );
+
+    assert_eq!(profile(&repeated), None);
+
+    // chunks disagreeing on how many there are
+    let mut disagreeing = segments.clone();
+
+    disagreeing[1][17] = 4;
+
+    assert_eq!(profile(&with_chunks(&data, &disagreeing)), None);
+
+    // zero chunks
+    let mut zero = segments;
+
+    zero.iter_mut().for_each(|x| x[17] = 0);
+
+    let zero = with_chunks(&data, &zero);
+
+    assert_eq!(profile(&zero), None);
+
+    // the image is still decoded
+    assert!(Decoder::new().decode_buffer(&zero).unwrap() == pixels);
+}
+
+#[test]
+fn icc_single_chunk()
+{
+    let mut segment = vec![0xFF, 0xE2, 0x00, 0x14];
+
+    segment.extend_from_slice(b"ICC_PROFILE\0");
+
+    segment.extend_from_slice(&[1, 1, 0xAB, 0xCD, 0xEF, 0x01]);
+
+    let mut data = read("lenient/2x2.jpg");
+
+    data.splice(2..2, segment);
+
+    assert_eq!(
+        profile(&data).as_deref(),
+        Some(&[0xAB, 0xCD, 0xEF, 0x01][..])
+    );
+
+    // other APP2 segments are not profiles
+    data[6] = b'X';
+
+    assert_eq!(profile(&data), None);
+}
+
+#[test]
+fn icc_missing()
+{
+    let mut decoder = Decoder::new();
+
+    assert_eq!(decoder.icc_profile(), None);
+
+    decoder.read_headers(&read("icc/three_chunks.jpg")).unwrap();
+
+    assert!(decoder.icc_profile().is_some());
+
+    // nothing is left over from the previous image
+    decoder.read_headers(&read("lenient/2x2.jpg")).unwrap();
+
+    assert_eq!(decoder.icc_profile(), None);
+}
+
+#[test]
+fn icc_invalid_warning()
+{
+    let data = read("icc/three_chunks.jpg");
+
+    let ranges = chunks(&data);
+
+    let segments: Vec<Vec<u8>> = ranges.iter().map(|x| data[x.clone()].to_vec()).collect();
+
+    let missing = with_chunks(&data, &[segments[0].clone(), segments[2].clone()]);
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&missing).unwrap();
+
+    let warnings = decoder.warnings();
+
+    assert_eq!(warnings.len(), 1);
+
+    assert_eq!(warnings[0].kind, WarningKind::InvalidIccProfile);
+
+    // reported at the first chunk
+    assert_eq!(warnings[0].offset, ranges[0].start);
+
+    assert_eq!(warnings[0].marker, Some(0xE2));
+
+    // whole profiles are not warned about
+    decoder.read_headers(&data).unwrap();
+
+    assert!(decoder.warnings().is_empty());
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_warnings_as_errors(&[WarningKind::InvalidIccProfile]);
+
+    let err = decoder.decode_buffer(&missing).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::Warning(x) if x.kind == WarningKind::InvalidIccProfile));
+}
@@ -0,0 +1,226 @@
+//! Decoding images given to the decoder a chunk at a time
+use zune_jpeg::{Decoder, FeedStatus, IncrementalDecoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// What happened while decoding an image incrementally
+#[derive(Default)]
+struct Decoded
+{
+    pixels: Vec<u8>,
+    /// Number of times the decoder returned rows
+    batches: usize,
+    /// Number of progressive scans decoded
+    scans: usize,
+    /// Number of bytes fed to the decoder when it returned the first rows
+    first_rows: usize,
+}
+
+/// Feed `data` to the decoder `chunk` bytes at a time
+fn decode(data: &[u8], chunk: usize) -> Decoded
+{
+    let mut decoder = IncrementalDecoder::new(Decoder::new());
+
+    let mut decoded = Decoded::default();
+
+    let mut headers = false;
+
+    let mut fed = 0;
+
+    let mut handle = |status: FeedStatus, fed: usize| {
+        match status
+        {
+            FeedStatus::NeedMoreData => (),
+            FeedStatus::Headers => headers = true,
+            FeedStatus::Rows(rows) =>
+            {
+                if decoded.batches == 0
+                {
+                    decoded.first_rows = fed;
+                }
+                decoded.pixels.extend_from_slice(rows);
+
+                decoded.batches += 1;
+            }
+            FeedStatus::Scan => decoded.scans += 1,
+            FeedStatus::Finished => return false,
+        }
+        status != FeedStatus::NeedMoreData
+    };
+
+    for chunk in data.chunks(chunk)
+    {
+        fed += chunk.len();
+
+        let mut more = handle(decoder.feed(chunk).unwrap(), fed);
+
+        while more
+        {
+            more = handle(decoder.feed(&[]).unwrap(), fed);
+        }
+    }
+    while handle(decoder.finish().unwrap(), fed)
+    {}
+
+    assert!(headers);
+
+    decoded
+}
+
+#[test]
+fn incremental_baseline()
+{
+    for name in [
+        "medium_horiz_samp_2500x1786.jpg",
+        "medium_no_samp_2500x1786.jpg",
+        "crop/2x2_rst.jpg",
+        "dnl/2x1_rst.jpg",
+        "scanlines/2x1_odd_rows.jpg",
+    ]
+    {
+        let data = read(name);
+
+        let reference = Decoder::new().decode_buffer(&data).unwrap();
+
+        for chunk in [1000, 4096, data.len()]
+        {
+            let decoded = decode(&data, chunk);
+
+            assert!(decoded.pixels == reference, "{name} in chunks of {chunk}");
+
+            assert!(decoded.batches > 1);
+        }
+    }
+}
+
+#[test]
+fn incremental_byte_by_byte()
+{
+    // rows are decoded as soon as their data has arrived
+    let data = read("crop/2x2_rst.jpg");
+
+    let decoded = decode(&data, 1);
+
+    assert!(decoded.pixels == Decoder::new().decode_buffer(&data).unwrap());
+
+    // 32 rows at a time
+    assert_eq!(decoded.batches, 8);
+
+    assert!(decoded.first_rows < data.len() / 4);
+}
+
+#[test]
+fn incremental_progressive()
+{
+    for name in ["dnl/progressive.jpg", "coefficients/2x2_progressive.jpg"]
+    {
+        let data = read(name);
+
+        let reference = Decoder::new().decode_buffer(&data).unwrap();
+
+        for chunk in [1, 100, data.len()]
+        {
+            let decoded = decode(&data, chunk);
+
+            assert!(decoded.pixels == reference, "{name} in chunks of {chunk}");
+
+            assert_eq!((decoded.scans, decoded.batches), (10, 1));
+        }
+    }
+}
+
+#[test]
+fn incremental_previews()
+{
+    let data = read("coefficients/2x2_progressive.jpg");
+
+    let mut decoder = IncrementalDecoder::new(Decoder::new());
+
+    // everything up to the end of the second scan
+    let second_scan = data
+        .windows(2)
+        .enumerate()
+        .filter(|(_, x)| *x == [0xFF, 0xDA])
+        .nth(2)
+        .unwrap()
+        .0;
+
+    assert_eq!(
+        decoder.feed(&data[..second_scan]).unwrap(),
+        FeedStatus::Headers
+    );
+
+    assert!(decoder.preview().is_none());
+
+    assert_eq!(decoder.feed(&[]).unwrap(), FeedStatus::Scan);
+
+    assert_eq!(decoder.feed(&[]).unwrap(), FeedStatus::Scan);
+
+    assert_eq!(decoder.feed(&[]).unwrap(), FeedStatus::NeedMoreData);
+
+    let mut reader_decoder = Decoder::new();
+
+    let mut scans = reader_decoder.progressive_scans(&data).unwrap();
+
+    scans.next_scan().unwrap();
+
+    scans.next_scan().unwrap();
+
+    assert!(decoder.preview().unwrap() == scans.render());
+}
+
+#[test]
+fn incremental_whole_images()
+{
+    for name in [
+        "arithmetic/sof9_2x1.jpg",
+        "lossless/rgb8_p6_rst.jpg",
+        "hierarchical/color.jpg",
+        "dnl/dnl_2x1_rst.jpg",
+    ]
+    {
+        let data = read(name);
+
+        let decoded = decode(&data, 512);
+
+        assert!(
+            decoded.pixels == Decoder::new().decode_buffer(&data).unwrap(),
+            "{name}"
+        );
+
+        assert_eq!(decoded.batches, 1);
+    }
+}
+
+#[test]
+fn incremental_truncated_image()
+{
+    let data = read("crop/2x2_rst.jpg");
+
+    let truncated = &data[..data.len() / 2];
+
+    let decoded = decode(truncated, 4096);
+
+    assert!(decoded.pixels == Decoder::new().decode_buffer(truncated).unwrap());
+
+    // headers that never completed are an error
+    let mut decoder = IncrementalDecoder::new(Decoder::new());
+
+    assert_eq!(
+        decoder.feed(&data[..100]).unwrap(),
+        FeedStatus::NeedMoreData
+    );
+
+    assert!(decoder.finish().is_err());
+
+    // as is data which is not an image
+    let mut decoder = IncrementalDecoder::new(Decoder::new());
+
+    assert!(decoder.feed(b"GIF89a").is_err());
+
+    // and every call after an error
+    assert!(decoder.feed(&data).is_err());
+}
@@ -1,4 +1,16 @@
-use zune_jpeg::Decoder;
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{Decoder, FeedStatus, IncrementalDecoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// Position of the first `marker` segment in `data`
+fn find_marker(data: &[u8], marker: u8) -> usize
+{
+    data.windows(2).position(|x| x == [0xFF, marker]).unwrap()
+}
 
 #[test]
 fn eof()
@@ -53,7 +65,6 @@ fn huffman_length_subtraction_overflow()
 }
 
 #[test]
-#[ignore = "TODO: fix this"]
 fn index_oob()
 {
     let mut decoder = Decoder::new();
@@ -63,7 +74,7 @@ fn index_oob()
         .unwrap_err();
 
     assert!(
-        matches!(err, zune_jpeg::errors::DecodeErrors::HuffmanDecode(x) if x == "Invalid Huffman length in image")
+        matches!(err, zune_jpeg::errors::DecodeErrors::SosError(x) if x == "Scan refers to component 0, which is not in the frame")
     );
 }
 
@@ -80,3 +91,121 @@ fn mul_with_overflow()
         matches!(err, zune_jpeg::errors::DecodeErrors::SofError(x) if x == "Length of start of frame differs from expected 584,value is 65281")
     );
 }
+
+#[test]
+fn unknown_marker_in_scan()
+{
+    let mut data = read("crop/2x2_rst.jpg");
+
+    let middle = find_marker(&data, 0xDA) + (data.len() - find_marker(&data, 0xDA)) / 2;
+
+    // 0xFF05 is a reserved marker
+    data.splice(middle..middle, [0xFF, 0x05]);
+
+    let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::MCUError(_)));
+}
+
+#[test]
+fn unsupported_sampling_factors()
+{
+    let data = read("crop/2x2_rst.jpg");
+
+    let sof = find_marker(&data, 0xC0);
+
+    // the luma component, then the first chroma component
+    for (position, factors) in [(11, 0x33), (11, 0x00), (14, 0x22)]
+    {
+        let mut data = data.clone();
+
+        data[sof + position] = factors;
+
+        let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+        assert!(matches!(err, DecodeErrors::SofError(_)), "{factors:x}");
+    }
+}
+
+#[test]
+fn bad_successive_approximation()
+{
+    let mut data = read("coefficients/2x2_progressive.jpg");
+
+    let sos = find_marker(&data, 0xDA);
+
+    let length = usize::from(u16::from_be_bytes([data[sos + 2], data[sos + 3]]));
+
+    // Al of 15 would shift coefficients out of 16 bits
+    data[sos + 1 + length] = 0x0F;
+
+    let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::SosError(_)));
+}
+
+#[test]
+fn hierarchical_frame_without_scans()
+{
+    let data = read("hierarchical/color.jpg");
+
+    // cut the image right after the header of its last frame
+    let last_sos = data.windows(2).rposition(|x| x == [0xFF, 0xDA]).unwrap();
+
+    let err = Decoder::new().decode_buffer(&data[..last_sos]).unwrap_err();
+
+    assert!(matches!(err, DecodeErrors::Format(_)));
+}
+
+#[test]
+fn fuzzed_images()
+{
+    // inputs which used to panic, they may decode to something or fail but must not
+    // bring down the caller
+    let directory = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/fuzz";
+
+    for entry in std::fs::read_dir(directory).unwrap()
+    {
+        let data = std::fs::read(entry.unwrap().path()).unwrap();
+
+        let _ = Decoder::new().decode_buffer(&data);
+
+        let _ = Decoder::new().decode_buffer_u16(&data);
+
+        let mut decoder = Decoder::new();
+
+        if let Ok(mut scanlines) = decoder.scanlines(&data)
+        {
+            while let Ok(Some(_)) = scanlines.next_rows()
+            {}
+        }
+
+        let mut decoder = Decoder::new();
+
+        if let Ok(mut scans) = decoder.progressive_scans(&data)
+        {
+            scans.set_block_smoothing(true);
+
+            while let Ok(true) = scans.next_scan()
+            {
+                scans.render();
+            }
+        }
+
+        let mut decoder = IncrementalDecoder::new(Decoder::new());
+
+        // carrying on after an error must be safe too
+        for chunk in data.chunks(97)
+        {
+            let mut status = decoder.feed(chunk);
+
+            while let Ok(FeedStatus::Headers | FeedStatus::Rows(_) | FeedStatus::Scan) = status
+            {
+                status = decoder.feed(&[]);
+            }
+        }
+        while let Ok(FeedStatus::Headers | FeedStatus::Rows(_) | FeedStatus::Scan) =
+            decoder.finish()
+        {}
+    }
+}
@@ -0,0 +1,222 @@
+//! Concealing errors in truncated and corrupt images
+//!
+//! `lenient/2x2.jpg` is a 477x263 2x2 sampled baseline image, `2x2_rst.jpg` the same
+//! image with a restart marker every five MCUs, and `2x2_prog.jpg` and
+//! `2x2_prog_rst.jpg` progressive versions of it, the latter with a restart marker
+//! every three MCUs.
+use zune_jpeg::errors::{DecodeErrors, DecodeWarning, WarningKind};
+use zune_jpeg::Decoder;
+
+const WIDTH: usize = 477;
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+fn decode(data: &[u8], strict: bool) -> (Result<Vec<u8>, DecodeErrors>, Vec<DecodeWarning>)
+{
+    let mut decoder = Decoder::new();
+
+    decoder.set_strict_mode(strict);
+
+    let pixels = decoder.decode_buffer(data);
+
+    (pixels, decoder.warnings().to_vec())
+}
+
+/// Kinds of `warnings`
+fn kinds(warnings: &[DecodeWarning]) -> Vec<WarningKind>
+{
+    warnings.iter().map(|x| x.kind).collect()
+}
+
+/// Positions of the restart markers in `data`
+fn restart_markers(data: &[u8]) -> Vec<usize>
+{
+    data.windows(2)
+        .enumerate()
+        .filter(|(_, x)| x[0] == 0xFF && (0xD0..=0xD7).contains(&x[1]))
+        .map(|(pos, _)| pos)
+        .collect()
+}
+
+/// Rows `start..end` of a decoded image
+fn rows(pixels: &[u8], start: usize, end: usize) -> &[u8]
+{
+    &pixels[start * WIDTH * 3..end * WIDTH * 3]
+}
+
+#[test]
+fn lenient_restart_markers()
+{
+    // restart intervals do not line up with MCU rows, or with the blocks of
+    // non interleaved scans
+    for (name, reference) in [
+        ("lenient/2x2_rst.jpg", "lenient/2x2.jpg"),
+        ("lenient/2x2_prog_rst.jpg", "lenient/2x2_prog.jpg"),
+    ]
+    {
+        let expected = decode(&read(reference), true).0.unwrap();
+
+        for strict in [true, false]
+        {
+            let (pixels, warnings) = decode(&read(name), strict);
+
+            assert!(pixels.unwrap() == expected, "{name}");
+
+            assert!(warnings.is_empty(), "{name}: {warnings:?}");
+        }
+    }
+}
+
+#[test]
+fn lenient_truncated()
+{
+    let data = read("lenient/2x2.jpg");
+
+    let expected = decode(&data, true).0.unwrap();
+
+    assert!(Decoder::new().get_strict_mode());
+
+    let (pixels, warnings) = decode(&data[..data.len() / 2], false);
+
+    let pixels = pixels.unwrap();
+
+    assert_eq!(kinds(&warnings), [WarningKind::MissingData]);
+
+    assert_eq!(warnings[0].offset, data.len() / 2);
+
+    // what could be decoded is kept, the rest is gray
+    assert!(rows(&pixels, 0, 64) == rows(&expected, 0, 64));
+
+    assert!(rows(&pixels, 200, 256).iter().all(|x| *x == 128));
+}
+
+#[test]
+fn lenient_corrupt_restart_interval()
+{
+    let mut data = read("lenient/2x2_rst.jpg");
+
+    let markers = restart_markers(&data);
+
+    // a run of ones is never a valid Huffman code
+    let start = (markers[19] + markers[20]) / 2;
+
+    for pos in (start..start + 8).step_by(2)
+    {
+        data[pos..pos + 2].copy_from_slice(&[0xFF, 0x00]);
+    }
+
+    assert!(decode(&data, true).0.is_err());
+
+    let expected = decode(&read("lenient/2x2_rst.jpg"), true).0.unwrap();
+
+    let (pixels, warnings) = decode(&data, false);
+
+    let pixels = pixels.unwrap();
+
+    assert_eq!(kinds(&warnings), [WarningKind::CorruptData]);
+
+    // entropy coded data is read a few bytes ahead
+    assert!((start..markers[21]).contains(&warnings[0].offset));
+
+    // the interval is on the fourth MCU row, decoding resumes at the marker ending it
+    assert!(rows(&pixels, 0, 48) == rows(&expected, 0, 48));
+
+    assert!(rows(&pixels, 80, 263) == rows(&expected, 80, 263));
+
+    assert!(pixels != expected);
+}
+
+#[test]
+fn lenient_missing_restart_marker()
+{
+    let mut data = read("lenient/2x2_rst.jpg");
+
+    let markers = restart_markers(&data);
+
+    assert_eq!(data[markers[20] + 1], 0xD4);
+
+    data.drain(markers[20]..markers[20] + 2);
+
+    let expected = decode(&read("lenient/2x2_rst.jpg"), true).0.unwrap();
+
+    let (pixels, warnings) = decode(&data, false);
+
+    let pixels = pixels.unwrap();
+
+    // RST5 tells us RST4 was lost, the interval following RST5 is left out
+    assert_eq!(kinds(&warnings), [WarningKind::UnexpectedRestartMarker]);
+
+    assert_eq!(warnings[0].marker, Some(0xD5));
+
+    assert_eq!(warnings[0].offset, markers[21] - 2);
+
+    assert!(rows(&pixels, 0, 48) == rows(&expected, 0, 48));
+
+    assert!(rows(&pixels, 80, 263) == rows(&expected, 80, 263));
+}
+
+#[test]
+fn lenient_truncated_progressive()
+{
+    let data = read("lenient/2x2_prog_rst.jpg");
+
+    let scans: Vec<usize> = data
+        .windows(2)
+        .enumerate()
+        .filter(|(_, x)| *x == [0xFF, 0xDA])
+        .map(|(pos, _)| pos)
+        .collect();
+
+    // ending between two scans is not an error
+    let (expected, warnings) = decode(&data[..scans[5]], true);
+
+    let expected = expected.unwrap();
+
+    assert!(warnings.is_empty());
+
+    // ending in the header of the next scan is one
+    let truncated = &data[..scans[5] + 5];
+
+    assert!(decode(truncated, true).0.is_err());
+
+    let (pixels, warnings) = decode(truncated, false);
+
+    assert!(pixels.unwrap() == expected);
+
+    assert_eq!(kinds(&warnings), [WarningKind::SkippedScans]);
+
+    assert_eq!(warnings[0].marker, Some(0xDA));
+
+    // ending in the middle of a scan leaves the blocks it did not reach as earlier
+    // scans left them
+    let (pixels, warnings) = decode(&data[..(scans[5] + scans[6]) / 2], false);
+
+    assert_eq!(kinds(&warnings), [WarningKind::MissingData]);
+
+    let pixels = pixels.unwrap();
+
+    assert!(rows(&pixels, 240, 263) == rows(&expected, 240, 263));
+
+    assert!(pixels != expected);
+}
+
+#[test]
+fn lenient_warnings_cleared()
+{
+    let data = read("lenient/2x2.jpg");
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_strict_mode(false);
+
+    decoder.decode_buffer(&data[..data.len() / 2]).unwrap();
+
+    assert_eq!(decoder.warnings().len(), 1);
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert!(decoder.warnings().is_empty());
+}
@@ -0,0 +1,118 @@
+//! Lossless (SOF3) images
+//!
+//! Each image comes with a `.raw` file holding the samples it was encoded from
+//! as big endian `u16`'s, one sample per component, since decoding is lossless we
+//! should get those exact samples back.
+use zune_jpeg::Decoder;
+
+fn path(name: &str) -> String
+{
+    env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/lossless/" + name
+}
+
+fn read_reference(name: &str) -> Vec<u16>
+{
+    std::fs::read(path(name))
+        .unwrap()
+        .chunks_exact(2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .collect()
+}
+
+fn decode(name: &str) -> (Vec<u16>, u16, u16)
+{
+    let data = std::fs::read(path(name)).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    let pixels = decoder
+        .decode_buffer_u16(&data)
+        .expect("Test failed decoding");
+
+    (pixels, decoder.width(), decoder.height())
+}
+
+#[test]
+fn lossless_16bit_all_predictors()
+{
+    let reference = read_reference("gray16.raw");
+
+    for predictor in 1..=7
+    {
+        let (pixels, width, height) = decode(&format!("gray16_p{predictor}.jpg"));
+
+        assert_eq!((width, height), (61, 47));
+
+        assert!(pixels == reference, "Predictor {predictor} failed");
+    }
+}
+
+#[test]
+fn lossless_2bit()
+{
+    let (pixels, ..) = decode("gray2.jpg");
+
+    assert!(pixels == read_reference("gray2.raw"));
+}
+
+#[test]
+fn lossless_point_transform_and_restart_markers()
+{
+    let (pixels, ..) = decode("gray12_pt2_rst.jpg");
+
+    assert!(pixels == read_reference("gray12_pt2_rst.raw"));
+}
+
+#[test]
+fn lossless_interleaved_restart_markers()
+{
+    let (pixels, ..) = decode("rgb8_p6_rst.jpg");
+
+    assert!(pixels == read_reference("rgb8_p6_rst.raw"));
+}
+
+#[test]
+fn lossless_interleaved_sampling_factors()
+{
+    // first component is sampled 2x2, the others 1x1
+    let (pixels, ..) = decode("sampling_2x2.jpg");
+
+    assert!(pixels == read_reference("sampling_2x2.raw"));
+}
+
+#[test]
+fn lossless_non_interleaved_scans()
+{
+    // one scan per component, each with a different predictor
+    let (pixels, ..) = decode("scans_2x1.jpg");
+
+    assert!(pixels == read_reference("scans_2x1.raw"));
+}
+
+#[test]
+fn lossless_dng_style_components()
+{
+    // two components numbered from zero, as DNG files do
+    let (pixels, ..) = decode("dng_2comp.jpg");
+
+    assert!(pixels == read_reference("dng_2comp.raw"));
+}
+
+#[test]
+fn lossless_8bit_u8_output()
+{
+    let mut decoder = Decoder::new();
+
+    let pixels = decoder
+        .decode_file(path("rgb8_p6_rst.jpg"))
+        .expect("Test failed decoding");
+
+    let reference = read_reference("rgb8_p6_rst.raw");
+
+    assert!(pixels
+        .iter()
+        .zip(reference.iter())
+        .all(|(a, b)| u16::from(*a) == *b));
+
+    assert_eq!(pixels.len(), reference.len());
+}
@@ -134,3 +134,35 @@ fn medium_horizontal_sampling_cymk()
         OutColorSpace::JCS_YCbCr,
     );
 }
+
+#[test]
+fn medium_rgb_matches_rgba()
+{
+    // the last columns of every row don't fill a whole run of 16 pixels, and are
+    // converted separately
+    for name in [
+        "medium_no_samp_2500x1786.jpg",
+        "medium_horiz_samp_2500x1786.jpg",
+    ]
+    {
+        let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name;
+
+        let mut decoder = Decoder::new();
+
+        decoder.set_output_colorspace(ColorSpace::RGB);
+
+        let rgb = decoder.decode_file(&path).expect("Test failed decoding");
+
+        decoder.set_output_colorspace(ColorSpace::RGBA);
+
+        let rgba = decoder.decode_file(&path).expect("Test failed decoding");
+
+        let rgba_without_alpha: Vec<u8> = rgba
+            .chunks_exact(4)
+            .flat_map(|x| &x[..3])
+            .copied()
+            .collect();
+
+        assert!(rgb == rgba_without_alpha, "{name}");
+    }
+}
@@ -0,0 +1,329 @@
+//! Turning images upright per their Exif orientation
+//!
+//! Images are given the Exif segment of `exif/big_endian.jpg`, with its orientation
+//! changed, and compared with what turning them as stored gives.
+use zune_jpeg::{ColorSpace, Decoder, FeedStatus, IncrementalDecoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// `name` with the Exif segment of `exif/big_endian.jpg`, saying `orientation`
+fn with_orientation(name: &str, orientation: u8) -> Vec<u8>
+{
+    let exif = read("exif/big_endian.jpg");
+
+    let length = usize::from(u16::from_be_bytes([exif[4], exif[5]]));
+
+    let mut segment = exif[2..4 + length].to_vec();
+
+    // the orientation tag, a single SHORT
+    let tag = segment
+        .windows(10)
+        .position(|x| x[..9] == [0x01, 0x12, 0, 3, 0, 0, 0, 1, 0])
+        .unwrap();
+
+    segment[tag + 9] = orientation;
+
+    let mut data = read(name);
+
+    data.splice(2..2, segment);
+
+    data
+}
+
+/// Turn `pixels`, a `width` x `height` image, as Exif orientation `orientation` says
+fn turn(pixels: &[u8], width: usize, height: usize, orientation: u8) -> Vec<u8>
+{
+    let channels = pixels.len() / (width * height);
+
+    let mut turned = vec![0; pixels.len()];
+
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            let (tx, ty, turned_width) = match orientation
+            {
+                1 => (x, y, width),
+                2 => (width - 1 - x, y, width),
+                3 => (width - 1 - x, height - 1 - y, width),
+                4 => (x, height - 1 - y, width),
+                5 => (y, x, height),
+                6 => (height - 1 - y, x, height),
+                7 => (height - 1 - y, width - 1 - x, height),
+                _ => (y, width - 1 - x, height),
+            };
+            let from = (y * width + x) * channels;
+
+            let to = (ty * turned_width + tx) * channels;
+
+            turned[to..to + channels].copy_from_slice(&pixels[from..from + channels]);
+        }
+    }
+    turned
+}
+
+/// Check every orientation of `name`, decoded by `decode` after `setup`
+fn check<F, G>(name: &str, setup: F, decode: G)
+where
+    F: Fn(&mut Decoder),
+    G: Fn(&mut Decoder, &[u8]) -> Vec<u8>,
+{
+    let mut decoder = Decoder::new();
+
+    setup(&mut decoder);
+
+    let stored = decode(&mut decoder, &read(name));
+
+    let (width, height) = decoder.output_dimensions().unwrap();
+
+    for orientation in 1..=8
+    {
+        let data = with_orientation(name, orientation);
+
+        let mut decoder = Decoder::new();
+
+        setup(&mut decoder);
+
+        // nothing is turned unless asked for
+        assert!(decode(&mut decoder, &data) == stored, "{name}");
+
+        decoder.set_apply_orientation(true);
+
+        let pixels = decode(&mut decoder, &data);
+
+        assert!(
+            pixels == turn(&stored, width, height, orientation),
+            "{name} orientation {orientation}"
+        );
+
+        let expected = if orientation >= 5
+        {
+            (height, width)
+        }
+        else
+        {
+            (width, height)
+        };
+
+        assert_eq!(decoder.output_dimensions(), Some(expected));
+    }
+}
+
+fn decode_buffer(decoder: &mut Decoder, data: &[u8]) -> Vec<u8>
+{
+    decoder.decode_buffer(data).unwrap()
+}
+
+#[test]
+fn orientation_baseline()
+{
+    for name in ["lenient/2x2.jpg", "dnl/2x1.jpg", "dnl/gray.jpg"]
+    {
+        check(name, |_| (), decode_buffer);
+    }
+    // four channels per pixel
+    check(
+        "dnl/2x1.jpg",
+        |x| x.set_output_colorspace(ColorSpace::RGBA),
+        decode_buffer,
+    );
+}
+
+#[test]
+fn orientation_progressive()
+{
+    for name in [
+        "dnl/progressive.jpg",
+        "lenient/2x2_prog.jpg",
+        "arithmetic/sof10_1x1.jpg",
+        "arithmetic/sof9_gray.jpg",
+    ]
+    {
+        check(name, |_| (), decode_buffer);
+    }
+}
+
+#[test]
+fn orientation_whole_planes()
+{
+    check("lossless/rgb8_p6_rst.jpg", |_| (), decode_buffer);
+
+    check("scaled/2x2.jpg", |x| x.set_scale(1, 4), decode_buffer);
+
+    // the region is one of the image as stored
+    check(
+        "crop/2x2_rst.jpg",
+        |x| x.set_crop(5, 17, 40, 23),
+        decode_buffer,
+    );
+
+    check(
+        "lossless/rgb8_p6_rst.jpg",
+        |x| x.set_crop(3, 2, 20, 11),
+        decode_buffer,
+    );
+
+    check("hierarchical/color.jpg", |_| (), decode_buffer);
+
+    check("cmyk/ycck.jpg", |_| (), decode_buffer);
+
+    // 4:2:0 progressive images are reconstructed a plane at a time
+    check("coefficients/2x2_progressive.jpg", |_| (), decode_buffer);
+}
+
+#[test]
+fn orientation_other_outputs()
+{
+    check(
+        "lenient/2x2.jpg",
+        |_| (),
+        |decoder, data| decoder.decode_reader(data).unwrap(),
+    );
+
+    check(
+        "dnl/2x1.jpg",
+        |_| (),
+        |decoder, data| {
+            decoder.read_headers(data).unwrap();
+
+            let mut out = vec![0; decoder.output_buffer_size().unwrap()];
+
+            decoder.decode_into(data, &mut out).unwrap();
+
+            out
+        },
+    );
+
+    // 12 bit samples, as bytes
+    check(
+        "extended/sof1_2x1.jpg",
+        |_| (),
+        |decoder, data| {
+            let samples = decoder.decode_buffer_u16(data).unwrap();
+
+            samples.iter().flat_map(|x| x.to_be_bytes()).collect()
+        },
+    );
+
+    check(
+        "hierarchical/lossless_16bit.jpg",
+        |_| (),
+        |decoder, data| {
+            let samples = decoder.decode_buffer_u16(data).unwrap();
+
+            samples.iter().flat_map(|x| x.to_be_bytes()).collect()
+        },
+    );
+}
+
+/// Width and height of the image, as `info` reports them
+fn dimensions(decoder: &Decoder) -> (u16, u16)
+{
+    let info = decoder.info().unwrap();
+
+    // `width` and `height` agree with it
+    assert_eq!(
+        (decoder.width(), decoder.height()),
+        (info.width, info.height)
+    );
+
+    (info.width, info.height)
+}
+
+#[test]
+fn orientation_info()
+{
+    let data = with_orientation("lenient/2x2.jpg", 6);
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    assert!(!decoder.get_apply_orientation());
+
+    assert_eq!(decoder.info().unwrap().exif.unwrap().orientation, Some(6));
+
+    assert_eq!(dimensions(&decoder), (477, 263));
+
+    decoder.set_apply_orientation(true);
+
+    assert_eq!(dimensions(&decoder), (263, 477));
+
+    // images without an orientation are left alone
+    decoder.read_headers(&read("lenient/2x2.jpg")).unwrap();
+
+    assert_eq!(dimensions(&decoder), (477, 263));
+}
+
+#[test]
+fn orientation_not_applied_to_rows()
+{
+    // progressive and lossless images are decoded whole by these readers, they are
+    // left as stored like baseline images, which are decoded a row at a time
+    for name in [
+        "dnl/2x1.jpg",
+        "dnl/progressive.jpg",
+        "lossless/rgb8_p6_rst.jpg",
+    ]
+    {
+        let mut reference = Decoder::new();
+
+        let stored = reference.decode_buffer(&read(name)).unwrap();
+
+        let (_, height) = reference.output_dimensions().unwrap();
+
+        let data = with_orientation(name, 6);
+
+        let mut decoder = Decoder::new();
+
+        decoder.set_apply_orientation(true);
+
+        let mut scanlines = decoder.scanlines(&data).unwrap();
+
+        assert_eq!(scanlines.row_size(), stored.len() / height);
+
+        let mut rows = vec![];
+
+        while let Some(batch) = scanlines.next_rows().unwrap()
+        {
+            rows.extend_from_slice(batch);
+        }
+        assert!(rows == stored, "{name}");
+
+        let mut decoder = Decoder::new();
+
+        decoder.set_apply_orientation(true);
+
+        let mut scans = decoder.progressive_scans(&data).unwrap();
+
+        while scans.next_scan().unwrap()
+        {}
+
+        assert!(scans.render() == stored, "{name}");
+
+        let mut decoder = Decoder::new();
+
+        decoder.set_apply_orientation(true);
+
+        let mut incremental = IncrementalDecoder::new(decoder);
+
+        let mut rows = vec![];
+
+        let mut status = incremental.feed(&data).unwrap();
+
+        loop
+        {
+            match status
+            {
+                FeedStatus::Rows(batch) => rows.extend_from_slice(batch),
+                FeedStatus::Finished => break,
+                _ => (),
+            }
+            status = incremental.finish().unwrap();
+        }
+        assert!(rows == stored, "{name}");
+    }
+}
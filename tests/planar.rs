@@ -0,0 +1,132 @@
+//! Decoding images to planes at their native sampling resolution
+use zune_jpeg::{ColorSpace, Decoder, Plane};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+fn planar(name: &str, denom: u32) -> Vec<Plane>
+{
+    let mut decoder = Decoder::new();
+
+    decoder.set_scale(1, denom);
+
+    decoder.decode_planar(&read(name)).unwrap()
+}
+
+/// Samples of a plane without the padding at the end of each row
+fn samples(plane: &Plane) -> Vec<u8>
+{
+    assert_eq!(plane.data.len(), plane.stride * plane.height);
+
+    plane
+        .data
+        .chunks_exact(plane.stride)
+        .flat_map(|row| &row[..plane.width])
+        .copied()
+        .collect()
+}
+
+/// Decode an image through the whole plane pipeline, which shares the IDCT with
+/// planar output
+fn decode(name: &str, colorspace: ColorSpace, denom: u32) -> Vec<u8>
+{
+    let data = read(name);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    decoder.set_scale(1, denom);
+
+    decoder.read_headers(&data).unwrap();
+
+    let (width, height) = decoder.output_dimensions().unwrap();
+
+    decoder.set_crop(0, 0, width, height);
+
+    decoder.decode_buffer(&data).unwrap()
+}
+
+#[test]
+fn planar_2x2_sampling()
+{
+    let planes = planar("scaled/2x2.jpg", 1);
+
+    let dimensions: Vec<_> = planes
+        .iter()
+        .map(|x| (x.width, x.height, x.stride))
+        .collect();
+
+    assert_eq!(
+        dimensions,
+        [(480, 256, 480), (240, 128, 240), (240, 128, 240)]
+    );
+
+    assert!(samples(&planes[0]) == decode("scaled/2x2.jpg", ColorSpace::GRAYSCALE, 1));
+
+    // chroma of a 2x2 image scaled by 1/2 is not up-sampled, so it should match
+    let half = decode("scaled/2x2.jpg", ColorSpace::YCbCr, 2);
+
+    for (component, plane) in planes.iter().enumerate().skip(1)
+    {
+        let chroma: Vec<u8> = half.iter().skip(component).step_by(3).copied().collect();
+
+        assert!(samples(plane) == chroma);
+    }
+}
+
+#[test]
+fn planar_padded_rows()
+{
+    // 50x37, MCUs are 16x8
+    let planes = planar("dnl/2x1_rst.jpg", 1);
+
+    let dimensions: Vec<_> = planes
+        .iter()
+        .map(|x| (x.width, x.height, x.stride))
+        .collect();
+
+    assert_eq!(dimensions, [(50, 37, 64), (25, 37, 32), (25, 37, 32)]);
+
+    assert!(samples(&planes[0]) == decode("dnl/2x1_rst.jpg", ColorSpace::GRAYSCALE, 1));
+}
+
+#[test]
+fn planar_other_images()
+{
+    for name in [
+        "dnl/gray.jpg",
+        "dnl/progressive.jpg",
+        "arithmetic/sof9_2x1.jpg",
+    ]
+    {
+        let planes = planar(name, 1);
+
+        assert!(samples(&planes[0]) == decode(name, ColorSpace::GRAYSCALE, 1));
+    }
+
+    assert_eq!(planar("dnl/gray.jpg", 1).len(), 1);
+}
+
+#[test]
+fn planar_scaled()
+{
+    let planes = planar("scaled/2x2.jpg", 2);
+
+    assert_eq!((planes[0].width, planes[0].height), (240, 128));
+
+    assert_eq!((planes[1].width, planes[1].height), (120, 64));
+
+    assert!(samples(&planes[0]) == decode("scaled/2x2.jpg", ColorSpace::GRAYSCALE, 2));
+}
+
+#[test]
+fn planar_unsupported_images()
+{
+    for name in ["lossless/rgb8_p6_rst.jpg", "extended/sof1_gray.jpg"]
+    {
+        assert!(Decoder::new().decode_planar(&read(name)).is_err());
+    }
+}
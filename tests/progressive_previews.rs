@@ -0,0 +1,120 @@
+//! Rendering progressive images after each scan
+use zune_jpeg::Decoder;
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// Mean absolute difference between two images
+fn difference(a: &[u8], b: &[u8]) -> f64
+{
+    assert_eq!(a.len(), b.len());
+
+    let sum: u64 = a
+        .iter()
+        .zip(b.iter())
+        .map(|(a, b)| u64::from(a.abs_diff(*b)))
+        .sum();
+
+    sum as f64 / a.len() as f64
+}
+
+/// Render the image after every scan, returning how far each preview is from the
+/// decoded image
+fn previews(name: &str, smoothing: bool) -> Vec<f64>
+{
+    let data = read(name);
+
+    let reference = Decoder::new().decode_buffer(&data).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    let mut scans = decoder.progressive_scans(&data).unwrap();
+
+    scans.set_block_smoothing(smoothing);
+
+    let mut differences = vec![];
+
+    while scans.next_scan().unwrap()
+    {
+        differences.push(difference(scans.render(), &reference));
+    }
+    assert!(!scans.next_scan().unwrap());
+
+    assert_eq!(scans.scans_decoded(), differences.len());
+
+    differences
+}
+
+#[test]
+fn previews_converge()
+{
+    for name in ["dnl/progressive.jpg", "coefficients/2x2_progressive.jpg"]
+    {
+        let data = read(name);
+
+        let differences = previews(name, false);
+
+        // one preview per scan
+        let sos = data.windows(2).filter(|x| *x == [0xFF, 0xDA]).count();
+
+        assert_eq!(differences.len(), sos);
+
+        // the last one is the decoded image
+        assert!(differences[0] > 1.0);
+
+        assert_eq!(*differences.last().unwrap(), 0.0);
+    }
+}
+
+#[test]
+fn previews_block_smoothing()
+{
+    let plain = previews("dnl/progressive.jpg", false);
+
+    let smoothed = previews("dnl/progressive.jpg", true);
+
+    // the first scans only carry DC coefficients
+    assert!(smoothed[0] < plain[0]);
+
+    // nothing is left to estimate once every scan has been decoded
+    assert_eq!(*smoothed.last().unwrap(), 0.0);
+}
+
+#[test]
+fn previews_of_other_images()
+{
+    // decoded whole by the first scan
+    assert_eq!(previews("medium_horiz_samp_2500x1786.jpg", true), [0.0]);
+
+    assert_eq!(previews("arithmetic/sof10_1x1.jpg", false), [0.0]);
+}
+
+#[test]
+fn previews_scaled()
+{
+    let data = read("coefficients/2x2_progressive.jpg");
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_scale(1, 8);
+
+    let reference = decoder.decode_buffer(&data).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_scale(1, 8);
+
+    let mut scans = decoder.progressive_scans(&data).unwrap();
+
+    let mut last = vec![];
+
+    while scans.next_scan().unwrap()
+    {
+        last = scans.render().to_vec();
+
+        assert_eq!(last.len(), 60 * 32 * 3);
+    }
+    assert!(last == reference);
+}
@@ -4,6 +4,11 @@ use std::io::Write;
 use mozjpeg::ColorSpace as OutColorSpace;
 use zune_jpeg::{ColorSpace, Decoder};
 
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
 fn write_output(name: &str, pixels: &[u8], width: usize, height: usize, colorspace: OutColorSpace)
 {
     let output: String = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/outputs/random/";
@@ -54,3 +59,71 @@ fn huffman_third_index()
         OutColorSpace::JCS_GRAYSCALE,
     );
 }
+
+#[test]
+fn huffman_large_fast_ac_values()
+{
+    // an optimized table of pixel sized noise at quality 100 gives AC values of
+    // six bits a three bit code, short enough for the fast AC table, which only
+    // has room for values from -32 to 31
+    let path = env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/huffman_large_fast_ac.jpg";
+
+    let data = std::fs::read(path).unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(ColorSpace::GRAYSCALE);
+
+    let pixels = decoder.decode_buffer(&data).expect("Test failed decoding");
+
+    let reference: Vec<u8> = mozjpeg::Decompress::new_mem(&data)
+        .unwrap()
+        .grayscale()
+        .unwrap()
+        .read_scanlines()
+        .unwrap();
+
+    assert_eq!(pixels.len(), reference.len());
+
+    // the IDCT rounds differently from libjpeg-turbo's
+    assert!(pixels
+        .iter()
+        .zip(&reference)
+        .all(|(x, y)| x.abs_diff(*y) <= 1));
+}
+
+#[test]
+fn progressive_2x2_sampling()
+{
+    // a progressive image holding the same coefficients as a baseline one decodes to
+    // the same pixels, the baseline image is cropped to its whole size to go through
+    // the same reconstruction
+    let progressive = Decoder::new()
+        .decode_buffer(&read("coefficients/2x2_progressive.jpg"))
+        .unwrap();
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_crop(0, 0, 480, 256);
+
+    let baseline = decoder.decode_buffer(&read("crop/2x2_rst.jpg")).unwrap();
+
+    assert!(progressive == baseline);
+
+    let reference: Vec<u8> =
+        mozjpeg::Decompress::new_mem(&read("coefficients/2x2_progressive.jpg"))
+            .unwrap()
+            .rgb()
+            .unwrap()
+            .read_scanlines_flat()
+            .unwrap();
+
+    assert_eq!(progressive.len(), reference.len());
+
+    // chroma is up-sampled like libjpeg-turbo's fancy up-sampling, the IDCT rounds
+    // differently
+    assert!(progressive
+        .iter()
+        .zip(&reference)
+        .all(|(x, y)| x.abs_diff(*y) <= 1));
+}
@@ -0,0 +1,93 @@
+//! Reading image information without decoding the image
+use zune_jpeg::{ColorSpace, Decoder, SOFMarkers};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+#[test]
+fn headers_baseline()
+{
+    let mut decoder = Decoder::new();
+
+    assert!(decoder.info().is_none());
+
+    decoder.read_headers(&read("dnl/2x1_rst.jpg")).unwrap();
+
+    let info = decoder.info().unwrap();
+
+    assert_eq!((info.width, info.height), (50, 37));
+
+    assert_eq!(info.components, 3);
+
+    assert_eq!(info.sampling_factors, [(2, 1), (1, 1), (1, 1)]);
+
+    assert!(info.sof == SOFMarkers::BaselineDct);
+
+    assert!(!info.progressive);
+
+    // one MCU row
+    assert_eq!(info.restart_interval, 4);
+}
+
+#[test]
+fn headers_progressive()
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&read("dnl/progressive.jpg")).unwrap();
+
+    let info = decoder.info().unwrap();
+
+    assert_eq!(info.sampling_factors, [(1, 1), (1, 1), (1, 1)]);
+
+    assert!(info.sof == SOFMarkers::ProgressiveDctHuffman);
+
+    assert!(info.progressive);
+
+    assert_eq!(info.restart_interval, 0);
+}
+
+#[test]
+fn headers_deferred_height()
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&read("dnl/dnl_gray.jpg")).unwrap();
+
+    let info = decoder.info().unwrap();
+
+    assert_eq!((info.width, info.height), (61, 45));
+
+    assert_eq!(info.sampling_factors, [(1, 1)]);
+}
+
+#[test]
+fn decode_after_headers()
+{
+    for name in ["dnl/2x1_rst.jpg", "dnl/progressive.jpg", "dnl/dnl_gray.jpg"]
+    {
+        let data = read(name);
+
+        let mut decoder = Decoder::new();
+
+        decoder.set_output_colorspace(ColorSpace::RGB);
+
+        decoder.read_headers(&data).unwrap();
+
+        let pixels = decoder.decode_buffer(&data).unwrap();
+
+        let mut reference = Decoder::new();
+
+        reference.set_output_colorspace(ColorSpace::RGB);
+
+        assert!(pixels == reference.decode_buffer(&data).unwrap());
+    }
+}
+
+#[test]
+fn headers_invalid()
+{
+    assert!(Decoder::new().read_headers(&[0xFF, 0xD8, 0xFF]).is_err());
+}
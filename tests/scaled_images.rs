@@ -0,0 +1,176 @@
+//! Decoding images at a reduced size
+//!
+//! The `.ppm`/`.pgm` files in `tests/inputs/scaled` are what libjpeg-turbo's `djpeg -scale`
+//! decodes the images to.
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// Read samples from a binary 8 bit PPM/PGM file
+fn read_reference(name: &str) -> Vec<u8>
+{
+    let data = read(name);
+
+    // skip the three header lines (magic, dimensions and max value)
+    let mut newlines = 0;
+
+    let start = data
+        .iter()
+        .position(|x| {
+            newlines += usize::from(*x == b'\n');
+            newlines == 3
+        })
+        .unwrap();
+
+    data[start + 1..].to_vec()
+}
+
+fn decode(name: &str, colorspace: ColorSpace, denom: u32) -> (Vec<u8>, (usize, usize))
+{
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    decoder.set_scale(1, denom);
+
+    let pixels = decoder.decode_buffer(&read(name)).unwrap();
+
+    (pixels, decoder.output_dimensions().unwrap())
+}
+
+#[test]
+fn scaled_2x2_sampling()
+{
+    for (denom, dimensions) in [(2, (240, 128)), (4, (120, 64)), (8, (60, 32))]
+    {
+        let (pixels, size) = decode("scaled/2x2.jpg", ColorSpace::RGB, denom);
+
+        assert_eq!(size, dimensions);
+
+        assert!(pixels == read_reference(&format!("scaled/2x2_1_{denom}.ppm")));
+    }
+}
+
+#[test]
+fn scaled_grayscale()
+{
+    let (pixels, size) = decode("dnl/gray.jpg", ColorSpace::GRAYSCALE, 4);
+
+    // 61x45 rounded up
+    assert_eq!(size, (16, 12));
+
+    assert!(pixels == read_reference("scaled/gray_1_4.pgm"));
+}
+
+#[test]
+fn scaled_progressive()
+{
+    let (pixels, _) = decode("dnl/progressive.jpg", ColorSpace::RGB, 2);
+
+    assert!(pixels == read_reference("scaled/progressive_1_2.ppm"));
+}
+
+#[test]
+fn scaled_arithmetic()
+{
+    let (pixels, _) = decode("arithmetic/sof9_2x1.jpg", ColorSpace::RGB, 8);
+
+    assert!(pixels == read_reference("scaled/sof9_2x1_1_8.ppm"));
+}
+
+#[test]
+fn scaled_2x1_sampling()
+{
+    let (pixels, _) = decode("dnl/2x1.jpg", ColorSpace::RGB, 2);
+
+    let reference = read_reference("scaled/2x1_1_2.ppm");
+
+    assert_eq!(pixels.len(), reference.len());
+
+    // up-sampling rounds slightly differently from libjpeg
+    assert!(pixels
+        .iter()
+        .zip(reference.iter())
+        .all(|(a, b)| a.abs_diff(*b) <= 2));
+}
+
+#[test]
+fn scale_rounding()
+{
+    let data = read("scaled/2x2.jpg");
+
+    // scales are rounded up to the next supported one
+    for ((num, denom), dimensions) in [
+        ((3, 8), (240, 128)),
+        ((1, 5), (120, 64)),
+        ((1, 16), (60, 32)),
+        ((3, 4), (480, 256)),
+        ((2, 1), (480, 256)),
+    ]
+    {
+        let mut decoder = Decoder::new();
+
+        decoder.set_scale(num, denom);
+
+        decoder.read_headers(&data).unwrap();
+
+        assert_eq!(decoder.output_dimensions(), Some(dimensions));
+
+        assert_eq!(
+            decoder.output_buffer_size(),
+            Some(dimensions.0 * dimensions.1 * 3)
+        );
+    }
+}
+
+#[test]
+fn scaled_decode_into_and_scanlines()
+{
+    let data = read("scaled/2x2.jpg");
+
+    let (reference, _) = decode("scaled/2x2.jpg", ColorSpace::RGB, 4);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_scale(1, 4);
+
+    let mut out = vec![0; reference.len()];
+
+    decoder.decode_into(&data, &mut out).unwrap();
+
+    assert!(out == reference);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_scale(1, 4);
+
+    let mut scanlines = decoder.scanlines(&data).unwrap();
+
+    assert_eq!(scanlines.row_size(), 120 * 3);
+
+    let mut pixels = vec![];
+
+    while let Some(rows) = scanlines.next_rows().unwrap()
+    {
+        pixels.extend_from_slice(rows);
+    }
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn scale_ignored_for_lossless()
+{
+    let data = read("lossless/rgb8_p6_rst.jpg");
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_scale(1, 2);
+
+    let pixels = decoder.decode_buffer(&data).unwrap();
+
+    assert!(pixels == Decoder::new().decode_buffer(&data).unwrap());
+}
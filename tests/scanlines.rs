@@ -0,0 +1,137 @@
+//! Decoding images row by row
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// Decode an image with a `ScanlineReader` and `decode_buffer`, the results should match
+fn compare(name: &str, colorspace: ColorSpace)
+{
+    let data = read(name);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_output_colorspace(colorspace);
+
+    let mut scanlines = decoder.scanlines(&data).unwrap();
+
+    let row_size = scanlines.row_size();
+
+    let batch_size = row_size * scanlines.rows_per_batch();
+
+    let mut pixels = vec![];
+
+    let mut last = false;
+
+    while let Some(rows) = scanlines.next_rows().unwrap()
+    {
+        // only the last batch may be short
+        assert!(!last);
+
+        assert_eq!(rows.len() % row_size, 0);
+
+        last = rows.len() != batch_size;
+
+        pixels.extend_from_slice(rows);
+    }
+    let mut reference = Decoder::new();
+
+    reference.set_output_colorspace(colorspace);
+
+    assert!(pixels == reference.decode_buffer(&data).unwrap());
+}
+
+#[test]
+fn scanlines_baseline()
+{
+    compare("dnl/gray.jpg", ColorSpace::GRAYSCALE);
+
+    compare("dnl/2x1.jpg", ColorSpace::RGBA);
+
+    compare("dnl/2x1_rst.jpg", ColorSpace::RGB);
+}
+
+#[test]
+fn scanlines_large_baseline()
+{
+    compare("medium_horiz_samp_2500x1786.jpg", ColorSpace::RGB);
+
+    compare("medium_no_samp_2500x1786.jpg", ColorSpace::YCbCr);
+}
+
+#[test]
+fn scanlines_other_images()
+{
+    compare("dnl/progressive.jpg", ColorSpace::RGB);
+
+    compare("dnl/dnl_gray.jpg", ColorSpace::GRAYSCALE);
+
+    compare("arithmetic/sof9_2x1.jpg", ColorSpace::RGB);
+
+    compare("lossless/rgb8_p6_rst.jpg", ColorSpace::RGB);
+
+    // ends in lossless frames, so the rows are not converted to CMYK
+    compare("hierarchical/color_2x1_lossless.jpg", ColorSpace::CMYK);
+}
+
+#[test]
+fn scanlines_odd_mcu_rows()
+{
+    // rows of two MCU rows are decoded at a time, these images have an odd
+    // number of MCU rows, so the last one is decoded by itself
+    for name in ["exif/big_endian.jpg", "scanlines/2x1_odd_rows.jpg"]
+    {
+        compare(name, ColorSpace::RGB);
+
+        let data = read(name);
+
+        let pixels = Decoder::new().decode_buffer(&data).unwrap();
+
+        let mut reference = mozjpeg::Decompress::new_mem(&data).unwrap();
+
+        reference.do_fancy_upsampling(false);
+
+        let reference: Vec<u8> = reference.rgb().unwrap().read_scanlines_flat().unwrap();
+
+        assert_eq!(pixels.len(), reference.len());
+
+        // the IDCT rounds differently from libjpeg-turbo's
+        assert!(
+            pixels
+                .iter()
+                .zip(&reference)
+                .all(|(x, y)| x.abs_diff(*y) <= 1),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn scanlines_batches()
+{
+    let data = read("dnl/2x1_rst.jpg");
+
+    let mut decoder = Decoder::new();
+
+    let mut scanlines = decoder.scanlines(&data).unwrap();
+
+    let info = scanlines.info();
+
+    assert_eq!((info.width, info.height), (50, 37));
+
+    assert_eq!(scanlines.row_size(), 50 * 3);
+
+    // MCUs of 2x1 sampled images are 16 pixels wide, written two at a time
+    assert_eq!(scanlines.rows_per_batch(), 16);
+
+    let mut rows = vec![];
+
+    while let Some(batch) = scanlines.next_rows().unwrap()
+    {
+        rows.push(batch.len() / (50 * 3));
+    }
+
+    assert_eq!(rows, [16, 16, 5]);
+}
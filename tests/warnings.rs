@@ -0,0 +1,280 @@
+//! Warnings about problems which do not stop an image from being decoded
+//!
+//! Damaged images are mostly made from `lenient/2x2.jpg` by inserting bytes in
+//! front of its quantization tables.
+use zune_jpeg::errors::{DecodeErrors, DecodeWarning, WarningKind};
+use zune_jpeg::{Decoder, FeedStatus, IncrementalDecoder};
+
+fn read(name: &str) -> Vec<u8>
+{
+    std::fs::read(env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + name).unwrap()
+}
+
+/// The test image with `bytes` inserted before its first DQT segment, and where they
+/// were inserted
+fn insert(bytes: &[u8]) -> (Vec<u8>, usize)
+{
+    let mut data = read("lenient/2x2.jpg");
+
+    let pos = data.windows(2).position(|x| x == [0xFF, 0xDB]).unwrap();
+
+    data.splice(pos..pos, bytes.iter().copied());
+
+    (data, pos)
+}
+
+fn decode(data: &[u8]) -> (Vec<u8>, Vec<DecodeWarning>)
+{
+    let mut decoder = Decoder::new();
+
+    let pixels = decoder.decode_buffer(data).unwrap();
+
+    (pixels, decoder.warnings().to_vec())
+}
+
+#[test]
+fn warnings_none_for_valid_images()
+{
+    let expected = decode(&read("lenient/2x2.jpg"));
+
+    assert!(expected.1.is_empty());
+
+    // comments, application segments and fill bytes are allowed
+    let (data, _) = insert(&[
+        0xFF, 0xFE, 0x00, 0x05, b'a', b'b', b'c', 0xFF, 0xE5, 0x00, 0x04, 0xFF, 0xD4, 0xFF, 0xFF,
+    ]);
+
+    assert!(decode(&data) == expected);
+}
+
+#[test]
+fn warnings_skipped_bytes()
+{
+    let (data, pos) = insert(&[0x12, 0x34, 0x56]);
+
+    let (pixels, warnings) = decode(&data);
+
+    assert!(pixels == decode(&read("lenient/2x2.jpg")).0);
+
+    assert_eq!(warnings.len(), 1);
+
+    assert_eq!(warnings[0].kind, WarningKind::SkippedBytes);
+
+    assert_eq!(warnings[0].marker, Some(0xDB));
+
+    assert_eq!(warnings[0].offset, pos);
+}
+
+#[test]
+fn warnings_skipped_markers()
+{
+    // a marker the standard does not define, with a segment
+    let (data, pos) = insert(&[0xFF, 0x50, 0x00, 0x04, 0x00, 0x00]);
+
+    let warnings = decode(&data).1;
+
+    assert_eq!(warnings.len(), 1);
+
+    assert_eq!(warnings[0].kind, WarningKind::UnknownMarker);
+
+    assert_eq!(warnings[0].marker, Some(0x50));
+
+    assert_eq!(warnings[0].offset, pos);
+
+    // a restart marker outside of entropy coded data
+    let (data, pos) = insert(&[0xFF, 0xD3]);
+
+    let warnings = decode(&data).1;
+
+    assert_eq!(warnings.len(), 1);
+
+    assert_eq!(warnings[0].kind, WarningKind::UnexpectedMarker);
+
+    assert_eq!(warnings[0].marker, Some(0xD3));
+
+    assert_eq!(warnings[0].offset, pos);
+
+    assert_eq!(
+        warnings[0].to_string(),
+        format!("Skipped marker RST(3) in the headers at offset {pos}")
+    );
+}
+
+#[test]
+fn warnings_as_errors()
+{
+    let (data, pos) = insert(&[0x12, 0x34, 0x56]);
+
+    let mut decoder = Decoder::new();
+
+    assert!(decoder.get_warnings_as_errors().is_empty());
+
+    // other kinds are still warnings
+    decoder.set_warnings_as_errors(&[WarningKind::UnknownMarker]);
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.warnings().len(), 1);
+
+    decoder.set_warnings_as_errors(&[WarningKind::UnknownMarker, WarningKind::SkippedBytes]);
+
+    assert_eq!(
+        decoder.get_warnings_as_errors(),
+        [WarningKind::UnknownMarker, WarningKind::SkippedBytes]
+    );
+
+    match decoder.decode_buffer(&data)
+    {
+        Err(DecodeErrors::Warning(warning)) =>
+        {
+            assert_eq!(warning.kind, WarningKind::SkippedBytes);
+
+            assert_eq!(warning.offset, pos);
+        }
+        result => panic!("{:?}", result.err()),
+    }
+    assert!(decoder.warnings().is_empty());
+}
+
+#[test]
+fn warnings_lenient_as_errors()
+{
+    let data = read("lenient/2x2.jpg");
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_strict_mode(false);
+
+    decoder.set_warnings_as_errors(&[WarningKind::MissingData]);
+
+    // truncated images fail to decode rather than being filled in
+    match decoder.decode_buffer(&data[..data.len() / 2])
+    {
+        Err(DecodeErrors::Warning(warning)) =>
+        {
+            assert_eq!(warning.kind, WarningKind::MissingData);
+
+            assert_eq!(warning.offset, data.len() / 2);
+        }
+        result => panic!("{:?}", result.err()),
+    }
+}
+
+#[test]
+fn warnings_offsets_in_streams()
+{
+    let mut data = insert(&[0x12, 0x34, 0x56]).0;
+
+    data.truncate(data.len() / 2);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_strict_mode(false);
+
+    let expected = decoder.decode_buffer(&data).unwrap();
+
+    let warnings = decoder.warnings().to_vec();
+
+    assert_eq!(
+        warnings.iter().map(|x| x.kind).collect::<Vec<_>>(),
+        [WarningKind::SkippedBytes, WarningKind::MissingData]
+    );
+
+    assert_eq!(warnings[1].offset, data.len());
+
+    // the same offsets for images read from a stream, or fed a chunk at a time
+    assert!(decoder.decode_reader(&data[..]).unwrap() == expected);
+
+    assert_eq!(decoder.warnings(), warnings);
+
+    for chunk in [1, 1000]
+    {
+        let mut decoder = Decoder::new();
+
+        decoder.set_strict_mode(false);
+
+        let mut incremental = IncrementalDecoder::new(decoder);
+
+        for chunk in data.chunks(chunk)
+        {
+            let mut status = incremental.feed(chunk).unwrap();
+
+            while status != FeedStatus::NeedMoreData
+            {
+                status = incremental.feed(&[]).unwrap();
+            }
+        }
+        while incremental.finish().unwrap() != FeedStatus::Finished
+        {}
+
+        assert_eq!(incremental.warnings(), warnings, "chunks of {chunk}");
+    }
+}
+
+#[test]
+fn warnings_hierarchical_images()
+{
+    let mut data = read("hierarchical/dct_gray.jpg");
+
+    // in front of the EXP segment ending the first frame
+    let pos = data.windows(2).position(|x| x == [0xFF, 0xDF]).unwrap();
+
+    data.splice(
+        pos..pos,
+        [
+            0xFF, 0x50, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xFE, 0x00, 0x02, 0xFF, 0xD3,
+        ],
+    );
+
+    let (pixels, warnings) = decode(&data);
+
+    assert!(pixels == decode(&read("hierarchical/dct_gray.jpg")).0);
+
+    assert_eq!(
+        warnings
+            .iter()
+            .map(|x| (x.kind, x.marker, x.offset))
+            .collect::<Vec<_>>(),
+        [
+            (WarningKind::UnknownMarker, Some(0x50), pos),
+            (WarningKind::UnexpectedMarker, Some(0xD3), pos + 10)
+        ]
+    );
+
+    assert_eq!(
+        warnings[1].to_string(),
+        format!(
+            "Skipped marker RST(3) between frames at offset {}",
+            pos + 10
+        )
+    );
+}
+
+#[test]
+fn warnings_missing_dnl()
+{
+    assert!(decode(&read("dnl/dnl_gray.jpg")).1.is_empty());
+
+    let data = read("dnl/no_dnl_gray_rst.jpg");
+
+    let (pixels, warnings) = decode(&data);
+
+    assert!(pixels == decode(&read("dnl/gray_rst.jpg")).0);
+
+    // the lines are counted from the start of the first scan
+    let sos = data.windows(2).position(|x| x == [0xFF, 0xDA]).unwrap();
+
+    let scan = sos + 2 + usize::from(u16::from_be_bytes([data[sos + 2], data[sos + 3]]));
+
+    assert_eq!(warnings.len(), 1);
+
+    assert_eq!(warnings[0].kind, WarningKind::MissingDnl);
+
+    assert_eq!(warnings[0].offset, scan);
+
+    let mut decoder = Decoder::new();
+
+    decoder.set_warnings_as_errors(&[WarningKind::MissingDnl]);
+
+    assert!(decoder.decode_buffer(&data).is_err());
+}